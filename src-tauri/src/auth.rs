@@ -1,14 +1,51 @@
 use aes_gcm::{
-    aead::{Aead, AeadCore, KeyInit, OsRng},
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
     Aes256Gcm, Nonce,
 };
 
-use crate::models::TokenRefreshResult;
+use crate::http_client::HttpClientFactory;
+use crate::ipc::EventSink;
+use crate::models::{TokenRefreshResult, TokensRefreshedEvent};
+use crate::oauth::{OAuthRefresh, OAuthTokens};
+use crate::Database;
+use base64::Engine;
 use std::fmt;
 use std::fs;
-use std::path::Path;
-use std::sync::Arc;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
 use std::time::Duration;
+use tracing::{debug, info, warn};
+
+/// Refresh token, зашифрованный TokenEncryption (app_meta)
+const META_REFRESH_TOKEN: &str = "auth_refresh_token";
+/// Token endpoint и client_id провайдера, если вход был через SSO (oauth.rs); пусто — API /auth/refresh
+const META_OAUTH_CLIENT: &str = "auth_oauth_client";
+
+/// Access token обновляется заранее, если до `exp` осталось меньше
+pub const TOKEN_REFRESH_LEEWAY_SECS: i64 = 60;
+
+/// Claims JWT без проверки подписи
+fn jwt_claims(token: &str) -> Option<serde_json::Value> {
+    let claims = token.split('.').nth(1)?;
+    let decoded = base64::engine::general_purpose::URL_SAFE_NO_PAD
+        .decode(claims.trim_end_matches('='))
+        .ok()?;
+    serde_json::from_slice(&decoded).ok()
+}
+
+/// Claim `exp` (unix секунды) из JWT без проверки подписи — только чтобы решить, когда обновлять
+pub fn jwt_expiry(token: &str) -> Option<i64> {
+    jwt_claims(token)?.get("exp")?.as_i64()
+}
+
+/// Claim `sub` из JWT без проверки подписи (id_token, полученный напрямую от token endpoint)
+pub fn jwt_subject(token: &str) -> Option<String> {
+    jwt_claims(token)?
+        .get("sub")?
+        .as_str()
+        .filter(|sub| !sub.is_empty())
+        .map(str::to_string)
+}
 
 /// Ошибки аутентификации (для разбора и логирования)
 #[derive(Debug)]
@@ -17,6 +54,19 @@ pub enum AuthError {
     Network(String),
     Http { status: u16 },
     Parse(String),
+    // Ошибка OAuth-провайдера (`error` из ответа token endpoint: invalid_grant, ...)
+    OAuth(String),
+}
+
+impl AuthError {
+    /// Refresh token отозван или истёк — повторять с ним бессмысленно
+    pub fn is_refresh_rejected(&self) -> bool {
+        match self {
+            AuthError::Http { status } => *status == 401 || *status == 403,
+            AuthError::OAuth(code) => code == "invalid_grant",
+            _ => false,
+        }
+    }
 }
 
 impl fmt::Display for AuthError {
@@ -26,26 +76,68 @@ impl fmt::Display for AuthError {
             AuthError::Network(s) => write!(f, "Network: {}", s),
             AuthError::Http { status } => write!(f, "HTTP {}", status),
             AuthError::Parse(s) => write!(f, "Parse: {}", s),
+            AuthError::OAuth(s) => write!(f, "OAuth: {}", s),
         }
     }
 }
 
-/// Конфигурация AuthManager (api_base_url, таймаут HTTP)
+/// Конфигурация AuthManager (api_base_url, таймаут HTTP, сетевые настройки)
 #[derive(Clone)]
 pub struct AuthConfig {
     pub api_base_url: String,
     pub http_timeout_secs: u64,
+    pub http: HttpClientFactory,
+    /// Куда отправлять auth-tokens-refreshed (общий с SyncManager)
+    pub event_sink: Arc<RwLock<Option<EventSink>>>,
+    /// БД для refresh token между перезапусками (None — только в памяти)
+    pub token_store: Option<Arc<Database>>,
 }
 
 impl Default for AuthConfig {
     fn default() -> Self {
         Self {
-            api_base_url: "https://app.automatonsoft.de/api".to_string(),
+            api_base_url: crate::endpoint::DEFAULT_API_BASE_URL.to_string(),
             http_timeout_secs: 10,
+            http: HttpClientFactory::default(),
+            event_sink: Arc::new(RwLock::new(None)),
+            token_store: None,
+        }
+    }
+}
+
+/// Сохранённый refresh token (расшифрованный); нет или не расшифровывается — None
+fn load_refresh_token(db: &Database) -> Option<String> {
+    let encrypted = db
+        .get_app_meta(META_REFRESH_TOKEN)
+        .ok()
+        .flatten()
+        .filter(|v| !v.is_empty())?;
+    match db.encryption.decrypt(&encrypted) {
+        Ok(token) => Some(token),
+        Err(e) => {
+            warn!("[AUTH] Failed to decrypt stored refresh token: {}", e);
+            None
         }
     }
 }
 
+/// Записать refresh token (None — удалить)
+fn persist_refresh_token(db: &Database, token: Option<&str>) {
+    let value = match token {
+        Some(token) => match db.encryption.encrypt(token) {
+            Ok(encrypted) => encrypted,
+            Err(e) => {
+                warn!("[AUTH] Failed to encrypt refresh token: {}", e);
+                return;
+            }
+        },
+        None => String::new(),
+    };
+    if let Err(e) = db.set_app_meta(META_REFRESH_TOKEN, &value) {
+        warn!("[AUTH] Failed to persist refresh token: {}", e);
+    }
+}
+
 /// Менеджер аутентификации для получения токенов
 /// Получает токены из localStorage через Tauri команду
 pub struct AuthManager {
@@ -53,33 +145,83 @@ pub struct AuthManager {
     client: reqwest::Client,
     pub access_token: Arc<tokio::sync::RwLock<Option<String>>>,
     pub refresh_token: Arc<tokio::sync::RwLock<Option<String>>>,
+    /// Single-flight: refresh token ротируется сервером — параллельные refresh инвалидировали бы друг друга
+    refresh_lock: tokio::sync::Mutex<()>,
+    event_sink: Arc<RwLock<Option<EventSink>>>,
+    token_store: Option<Arc<Database>>,
+    /// Refresh token, который уже ротировал Rust: frontend, пропустивший auth-tokens-refreshed,
+    /// может прислать его снова через set_auth_tokens
+    superseded_refresh_token: tokio::sync::Mutex<Option<String>>,
+    /// Вход через SSO: refresh идёт в token endpoint провайдера
+    oauth_client: RwLock<Option<OAuthRefresh>>,
 }
 
 impl AuthManager {
     pub fn new(api_base_url: String) -> Self {
         Self::new_with_config(AuthConfig {
             api_base_url,
-            http_timeout_secs: 10,
+            ..Default::default()
         })
     }
 
     pub fn new_with_config(config: AuthConfig) -> Self {
-        let client = reqwest::Client::builder()
-            .timeout(Duration::from_secs(config.http_timeout_secs))
-            .build()
-            .unwrap_or_else(|_| reqwest::Client::new());
+        let client = config
+            .http
+            .build(Duration::from_secs(config.http_timeout_secs));
+        // Фоновая синхронизация после перезапуска не ждёт set_auth_tokens от webview
+        let stored_refresh = config.token_store.as_deref().and_then(load_refresh_token);
+        if stored_refresh.is_some() {
+            info!("[AUTH] Restored refresh token from local store");
+        }
+        let oauth_client = config
+            .token_store
+            .as_deref()
+            .and_then(|db| db.get_app_meta(META_OAUTH_CLIENT).ok().flatten())
+            .and_then(|json| serde_json::from_str::<OAuthRefresh>(&json).ok());
         Self {
             api_base_url: config.api_base_url.clone(),
             client,
             access_token: Arc::new(tokio::sync::RwLock::new(None)),
-            refresh_token: Arc::new(tokio::sync::RwLock::new(None)),
+            refresh_token: Arc::new(tokio::sync::RwLock::new(stored_refresh)),
+            refresh_lock: tokio::sync::Mutex::new(()),
+            event_sink: config.event_sink,
+            token_store: config.token_store,
+            superseded_refresh_token: tokio::sync::Mutex::new(None),
+            oauth_client: RwLock::new(oauth_client),
         }
     }
 
     /// Установить токены (вызывается из Tauri команды)
     pub async fn set_tokens(&self, access_token: Option<String>, refresh_token: Option<String>) {
+        if refresh_token.is_some() && *self.superseded_refresh_token.lock().await == refresh_token {
+            let current = (
+                self.access_token.read().await.clone(),
+                self.refresh_token.read().await.clone(),
+            );
+            // Frontend прислал уже ротированный или отозванный при выходе токен — не применяем
+            debug!("[AUTH] Ignoring superseded refresh token from frontend");
+            if let (Some(access), Some(refresh)) = current {
+                self.emit_tokens_refreshed(&access, &refresh);
+            }
+            return;
+        }
+        self.store_tokens(access_token, refresh_token).await;
+    }
+
+    /// Записать токены; refresh token сохраняется в БД только при изменении
+    async fn store_tokens(&self, access_token: Option<String>, refresh_token: Option<String>) {
+        if access_token.is_none() && refresh_token.is_none() {
+            // Выход: следующий вход может быть обычным (API /auth/refresh)
+            self.set_oauth_client(None);
+        }
         *self.access_token.write().await = access_token;
-        *self.refresh_token.write().await = refresh_token;
+        let mut current = self.refresh_token.write().await;
+        if *current != refresh_token {
+            if let Some(db) = &self.token_store {
+                persist_refresh_token(db, refresh_token.as_deref());
+            }
+            *current = refresh_token;
+        }
     }
 
     /// Получить access token
@@ -96,11 +238,142 @@ impl AuthManager {
         Ok(self.refresh_token.read().await.clone())
     }
 
-    /// Обновить токен через refresh token
+    /// Access token для запроса; истекающий (exp в пределах TOKEN_REFRESH_LEEWAY_SECS) обновляется заранее.
+    /// Не удалось обновить — возвращается текущий токен (сервер ответит 401, sync_task повторит refresh).
+    /// Без access token (перезапуск), но с сохранённым refresh token — access token запрашивается сразу.
+    pub async fn get_fresh_token(&self) -> Result<String, AuthError> {
+        let access_token = self.access_token.read().await.clone();
+        let token = match access_token {
+            Some(token) => token,
+            None if self.refresh_token.read().await.is_some() => {
+                return self.refresh_access_token(None).await;
+            }
+            None => return self.get_access_token().await,
+        };
+        let expires_soon = matches!(
+            jwt_expiry(&token),
+            Some(exp) if exp - chrono::Utc::now().timestamp() <= TOKEN_REFRESH_LEEWAY_SECS
+        );
+        if !expires_soon || self.refresh_token.read().await.is_none() {
+            return Ok(token);
+        }
+        match self.refresh_access_token(Some(&token)).await {
+            Ok(fresh) => Ok(fresh),
+            Err(e) => {
+                warn!("[AUTH] Proactive token refresh failed: {}", e);
+                Ok(token)
+            }
+        }
+    }
+
+    /// Single-flight refresh после отказа в `rejected_token` (401 или истекает).
+    /// Если пока ждали блокировку, токен уже обновил другой вызов — возвращается его результат без запроса.
+    /// None — access token ещё не было (после перезапуска).
+    pub async fn refresh_access_token(
+        &self,
+        rejected_token: Option<&str>,
+    ) -> Result<String, AuthError> {
+        let _guard = self.refresh_lock.lock().await;
+        if let Some(current) = self.access_token.read().await.clone() {
+            if Some(current.as_str()) != rejected_token {
+                return Ok(current);
+            }
+        }
+        let refresh = self
+            .get_refresh_token()
+            .await?
+            .ok_or_else(|| AuthError::TokenNotSet("No refresh token available".into()))?;
+
+        let result = match self.refresh_token(&refresh).await {
+            Ok(result) => result,
+            Err(e) if e.is_refresh_rejected() => {
+                // Refresh token отозван или истёк — не повторяем с ним; новый придёт при входе
+                warn!("[AUTH] Refresh token rejected ({}), clearing it", e);
+                self.store_tokens(None, None).await;
+                return Err(e);
+            }
+            Err(e) => return Err(e),
+        };
+        // Сервер может не ротировать refresh token — тогда остаётся прежний
+        let refresh_token = result.refresh_token.unwrap_or_else(|| refresh.clone());
+        if refresh_token != refresh {
+            *self.superseded_refresh_token.lock().await = Some(refresh);
+        }
+        self.store_tokens(
+            Some(result.access_token.clone()),
+            Some(refresh_token.clone()),
+        )
+        .await;
+        info!("[AUTH] Access token refreshed");
+        self.emit_tokens_refreshed(&result.access_token, &refresh_token);
+        Ok(result.access_token)
+    }
+
+    /// Токены от OAuth-провайдера (oauth.rs): сохраняются как обычные, refresh — у провайдера
+    pub async fn sign_in_with_oauth(&self, tokens: OAuthTokens, client: OAuthRefresh) {
+        let _guard = self.refresh_lock.lock().await;
+        self.set_oauth_client(Some(client));
+        *self.superseded_refresh_token.lock().await = None;
+        self.store_tokens(
+            Some(tokens.access_token.clone()),
+            tokens.refresh_token.clone(),
+        )
+        .await;
+        info!("[AUTH] Signed in with OAuth provider");
+        self.emit_tokens_refreshed(
+            &tokens.access_token,
+            tokens.refresh_token.as_deref().unwrap_or(""),
+        );
+    }
+
+    fn set_oauth_client(&self, client: Option<OAuthRefresh>) {
+        if let Some(db) = &self.token_store {
+            let json = client
+                .as_ref()
+                .and_then(|c| serde_json::to_string(c).ok())
+                .unwrap_or_default();
+            if let Err(e) = db.set_app_meta(META_OAUTH_CLIENT, &json) {
+                warn!("[AUTH] Failed to persist OAuth client: {}", e);
+            }
+        }
+        if let Ok(mut guard) = self.oauth_client.write() {
+            *guard = client;
+        }
+    }
+
+    /// auth-tokens-refreshed → frontend обновляет свою копию (localStorage)
+    fn emit_tokens_refreshed(&self, access_token: &str, refresh_token: &str) {
+        let sink = match self.event_sink.read() {
+            Ok(guard) => guard.clone(),
+            Err(_) => return,
+        };
+        let event = TokensRefreshedEvent {
+            access_token: access_token.to_string(),
+            refresh_token: refresh_token.to_string(),
+            expires_at: jwt_expiry(access_token),
+        };
+        if let (Some(sink), Ok(value)) = (sink, serde_json::to_value(&event)) {
+            sink(crate::ipc::events::TOKENS_REFRESHED, value);
+        }
+    }
+
+    /// Обновить токен через refresh token (один HTTP-запрос, без блокировки — см. refresh_access_token)
     pub async fn refresh_token(
         &self,
         refresh_token: &str,
     ) -> Result<TokenRefreshResult, AuthError> {
+        let oauth_client = self
+            .oauth_client
+            .read()
+            .ok()
+            .and_then(|guard| guard.clone());
+        if let Some(client) = oauth_client {
+            let tokens = crate::oauth::refresh(&self.client, &client, refresh_token).await?;
+            return Ok(TokenRefreshResult {
+                access_token: tokens.access_token,
+                refresh_token: tokens.refresh_token,
+            });
+        }
         let url = format!("{}/auth/refresh", self.api_base_url);
         let response = self
             .client
@@ -111,7 +384,7 @@ impl AuthManager {
             }))
             .send()
             .await
-            .map_err(|e| AuthError::Network(e.to_string()))?;
+            .map_err(|e| AuthError::Network(crate::http_client::error_chain(&e)))?;
 
         let status = response.status();
         if !status.is_success() {
@@ -137,6 +410,67 @@ impl AuthManager {
             refresh_token,
         })
     }
+
+    /// Выход: отозвать refresh token на сервере (или у OAuth-провайдера) и стереть токены из памяти и БД.
+    /// Отзыв — best effort: локальные токены удаляются в любом случае. Возвращает true, если сервер подтвердил отзыв.
+    pub async fn sign_out(&self) -> bool {
+        // Под refresh_lock: параллельный refresh не вернёт только что удалённые токены
+        let _guard = self.refresh_lock.lock().await;
+        let access_token = self.access_token.read().await.clone();
+        let refresh_token = self.refresh_token.read().await.clone();
+        let revocation = self
+            .revoke(access_token.as_deref(), refresh_token.as_deref())
+            .await;
+        let revoked = match revocation {
+            Ok(revoked) => revoked,
+            Err(e) => {
+                warn!("[AUTH] Token revocation failed: {}", e);
+                false
+            }
+        };
+        *self.superseded_refresh_token.lock().await = refresh_token;
+        self.store_tokens(None, None).await;
+        info!("[AUTH] Signed out (revoked: {})", revoked);
+        revoked
+    }
+
+    async fn revoke(
+        &self,
+        access_token: Option<&str>,
+        refresh_token: Option<&str>,
+    ) -> Result<bool, String> {
+        let oauth_client = self
+            .oauth_client
+            .read()
+            .ok()
+            .and_then(|guard| guard.clone());
+        if let (Some(client), Some(refresh_token)) = (oauth_client, refresh_token) {
+            return crate::oauth::revoke(&self.client, &client, refresh_token).await;
+        }
+        // POST /auth/logout требует Bearer; без access token отзывать нечем
+        let access_token = match access_token {
+            Some(token) => token,
+            None => return Ok(false),
+        };
+        let body = match refresh_token {
+            Some(token) => serde_json::json!({ "refreshToken": token }),
+            None => serde_json::json!({}),
+        };
+        let response = self
+            .client
+            .post(format!("{}/auth/logout", self.api_base_url))
+            .bearer_auth(access_token)
+            .json(&body)
+            .send()
+            .await
+            .map_err(|e| crate::http_client::error_chain(&e))?;
+        match response.status().as_u16() {
+            200..=299 => Ok(true),
+            // Токен уже недействителен — отзывать нечего
+            401 => Ok(false),
+            status => Err(format!("Logout returned HTTP {}", status)),
+        }
+    }
 }
 
 // ============================================
@@ -144,43 +478,168 @@ impl AuthManager {
 // ============================================
 
 /// Шифрование токенов перед сохранением в SQLite
-/// Использует AES-256-GCM для шифрования
+/// Использует AES-256-GCM для шифрования.
+/// Шифротекст начинается с id ключа (см. CIPHERTEXT_MAGIC), поэтому после ротации
+/// данные, зашифрованные прежним ключом, расшифровываются до перешифрования.
 pub struct TokenEncryption {
+    /// Активный ключ первым, затем ключи до ротации (PREVIOUS_KEYS_FILE)
+    keys: RwLock<Vec<EncryptionKey>>,
+    /// Каталог fallback-файла ключа (для purge_key_material и ротации)
+    key_dir: Option<PathBuf>,
+    /// Ключ задан HUBNITY_ENCRYPTION_KEY — приложение не может его ротировать
+    from_env: bool,
+}
+
+/// Ключ шифрования и его id (первые 4 байта SHA-256 — сам ключ id не раскрывает)
+#[derive(Clone)]
+struct EncryptionKey {
+    id: u32,
+    raw: [u8; 32],
     cipher: Aes256Gcm,
 }
 
+impl EncryptionKey {
+    fn new(raw: [u8; 32]) -> Self {
+        use sha2::{Digest, Sha256};
+        let mut hasher = Sha256::new();
+        hasher.update(b"hubnity-key-id-v1");
+        hasher.update(raw);
+        let digest = hasher.finalize();
+        Self {
+            id: u32::from_be_bytes([digest[0], digest[1], digest[2], digest[3]]),
+            raw,
+            cipher: Aes256Gcm::new(&raw.into()),
+        }
+    }
+
+    fn from_hex(hex_key: &str) -> Result<Self, String> {
+        let key = hex::decode(hex_key.trim()).map_err(|e| format!("Invalid key hex: {}", e))?;
+        let raw: [u8; 32] = key
+            .try_into()
+            .map_err(|_| "Encryption key must be 32 bytes".to_string())?;
+        Ok(Self::new(raw))
+    }
+
+    /// Ключ SQLCipher: производный от ключа шифрования, не равен ему
+    fn database_key(&self) -> String {
+        use sha2::{Digest, Sha256};
+        let mut hasher = Sha256::new();
+        hasher.update(b"hubnity-sqlcipher-v1");
+        hasher.update(self.raw);
+        format!("x'{}'", hex::encode(hasher.finalize()))
+    }
+}
+
 const KEYRING_SERVICE: &str = "com.balabiturembek.hubnity";
 const KEYRING_USER: &str = "encryption_key";
+/// Активный ключ (hex) — формат не изменился, его читают и версии до ротации
+const KEY_FILE: &str = ".hubnity_encryption_key";
+/// Ключи до ротации (по строке hex): нужны, пока не перешифрованы все данные
+const PREVIOUS_KEYS_FILE: &str = ".hubnity_encryption_key.previous";
+/// Заголовок шифротекста: magic, версия формата, id ключа (u32 BE), затем nonce + ciphertext.
+/// Данные без заголовка — формат до key id (nonce + ciphertext).
+const CIPHERTEXT_MAGIC: &[u8; 3] = b"HBK";
+const CIPHERTEXT_V1: u8 = 1;
+/// v2: AES-GCM с associated data — расшифровывается только с тем же aad (encrypt_with_aad)
+const CIPHERTEXT_V2: u8 = 2;
+const HEADER_LEN: usize = 8;
+const NONCE_LEN: usize = 12;
+
+/// (id ключа, nonce + ciphertext), если данные в формате `version` с заголовком
+fn split_header(data: &[u8], version: u8) -> Option<(u32, &[u8])> {
+    if data.len() < HEADER_LEN + NONCE_LEN || &data[..3] != CIPHERTEXT_MAGIC || data[3] != version {
+        return None;
+    }
+    let id = u32::from_be_bytes([data[4], data[5], data[6], data[7]]);
+    Some((id, &data[HEADER_LEN..]))
+}
+
+fn decrypt_with(cipher: &Aes256Gcm, data: &[u8], aad: &[u8]) -> Result<Vec<u8>, String> {
+    if data.len() < NONCE_LEN {
+        return Err("Invalid encrypted data length".to_string());
+    }
+    // Извлекаем nonce (первые 12 байт) и ciphertext (остальное)
+    let nonce = Nonce::from_slice(&data[..NONCE_LEN]);
+    let payload = Payload {
+        msg: &data[NONCE_LEN..],
+        aad,
+    };
+    cipher
+        .decrypt(nonce, payload)
+        .map_err(|e| format!("Decryption failed: {}", e))
+}
+
+/// Запись через временный файл + rename: обрезанный файл ключа = потерянные данные
+fn write_key_file(path: &Path, contents: &str) -> Result<(), String> {
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, contents).map_err(|e| format!("Failed to write key file: {}", e))?;
+    fs::rename(&tmp, path).map_err(|e| {
+        let _ = fs::remove_file(&tmp);
+        format!("Failed to replace key file: {}", e)
+    })
+}
 
 impl TokenEncryption {
     /// Создать новый экземпляр с ключом из:
     /// 1. HUBNITY_ENCRYPTION_KEY (hex) env var
     /// 2. OS Keychain (macOS) / Credential Manager (Windows) / Secret Service (Linux)
     /// 3. Fallback: randomly generated key stored in app_data_dir (never hardcoded)
+    ///
+    /// Плюс ключи незавершённой ротации из PREVIOUS_KEYS_FILE (только для расшифровки).
     pub fn new(app_data_dir: Option<&Path>) -> Result<Self, String> {
-        let key = Self::resolve_encryption_key(app_data_dir)?;
-
-        if key.len() != 32 {
-            return Err("Encryption key must be 32 bytes".to_string());
-        }
+        let (keys, from_env) = Self::load_keys(app_data_dir)?;
+        Ok(Self {
+            keys: RwLock::new(keys),
+            key_dir: app_data_dir.map(Path::to_path_buf),
+            from_env,
+        })
+    }
 
-        let key_array: [u8; 32] = key
+    fn load_keys(app_data_dir: Option<&Path>) -> Result<(Vec<EncryptionKey>, bool), String> {
+        let from_env = Self::get_key_from_env().is_some();
+        let raw: [u8; 32] = Self::resolve_encryption_key(app_data_dir)?
             .try_into()
-            .map_err(|_| "Failed to convert key to array".to_string())?;
+            .map_err(|_| "Encryption key must be 32 bytes".to_string())?;
+        let mut keys = vec![EncryptionKey::new(raw)];
+        if let Some(dir) = app_data_dir {
+            for key in Self::read_previous_keys(dir) {
+                if keys.iter().all(|k| k.id != key.id) {
+                    keys.push(key);
+                }
+            }
+        }
+        Ok((keys, from_env))
+    }
 
-        let cipher = Aes256Gcm::new(&key_array.into());
+    fn read_previous_keys(app_data_dir: &Path) -> Vec<EncryptionKey> {
+        let contents = match fs::read_to_string(app_data_dir.join(PREVIOUS_KEYS_FILE)) {
+            Ok(contents) => contents,
+            Err(_) => return Vec::new(),
+        };
+        contents
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .filter_map(|line| match EncryptionKey::from_hex(line) {
+                Ok(key) => Some(key),
+                Err(e) => {
+                    warn!("[AUTH] Ignoring invalid previous encryption key: {}", e);
+                    None
+                }
+            })
+            .collect()
+    }
 
-        Ok(Self { cipher })
+    fn get_key_from_env() -> Option<Vec<u8>> {
+        let env_key = std::env::var("HUBNITY_ENCRYPTION_KEY").ok()?;
+        hex::decode(env_key.trim())
+            .ok()
+            .filter(|decoded| decoded.len() == 32)
     }
 
     fn resolve_encryption_key(app_data_dir: Option<&Path>) -> Result<Vec<u8>, String> {
         // 1. Env var (hex) - for CI/deployment override
-        if let Ok(env_key) = std::env::var("HUBNITY_ENCRYPTION_KEY") {
-            if let Ok(decoded) = hex::decode(env_key.trim()) {
-                if decoded.len() == 32 {
-                    return Ok(decoded);
-                }
-            }
+        if let Some(key) = Self::get_key_from_env() {
+            return Ok(key);
         }
 
         // 2. Fallback file only — keychain disabled on macOS (causes repeated prompts even with "Always Allow")
@@ -201,7 +660,7 @@ impl TokenEncryption {
 
     /// Read key from fallback file (no keychain access)
     fn get_key_from_fallback_file(app_data_dir: &Path) -> Result<Vec<u8>, String> {
-        let key_file = app_data_dir.join(".hubnity_encryption_key");
+        let key_file = app_data_dir.join(KEY_FILE);
         if !key_file.exists() {
             return Err("Fallback key file does not exist".to_string());
         }
@@ -217,14 +676,13 @@ impl TokenEncryption {
 
     /// Create new key and save to fallback file only (avoids keychain prompt loop on macOS)
     fn create_fallback_key_only(app_data_dir: &Path) -> Result<Vec<u8>, String> {
-        let key_file = app_data_dir.join(".hubnity_encryption_key");
+        let key_file = app_data_dir.join(KEY_FILE);
         let key: [u8; 32] = rand::random();
         let hex_key = hex::encode(key);
         fs::write(&key_file, hex_key).map_err(|e| format!("Failed to write key file: {}", e))?;
         Ok(key.to_vec())
     }
 
-    #[allow(dead_code)]
     fn get_key_from_keyring() -> Result<Vec<u8>, String> {
         use keyring::Entry;
         let entry = Entry::new(KEYRING_SERVICE, KEYRING_USER)
@@ -245,17 +703,153 @@ impl TokenEncryption {
             .map_err(|e| format!("Keyring set failed: {}", e))
     }
 
+    fn delete_keyring_entry() {
+        match keyring::Entry::new(KEYRING_SERVICE, KEYRING_USER)
+            .and_then(|entry| entry.delete_credential())
+        {
+            Ok(()) | Err(keyring::Error::NoEntry) => {}
+            // Keyring недоступен (нет Secret Service) — ключ там и не хранился
+            Err(e) => warn!("[AUTH] Failed to delete keyring entry: {}", e),
+        }
+    }
+
+    /// Выход с очисткой секретов: прежние ключи удаляются из keyring и файлов, активным становится
+    /// новый ключ (записан в KEY_FILE) — данные, зашифрованные после выхода, расшифровываются и после
+    /// перезапуска. Данные на прежнем ключе, в том числе в других БД каталога (ключ общий), больше
+    /// не расшифровать; их экземпляры TokenEncryption подхватывают новый ключ через reload_keys.
+    /// Ключ из HUBNITY_ENCRYPTION_KEY приложение не заменяет.
+    pub fn purge_key_material(&self) -> Result<(), String> {
+        let mut keys = self.keys.write().unwrap_or_else(|e| e.into_inner());
+        Self::delete_keyring_entry();
+        let new_key = EncryptionKey::new(rand::random());
+        if let Some(dir) = &self.key_dir {
+            // Сначала новый ключ (атомарная замена): сбой после неё не оставляет каталог без ключа
+            let mut stale = vec![PREVIOUS_KEYS_FILE];
+            if self.from_env {
+                stale.push(KEY_FILE);
+            } else {
+                write_key_file(&dir.join(KEY_FILE), &hex::encode(new_key.raw))?;
+            }
+            for file in stale {
+                match fs::remove_file(dir.join(file)) {
+                    Ok(()) => info!("[AUTH] Encryption key file {} removed", file),
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                    Err(e) => return Err(format!("Failed to remove key file: {}", e)),
+                }
+            }
+        }
+        keys.truncate(1);
+        if !self.from_env {
+            info!(
+                "[AUTH] Encryption key purged, new key id {:08x}",
+                new_key.id
+            );
+            keys[0] = new_key;
+        }
+        Ok(())
+    }
+
+    fn keys(&self) -> std::sync::RwLockReadGuard<'_, Vec<EncryptionKey>> {
+        self.keys.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn active_key(&self) -> EncryptionKey {
+        self.keys()[0].clone()
+    }
+
+    /// Id ключа, которым шифруются новые данные
+    pub fn active_key_id(&self) -> u32 {
+        self.keys()[0].id
+    }
+
+    /// Id всех известных ключей (активный первым)
+    pub fn key_ids(&self) -> Vec<u32> {
+        self.keys().iter().map(|k| k.id).collect()
+    }
+
+    /// Начать ротацию: новый ключ становится активным, прежние сохраняются в PREVIOUS_KEYS_FILE
+    /// и расшифровывают старые данные, пока не вызван retire_previous_keys. Возвращает id нового ключа.
+    pub fn rotate_key(&self) -> Result<u32, String> {
+        if self.from_env {
+            return Err(
+                "Encryption key is set by HUBNITY_ENCRYPTION_KEY and cannot be rotated by the app"
+                    .to_string(),
+            );
+        }
+        let dir = self
+            .key_dir
+            .as_deref()
+            .ok_or_else(|| "Encryption key has no key file to rotate".to_string())?;
+        let mut keys = self.keys.write().unwrap_or_else(|e| e.into_inner());
+        let new_key = EncryptionKey::new(rand::random());
+        // Сначала прежние ключи: сбой между записями не теряет ни один ключ
+        let previous: String = keys
+            .iter()
+            .map(|k| format!("{}\n", hex::encode(k.raw)))
+            .collect();
+        write_key_file(&dir.join(PREVIOUS_KEYS_FILE), &previous)?;
+        write_key_file(&dir.join(KEY_FILE), &hex::encode(new_key.raw))?;
+        let id = new_key.id;
+        keys.insert(0, new_key);
+        info!("[AUTH] Encryption key rotated, new key id {:08x}", id);
+        Ok(id)
+    }
+
+    /// Перечитать ключи из файлов (ротацию выполнил экземпляр другой БД того же каталога)
+    pub fn reload_keys(&self) -> Result<(), String> {
+        let (keys, _) = Self::load_keys(self.key_dir.as_deref())?;
+        *self.keys.write().unwrap_or_else(|e| e.into_inner()) = keys;
+        Ok(())
+    }
+
+    /// Завершить ротацию (все данные перешифрованы): прежние ключи удаляются из памяти,
+    /// fallback-файла и keyring. Возвращает число удалённых ключей.
+    pub fn retire_previous_keys(&self) -> Result<usize, String> {
+        let mut keys = self.keys.write().unwrap_or_else(|e| e.into_inner());
+        let retired = keys.len() - 1;
+        if let Some(dir) = &self.key_dir {
+            match fs::remove_file(dir.join(PREVIOUS_KEYS_FILE)) {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(format!("Failed to remove previous keys: {}", e)),
+            }
+        }
+        // В keyring мог остаться ключ версий, которые его использовали
+        if let Ok(stored) = Self::get_key_from_keyring() {
+            if stored.as_slice() != keys[0].raw.as_slice() {
+                Self::delete_keyring_entry();
+            }
+        }
+        keys.truncate(1);
+        Ok(retired)
+    }
+
+    /// Raw-ключ для `PRAGMA key` (`x'<hex>'`). None — сборка без feature `sqlcipher`,
+    /// файл БД не шифруется (шифруются только payload очереди и blob-файлы).
+    pub fn database_key(&self) -> Option<String> {
+        if cfg!(feature = "sqlcipher") {
+            Some(self.active_key().database_key())
+        } else {
+            None
+        }
+    }
+
+    /// Ключи SQLCipher всех известных ключей (активный первым): файл, открытый до завершения
+    /// ротации, ещё зашифрован прежним
+    pub fn database_keys(&self) -> Vec<String> {
+        if cfg!(feature = "sqlcipher") {
+            self.keys()
+                .iter()
+                .map(EncryptionKey::database_key)
+                .collect()
+        } else {
+            Vec::new()
+        }
+    }
+
     /// Зашифровать токен
     pub fn encrypt(&self, token: &str) -> Result<String, String> {
-        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
-        let ciphertext = self
-            .cipher
-            .encrypt(&nonce, token.as_bytes())
-            .map_err(|e| format!("Encryption failed: {}", e))?;
-
-        // Сохраняем nonce и ciphertext вместе (nonce + ciphertext)
-        let mut result = nonce.to_vec();
-        result.extend_from_slice(&ciphertext);
+        let result = self.encrypt_bytes(token.as_bytes())?;
 
         // Кодируем в base64 для хранения в SQLite
         use base64::{engine::general_purpose, Engine as _};
@@ -264,32 +858,130 @@ impl TokenEncryption {
 
     /// Расшифровать токен
     pub fn decrypt(&self, encrypted: &str) -> Result<String, String> {
+        let (plaintext, _) = self.decrypt_with_key_id(encrypted)?;
+        Ok(plaintext)
+    }
+
+    fn decode(encrypted: &str) -> Result<Vec<u8>, String> {
         use base64::{engine::general_purpose, Engine as _};
-        let data = general_purpose::STANDARD
+        general_purpose::STANDARD
             .decode(encrypted)
-            .map_err(|e| format!("Base64 decode failed: {}", e))?;
+            .map_err(|e| format!("Base64 decode failed: {}", e))
+    }
 
-        if data.len() < 12 {
-            return Err("Invalid encrypted data length".to_string());
-        }
+    /// (plaintext, id ключа из заголовка; None — формат без key id)
+    fn decrypt_with_key_id(&self, encrypted: &str) -> Result<(String, Option<u32>), String> {
+        let data = Self::decode(encrypted)?;
+        let (plaintext, key_id) = self.decrypt_bytes_with_key_id(&data)?;
+        let plaintext =
+            String::from_utf8(plaintext).map_err(|e| format!("UTF-8 decode failed: {}", e))?;
+        Ok((plaintext, key_id))
+    }
 
-        // Извлекаем nonce (первые 12 байт) и ciphertext (остальное)
-        let nonce = Nonce::from_slice(&data[..12]);
-        let ciphertext = &data[12..];
+    /// Зашифровать бинарные данные (заголовок + nonce + ciphertext, без base64) — для blob-файлов на диске
+    pub fn encrypt_bytes(&self, data: &[u8]) -> Result<Vec<u8>, String> {
+        self.seal(CIPHERTEXT_V1, data, &[])
+    }
 
-        let plaintext = self
+    fn seal(&self, version: u8, data: &[u8], aad: &[u8]) -> Result<Vec<u8>, String> {
+        let key = self.active_key();
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = key
             .cipher
-            .decrypt(nonce, ciphertext)
-            .map_err(|e| format!("Decryption failed: {}", e))?;
+            .encrypt(&nonce, Payload { msg: data, aad })
+            .map_err(|e| format!("Encryption failed: {}", e))?;
 
-        String::from_utf8(plaintext).map_err(|e| format!("UTF-8 decode failed: {}", e))
+        let mut result = Vec::with_capacity(HEADER_LEN + NONCE_LEN + ciphertext.len());
+        result.extend_from_slice(CIPHERTEXT_MAGIC);
+        result.push(version);
+        result.extend_from_slice(&key.id.to_be_bytes());
+        result.extend_from_slice(&nonce);
+        result.extend_from_slice(&ciphertext);
+        Ok(result)
+    }
+
+    /// Зашифровать с associated data (формат v2): расшифровать можно только с тем же `aad`.
+    /// Payload очереди привязывается к своей строке (database::payload_aad).
+    pub fn encrypt_with_aad(&self, plaintext: &str, aad: &[u8]) -> Result<String, String> {
+        let result = self.seal(CIPHERTEXT_V2, plaintext.as_bytes(), aad)?;
+        use base64::{engine::general_purpose, Engine as _};
+        Ok(general_purpose::STANDARD.encode(&result))
+    }
+
+    /// Расшифровать формат v2 с тем же `aad`. Другой aad (payload скопирован в чужую строку или
+    /// под другой entity_type) и данные без привязки — Err.
+    /// (plaintext, true) — зашифровано не активным ключом, вызывающий код перешифровывает.
+    pub fn decrypt_with_aad(&self, encrypted: &str, aad: &[u8]) -> Result<(String, bool), String> {
+        let data = Self::decode(encrypted)?;
+        let (id, body) = split_header(&data, CIPHERTEXT_V2)
+            .ok_or_else(|| "Ciphertext is not bound to associated data".to_string())?;
+        let keys = self.keys();
+        let key = keys
+            .iter()
+            .find(|k| k.id == id)
+            .ok_or_else(|| format!("Unknown encryption key {:08x}", id))?;
+        let plaintext = decrypt_with(&key.cipher, body, aad).map_err(|_| {
+            "Associated data mismatch: ciphertext belongs to another record".to_string()
+        })?;
+        let plaintext =
+            String::from_utf8(plaintext).map_err(|e| format!("UTF-8 decode failed: {}", e))?;
+        Ok((plaintext, id != keys[0].id))
+    }
+
+    /// Формат v2 (с associated data) — по заголовку, без расшифровки
+    pub fn is_bound(&self, encrypted: &str) -> bool {
+        Self::decode(encrypted)
+            .map(|data| split_header(&data, CIPHERTEXT_V2).is_some())
+            .unwrap_or(false)
+    }
+
+    /// Расшифровать бинарные данные в формате encrypt_bytes (или в формате без key id)
+    pub fn decrypt_bytes(&self, data: &[u8]) -> Result<Vec<u8>, String> {
+        self.decrypt_bytes_with_key_id(data)
+            .map(|(plaintext, _)| plaintext)
+    }
+
+    fn decrypt_bytes_with_key_id(&self, data: &[u8]) -> Result<(Vec<u8>, Option<u32>), String> {
+        let keys = self.keys();
+        if let Some((id, body)) = split_header(data, CIPHERTEXT_V1) {
+            if let Some(key) = keys.iter().find(|k| k.id == id) {
+                if let Ok(plaintext) = decrypt_with(&key.cipher, body, &[]) {
+                    return Ok((plaintext, Some(id)));
+                }
+            }
+        }
+        // Формат без key id (или nonce случайно начался с magic): пробуем все ключи
+        for key in keys.iter() {
+            if let Ok(plaintext) = decrypt_with(&key.cipher, data, &[]) {
+                return Ok((plaintext, None));
+            }
+        }
+        if data.len() < NONCE_LEN {
+            return Err("Invalid encrypted data length".to_string());
+        }
+        Err("Decryption failed: no known key matches".to_string())
+    }
+
+    /// Данные уже зашифрованы активным ключом (по заголовку, без расшифровки)
+    pub fn is_current_bytes(&self, data: &[u8]) -> bool {
+        let header =
+            split_header(data, CIPHERTEXT_V1).or_else(|| split_header(data, CIPHERTEXT_V2));
+        matches!(header, Some((id, _)) if id == self.active_key_id())
+    }
+
+    /// То же для base64-строки (payload очереди, app_meta)
+    pub fn is_current(&self, encrypted: &str) -> bool {
+        Self::decode(encrypted)
+            .map(|data| self.is_current_bytes(&data))
+            .unwrap_or(false)
     }
 
     /// Расшифровать с миграцией: при неудаче пробует legacy-ключ.
-    /// Возвращает (plaintext, true) если использован legacy — вызывающий код должен перешифровать и сохранить.
+    /// Возвращает (plaintext, true), если данные зашифрованы не активным ключом (legacy, формат
+    /// без key id или ключ до ротации) — вызывающий код должен перешифровать и сохранить.
     pub fn decrypt_with_legacy_fallback(&self, encrypted: &str) -> Result<(String, bool), String> {
-        match self.decrypt(encrypted) {
-            Ok(plaintext) => Ok((plaintext, false)),
+        match self.decrypt_with_key_id(encrypted) {
+            Ok((plaintext, key_id)) => Ok((plaintext, key_id != Some(self.active_key_id()))),
             Err(_) => {
                 if let Ok(plaintext) = Self::legacy_decrypt(encrypted) {
                     tracing::warn!(
@@ -305,23 +997,10 @@ impl TokenEncryption {
 
     /// Расшифровать legacy-данные (pre-keyring ключ)
     fn legacy_decrypt(encrypted: &str) -> Result<String, String> {
-        use base64::{engine::general_purpose, Engine as _};
-        let data = general_purpose::STANDARD
-            .decode(encrypted)
-            .map_err(|e| format!("Base64 decode failed: {}", e))?;
-
-        if data.len() < 12 {
-            return Err("Invalid encrypted data length".to_string());
-        }
-
+        let data = Self::decode(encrypted)?;
         let legacy_key: [u8; 32] = *b"default-encryption-key-32-bytes!";
         let legacy_cipher = Aes256Gcm::new(&legacy_key.into());
-
-        let nonce = Nonce::from_slice(&data[..12]);
-        let ciphertext = &data[12..];
-
-        let plaintext = legacy_cipher
-            .decrypt(nonce, ciphertext)
+        let plaintext = decrypt_with(&legacy_cipher, &data, &[])
             .map_err(|e| format!("Legacy decryption failed: {}", e))?;
 
         String::from_utf8(plaintext).map_err(|e| format!("UTF-8 decode failed: {}", e))
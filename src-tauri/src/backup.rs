@@ -0,0 +1,260 @@
+//! Online backups of a profile database.
+//! A backup is a consistent snapshot taken with SQLite's backup API from a read connection (the
+//! writer is not blocked), checked with `integrity_check` and kept in `backups/` next to the
+//! database: `<db file name>.<unix ts>.bak`, the newest `BACKUP_KEEP` per database.
+//! A restore never touches an open database: the chosen backup is verified and staged as
+//! `<db>.restore`, and `Database::open` swaps it in on the next start; the replaced file is kept as
+//! `<db>.<unix ts>.replaced` and its unsent queue is carried over into the restored database.
+
+use crate::db_pool::{apply_key, is_plaintext_database};
+use rusqlite::backup::Backup;
+use rusqlite::Error::InvalidParameterName;
+use rusqlite::{Connection, OpenFlags, Result as SqliteResult};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// Сколько последних копий хранить на каждую БД
+pub const BACKUP_KEEP: usize = 5;
+/// Период плановой копии
+pub const BACKUP_INTERVAL_SECS: i64 = 6 * 60 * 60;
+
+const BACKUP_DIR: &str = "backups";
+const BACKUP_SUFFIX: &str = ".bak";
+const RESTORE_SUFFIX: &str = ".restore";
+/// БД, заменённая восстановленной копией (не копия: в список и ротацию не входит)
+const REPLACED_SUFFIX: &str = ".replaced";
+
+/// Резервная копия на диске
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct BackupInfo {
+    /// Имя файла в каталоге backups (идентификатор для restore_backup)
+    pub file_name: String,
+    pub path: String,
+    /// Время создания (unix секунды)
+    pub created_at: i64,
+    pub size_bytes: u64,
+}
+
+pub fn backup_dir(db_path: &Path) -> PathBuf {
+    db_path
+        .parent()
+        .unwrap_or_else(|| Path::new("."))
+        .join(BACKUP_DIR)
+}
+
+fn db_file_name(db_path: &Path) -> String {
+    db_path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// Копии этой БД, новые первыми
+pub fn list_backups(db_path: &Path) -> Vec<BackupInfo> {
+    let prefix = format!("{}.", db_file_name(db_path));
+    let entries = match std::fs::read_dir(backup_dir(db_path)) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    let mut backups: Vec<BackupInfo> = entries
+        .filter_map(|e| e.ok())
+        .filter_map(|entry| {
+            let file_name = entry.file_name().to_str()?.to_string();
+            let created_at = file_name
+                .strip_prefix(&prefix)?
+                .strip_suffix(BACKUP_SUFFIX)?
+                .parse::<i64>()
+                .ok()?;
+            Some(BackupInfo {
+                path: entry.path().to_string_lossy().into_owned(),
+                size_bytes: entry.metadata().map(|m| m.len()).unwrap_or(0),
+                file_name,
+                created_at,
+            })
+        })
+        .collect();
+    backups.sort_by_key(|b| std::cmp::Reverse(b.created_at));
+    backups
+}
+
+/// Открыть копию только для чтения (копии до SQLCipher — без ключа)
+fn open_backup(path: &Path, key: Option<&str>) -> SqliteResult<Connection> {
+    let conn = Connection::open_with_flags(
+        path,
+        OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+    )?;
+    if !is_plaintext_database(path) {
+        apply_key(&conn, key)?;
+    }
+    Ok(conn)
+}
+
+/// integrity_check копии; Err — копию нельзя восстанавливать
+pub fn verify_backup(path: &Path, key: Option<&str>) -> Result<(), String> {
+    let conn = open_backup(path, key).map_err(|e| format!("Failed to open backup: {}", e))?;
+    let integrity: String = conn
+        .query_row("PRAGMA integrity_check", [], |r| r.get(0))
+        .map_err(|e| format!("Backup integrity check failed: {}", e))?;
+    if integrity.eq_ignore_ascii_case("ok") {
+        Ok(())
+    } else {
+        Err(format!("Backup is corrupted: {}", integrity))
+    }
+}
+
+/// Снимок `src` (соединение этой БД) в новый файл копии + ротация.
+/// Копия пишется во временный файл и становится видимой в списке только после integrity_check.
+pub(crate) fn create_backup(
+    src: &Connection,
+    db_path: &Path,
+    key: Option<&str>,
+) -> SqliteResult<BackupInfo> {
+    let dir = backup_dir(db_path);
+    std::fs::create_dir_all(&dir)
+        .map_err(|e| InvalidParameterName(format!("Failed to create backup dir: {}", e)))?;
+    // Не больше одной копии в секунду: повтор в ту же секунду перезаписывает копию
+    let created_at = chrono::Utc::now().timestamp();
+    let file_name = format!("{}.{}{}", db_file_name(db_path), created_at, BACKUP_SUFFIX);
+    let target = dir.join(&file_name);
+    let tmp = with_suffix(&target, ".tmp");
+    let _ = std::fs::remove_file(&tmp);
+
+    let result = (|| {
+        let mut dst = Connection::open(&tmp)?;
+        apply_key(&dst, key)?;
+        // Все страницы за один шаг — согласованный снимок одной read-транзакции
+        Backup::new(src, &mut dst)?.run_to_completion(i32::MAX, Duration::ZERO, None)?;
+        drop(dst);
+        verify_backup(&tmp, key).map_err(InvalidParameterName)?;
+        std::fs::rename(&tmp, &target)
+            .map_err(|e| InvalidParameterName(format!("Failed to store backup: {}", e)))
+    })();
+    if let Err(e) = result {
+        let _ = std::fs::remove_file(&tmp);
+        return Err(e);
+    }
+
+    for old in list_backups(db_path).into_iter().skip(BACKUP_KEEP) {
+        if let Err(e) = std::fs::remove_file(&old.path) {
+            warn!(
+                "[BACKUP] Failed to remove old backup {}: {}",
+                old.file_name, e
+            );
+        }
+    }
+    Ok(BackupInfo {
+        path: target.to_string_lossy().into_owned(),
+        size_bytes: std::fs::metadata(&target).map(|m| m.len()).unwrap_or(0),
+        file_name,
+        created_at,
+    })
+}
+
+/// Удалить все копии этой БД, кроме `keep` (после ротации ключа старые копии не расшифровать)
+pub(crate) fn remove_backups_except(db_path: &Path, keep: &str) -> usize {
+    let mut removed = 0;
+    for old in list_backups(db_path) {
+        if old.file_name == keep {
+            continue;
+        }
+        match std::fs::remove_file(&old.path) {
+            Ok(()) => removed += 1,
+            Err(e) => warn!(
+                "[BACKUP] Failed to remove old backup {}: {}",
+                old.file_name, e
+            ),
+        }
+    }
+    removed
+}
+
+/// Проверить копию и подготовить её к восстановлению при следующем запуске
+pub fn stage_restore(db_path: &Path, backup: &BackupInfo, key: Option<&str>) -> Result<(), String> {
+    let backup_path = Path::new(&backup.path);
+    verify_backup(backup_path, key)?;
+    let staged = with_suffix(db_path, RESTORE_SUFFIX);
+    let tmp = with_suffix(&staged, ".tmp");
+    std::fs::copy(backup_path, &tmp).map_err(|e| format!("Failed to copy backup: {}", e))?;
+    std::fs::rename(&tmp, &staged).map_err(|e| format!("Failed to stage backup: {}", e))?;
+    info!(
+        "[BACKUP] {} staged, will be restored on restart",
+        backup.file_name
+    );
+    Ok(())
+}
+
+fn remove_wal_files(db_path: &Path) {
+    for suffix in ["-wal", "-shm"] {
+        let _ = std::fs::remove_file(with_suffix(db_path, suffix));
+    }
+}
+
+/// Вызывается до открытия БД: подменить файл подготовленной копией (stage_restore).
+/// Текущая БД не удаляется: она сохраняется в backups как `<db>.<ts>.replaced` — вне списка копий
+/// и их ротации — и возвращается, чтобы перенести её неотправленную очередь (salvage_queue_into).
+pub(crate) fn apply_staged_restore(
+    db_path: &Path,
+    key: Option<&str>,
+) -> SqliteResult<Option<PathBuf>> {
+    let staged = with_suffix(db_path, RESTORE_SUFFIX);
+    if !staged.exists() {
+        return Ok(None);
+    }
+    let mut replaced = None;
+    if db_path.exists() {
+        // WAL текущей БД — в основной файл, иначе копия без последних записей
+        if let Ok(conn) = Connection::open(db_path) {
+            let keyed = if is_plaintext_database(db_path) {
+                Ok(())
+            } else {
+                apply_key(&conn, key)
+            };
+            if let Err(e) = keyed
+                .and_then(|_| conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(())))
+            {
+                warn!("[BACKUP] Checkpoint before restore failed: {}", e);
+            }
+        }
+        let dir = backup_dir(db_path);
+        let kept = dir.join(format!(
+            "{}.{}{}",
+            db_file_name(db_path),
+            chrono::Utc::now().timestamp(),
+            REPLACED_SUFFIX
+        ));
+        std::fs::create_dir_all(&dir)
+            .and_then(|_| std::fs::rename(db_path, &kept))
+            .map_err(|e| InvalidParameterName(format!("Failed to keep current database: {}", e)))?;
+        replaced = Some(kept);
+    }
+    remove_wal_files(db_path);
+    std::fs::rename(&staged, db_path)
+        .map_err(|e| InvalidParameterName(format!("Failed to restore backup: {}", e)))?;
+    info!("[BACKUP] Database restored from backup");
+    Ok(replaced)
+}
+
+/// Путь повреждённой БД (файл уже перемещён): восстановить самую новую копию, прошедшую
+/// integrity_check. None — годной копии нет, БД создаётся заново.
+pub fn restore_newest_good(db_path: &Path, key: Option<&str>) -> Option<BackupInfo> {
+    for backup in list_backups(db_path) {
+        if let Err(e) = verify_backup(Path::new(&backup.path), key) {
+            warn!("[BACKUP] Skipping {}: {}", backup.file_name, e);
+            continue;
+        }
+        remove_wal_files(db_path);
+        match std::fs::copy(&backup.path, db_path) {
+            Ok(_) => return Some(backup),
+            Err(e) => warn!("[BACKUP] Failed to restore {}: {}", backup.file_name, e),
+        }
+    }
+    None
+}
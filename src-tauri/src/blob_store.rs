@@ -0,0 +1,183 @@
+//! Content-addressed on-disk store for large queued payloads (screenshots).
+//! Blobs are encrypted with the same key as sync_queue payloads; the queue row keeps only
+//! the SHA-256 reference (`sync_queue.blob_ref`), so images never go through SQLite/WAL.
+
+use crate::auth::TokenEncryption;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, SystemTime};
+use tracing::{debug, warn};
+
+const BLOB_EXTENSION: &str = "blob";
+
+pub struct BlobStore {
+    dir: PathBuf,
+    encryption: Arc<TokenEncryption>,
+    /// «put → INSERT строки очереди» против «проверка ссылок → удаление файла»
+    refs: Mutex<()>,
+}
+
+impl BlobStore {
+    pub fn new(dir: PathBuf, encryption: Arc<TokenEncryption>) -> Self {
+        Self {
+            dir,
+            encryption,
+            refs: Mutex::new(()),
+        }
+    }
+
+    /// Держать от `put` до INSERT строки со ссылкой и при освобождении blob: иначе одинаковый
+    /// скриншот, поставленный в очередь во время отправки прежнего, теряет уже существующий файл
+    pub fn lock_refs(&self) -> MutexGuard<'_, ()> {
+        self.refs.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// SHA-256 (hex) — ссылка на blob, хранится в sync_queue.blob_ref
+    pub fn hash_of(data: &[u8]) -> String {
+        hex::encode(Sha256::digest(data))
+    }
+
+    /// Путь к файлу blob. Отклоняет всё, что не похоже на hex SHA-256 (защита от path traversal).
+    pub(crate) fn path_for(&self, blob_ref: &str) -> Result<PathBuf, String> {
+        if blob_ref.len() != 64 || !blob_ref.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(format!("Invalid blob reference: {}", blob_ref));
+        }
+        Ok(self.dir.join(format!("{}.{}", blob_ref, BLOB_EXTENSION)))
+    }
+
+    /// Записать данные (один раз): одинаковое содержимое → один файл.
+    /// Запись через временный файл + rename, чтобы не оставить обрезанный blob при сбое.
+    pub fn put(&self, data: &[u8]) -> Result<String, String> {
+        let blob_ref = Self::hash_of(data);
+        let path = self.path_for(&blob_ref)?;
+        if path.exists() {
+            debug!("[BLOB] {} already stored, reusing", blob_ref);
+            return Ok(blob_ref);
+        }
+        fs::create_dir_all(&self.dir)
+            .map_err(|e| format!("Failed to create blob dir {}: {}", self.dir.display(), e))?;
+        let encrypted = self.encryption.encrypt_bytes(data)?;
+        let tmp_path = path.with_extension(format!("{}.tmp", BLOB_EXTENSION));
+        fs::write(&tmp_path, &encrypted)
+            .map_err(|e| format!("Failed to write blob {}: {}", tmp_path.display(), e))?;
+        fs::rename(&tmp_path, &path).map_err(|e| {
+            let _ = fs::remove_file(&tmp_path);
+            format!("Failed to commit blob {}: {}", path.display(), e)
+        })?;
+        Ok(blob_ref)
+    }
+
+    /// Прочитать и расшифровать blob; проверяет, что содержимое совпадает со ссылкой
+    pub fn get(&self, blob_ref: &str) -> Result<Vec<u8>, String> {
+        let path = self.path_for(blob_ref)?;
+        let encrypted =
+            fs::read(&path).map_err(|e| format!("Failed to read blob {}: {}", blob_ref, e))?;
+        let data = self.encryption.decrypt_bytes(&encrypted)?;
+        if Self::hash_of(&data) != blob_ref {
+            return Err(format!("Blob {} content hash mismatch", blob_ref));
+        }
+        Ok(data)
+    }
+
+    /// Перешифровать активным ключом blobs, зашифрованные прежним (ротация ключа).
+    /// Возвращает (перешифровано, не расшифровано).
+    pub fn reencrypt_all(&self) -> (usize, usize) {
+        let mut reencrypted = 0;
+        let mut failed = 0;
+        for (blob_ref, _) in self.list_with_mtime() {
+            let result = self.path_for(&blob_ref).and_then(|path| {
+                let encrypted = fs::read(&path)
+                    .map_err(|e| format!("Failed to read blob {}: {}", blob_ref, e))?;
+                if self.encryption.is_current_bytes(&encrypted) {
+                    return Ok(false);
+                }
+                let data = self.encryption.decrypt_bytes(&encrypted)?;
+                let encrypted = self.encryption.encrypt_bytes(&data)?;
+                let tmp_path = path.with_extension(format!("{}.tmp", BLOB_EXTENSION));
+                fs::write(&tmp_path, &encrypted)
+                    .and_then(|_| fs::rename(&tmp_path, &path))
+                    .map_err(|e| {
+                        let _ = fs::remove_file(&tmp_path);
+                        format!("Failed to rewrite blob {}: {}", blob_ref, e)
+                    })?;
+                Ok(true)
+            });
+            match result {
+                Ok(true) => reencrypted += 1,
+                Ok(false) => {}
+                Err(e) => {
+                    warn!("[BLOB] Re-encryption: {}", e);
+                    failed += 1;
+                }
+            }
+        }
+        (reencrypted, failed)
+    }
+
+    /// Удалить blob (отсутствующий файл — не ошибка)
+    pub fn remove(&self, blob_ref: &str) -> Result<(), String> {
+        Self::remove_file(&self.path_for(blob_ref)?)
+    }
+
+    /// Удалить файл blob по пути из `path_for` (в потоке-писателе БД)
+    pub(crate) fn remove_file(path: &Path) -> Result<(), String> {
+        match fs::remove_file(path) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(format!("Failed to remove blob {}: {}", path.display(), e)),
+        }
+    }
+
+    /// Удалить все blobs (clear_sync_queue / clear_user_data)
+    pub fn remove_all(&self) -> Result<usize, String> {
+        let mut removed = 0;
+        for (blob_ref, _) in self.list_with_mtime() {
+            self.remove(&blob_ref)?;
+            removed += 1;
+        }
+        Ok(removed)
+    }
+
+    /// Удалить blobs, на которые не ссылается ни одна живая задача.
+    /// Файлы моложе `min_age` не трогаем: blob пишется до INSERT в sync_queue.
+    pub fn sweep_unreferenced(&self, referenced: &HashSet<String>, min_age: Duration) -> usize {
+        let now = SystemTime::now();
+        let mut removed = 0;
+        for (blob_ref, modified) in self.list_with_mtime() {
+            if referenced.contains(&blob_ref) {
+                continue;
+            }
+            let age = modified.and_then(|m| now.duration_since(m).ok());
+            if matches!(age, Some(age) if age < min_age) {
+                continue;
+            }
+            match self.remove(&blob_ref) {
+                Ok(()) => removed += 1,
+                Err(e) => warn!("[BLOB] Sweep: {}", e),
+            }
+        }
+        removed
+    }
+
+    fn list_with_mtime(&self) -> Vec<(String, Option<SystemTime>)> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(_) => return Vec::new(),
+        };
+        entries
+            .filter_map(|e| e.ok())
+            .filter_map(|entry| {
+                let path = entry.path();
+                if path.extension().and_then(|e| e.to_str()) != Some(BLOB_EXTENSION) {
+                    return None;
+                }
+                let blob_ref = path.file_stem()?.to_str()?.to_string();
+                let modified = entry.metadata().ok().and_then(|m| m.modified().ok());
+                Some((blob_ref, modified))
+            })
+            .collect()
+    }
+}
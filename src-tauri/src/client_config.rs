@@ -0,0 +1,205 @@
+//! Server-driven client configuration (GET /client-config).
+//! Admins tune idle threshold, poll/sync intervals and screenshot limits per organization.
+//! Document is cached in app_meta together with its ETag; offline → last cached copy, else defaults.
+
+use crate::Database;
+use serde::{Deserialize, Serialize};
+use std::sync::{OnceLock, RwLock};
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+const META_CONFIG_JSON: &str = "client_config_json";
+const META_CONFIG_ETAG: &str = "client_config_etag";
+
+/// Как часто перезапрашивать конфиг (304 при неизменном ETag — почти бесплатно)
+pub const REFRESH_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+/// Runtime-параметры клиента. Отсутствующие поля документа = значения по умолчанию.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ClientConfig {
+    /// Idle ниже порога = Active (activity monitor)
+    pub idle_threshold_secs: u64,
+    /// Период опроса system idle time
+    pub poll_interval_secs: u64,
+    /// Максимальный размер скриншота (ресайз с сохранением пропорций)
+    pub screenshot_max_width: u32,
+    pub screenshot_max_height: u32,
+    /// Целевой размер JPEG: качество снижается, пока не уложимся
+    pub screenshot_max_jpeg_bytes: usize,
+    /// Период фоновой синхронизации очереди
+    pub sync_interval_secs: u64,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            idle_threshold_secs: 60,
+            poll_interval_secs: 5,
+            screenshot_max_width: 1280,
+            screenshot_max_height: 720,
+            screenshot_max_jpeg_bytes: 300 * 1024,
+            sync_interval_secs: 60,
+        }
+    }
+}
+
+impl ClientConfig {
+    /// Ограничить значения разумными пределами — ошибка админа не должна сломать трекинг
+    pub fn sanitized(self) -> Self {
+        Self {
+            idle_threshold_secs: self.idle_threshold_secs.clamp(10, 3600),
+            poll_interval_secs: self.poll_interval_secs.clamp(1, 60),
+            screenshot_max_width: self.screenshot_max_width.clamp(320, 3840),
+            screenshot_max_height: self.screenshot_max_height.clamp(240, 2160),
+            screenshot_max_jpeg_bytes: self
+                .screenshot_max_jpeg_bytes
+                .clamp(50 * 1024, 5 * 1024 * 1024),
+            sync_interval_secs: self.sync_interval_secs.clamp(10, 3600),
+        }
+    }
+
+    pub fn idle_threshold(&self) -> Duration {
+        Duration::from_secs(self.idle_threshold_secs)
+    }
+
+    pub fn poll_interval(&self) -> Duration {
+        Duration::from_secs(self.poll_interval_secs)
+    }
+
+    pub fn sync_interval(&self) -> Duration {
+        Duration::from_secs(self.sync_interval_secs)
+    }
+}
+
+/// Результат запроса конфига
+#[derive(Debug, PartialEq, Eq)]
+pub enum RefreshOutcome {
+    /// 200: новый документ применён и закэширован
+    Updated,
+    /// 304: ETag совпал, кэш актуален
+    NotModified,
+}
+
+/// Текущий конфиг (читается activity loop, скриншотами и sync loop)
+pub struct ClientConfigStore {
+    current: RwLock<ClientConfig>,
+}
+
+impl Default for ClientConfigStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ClientConfigStore {
+    pub fn new() -> Self {
+        Self {
+            current: RwLock::new(ClientConfig::default()),
+        }
+    }
+
+    pub fn get(&self) -> ClientConfig {
+        self.current.read().map(|c| c.clone()).unwrap_or_default()
+    }
+
+    pub fn set(&self, config: ClientConfig) {
+        if let Ok(mut current) = self.current.write() {
+            *current = config.sanitized();
+        }
+    }
+
+    /// Применить закэшированный документ (старт приложения / offline)
+    pub fn load_cached(&self, db: &Database) -> bool {
+        let cached = match db.get_app_meta(META_CONFIG_JSON) {
+            Ok(Some(json)) => json,
+            _ => return false,
+        };
+        match serde_json::from_str::<ClientConfig>(&cached) {
+            Ok(config) => {
+                self.set(config);
+                debug!("[CONFIG] Applied cached client config");
+                true
+            }
+            Err(e) => {
+                warn!(
+                    "[CONFIG] Cached client config is invalid, using defaults: {}",
+                    e
+                );
+                false
+            }
+        }
+    }
+
+    /// Запросить конфиг с If-None-Match. При ошибке сети текущий (закэшированный) конфиг остаётся.
+    pub async fn refresh(
+        &self,
+        db: &Database,
+        client: &reqwest::Client,
+        api_base_url: &str,
+        access_token: &str,
+    ) -> Result<RefreshOutcome, String> {
+        let url = format!("{}/client-config", api_base_url);
+        let mut request = client
+            .get(&url)
+            .header("Authorization", format!("Bearer {}", access_token))
+            .header("Accept", "application/json");
+        let etag = db.get_app_meta(META_CONFIG_ETAG).ok().flatten();
+        // ETag без документа бесполезен: 304 нечем применить
+        let has_cached = matches!(db.get_app_meta(META_CONFIG_JSON), Ok(Some(_)));
+        if let (Some(etag), true) = (etag.as_deref().filter(|e| !e.is_empty()), has_cached) {
+            request = request.header("If-None-Match", etag);
+        }
+
+        let response = request
+            .send()
+            .await
+            .map_err(|e| format!("Client config request failed: {}", e))?;
+        let status = response.status();
+        if status == reqwest::StatusCode::NOT_MODIFIED {
+            debug!("[CONFIG] Client config not modified");
+            return Ok(RefreshOutcome::NotModified);
+        }
+        if !status.is_success() {
+            return Err(format!("Client config HTTP {}", status.as_u16()));
+        }
+
+        let new_etag = response
+            .headers()
+            .get(reqwest::header::ETAG)
+            .and_then(|v| v.to_str().ok())
+            .map(|s| s.to_string());
+        let body = response
+            .text()
+            .await
+            .map_err(|e| format!("Client config read failed: {}", e))?;
+        let config: ClientConfig = serde_json::from_str(&body)
+            .map_err(|e| format!("Client config parse failed: {}", e))?;
+        let config = config.sanitized();
+
+        let json = serde_json::to_string(&config)
+            .map_err(|e| format!("Client config serialize failed: {}", e))?;
+        db.set_app_meta(META_CONFIG_JSON, &json)
+            .map_err(|e| format!("Failed to cache client config: {}", e))?;
+        db.set_app_meta(META_CONFIG_ETAG, new_etag.as_deref().unwrap_or(""))
+            .map_err(|e| format!("Failed to cache client config ETag: {}", e))?;
+
+        if self.get() != config {
+            info!("[CONFIG] Applied client config from server: {:?}", config);
+        }
+        self.set(config);
+        Ok(RefreshOutcome::Updated)
+    }
+}
+
+static GLOBAL: OnceLock<ClientConfigStore> = OnceLock::new();
+
+/// Глобальный store (activity loops и capture_screenshot_jpeg — свободные функции без State)
+pub fn global() -> &'static ClientConfigStore {
+    GLOBAL.get_or_init(ClientConfigStore::new)
+}
+
+/// Снимок текущего конфига
+pub fn current() -> ClientConfig {
+    global().get()
+}
@@ -0,0 +1,121 @@
+//! Server clock-offset estimation from response `Date` headers.
+//! Each sync response gives a sample `server_time - local_midpoint(request)`; samples are smoothed
+//! with an EWMA so a single slow response does not move the estimate. The estimate is persisted
+//! in app_meta and used to stamp time-entry payloads with local and server-corrected times.
+
+use crate::Database;
+use std::sync::Mutex;
+use std::time::Duration;
+use tracing::{info, warn};
+
+pub(crate) const META_CLOCK_OFFSET: &str = "server_clock_offset_ms";
+/// Вес нового наблюдения в EWMA
+const EWMA_ALPHA: f64 = 0.2;
+/// Ответы медленнее этого не дают полезной точности (Date — секундная точность + RTT/2)
+const MAX_SAMPLE_RTT: Duration = Duration::from_secs(5);
+/// Наблюдение дальше от оценки — локальные часы переставили, оценку сбрасываем без сглаживания
+const CLOCK_JUMP_MS: f64 = 5.0 * 60.0 * 1000.0;
+/// Расхождение больше порога — payload помечается clockSkewed
+pub const CLOCK_SKEW_THRESHOLD_MS: i64 = 60_000;
+
+/// Оценка смещения часов сервера относительно локальных (server = local + offset)
+pub struct ClockOffset {
+    offset_ms: Mutex<Option<f64>>,
+}
+
+impl ClockOffset {
+    pub fn new(offset_ms: Option<i64>) -> Self {
+        Self {
+            offset_ms: Mutex::new(offset_ms.map(|v| v as f64)),
+        }
+    }
+
+    /// Последняя сохранённая оценка из app_meta (нет — смещение неизвестно)
+    pub fn load(db: &Database) -> Self {
+        let stored = db
+            .get_app_meta(META_CLOCK_OFFSET)
+            .ok()
+            .flatten()
+            .and_then(|v| v.trim().parse::<i64>().ok());
+        Self::new(stored)
+    }
+
+    pub fn offset_ms(&self) -> Option<i64> {
+        self.offset_ms
+            .lock()
+            .ok()
+            .and_then(|guard| *guard)
+            .map(|v| v.round() as i64)
+    }
+
+    pub fn is_skewed(&self) -> bool {
+        matches!(self.offset_ms(), Some(offset) if offset.abs() > CLOCK_SKEW_THRESHOLD_MS)
+    }
+
+    /// Учесть заголовок Date ответа на запрос, отправленный в `sent_at_ms` и полученный в `received_at_ms`
+    /// (локальные unix ms). Возвращает новую оценку, если наблюдение принято.
+    pub fn observe(&self, date_header: &str, sent_at_ms: i64, received_at_ms: i64) -> Option<i64> {
+        let server = chrono::DateTime::parse_from_rfc2822(date_header.trim()).ok()?;
+        let rtt_ms = received_at_ms.checked_sub(sent_at_ms)?;
+        if rtt_ms < 0 || rtt_ms as u128 > MAX_SAMPLE_RTT.as_millis() {
+            return None;
+        }
+        // Date усечён до секунды: ожидаемое серверное время — на полсекунды позже
+        let server_ms = server.timestamp_millis() + 500;
+        let sample = (server_ms - (sent_at_ms + rtt_ms / 2)) as f64;
+
+        let mut guard = self.offset_ms.lock().ok()?;
+        let was_skewed = matches!(*guard, Some(v) if v.abs() > CLOCK_SKEW_THRESHOLD_MS as f64);
+        let updated = match *guard {
+            Some(current) if (sample - current).abs() < CLOCK_JUMP_MS => {
+                current + EWMA_ALPHA * (sample - current)
+            }
+            Some(current) => {
+                info!(
+                    "[CLOCK] Local clock jumped ({} ms → {} ms offset), resetting estimate",
+                    current.round(),
+                    sample
+                );
+                sample
+            }
+            None => sample,
+        };
+        *guard = Some(updated);
+        let skewed = updated.abs() > CLOCK_SKEW_THRESHOLD_MS as f64;
+        if skewed && !was_skewed {
+            warn!(
+                "[CLOCK_SKEW] Local clock differs from server by {} ms",
+                updated.round()
+            );
+        }
+        Some(updated.round() as i64)
+    }
+
+    /// Сохранить оценку в app_meta
+    pub fn persist(&self, db: &Database) {
+        if let Some(offset) = self.offset_ms() {
+            if let Err(e) = db.set_app_meta(META_CLOCK_OFFSET, &offset.to_string()) {
+                warn!("[CLOCK] Failed to persist clock offset: {}", e);
+            }
+        }
+    }
+
+    /// `clientTime` для time-entry payload: локальное время события, оно же по часам сервера и флаг расхождения
+    pub fn stamp(&self, local_ms: i64) -> serde_json::Value {
+        Self::stamp_with_offset(local_ms, self.offset_ms())
+    }
+
+    /// `clientTime` по заданной оценке смещения (например, сохранённой при enqueue)
+    pub fn stamp_with_offset(local_ms: i64, offset: Option<i64>) -> serde_json::Value {
+        let to_rfc3339 = |ms: i64| {
+            chrono::DateTime::from_timestamp_millis(ms)
+                .map(|t| t.to_rfc3339_opts(chrono::SecondsFormat::Millis, true))
+        };
+        serde_json::json!({
+            "localAt": to_rfc3339(local_ms),
+            "correctedAt": offset.and_then(|o| to_rfc3339(local_ms + o)),
+            "offsetMs": offset,
+            "clockSkewed": matches!(offset, Some(o) if o.abs() > CLOCK_SKEW_THRESHOLD_MS),
+        })
+    }
+}
@@ -1,10 +1,18 @@
-use crate::check_online_status;
-use crate::engine::{TimerEngine, TimerStateResponse};
+use crate::backup::BackupInfo;
+use crate::connectivity::ConnectivityState;
+use crate::endpoint::ApiEndpoint;
+use crate::engine::TimerStateResponse;
+use crate::http_client::HttpClientSettings;
 #[cfg(target_os = "macos")]
 use crate::extract_url_from_title;
 use crate::models::ActiveWindowInfo;
-use crate::models::{FailedTaskInfo, QueueStats};
+use crate::models::{
+    CachedProject, CachedTask, FailedTaskInfo, KeyRotationReport, LoggedOutEvent,
+    LogoutQueuePolicy, MaintenanceReport, ProjectCacheRefresh, QueueStats, SyncMetricsReport,
+};
 use crate::monitor::ActivityMonitor;
+use crate::oauth::{DeviceAuthorization, OAuthRefresh, OAuthSettings, OAuthTokens};
+use crate::profiles::ProfileManager;
 use crate::sync::SyncManager;
 use crate::SyncStatusResponse;
 use std::sync::Arc;
@@ -20,13 +28,14 @@ pub(crate) mod activity_emit {
     use std::time::{Duration, Instant};
 
     /// Idle below this = Active. 60s matches Hubstaff, filters micro-events (bumped desk, cat).
+    /// Default only — runtime value comes from client config (see idle_threshold()).
     pub const IDLE_THRESHOLD_SECS: u64 = 60;
     pub const IDLE_THRESHOLD: Duration = Duration::from_secs(IDLE_THRESHOLD_SECS);
 
     /// Min interval between activity-detected emits (rate limit).
     pub const MIN_EMIT_INTERVAL: Duration = Duration::from_secs(10);
 
-    /// Fixed poll interval (5s) — reduces load, aligns with Hubstaff.
+    /// Default poll interval (5s) — reduces load, aligns with Hubstaff.
     pub const POLL_INTERVAL_SECS: u64 = 5;
     pub const POLL_INTERVAL: Duration = Duration::from_secs(POLL_INTERVAL_SECS);
 
@@ -37,16 +46,22 @@ pub(crate) mod activity_emit {
         current_idle: Duration,
         last_emit_time: Instant,
     ) -> (bool, bool) {
-        let prev_active = prev_idle.map_or(false, |p| p < IDLE_THRESHOLD);
-        let current_active = current_idle < IDLE_THRESHOLD;
+        let threshold = idle_threshold();
+        let prev_active = prev_idle.map_or(false, |p| p < threshold);
+        let current_active = current_idle < threshold;
         let interval_ok = Instant::now().duration_since(last_emit_time) >= MIN_EMIT_INTERVAL;
         let should_emit = prev_active && current_active && interval_ok;
         (should_emit, current_active)
     }
 
-    /// Fixed poll interval (configurable via constant).
+    /// Idle threshold from server-driven client config (default IDLE_THRESHOLD).
+    pub fn idle_threshold() -> Duration {
+        crate::client_config::current().idle_threshold()
+    }
+
+    /// Poll interval from server-driven client config (default POLL_INTERVAL).
     pub fn poll_interval() -> Duration {
-        POLL_INTERVAL
+        crate::client_config::current().poll_interval()
     }
 
     #[cfg(test)]
@@ -144,11 +159,12 @@ mod activity_emit_tests {
 pub async fn start_activity_monitoring(
     monitor: State<'_, ActivityMonitor>,
     app: AppHandle,
-    engine: State<'_, Arc<TimerEngine>>,
+    profiles: State<'_, Arc<ProfileManager>>,
 ) -> Result<(), String> {
     let is_monitoring = monitor.is_monitoring.clone();
     let last_activity = monitor.last_activity.clone();
-    let engine_clone = engine.inner().clone();
+    // Профиль может смениться, пока идёт мониторинг — engine берётся на каждой проверке
+    let profiles_clone = profiles.inner().clone();
 
     // Use a single lock to check and set atomically to prevent race conditions
     {
@@ -177,7 +193,7 @@ pub async fn start_activity_monitoring(
         let is_monitoring_clone = is_monitoring.clone();
         let last_activity_clone = last_activity.clone();
         let app_clone = app.clone();
-        let engine_mac = engine_clone.clone();
+        let profiles_mac = profiles_clone.clone();
 
         // Use system idle time instead of mouse position — more reliable, no false positives
         // when user is away (NSEvent.mouseLocation could drift due to Retina scaling, etc.)
@@ -219,14 +235,14 @@ pub async fn start_activity_monitoring(
                 debug!(
                     "[IDLE] System: {}s, Threshold: {}s, Status: {}",
                     idle_duration.as_secs(),
-                    activity_emit::IDLE_THRESHOLD_SECS,
+                    activity_emit::idle_threshold().as_secs(),
                     if is_active { "Active" } else { "Idle" }
                 );
 
                 if should_emit {
                     // WAKE: Suppress false "active" (idle 0) for 30s after sleep — get_idle_time resets
                     let idle_secs = idle_duration.as_secs() as u32;
-                    if idle_secs == 0 && engine_mac.is_just_awoken() {
+                    if idle_secs == 0 && profiles_mac.engine().is_just_awoken() {
                         tokio::time::sleep(activity_emit::poll_interval()).await;
                         continue;
                     }
@@ -253,7 +269,7 @@ pub async fn start_activity_monitoring(
         let is_monitoring_clone = is_monitoring.clone();
         let last_activity_clone = last_activity.clone();
         let app_clone = app.clone();
-        let engine_win = engine_clone.clone();
+        let profiles_win = profiles_clone.clone();
 
         tokio::spawn(async move {
             use tauri::Emitter;
@@ -293,13 +309,13 @@ pub async fn start_activity_monitoring(
                 debug!(
                     "[IDLE] System: {}s, Threshold: {}s, Status: {}",
                     idle_duration.as_secs(),
-                    activity_emit::IDLE_THRESHOLD_SECS,
+                    activity_emit::idle_threshold().as_secs(),
                     if is_active { "Active" } else { "Idle" }
                 );
 
                 if should_emit {
                     let idle_secs = idle_duration.as_secs() as u32;
-                    if idle_secs == 0 && engine_win.is_just_awoken() {
+                    if idle_secs == 0 && profiles_win.engine().is_just_awoken() {
                         tokio::time::sleep(activity_emit::poll_interval()).await;
                         continue;
                     }
@@ -327,7 +343,7 @@ pub async fn start_activity_monitoring(
         let is_monitoring_clone = is_monitoring.clone();
         let last_activity_clone = last_activity.clone();
         let app_clone = app.clone();
-        let engine_linux = engine_clone.clone();
+        let profiles_linux = profiles_clone.clone();
 
         tokio::spawn(async move {
             use tauri::Emitter;
@@ -367,13 +383,13 @@ pub async fn start_activity_monitoring(
                 debug!(
                     "[IDLE] System: {}s, Threshold: {}s, Status: {}",
                     idle_duration.as_secs(),
-                    activity_emit::IDLE_THRESHOLD_SECS,
+                    activity_emit::idle_threshold().as_secs(),
                     if is_active { "Active" } else { "Idle" }
                 );
 
                 if should_emit {
                     let idle_secs = idle_duration.as_secs() as u32;
-                    if idle_secs == 0 && engine_linux.is_just_awoken() {
+                    if idle_secs == 0 && profiles_linux.engine().is_just_awoken() {
                         tokio::time::sleep(activity_emit::poll_interval()).await;
                         continue;
                     }
@@ -470,8 +486,9 @@ pub async fn upload_screenshot(
     time_entry_id: String,
     access_token: String,
     refresh_token: Option<String>,
-    sync_manager: State<'_, SyncManager>,
+    profiles: State<'_, Arc<ProfileManager>>,
 ) -> Result<(), String> {
+    let sync_manager = profiles.sync_manager();
     if png_data.len() > MAX_SCREENSHOT_BODY_BYTES {
         return Err(format!(
             "Screenshot too large ({} bytes, max {} MB)",
@@ -509,8 +526,9 @@ pub async fn enqueue_time_entry(
     payload: serde_json::Value,
     access_token: String,
     refresh_token: Option<String>,
-    sync_manager: State<'_, SyncManager>,
+    profiles: State<'_, Arc<ProfileManager>>,
 ) -> Result<i64, String> {
+    let sync_manager = profiles.sync_manager();
     info!("[RUST] Enqueueing time entry operation: {}", operation);
 
     let queue_id =
@@ -585,8 +603,9 @@ fn capture_screenshot_jpeg() -> Result<Vec<u8>, String> {
             "Failed to create ImageBuffer from RGBA data".to_string()
         })?;
 
-    let max_width = 1280u32;
-    let max_height = 720u32;
+    let client_config = crate::client_config::current();
+    let max_width = client_config.screenshot_max_width;
+    let max_height = client_config.screenshot_max_height;
     let final_buffer = if width > max_width || height > max_height {
         info!(
             "[SCREENSHOT] Image too large ({}x{}), resizing to max {}x{}",
@@ -625,7 +644,7 @@ fn capture_screenshot_jpeg() -> Result<Vec<u8>, String> {
             Rgb([pixel[0], pixel[1], pixel[2]])
         });
 
-    const MAX_JPEG_BYTES: usize = 300 * 1024;
+    let max_jpeg_bytes = client_config.screenshot_max_jpeg_bytes;
     let dynamic_img = DynamicImage::ImageRgb8(rgb_buffer);
     let mut jpeg_bytes = Vec::new();
     for quality in [85u8, 75, 60, 45, 30] {
@@ -640,7 +659,7 @@ fn capture_screenshot_jpeg() -> Result<Vec<u8>, String> {
                 error!("[SCREENSHOT ERROR] {}", err_msg);
                 err_msg
             })?;
-        if jpeg_bytes.len() <= MAX_JPEG_BYTES {
+        if jpeg_bytes.len() <= max_jpeg_bytes {
             break;
         }
     }
@@ -651,8 +670,9 @@ fn capture_screenshot_jpeg() -> Result<Vec<u8>, String> {
     }
 
     info!(
-        "[SCREENSHOT] Final JPEG size: {} bytes (target < 300KB)",
-        jpeg_bytes.len()
+        "[SCREENSHOT] Final JPEG size: {} bytes (target < {} bytes)",
+        jpeg_bytes.len(),
+        max_jpeg_bytes
     );
     Ok(jpeg_bytes)
 }
@@ -703,8 +723,9 @@ pub async fn upload_screenshot_from_path(
     time_entry_id: String,
     access_token: String,
     refresh_token: Option<String>,
-    sync_manager: State<'_, SyncManager>,
+    profiles: State<'_, Arc<ProfileManager>>,
 ) -> Result<(), String> {
+    let sync_manager = profiles.sync_manager();
     use std::path::Path;
 
     let path = Path::new(&temp_path);
@@ -1130,18 +1151,18 @@ pub async fn get_active_window_info() -> Result<ActiveWindowInfo, String> {
 // ============================================
 
 /// Установить токены для синхронизации (вызывается из frontend).
-/// Сброс таймера и очереди — только при смене пользователя (A → B); при входе после логаута ("" → A) не сбрасываем.
+/// Вход пользователя делает активным его профиль (своя БД, таймер и очередь); данные прежнего
+/// пользователя остаются в его профиле и синхронизируются его токенами. Выход профиль не меняет.
 #[tauri::command]
 pub async fn set_auth_tokens(
-    sync_manager: State<'_, SyncManager>,
-    engine: State<'_, Arc<crate::engine::TimerEngine>>,
+    profiles: State<'_, Arc<ProfileManager>>,
     access_token: Option<String>,
     refresh_token: Option<String>,
     user_id: Option<String>,
 ) -> Result<(), String> {
+    let app_db = profiles.app_db();
     let new_id = user_id.as_deref().unwrap_or("");
-    let current_id = sync_manager
-        .db
+    let current_id = app_db
         .get_app_meta("current_user_id")
         .map_err(|e| format!("Failed to get current user: {}", e))?
         .unwrap_or_default();
@@ -1149,33 +1170,17 @@ pub async fn set_auth_tokens(
         .as_ref()
         .map(|s| !s.is_empty())
         .unwrap_or(false);
-    // Сброс таймера и данных только при смене пользователя (A → B), не при входе после логаута ("" → A)
-    if !new_id.is_empty() && !current_id.is_empty() && new_id != current_id {
-        sync_manager
-            .db
-            .clear_user_data()
-            .map_err(|e| format!("Failed to clear user data: {}", e))?;
-        engine
-            .reset_state()
-            .map_err(|e| format!("Failed to reset timer: {}", e))?;
-        sync_manager
-            .db
-            .set_app_meta("current_user_id", new_id)
-            .map_err(|e| format!("Failed to set current user: {}", e))?;
-    } else if new_id.is_empty() && !current_id.is_empty() && !has_tokens {
+    if !new_id.is_empty() {
+        profiles.sign_in(new_id).await?;
+    } else if !current_id.is_empty() && !has_tokens {
         // Logout: НЕ сбрасываем таймер - активный time entry продолжает работать на сервере
         // При повторном входе loadActiveTimeEntry() восстановит активный time entry и синхронизирует Timer Engine
         // Очищаем только current_user_id для безопасности
-        sync_manager
-            .db
+        app_db
             .set_app_meta("current_user_id", "")
             .map_err(|e| format!("Failed to clear current user: {}", e))?;
-    } else if !new_id.is_empty() {
-        sync_manager
-            .db
-            .set_app_meta("current_user_id", new_id)
-            .map_err(|e| format!("Failed to set current user: {}", e))?;
     }
+    let sync_manager = profiles.sync_manager();
     sync_manager
         .auth_manager
         .set_tokens(access_token.clone(), refresh_token.clone())
@@ -1192,19 +1197,64 @@ pub async fn set_auth_tokens(
     Ok(())
 }
 
+/// Выход: отзыв токенов на сервере, очистка AuthManager, политика очереди (flush / keep),
+/// по запросу — удаление ключа шифрования (отказ, если он нужен другим БД). По завершении — событие auth-logged-out.
+#[tauri::command]
+pub async fn logout(
+    queue_policy: LogoutQueuePolicy,
+    purge_keys: Option<bool>,
+    profiles: State<'_, Arc<ProfileManager>>,
+) -> Result<LoggedOutEvent, String> {
+    let profile = profiles.active();
+    let purge_keys = purge_keys.unwrap_or(false);
+    if purge_keys {
+        // Ключ общий для всех БД в каталоге: удалить его можно, только если он больше ничего не защищает
+        let dependents = profiles.key_dependents(&profile.user_id);
+        if !dependents.is_empty() {
+            let names: Vec<String> = dependents
+                .iter()
+                .filter_map(|p| p.file_name())
+                .map(|n| n.to_string_lossy().into_owned())
+                .collect();
+            return Err(format!(
+                "Cannot purge the encryption key: it still protects {}",
+                names.join(", ")
+            ));
+        }
+    }
+    let (sync_manager, engine) = (profile.sync_manager, profile.engine);
+    let event = sync_manager.logout(queue_policy, purge_keys).await?;
+    if event.keys_purged {
+        // Новый ключ записан экземпляром активного профиля — остальные БД шифруют им же
+        profiles.reload_keys()?;
+    }
+    profiles
+        .app_db()
+        .set_app_meta("current_user_id", "")
+        .map_err(|e| format!("Failed to clear current user: {}", e))?;
+    if event.queue_policy == LogoutQueuePolicy::Flush {
+        // Локальные time entries удалены — состояние таймера им больше не соответствует
+        engine
+            .reset_state()
+            .map_err(|e| format!("Failed to reset timer: {}", e))?;
+    }
+    Ok(event)
+}
+
 /// Получить текущий user_id из БД (для проверки смены пользователя на фронтенде)
 #[tauri::command]
 pub async fn get_current_user_id(
-    sync_manager: State<'_, SyncManager>,
+    profiles: State<'_, Arc<ProfileManager>>,
 ) -> Result<Option<String>, String> {
-    sync_manager
-        .db
+    profiles
+        .app_db()
         .get_app_meta("current_user_id")
         .map_err(|e| format!("Failed to get current user: {}", e))
 }
 
 #[tauri::command]
-pub async fn sync_queue_now(sync_manager: State<'_, SyncManager>) -> Result<usize, String> {
+pub async fn sync_queue_now(profiles: State<'_, Arc<ProfileManager>>) -> Result<usize, String> {
+    let sync_manager = profiles.sync_manager();
     let pending = sync_manager
         .db
         .get_pending_count()
@@ -1221,8 +1271,9 @@ pub async fn sync_queue_now(sync_manager: State<'_, SyncManager>) -> Result<usiz
 /// Получить статус синхронизации (количество pending/failed задач)
 #[tauri::command]
 pub async fn get_sync_status(
-    sync_manager: State<'_, SyncManager>,
+    profiles: State<'_, Arc<ProfileManager>>,
 ) -> Result<SyncStatusResponse, String> {
+    let sync_manager = profiles.sync_manager();
     let pending_count = sync_manager
         .db
         .get_pending_count()
@@ -1233,8 +1284,9 @@ pub async fn get_sync_status(
         .get_failed_count()
         .map_err(|e| format!("Failed to get failed count: {}", e))?;
 
-    // Проверяем online статус через попытку HTTP запроса (легковесный HEAD запрос)
-    let is_online = check_online_status().await;
+    // Состояние связи с API из ConnectivityMonitor (кэш, проба health endpoint только если устарело)
+    let connectivity = sync_manager.connectivity.current().await;
+    let is_online = connectivity != ConnectivityState::Offline;
 
     let last_sync_at = sync_manager
         .db
@@ -1252,6 +1304,7 @@ pub async fn get_sync_status(
         pending_count,
         failed_count,
         is_online,
+        connectivity,
         last_sync_at,
     })
 }
@@ -1259,17 +1312,32 @@ pub async fn get_sync_status(
 /// Получить детальную статистику очереди синхронизации
 #[tauri::command]
 pub async fn get_sync_queue_stats(
-    sync_manager: State<'_, SyncManager>,
+    profiles: State<'_, Arc<ProfileManager>>,
 ) -> Result<QueueStats, String> {
+    let sync_manager = profiles.sync_manager();
     sync_manager
         .db
         .get_queue_stats()
         .map_err(|e| format!("Failed to get queue stats: {}", e))
 }
 
+/// История метрик синхронизации (последние `limit` запусков, по умолчанию 50) и сводка по ним
+#[tauri::command]
+pub async fn get_sync_metrics(
+    limit: Option<i64>,
+    profiles: State<'_, Arc<ProfileManager>>,
+) -> Result<SyncMetricsReport, String> {
+    let sync_manager = profiles.sync_manager();
+    sync_manager
+        .db
+        .get_sync_metrics(limit.unwrap_or(50))
+        .map_err(|e| format!("Failed to get sync metrics: {}", e))
+}
+
 /// Очистить очередь синхронизации (safety valve — для пользователей с проблемами decryption/stuck tasks)
 #[tauri::command]
-pub async fn clear_sync_queue(sync_manager: State<'_, SyncManager>) -> Result<(), String> {
+pub async fn clear_sync_queue(profiles: State<'_, Arc<ProfileManager>>) -> Result<(), String> {
+    let sync_manager = profiles.sync_manager();
     sync_manager
         .db
         .clear_sync_queue()
@@ -1282,8 +1350,9 @@ pub async fn clear_sync_queue(sync_manager: State<'_, SyncManager>) -> Result<()
 #[tauri::command]
 pub async fn mark_task_sent_by_id(
     id: i64,
-    sync_manager: State<'_, SyncManager>,
+    profiles: State<'_, Arc<ProfileManager>>,
 ) -> Result<(), String> {
+    let sync_manager = profiles.sync_manager();
     sync_manager
         .db
         .mark_task_sent(id)
@@ -1297,9 +1366,10 @@ pub async fn mark_task_sent_by_id(
 /// Получить список failed задач с деталями
 #[tauri::command]
 pub async fn get_failed_tasks(
-    sync_manager: State<'_, SyncManager>,
+    profiles: State<'_, Arc<ProfileManager>>,
     limit: Option<i32>,
 ) -> Result<Vec<FailedTaskInfo>, String> {
+    let sync_manager = profiles.sync_manager();
     let limit = limit.unwrap_or(50); // По умолчанию 50 задач
     sync_manager
         .db
@@ -1310,9 +1380,10 @@ pub async fn get_failed_tasks(
 /// Сбросить failed задачи обратно в pending для повторной попытки
 #[tauri::command]
 pub async fn retry_failed_tasks(
-    sync_manager: State<'_, SyncManager>,
+    profiles: State<'_, Arc<ProfileManager>>,
     limit: Option<i32>,
 ) -> Result<i32, String> {
+    let sync_manager = profiles.sync_manager();
     let limit = limit.unwrap_or(100); // По умолчанию 100 задач
     let count = sync_manager
         .db
@@ -1337,8 +1408,9 @@ pub async fn retry_failed_tasks(
 #[tauri::command]
 pub async fn persist_time_entry_id(
     id: Option<String>,
-    sync_manager: State<'_, SyncManager>,
+    profiles: State<'_, Arc<ProfileManager>>,
 ) -> Result<(), String> {
+    let sync_manager = profiles.sync_manager();
     let value = id.unwrap_or_default();
     sync_manager
         .db
@@ -1346,11 +1418,322 @@ pub async fn persist_time_entry_id(
         .map_err(|e| format!("Failed to persist time entry id: {}", e))
 }
 
+/// Проекты из локального кэша (offline выбор проекта). Архивные — только с include_archived.
+#[tauri::command]
+pub async fn list_cached_projects(
+    include_archived: Option<bool>,
+    profiles: State<'_, Arc<ProfileManager>>,
+) -> Result<Vec<CachedProject>, String> {
+    let sync_manager = profiles.sync_manager();
+    sync_manager
+        .db
+        .list_cached_projects(include_archived.unwrap_or(false))
+        .map_err(|e| format!("Failed to list cached projects: {}", e))
+}
+
+/// Поиск проектов в кэше по имени проекта/клиента
+#[tauri::command]
+pub async fn search_cached_projects(
+    query: String,
+    limit: Option<i32>,
+    profiles: State<'_, Arc<ProfileManager>>,
+) -> Result<Vec<CachedProject>, String> {
+    let sync_manager = profiles.sync_manager();
+    sync_manager
+        .db
+        .search_cached_projects(&query, limit.unwrap_or(50).clamp(1, 500))
+        .map_err(|e| format!("Failed to search cached projects: {}", e))
+}
+
+/// Задачи из локального кэша (все или одного проекта)
+#[tauri::command]
+pub async fn list_cached_tasks(
+    project_id: Option<String>,
+    profiles: State<'_, Arc<ProfileManager>>,
+) -> Result<Vec<CachedTask>, String> {
+    let sync_manager = profiles.sync_manager();
+    sync_manager
+        .db
+        .list_cached_tasks(project_id.as_deref())
+        .map_err(|e| format!("Failed to list cached tasks: {}", e))
+}
+
+/// Полное обновление кэша проектов/задач с сервера (ручное, например после входа)
+#[tauri::command]
+pub async fn refresh_project_cache(
+    profiles: State<'_, Arc<ProfileManager>>,
+) -> Result<ProjectCacheRefresh, String> {
+    let sync_manager = profiles.sync_manager();
+    let token = sync_manager
+        .auth_manager
+        .get_access_token()
+        .await
+        .map_err(|e| e.to_string())?;
+    crate::project_cache::refresh(
+        &sync_manager.db,
+        &sync_manager.client,
+        &sync_manager.api_base_url,
+        &token,
+        true,
+    )
+    .await
+}
+
+/// Запустить обслуживание БД вручную (retention sent-задач, incremental vacuum, WAL truncate)
+#[tauri::command]
+pub async fn run_db_maintenance(
+    profiles: State<'_, Arc<ProfileManager>>,
+) -> Result<MaintenanceReport, String> {
+    let sync_manager = profiles.sync_manager();
+    let db = sync_manager.db.clone();
+    tokio::task::spawn_blocking(move || db.run_maintenance())
+        .await
+        .map_err(|e| format!("Maintenance task panicked: {}", e))?
+        .map_err(|e| format!("Database maintenance failed: {}", e))
+}
+
+/// Резервные копии БД активного профиля (новые первыми)
+#[tauri::command]
+pub fn list_backups(profiles: State<'_, Arc<ProfileManager>>) -> Result<Vec<BackupInfo>, String> {
+    Ok(crate::backup::list_backups(profiles.active().db.path()))
+}
+
+/// Восстановить БД активного профиля из копии. Копия проходит integrity_check и подменяет файл
+/// при перезапуске (открытую БД не трогаем); неотправленная очередь текущей БД переносится в восстановленную.
+#[tauri::command]
+pub async fn restore_backup(
+    file_name: String,
+    app: AppHandle,
+    profiles: State<'_, Arc<ProfileManager>>,
+) -> Result<(), String> {
+    let db = profiles.active().db;
+    // Только копии из списка — не произвольный путь от frontend
+    let backup = crate::backup::list_backups(db.path())
+        .into_iter()
+        .find(|b| b.file_name == file_name)
+        .ok_or_else(|| format!("Backup not found: {}", file_name))?;
+    let key = db.encryption.database_key();
+    let db_path = db.path().to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        crate::backup::stage_restore(&db_path, &backup, key.as_deref())
+    })
+    .await
+    .map_err(|e| format!("Restore task panicked: {}", e))??;
+
+    if let Err(e) = profiles.save_all() {
+        warn!("[BACKUP] Failed to save timer state before restart: {}", e);
+    }
+    info!("[BACKUP] Restarting to restore {}", file_name);
+    app.restart()
+}
+
+/// Ротация ключа шифрования: новый ключ, перешифрование очередей, секретов и blobs всех БД в каталоге
+/// (и незагруженных профилей; в фоне, пачками), затем прежний ключ удаляется из keyring и файла ключа
+#[tauri::command]
+pub async fn rotate_encryption_key(
+    profiles: State<'_, Arc<ProfileManager>>,
+) -> Result<KeyRotationReport, String> {
+    let profiles = profiles.inner().clone();
+    tokio::task::spawn_blocking(move || {
+        let dbs = profiles.all_databases()?;
+        crate::key_rotation::rotate_encryption_key(&dbs)
+    })
+    .await
+    .map_err(|e| format!("Key rotation task panicked: {}", e))?
+}
+
+/// Срок хранения отправленных задач (дни). 0–365, default 7.
+#[tauri::command]
+pub fn get_sync_retention_days(profiles: State<'_, Arc<ProfileManager>>) -> Result<i64, String> {
+    let sync_manager = profiles.sync_manager();
+    sync_manager
+        .db
+        .retention_policy()
+        .map(|p| p.sent_retention_days)
+        .map_err(|e| format!("Failed to get retention policy: {}", e))
+}
+
+/// Установить срок хранения отправленных задач (дни). 0–365.
+#[tauri::command]
+pub fn set_sync_retention_days(
+    days: i64,
+    profiles: State<'_, Arc<ProfileManager>>,
+) -> Result<(), String> {
+    let sync_manager = profiles.sync_manager();
+    sync_manager
+        .db
+        .set_app_meta("sync_retention_sent_days", &days.clamp(0, 365).to_string())
+        .map_err(|e| format!("Failed to set retention policy: {}", e))
+}
+
+/// Сетевые настройки (прокси, дополнительные CA). Пароль прокси не возвращается.
+#[tauri::command]
+pub fn get_network_settings(
+    profiles: State<'_, Arc<ProfileManager>>,
+) -> Result<HttpClientSettings, String> {
+    let mut settings = HttpClientSettings::load(profiles.app_db());
+    settings.proxy_password = None;
+    Ok(settings)
+}
+
+/// Сохранить сетевые настройки. Применяются после перезапуска приложения.
+/// proxy_password = null при том же логине — сохранённый пароль остаётся.
+#[tauri::command]
+pub fn set_network_settings(
+    mut settings: HttpClientSettings,
+    profiles: State<'_, Arc<ProfileManager>>,
+) -> Result<(), String> {
+    let app_db = profiles.app_db();
+    if settings.proxy_password.is_none() {
+        let stored = HttpClientSettings::load(app_db);
+        if stored.proxy_username == settings.proxy_username {
+            settings.proxy_password = stored.proxy_password;
+        }
+    }
+    settings.save(app_db)?;
+    info!("[HTTP] Network settings saved, restart required to apply");
+    Ok(())
+}
+
+/// Активный адрес API (источник: policy / env / settings / default)
+#[tauri::command]
+pub fn get_api_endpoint(endpoint: State<'_, ApiEndpoint>) -> ApiEndpoint {
+    endpoint.inner().clone()
+}
+
+/// Сохранить адрес API из настроек (null — по умолчанию). Применяется после перезапуска;
+/// задачи текущего сервера не будут отправлены на новый.
+#[tauri::command]
+pub fn set_api_endpoint(
+    url: Option<String>,
+    endpoint: State<'_, ApiEndpoint>,
+    profiles: State<'_, Arc<ProfileManager>>,
+) -> Result<Option<String>, String> {
+    if endpoint.locked {
+        return Err(format!(
+            "API endpoint is managed by {:?} and cannot be changed in settings",
+            endpoint.source
+        ));
+    }
+    crate::endpoint::save_user_url(profiles.app_db(), url.as_deref())
+}
+
+/// Настройки SSO-провайдера (issuer / endpoint'ы, client_id, scopes)
+#[tauri::command]
+pub fn get_oauth_settings(profiles: State<'_, Arc<ProfileManager>>) -> OAuthSettings {
+    OAuthSettings::load(profiles.app_db())
+}
+
+#[tauri::command]
+pub fn set_oauth_settings(
+    settings: OAuthSettings,
+    profiles: State<'_, Arc<ProfileManager>>,
+) -> Result<(), String> {
+    settings.save(profiles.app_db())?;
+    info!("[OAUTH] OAuth settings saved");
+    Ok(())
+}
+
+/// Настройки провайдера (из hubnity.db) с endpoint'ами из discovery
+async fn resolved_oauth_settings(
+    profiles: &ProfileManager,
+    sync_manager: &SyncManager,
+) -> Result<OAuthSettings, String> {
+    let settings = OAuthSettings::load(profiles.app_db());
+    if !settings.is_configured() {
+        return Err("SSO sign-in is not configured".to_string());
+    }
+    settings.discover(&sync_manager.client).await
+}
+
+/// Токены SSO — в профиль вошедшего пользователя (id из id_token или userinfo), а не в активный:
+/// до входа активным мог быть профиль другого пользователя
+pub(crate) async fn sign_in_sso_user(
+    profiles: &ProfileManager,
+    settings: &OAuthSettings,
+    client: &reqwest::Client,
+    tokens: OAuthTokens,
+    refresh_client: OAuthRefresh,
+) -> Result<(), String> {
+    let user_id = crate::oauth::resolve_user_id(settings, client, &tokens).await?;
+    let profile = profiles.sign_in(&user_id).await?;
+    profile
+        .sync_manager
+        .auth_manager
+        .sign_in_with_oauth(tokens, refresh_client)
+        .await;
+    Ok(())
+}
+
+/// Вход через SSO в системном браузере (PKCE + loopback redirect).
+/// Токены уходят в AuthManager; frontend получает их событием auth-tokens-refreshed.
+#[tauri::command]
+pub async fn login_with_sso(
+    app: AppHandle,
+    profiles: State<'_, Arc<ProfileManager>>,
+) -> Result<(), String> {
+    let sync_manager = profiles.sync_manager();
+    use tauri_plugin_opener::OpenerExt;
+
+    let settings = resolved_oauth_settings(&profiles, &sync_manager).await?;
+    let refresh_client = settings.refresh_client()?;
+    let tokens = crate::oauth::login_with_loopback(
+        &settings,
+        &sync_manager.client,
+        |url| {
+            app.opener()
+                .open_url(url, None::<&str>)
+                .map_err(|e| format!("Failed to open browser: {}", e))
+        },
+        crate::oauth::LOOPBACK_LOGIN_TIMEOUT,
+    )
+    .await?;
+    sign_in_sso_user(
+        &profiles,
+        &settings,
+        &sync_manager.client,
+        tokens,
+        refresh_client,
+    )
+    .await
+}
+
+/// Device authorization grant: код и адрес для входа с другого устройства
+#[tauri::command]
+pub async fn start_device_login(
+    profiles: State<'_, Arc<ProfileManager>>,
+) -> Result<DeviceAuthorization, String> {
+    let sync_manager = profiles.sync_manager();
+    let settings = resolved_oauth_settings(&profiles, &sync_manager).await?;
+    crate::oauth::start_device_authorization(&settings, &sync_manager.client).await
+}
+
+/// Дождаться подтверждения device-входа (poll token endpoint с интервалом провайдера)
+#[tauri::command]
+pub async fn complete_device_login(
+    device: DeviceAuthorization,
+    profiles: State<'_, Arc<ProfileManager>>,
+) -> Result<(), String> {
+    let sync_manager = profiles.sync_manager();
+    let settings = resolved_oauth_settings(&profiles, &sync_manager).await?;
+    let refresh_client = settings.refresh_client()?;
+    let tokens = crate::oauth::poll_device_token(&settings, &sync_manager.client, &device).await?;
+    sign_in_sso_user(
+        &profiles,
+        &settings,
+        &sync_manager.client,
+        tokens,
+        refresh_client,
+    )
+    .await
+}
+
 /// Получить порог sleep detection (минуты) — разрыв wall/monotonic для авто-паузы
 #[tauri::command]
 pub fn get_sleep_gap_threshold_minutes(
-    sync_manager: State<'_, SyncManager>,
+    profiles: State<'_, Arc<ProfileManager>>,
 ) -> Result<u64, String> {
+    let sync_manager = profiles.sync_manager();
     let val = sync_manager
         .db
         .get_app_meta("sleep_gap_threshold_minutes")
@@ -1365,8 +1748,9 @@ pub fn get_sleep_gap_threshold_minutes(
 #[tauri::command]
 pub fn set_sleep_gap_threshold_minutes(
     minutes: u64,
-    sync_manager: State<'_, SyncManager>,
+    profiles: State<'_, Arc<ProfileManager>>,
 ) -> Result<(), String> {
+    let sync_manager = profiles.sync_manager();
     let clamped = minutes.clamp(1, 120);
     sync_manager
         .db
@@ -1378,8 +1762,9 @@ pub fn set_sleep_gap_threshold_minutes(
 /// Fallback: ищет в очереди sync (pause/resume задачи содержат id)
 #[tauri::command]
 pub async fn get_last_time_entry_id(
-    sync_manager: State<'_, SyncManager>,
+    profiles: State<'_, Arc<ProfileManager>>,
 ) -> Result<Option<String>, String> {
+    let sync_manager = profiles.sync_manager();
     let from_meta = sync_manager
         .db
         .get_app_meta("last_active_time_entry_id")
@@ -1402,8 +1787,9 @@ pub async fn get_last_time_entry_id(
 #[tauri::command]
 pub async fn start_timer(
     app: AppHandle,
-    engine: State<'_, Arc<TimerEngine>>,
+    profiles: State<'_, Arc<ProfileManager>>,
 ) -> Result<TimerStateResponse, String> {
+    let engine = profiles.engine();
     let prev = engine.get_state().ok().map(|s| format!("{:?}", s.state));
     engine.start()?;
     let state = engine.get_state()?;
@@ -1419,8 +1805,9 @@ pub async fn start_timer(
 #[tauri::command]
 pub async fn pause_timer(
     app: AppHandle,
-    engine: State<'_, Arc<TimerEngine>>,
+    profiles: State<'_, Arc<ProfileManager>>,
 ) -> Result<TimerStateResponse, String> {
+    let engine = profiles.engine();
     let prev = engine.get_state().ok().map(|s| format!("{:?}", s.state));
     engine.pause()?;
     let state = engine.get_state()?;
@@ -1439,8 +1826,9 @@ pub async fn pause_timer(
 pub async fn pause_timer_idle(
     work_elapsed_secs: u64,
     app: AppHandle,
-    engine: State<'_, Arc<TimerEngine>>,
+    profiles: State<'_, Arc<ProfileManager>>,
 ) -> Result<TimerStateResponse, String> {
+    let engine = profiles.engine();
     let prev = engine.get_state().ok().map(|s| format!("{:?}", s.state));
     engine.pause_with_work_elapsed(work_elapsed_secs)?;
     let state = engine.get_state()?;
@@ -1456,8 +1844,9 @@ pub async fn pause_timer_idle(
 #[tauri::command]
 pub async fn resume_timer(
     app: AppHandle,
-    engine: State<'_, Arc<TimerEngine>>,
+    profiles: State<'_, Arc<ProfileManager>>,
 ) -> Result<TimerStateResponse, String> {
+    let engine = profiles.engine();
     let prev = engine.get_state().ok().map(|s| format!("{:?}", s.state));
     engine.resume()?;
     let state = engine.get_state()?;
@@ -1473,8 +1862,9 @@ pub async fn resume_timer(
 #[tauri::command]
 pub async fn stop_timer(
     app: AppHandle,
-    engine: State<'_, Arc<TimerEngine>>,
+    profiles: State<'_, Arc<ProfileManager>>,
 ) -> Result<TimerStateResponse, String> {
+    let engine = profiles.engine();
     let prev = engine.get_state().ok().map(|s| format!("{:?}", s.state));
     engine.stop()?;
     let state = engine.get_state()?;
@@ -1490,17 +1880,20 @@ pub async fn stop_timer(
 
 #[tauri::command]
 pub async fn get_timer_state(
-    engine: State<'_, Arc<TimerEngine>>,
+    profiles: State<'_, Arc<ProfileManager>>,
 ) -> Result<TimerStateResponse, String> {
+    let engine = profiles.engine();
     engine.get_state()
 }
 
 #[tauri::command]
-pub async fn reset_timer_day(engine: State<'_, Arc<TimerEngine>>) -> Result<(), String> {
+pub async fn reset_timer_day(profiles: State<'_, Arc<ProfileManager>>) -> Result<(), String> {
+    let engine = profiles.engine();
     engine.reset_day()
 }
 
 #[tauri::command]
-pub async fn save_timer_state(engine: State<'_, Arc<TimerEngine>>) -> Result<(), String> {
+pub async fn save_timer_state(profiles: State<'_, Arc<ProfileManager>>) -> Result<(), String> {
+    let engine = profiles.engine();
     engine.save_state()
 }
@@ -0,0 +1,204 @@
+//! Connectivity state machine tied to the API host.
+//! Probes a health endpoint (default `{api_base_url}/health`), caches the result and switches
+//! online/degraded/offline only after several consecutive observations (hysteresis).
+//! Sync request outcomes are fed in as passive observations, so a working sync never waits for a probe.
+
+use crate::http_client::HttpClientFactory;
+use crate::ipc::EventSink;
+use serde::Serialize;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tracing::{debug, info};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConnectivityState {
+    Online,
+    /// API отвечает, но медленно или 5xx
+    Degraded,
+    Offline,
+}
+
+/// Payload события connectivity-changed
+#[derive(Debug, Clone, Serialize)]
+pub struct ConnectivityChangedEvent {
+    pub state: ConnectivityState,
+    pub previous: Option<ConnectivityState>,
+    pub latency_ms: Option<u64>,
+}
+
+#[derive(Clone)]
+pub struct ConnectivityConfig {
+    pub health_url: String,
+    pub probe_timeout: Duration,
+    /// Ответ медленнее — degraded
+    pub degraded_latency: Duration,
+    /// Столько одинаковых наблюдений подряд нужно для смены состояния
+    pub transition_threshold: u32,
+    /// Результат пробы считается свежим это время (get_sync_status не пробует заново)
+    pub cache_ttl: Duration,
+    /// Период фоновой пробы
+    pub probe_interval: Duration,
+}
+
+impl ConnectivityConfig {
+    pub fn for_api(api_base_url: &str) -> Self {
+        Self {
+            health_url: format!("{}/health", api_base_url.trim_end_matches('/')),
+            probe_timeout: Duration::from_secs(3),
+            degraded_latency: Duration::from_millis(1500),
+            transition_threshold: 2,
+            cache_ttl: Duration::from_secs(10),
+            probe_interval: Duration::from_secs(15),
+        }
+    }
+}
+
+#[derive(Default)]
+struct MonitorState {
+    /// None — ещё не было ни одного наблюдения
+    state: Option<ConnectivityState>,
+    /// Кандидат на смену состояния и сколько раз подряд он наблюдался
+    pending: Option<(ConnectivityState, u32)>,
+    last_observed_at: Option<Instant>,
+    last_latency_ms: Option<u64>,
+}
+
+pub struct ConnectivityMonitor {
+    config: ConnectivityConfig,
+    client: reqwest::Client,
+    inner: Mutex<MonitorState>,
+    event_sink: Arc<RwLock<Option<EventSink>>>,
+}
+
+impl ConnectivityMonitor {
+    pub fn new(
+        config: ConnectivityConfig,
+        http: &HttpClientFactory,
+        event_sink: Arc<RwLock<Option<EventSink>>>,
+    ) -> Self {
+        let client = http.build(config.probe_timeout);
+        Self {
+            config,
+            client,
+            inner: Mutex::new(MonitorState::default()),
+            event_sink,
+        }
+    }
+
+    pub fn probe_interval(&self) -> Duration {
+        self.config.probe_interval
+    }
+
+    /// Текущее состояние без сетевого запроса (None — ещё не определено)
+    pub fn state(&self) -> Option<ConnectivityState> {
+        self.inner.lock().ok().and_then(|s| s.state)
+    }
+
+    /// Состояние из кэша; если устарело — одна проба health endpoint
+    pub async fn current(&self) -> ConnectivityState {
+        let fresh = self.inner.lock().ok().and_then(|s| {
+            let observed_at = s.last_observed_at?;
+            if observed_at.elapsed() < self.config.cache_ttl {
+                s.state
+            } else {
+                None
+            }
+        });
+        match fresh {
+            Some(state) => state,
+            None => self.probe().await,
+        }
+    }
+
+    /// API доступен (online или degraded)
+    pub async fn is_online(&self) -> bool {
+        self.current().await != ConnectivityState::Offline
+    }
+
+    /// Запрос к health endpoint. Любой HTTP-ответ < 500 = хост доступен (404 тоже).
+    pub async fn probe(&self) -> ConnectivityState {
+        let started = Instant::now();
+        let result = self.client.get(&self.config.health_url).send().await;
+        let latency = started.elapsed();
+        let observed = match &result {
+            Ok(response) if response.status().is_server_error() => ConnectivityState::Degraded,
+            Ok(_) if latency >= self.config.degraded_latency => ConnectivityState::Degraded,
+            Ok(_) => ConnectivityState::Online,
+            Err(e) => {
+                debug!(
+                    "[NET] Health probe {} failed: {}",
+                    self.config.health_url, e
+                );
+                ConnectivityState::Offline
+            }
+        };
+        let latency_ms = result.is_ok().then_some(latency.as_millis() as u64);
+        self.observe(observed, latency_ms)
+    }
+
+    /// Учесть наблюдение (проба или результат sync-запроса). Возвращает состояние после него.
+    pub fn observe(
+        &self,
+        observed: ConnectivityState,
+        latency_ms: Option<u64>,
+    ) -> ConnectivityState {
+        let transition = {
+            let mut s = match self.inner.lock() {
+                Ok(s) => s,
+                Err(_) => return observed,
+            };
+            s.last_observed_at = Some(Instant::now());
+            if latency_ms.is_some() {
+                s.last_latency_ms = latency_ms;
+            }
+            let previous = s.state;
+            match previous {
+                // Первое наблюдение применяем сразу
+                None => {
+                    s.state = Some(observed);
+                    s.pending = None;
+                    Some((previous, observed))
+                }
+                Some(current) if current == observed => {
+                    s.pending = None;
+                    None
+                }
+                Some(_) => {
+                    let count = match s.pending {
+                        Some((candidate, count)) if candidate == observed => count + 1,
+                        _ => 1,
+                    };
+                    if count >= self.config.transition_threshold {
+                        s.state = Some(observed);
+                        s.pending = None;
+                        Some((previous, observed))
+                    } else {
+                        s.pending = Some((observed, count));
+                        None
+                    }
+                }
+            }
+        };
+
+        if let Some((previous, state)) = transition {
+            info!("[NET] Connectivity: {:?} -> {:?}", previous, state);
+            self.emit_changed(ConnectivityChangedEvent {
+                state,
+                previous,
+                latency_ms,
+            });
+        }
+        self.state().unwrap_or(observed)
+    }
+
+    fn emit_changed(&self, event: ConnectivityChangedEvent) {
+        let sink = match self.event_sink.read() {
+            Ok(guard) => guard.clone(),
+            Err(_) => return,
+        };
+        if let (Some(sink), Ok(payload)) = (sink, serde_json::to_value(&event)) {
+            sink(crate::ipc::events::CONNECTIVITY_CHANGED, payload);
+        }
+    }
+}
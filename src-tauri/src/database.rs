@@ -1,8 +1,11 @@
-use rusqlite::{params, Connection, Result as SqliteResult};
-use std::sync::{Arc, Mutex};
+use rusqlite::{params, Connection, OptionalExtension, Result as SqliteResult};
+use std::sync::Arc;
 use tracing::{error, warn};
 
 use crate::auth::TokenEncryption;
+use crate::blob_store::BlobStore;
+use crate::clock::META_CLOCK_OFFSET;
+use crate::db_pool::{DbWriter, ReadPool, READ_POOL_SIZE};
 
 /// Log IO-related DB errors for easier diagnosis (disk full, permission denied).
 /// Does not change error propagation — caller still returns Err.
@@ -51,36 +54,236 @@ pub fn enqueue_error_to_user_message(e: &rusqlite::Error) -> String {
     }
 }
 
-use crate::models::{FailedTaskInfo, QueueStats};
+use crate::models::{
+    ApiOriginSwitch, CachedProject, CachedTask, FailedTaskInfo, MaintenanceReport, QueueStats,
+    SyncMetricsReport, SyncRunMetrics,
+};
 use crate::sync::TaskPriority;
 use chrono::Utc;
+use rand::RngCore;
 use rusqlite::Error::InvalidParameterName;
-use std::collections::hash_map::DefaultHasher;
+use sha2::{Digest, Sha256};
+
+/// Idempotency key операции: UUIDv7 (RFC 9562) — уникален для каждой постановки в очередь,
+/// упорядочен по времени. Одинаковые payload получают разные ключи.
+pub(crate) fn new_idempotency_key() -> String {
+    let mut bytes = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let unix_ms = Utc::now().timestamp_millis().max(0) as u64;
+    bytes[..6].copy_from_slice(&unix_ms.to_be_bytes()[2..]);
+    bytes[6] = (bytes[6] & 0x0f) | 0x70; // version 7
+    bytes[8] = (bytes[8] & 0x3f) | 0x80; // variant RFC 9562
+    let hex = hex::encode(bytes);
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// Associated data шифротекста payload: тип задачи и idempotency key строки.
+/// Payload, скопированный в другую строку или под другой entity_type, не расшифровывается.
+pub(crate) fn payload_aad(entity_type: &str, idempotency_key: Option<&str>) -> Vec<u8> {
+    format!(
+        "sync_queue\0{}\0{}",
+        entity_type,
+        idempotency_key.unwrap_or("")
+    )
+    .into_bytes()
+}
+
+/// JSON с отсортированными ключами объектов (порядок полей не влияет на fingerprint)
+fn canonical_json(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::Object(map) => {
+            let mut keys: Vec<&String> = map.keys().collect();
+            keys.sort();
+            let fields: Vec<String> = keys
+                .into_iter()
+                .map(|k| {
+                    format!(
+                        "{}:{}",
+                        serde_json::Value::String(k.clone()),
+                        canonical_json(&map[k])
+                    )
+                })
+                .collect();
+            format!("{{{}}}", fields.join(","))
+        }
+        serde_json::Value::Array(items) => {
+            let items: Vec<String> = items.iter().map(canonical_json).collect();
+            format!("[{}]", items.join(","))
+        }
+        other => other.to_string(),
+    }
+}
+
+/// Fingerprint содержимого задачи для 5-секундного окна дубликатов:
+/// SHA-256 над entity_type и каноническим JSON payload (не-JSON — как есть)
+pub(crate) fn content_fingerprint(entity_type: &str, payload: &str) -> String {
+    let canonical = match serde_json::from_str::<serde_json::Value>(payload) {
+        Ok(value) => canonical_json(&value),
+        Err(_) => payload.to_string(),
+    };
+    let mut hasher = Sha256::new();
+    hasher.update(entity_type.as_bytes());
+    hasher.update([0u8]);
+    hasher.update(canonical.as_bytes());
+    hex::encode(hasher.finalize())
+}
+
+/// Blobs younger than this survive the startup sweep (written before their queue row is inserted)
+const BLOB_SWEEP_MIN_AGE: std::time::Duration = std::time::Duration::from_secs(10 * 60);
+
+/// Адрес API, для которого записаны задачи без api_origin (см. switch_api_origin)
+const META_API_ORIGIN: &str = "api_origin_active";
+
+/// Сколько последних запусков синхронизации хранить в sync_metrics
+pub const MAX_SYNC_METRICS_ROWS: i64 = 500;
+
+/// Период планового обслуживания (retention + vacuum)
+pub const MAINTENANCE_INTERVAL_SECS: i64 = 6 * 60 * 60;
+
+/// Время последней резервной копии (unix секунды)
+const META_BACKUP_LAST_RUN: &str = "backup_last_run_at";
+
+/// Отправленные задачи, чей payload хранится до конца retention (не удаляется drop_sent_payloads):
+/// из него get_last_time_entry_id_from_queue берёт id записи, когда app_meta пуст
+const SENT_PAYLOAD_KEPT_TYPES: &str =
+    "('time_entry_pause', 'time_entry_resume', 'time_entry_stop')";
+
+/// Хранение отправленных задач очереди
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    /// Сколько дней хранить метаданные sent-строк (0 — удалять при ближайшем обслуживании)
+    pub sent_retention_days: i64,
+    /// Удалять зашифрованный payload сразу после отправки (кроме pause/resume/stop — они хранятся
+    /// до конца retention)
+    pub drop_sent_payloads: bool,
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        Self {
+            sent_retention_days: 7,
+            drop_sent_payloads: true,
+        }
+    }
+}
 
-use std::hash::{Hash, Hasher};
 /// Менеджер базы данных
 pub struct Database {
-    pub(crate) conn: Arc<Mutex<Connection>>,
+    /// Единственное соединение с правом записи — в отдельном потоке (см. db_pool)
+    writer: DbWriter,
+    /// Read-only соединения: UI-запросы не ждут sync-записей и периодического save_state
+    readers: ReadPool,
+    path: std::path::PathBuf,
     pub(crate) encryption: Arc<TokenEncryption>,
+    /// Зашифрованные скриншоты очереди на диске (sync_queue.blob_ref → файл)
+    pub(crate) blobs: BlobStore,
 }
 
 impl Database {
-    /// Безопасная блокировка соединения с обработкой poisoned mutex
-    /// PRODUCTION: Обрабатывает случай, когда mutex был poisoned (panic в другом потоке)
-    fn lock_conn(&self) -> Result<std::sync::MutexGuard<'_, Connection>, rusqlite::Error> {
-        self.conn.lock().map_err(|e| {
-            InvalidParameterName(format!(
-                "Database mutex poisoned: {}. A panic occurred while holding the lock. \
-                 Please restart the application to recover.",
-                e
-            ))
-        })
+    /// Чтение на соединении из пула (последний закоммиченный снимок)
+    pub(crate) fn read<T>(
+        &self,
+        f: impl FnOnce(&Connection) -> SqliteResult<T>,
+    ) -> SqliteResult<T> {
+        let conn = self.readers.get()?;
+        f(&conn)
+    }
+
+    /// Запись в потоке-писателе; возвращается после commit.
+    /// Транзакции внутри — только savepoint (запись может попасть в общую транзакцию пакета).
+    pub(crate) fn write<T, F>(&self, f: F) -> SqliteResult<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> SqliteResult<T> + Send + 'static,
+    {
+        self.writer.write(f)
+    }
+
+    /// Отдельное read-write соединение для тестов (произвольный SQL поверх схемы)
+    #[cfg(test)]
+    pub(crate) fn test_conn(&self) -> Connection {
+        let conn = Connection::open(&self.path).expect("open test connection");
+        crate::db_pool::apply_key(&conn, self.encryption.database_key().as_deref())
+            .expect("database key");
+        conn.busy_timeout(std::time::Duration::from_secs(5))
+            .expect("busy timeout");
+        conn
     }
 
     pub fn new(db_path: &str) -> SqliteResult<Self> {
-        // pragma_update требует &mut self, поэтому нужен mut
-        #[allow(unused_mut)]
+        Self::open(db_path, None, true)
+    }
+
+    /// БД с отдельным каталогом blob-файлов (профили пользователей в общем app_data_dir)
+    pub fn with_blob_dir(db_path: &str, blob_dir: &std::path::Path) -> SqliteResult<Self> {
+        Self::open(db_path, Some(blob_dir), true)
+    }
+
+    /// Открыть без startup GC blobs: после восстановления повреждённой БД ссылки на скриншоты
+    /// появятся в очереди только после salvage (затем — sweep_orphan_blobs)
+    pub(crate) fn open_for_recovery(
+        db_path: &str,
+        blob_dir: Option<&std::path::Path>,
+    ) -> SqliteResult<Self> {
+        Self::open(db_path, blob_dir, false)
+    }
+
+    fn open(
+        db_path: &str,
+        blob_dir: Option<&std::path::Path>,
+        sweep_blobs: bool,
+    ) -> SqliteResult<Self> {
+        let app_data_dir = std::path::Path::new(db_path).parent();
+        let encryption =
+            Arc::new(TokenEncryption::new(app_data_dir).map_err(|e| InvalidParameterName(e))?);
+
+        // SQLCipher (feature `sqlcipher`): БД, созданная сборкой без шифрования, перешифровывается один раз
+        let db_key = encryption.database_key();
+        // Копия, выбранная в restore_backup, подменяет файл до открытия
+        let replaced =
+            crate::backup::apply_staged_restore(std::path::Path::new(db_path), db_key.as_deref())?;
+        if let Some(key) = &db_key {
+            if crate::db_pool::encrypt_plaintext_database(std::path::Path::new(db_path), key)? {
+                tracing::info!("[DB] Existing database encrypted with SQLCipher");
+            }
+        }
+
         let mut conn = Connection::open(db_path)?;
+        // Ротация ключа прервана до rekey: файл ещё на прежнем ключе — перешифровываем активным
+        // Ни один известный ключ не открывает файл — ключ удалён или чужой (для SQLCipher неотличимо
+        // от повреждённой первой страницы). Не corruption: файл не переносится в карантин.
+        let keys = encryption.database_keys();
+        let has_content = std::fs::metadata(db_path)
+            .map(|m| m.len() > 0)
+            .unwrap_or(false);
+        let file_key = if !keys.is_empty() && has_content {
+            Some(
+                crate::db_pool::find_database_key(std::path::Path::new(db_path), &keys)
+                    .ok_or_else(|| {
+                        InvalidParameterName(format!(
+                            "Database key mismatch: no known encryption key opens {}",
+                            db_path
+                        ))
+                    })?,
+            )
+        } else {
+            None
+        };
+        match (&file_key, &db_key) {
+            (Some(file_key), Some(active)) if file_key != active => {
+                crate::db_pool::configure_writer(&conn, Some(file_key))?;
+                conn.pragma_update(None, "rekey", active)?;
+                tracing::info!("[DB] Database re-keyed after interrupted key rotation");
+            }
+            _ => crate::db_pool::configure_writer(&conn, db_key.as_deref())?,
+        }
 
         // GUARD: Integrity check on startup — detect corruption before init
         let integrity: String = conn
@@ -119,95 +322,42 @@ impl Database {
             })
             .ok();
 
-        let app_data_dir = std::path::Path::new(db_path).parent();
-        let encryption = TokenEncryption::new(app_data_dir).map_err(|e| InvalidParameterName(e))?;
-
-        let db = Self {
-            conn: Arc::new(Mutex::new(conn)),
-            encryption: Arc::new(encryption),
+        let blob_dir = match blob_dir {
+            Some(dir) => dir.to_path_buf(),
+            None => app_data_dir
+                .unwrap_or_else(|| std::path::Path::new("."))
+                .join("blobs"),
         };
-        db.run_migrations()?;
-        Ok(db)
-    }
-
-    /// Current schema version (PRAGMA user_version). Bump when adding migrations.
-    const SCHEMA_VERSION: i32 = 5;
-
-    /// Versioned migrations using SQLite user_version pragma.
-    /// When releasing v0.2.0 with new columns (e.g. task_category), add migration 6 and bump SCHEMA_VERSION.
-    fn run_migrations(&self) -> SqliteResult<()> {
-        let conn = self.lock_conn()?;
-        let current: i32 = conn.query_row("PRAGMA user_version", [], |r| r.get(0))?;
 
-        if current < 1 {
-            conn.execute(
-                "CREATE TABLE IF NOT EXISTS time_entries (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                day TEXT NOT NULL,
-                accumulated_seconds INTEGER NOT NULL DEFAULT 0,
-                state TEXT NOT NULL,
-                last_updated_at INTEGER NOT NULL,
-                started_at INTEGER,
-                UNIQUE(day)
-            )",
-                [],
-            )?;
-            conn.execute(
-                "CREATE TABLE IF NOT EXISTS sync_queue (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                entity_type TEXT NOT NULL,
-                payload TEXT NOT NULL,
-                status TEXT NOT NULL DEFAULT 'pending',
-                retry_count INTEGER NOT NULL DEFAULT 0,
-                created_at INTEGER NOT NULL,
-                last_retry_at INTEGER,
-                error_message TEXT,
-                priority INTEGER NOT NULL DEFAULT 2,
-                idempotency_key TEXT
-            )",
-                [],
-            )?;
-            conn.execute(
-                "CREATE TABLE IF NOT EXISTS app_meta (key TEXT PRIMARY KEY, value TEXT)",
-                [],
-            )?;
-            conn.execute(
-                "CREATE INDEX IF NOT EXISTS idx_sync_queue_status ON sync_queue(status)",
-                [],
-            )?;
-            conn.execute(
-                "CREATE INDEX IF NOT EXISTS idx_time_entries_day ON time_entries(day)",
-                [],
-            )?;
-        }
+        // Миграции схемы (см. crate::migrations): каждый шаг в транзакции с проверкой результата.
+        // БД новее приложения — ошибка, файл не трогаем. До запуска писателя и читателей.
+        crate::migrations::run(&mut conn, &encryption)?;
 
-        // Migration 2: error_message (idempotent ALTER)
-        if current < 2 {
-            let _ = conn.execute("ALTER TABLE sync_queue ADD COLUMN error_message TEXT", []);
-        }
-        // Migration 3: priority
-        if current < 3 {
-            let _ = conn.execute(
-                "ALTER TABLE sync_queue ADD COLUMN priority INTEGER DEFAULT 2",
-                [],
-            );
-        }
-        // Migration 4: started_at
-        if current < 4 {
-            let _ = conn.execute("ALTER TABLE time_entries ADD COLUMN started_at INTEGER", []);
+        let db = Self {
+            readers: ReadPool::open(
+                std::path::Path::new(db_path),
+                READ_POOL_SIZE,
+                db_key.as_deref(),
+            )?,
+            writer: DbWriter::spawn(conn)?,
+            path: std::path::PathBuf::from(db_path),
+            blobs: BlobStore::new(blob_dir, encryption.clone()),
+            encryption,
+        };
+        // Неотправленная очередь заменённой БД — в восстановленную (до GC blobs: скриншоты её задач)
+        if let Some(replaced) = replaced {
+            if let Err(e) = crate::salvage::salvage_queue_into(&replaced, &db) {
+                warn!(
+                    "[DB] Failed to carry the queue over from {}: {}",
+                    replaced.display(),
+                    e
+                );
+            }
         }
-        // Migration 5: idempotency_key
-        if current < 5 {
-            let _ = conn.execute("ALTER TABLE sync_queue ADD COLUMN idempotency_key TEXT", []);
+        if sweep_blobs {
+            db.sweep_orphan_blobs();
         }
-
-        // Future: Migration 6 (v0.2.0): task_category
-        // if current < 6 {
-        //     let _ = conn.execute("ALTER TABLE sync_queue ADD COLUMN task_category TEXT", []);
-        // }
-
-        conn.pragma_update(None, "user_version", Self::SCHEMA_VERSION)?;
-        Ok(())
+        Ok(db)
     }
 
     /// Сохранить состояние таймера
@@ -219,93 +369,83 @@ impl Database {
         state: &str,
         started_at: Option<u64>,
     ) -> SqliteResult<()> {
-        let conn = self.lock_conn()?;
-        let now = Utc::now().timestamp();
+        let day = day.to_string();
+        let state = state.to_string();
+        self.write(move |conn| {
+            let now = Utc::now().timestamp();
 
-        // GUARD: Начинаем транзакцию для атомарности
-        // BEGIN IMMEDIATE гарантирует, что транзакция начнется немедленно
-        // и не будет ждать освобождения блокировки
-        conn.execute("BEGIN IMMEDIATE TRANSACTION", [])
-            .map_err(|e| {
+            // GUARD: Savepoint для атомарности (drop без commit — откат)
+            let tx = conn.savepoint().map_err(|e| {
                 log_io_error_if_any("save_timer_state begin", &e);
                 error!("[DB] Failed to begin transaction: {}", e);
                 e
             })?;
 
-        // Выполняем операцию внутри транзакции
-        let result = conn.execute(
-            "INSERT INTO time_entries (day, accumulated_seconds, state, last_updated_at, started_at)
+            tx.execute(
+                "INSERT INTO time_entries (day, accumulated_seconds, state, last_updated_at, started_at)
      VALUES (?1, ?2, ?3, ?4, ?5)
      ON CONFLICT(day) DO UPDATE SET
         accumulated_seconds = ?2,
         state = ?3,
         last_updated_at = ?4,
         started_at = ?5",
-            params![day, accumulated_seconds, state, now, started_at],
-        );
-
-        // GUARD: Коммитим или откатываем транзакцию
-        match result {
-            Ok(_) => {
-                // Успешно - коммитим транзакцию
-                conn.execute("COMMIT", []).map_err(|e| {
-                    log_io_error_if_any("save_timer_state commit", &e);
-                    error!("[DB] Failed to commit transaction: {}", e);
-                    // Пытаемся откатить
-                    let _ = conn.execute("ROLLBACK", []);
-                    e
-                })?;
-                // CLOCK SKEW: Store wall time for restore_state cap (protects against forward skew)
-                let _ = conn.execute(
-                    "INSERT INTO app_meta (key, value) VALUES ('last_heartbeat_wall_secs', ?1) ON CONFLICT(key) DO UPDATE SET value = ?1",
-                    params![now.to_string()],
-                );
-                Ok(())
-            }
-            Err(e) => {
+                params![day, accumulated_seconds, state, now, started_at],
+            )
+            .map_err(|e| {
                 log_io_error_if_any("save_timer_state", &e);
                 error!(
                     "[DB] Failed to save timer state: {}. Rolling back transaction.",
                     e
                 );
-                let _ = conn.execute("ROLLBACK", []);
-                Err(e)
-            }
-        }
+                e
+            })?;
+
+            tx.commit().map_err(|e| {
+                log_io_error_if_any("save_timer_state commit", &e);
+                error!("[DB] Failed to commit transaction: {}", e);
+                e
+            })?;
+            // CLOCK SKEW: Store wall time for restore_state cap (protects against forward skew)
+            let _ = conn.execute(
+                "INSERT INTO app_meta (key, value) VALUES ('last_heartbeat_wall_secs', ?1) ON CONFLICT(key) DO UPDATE SET value = ?1",
+                params![now.to_string()],
+            );
+            Ok(())
+        })
     }
 
     /// Загрузить последнее состояние таймера
     pub fn load_timer_state(&self) -> SqliteResult<Option<(String, u64, String, Option<u64>)>> {
-        let conn = self.lock_conn()?;
-
-        let mut stmt = conn.prepare(
-            "SELECT day, accumulated_seconds, state, started_at FROM time_entries
+        self.read(|conn| {
+            let mut stmt = conn.prepare(
+                "SELECT day, accumulated_seconds, state, started_at FROM time_entries
      ORDER BY last_updated_at DESC LIMIT 1",
-        )?;
-
-        let result = stmt.query_map([], |row| {
-            Ok((
-                row.get::<_, String>(0)?,
-                row.get::<_, u64>(1)?,
-                row.get::<_, String>(2)?,
-                row.get::<_, Option<i64>>(3)?.map(|v| v as u64),
-            ))
-        })?;
+            )?;
 
-        for row in result {
-            return Ok(Some(row?));
-        }
+            let result = stmt.query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, u64>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, Option<i64>>(3)?.map(|v| v as u64),
+                ))
+            })?;
 
-        Ok(None)
+            for row in result {
+                return Ok(Some(row?));
+            }
+
+            Ok(None)
+        })
     }
 
     /// Получить последний time entry ID из очереди (pending или sent) — fallback когда app_meta пуст
     pub fn get_last_time_entry_id_from_queue(&self) -> SqliteResult<Option<String>> {
-        let raw_rows: Vec<(i64, String, String)> = {
-            let conn = self.lock_conn()?;
+        let raw_rows: Vec<(i64, String, String, Option<String>)> = self.read(|conn| {
             let mut stmt = conn.prepare(
-                "SELECT id, entity_type, payload FROM sync_queue
+                "SELECT id, entity_type, payload, idempotency_key FROM sync_queue
                  WHERE entity_type IN ('time_entry_pause', 'time_entry_resume', 'time_entry_stop')
+                   AND payload != ''
                  ORDER BY created_at DESC LIMIT 5",
             )?;
             let rows = stmt.query_map([], |row| {
@@ -313,17 +453,19 @@ impl Database {
                     row.get::<_, i64>(0)?,
                     row.get::<_, String>(1)?,
                     row.get::<_, String>(2)?,
+                    row.get::<_, Option<String>>(3)?,
                 ))
             })?;
-            rows.filter_map(|r| r.ok()).collect()
-        };
+            Ok(rows.filter_map(|r| r.ok()).collect())
+        })?;
 
-        for (row_id, _entity_type, encrypted) in raw_rows {
+        for (row_id, entity_type, encrypted, idempotency_key) in raw_rows {
+            let aad = payload_aad(&entity_type, idempotency_key.as_deref());
             if let Ok((decrypted, needs_migration)) =
-                self.encryption.decrypt_with_legacy_fallback(&encrypted)
+                self.encryption.decrypt_with_aad(&encrypted, &aad)
             {
                 if needs_migration {
-                    if let Ok(new_encrypted) = self.encryption.encrypt(&decrypted) {
+                    if let Ok(new_encrypted) = self.encryption.encrypt_with_aad(&decrypted, &aad) {
                         let _ = self.update_sync_payload(row_id, &new_encrypted);
                     }
                 }
@@ -341,195 +483,361 @@ impl Database {
 
     /// Получить значение из app_meta (для изоляции данных по пользователю)
     pub fn get_app_meta(&self, key: &str) -> SqliteResult<Option<String>> {
-        let conn = self.lock_conn()?;
-        let mut stmt = conn.prepare("SELECT value FROM app_meta WHERE key = ?1")?;
-        let mut rows = stmt.query(params![key])?;
-        if let Some(row) = rows.next()? {
-            return Ok(Some(row.get(0)?));
-        }
-        Ok(None)
+        self.read(|conn| {
+            let mut stmt = conn.prepare("SELECT value FROM app_meta WHERE key = ?1")?;
+            let mut rows = stmt.query(params![key])?;
+            if let Some(row) = rows.next()? {
+                return Ok(Some(row.get(0)?));
+            }
+            Ok(None)
+        })
     }
 
     /// Записать значение в app_meta
     pub fn set_app_meta(&self, key: &str, value: &str) -> SqliteResult<()> {
-        let conn = self.lock_conn()?;
-        conn.execute(
-            "INSERT INTO app_meta (key, value) VALUES (?1, ?2) ON CONFLICT(key) DO UPDATE SET value = ?2",
-            params![key, value],
-        )?;
-        Ok(())
+        let (key, value) = (key.to_string(), value.to_string());
+        self.write(move |conn| {
+            conn.execute(
+                "INSERT INTO app_meta (key, value) VALUES (?1, ?2) ON CONFLICT(key) DO UPDATE SET value = ?2",
+                params![key, value],
+            )?;
+            Ok(())
+        })
     }
 
     /// Очистить локальные данные (таймер, очередь синхронизации).
     /// Вызывать только при реальной смене пользователя (A → B), не при входе после логаута ("" → A).
     pub fn clear_user_data(&self) -> SqliteResult<()> {
-        let conn = self.lock_conn()?;
-        conn.execute("DELETE FROM time_entries", [])?;
-        conn.execute("DELETE FROM sync_queue", [])?;
+        self.write(|conn| {
+            conn.execute("DELETE FROM time_entries", [])?;
+            conn.execute("DELETE FROM sync_queue", [])?;
+            // Проекты другого пользователя/компании не должны попасть в выбор
+            conn.execute("DELETE FROM projects_cache", [])?;
+            conn.execute("DELETE FROM tasks_cache", [])?;
+            conn.execute(
+                "DELETE FROM app_meta WHERE key IN ('projects_cache_cursor', 'tasks_cache_cursor', 'projects_cache_full_at')",
+                [],
+            )?;
+            Ok(())
+        })?;
+        if let Err(e) = self.blobs.remove_all() {
+            warn!("[DB] clear_user_data: failed to remove queued blobs: {}", e);
+        }
         let _ = self.set_app_meta("last_active_time_entry_id", "");
         Ok(())
     }
 
-    /// Добавить задачу в очередь синхронизации
-    /// Защита от дублирования: не добавляет задачу, если такая же задача уже в очереди (pending) за последние 5 секунд
-    /// CRITICAL FIX: Использует явную транзакцию для атомарности
-    pub fn enqueue_sync(&self, entity_type: &str, payload: &str) -> SqliteResult<i64> {
-        let conn = self.lock_conn()?;
-        let now = Utc::now().timestamp();
-        let duplicate_window = 5; // 5 секунд
-
-        // CRITICAL FIX: Генерируем idempotency key из entity_type + payload
-        // ДОКАЗАНО: Одинаковые entity_type + payload дают одинаковый ключ
-        let mut hasher = DefaultHasher::new();
-        entity_type.hash(&mut hasher);
-        payload.hash(&mut hasher);
-        let idempotency_key = format!("{}-{:x}", entity_type, hasher.finish());
+    /// Есть ли данные пользователя: учтённое время / незавершённый таймер или неотправленные задачи.
+    /// Пустая строка остановленного таймера (её пишет save_state) данными не считается.
+    pub fn has_user_data(&self) -> SqliteResult<bool> {
+        self.read(|conn| {
+            conn.query_row(
+                "SELECT EXISTS(SELECT 1 FROM time_entries WHERE accumulated_seconds > 0 OR state != 'stopped')
+                 OR EXISTS(SELECT 1 FROM sync_queue WHERE status IN ('pending', 'failed', 'parked'))",
+                [],
+                |row| row.get(0),
+            )
+        })
+    }
 
-        let encrypted_payload = self.encryption.encrypt(payload).map_err(|e| {
-            error!("[DB] Encryption failed for payload: {}", e);
-            InvalidParameterName(format!("Encryption error: {}", e))
+    /// Нужен ли этой БД общий ключ шифрования: файл SQLCipher, данные пользователя (payload очереди,
+    /// blobs) или зашифрованные значения app_meta (refresh token, пароль прокси в сетевых настройках)
+    pub fn depends_on_key(&self) -> SqliteResult<bool> {
+        if self.encryption.database_key().is_some()
+            || self.has_user_data()?
+            || crate::http_client::HttpClientSettings::has_stored_password(self)
+        {
+            return Ok(true);
+        }
+        let values: Vec<String> = self.read(|conn| {
+            let mut stmt = conn.prepare("SELECT value FROM app_meta WHERE value != ''")?;
+            let rows = stmt.query_map([], |row| row.get(0))?;
+            rows.collect()
         })?;
+        Ok(values.iter().any(|v| self.encryption.decrypt(v).is_ok()))
+    }
 
-        // CRITICAL FIX: Начинаем явную транзакцию для атомарности
-        // BEGIN IMMEDIATE гарантирует, что транзакция начнется немедленно
-        conn.execute("BEGIN IMMEDIATE TRANSACTION", [])
-            .map_err(|e| {
-                error!("[DB] Failed to begin transaction in enqueue_sync: {}", e);
-                e
-            })?;
+    /// Согласованная копия БД в новый файл (VACUUM INTO); файл не должен существовать
+    pub fn copy_to(&self, path: &std::path::Path) -> SqliteResult<()> {
+        let target = path
+            .to_str()
+            .ok_or_else(|| InvalidParameterName(format!("Invalid path: {}", path.display())))?
+            .to_string();
+        // VACUUM нельзя выполнить в транзакции; в потоке-писателе копия согласована с записями
+        self.writer.write_exclusive(move |conn| {
+            conn.execute("VACUUM INTO ?1", params![target])?;
+            Ok(())
+        })
+    }
 
-        // Проверяем, есть ли такая же задача в очереди (pending) за последние 5 секунд
-        let duplicate_check: i32 = match conn.query_row(
-            "SELECT COUNT(*) FROM sync_queue 
-             WHERE idempotency_key = ?1 
-             AND status = 'pending' 
-             AND created_at > ?2",
-            params![idempotency_key, now - duplicate_window],
-            |row| row.get(0),
-        ) {
-            Ok(count) => count,
-            Err(e) => {
-                let _ = conn.execute("ROLLBACK", []);
-                return Err(e);
+    /// Переключить активный сервер API (вызывается при старте, до запуска синхронизации).
+    /// Задачи без api_origin принадлежат предыдущему серверу; при смене адреса его pending/failed
+    /// задачи получают статус 'parked' и не отправляются, а parked-задачи нового адреса возвращаются
+    /// в pending. Кэши проектов и client config другого сервера очищаются.
+    pub fn switch_api_origin(&self, origin: &str) -> SqliteResult<ApiOriginSwitch> {
+        let origin = origin.to_string();
+        self.write(move |conn| {
+            let previous: Option<String> = conn
+                .query_row(
+                    "SELECT value FROM app_meta WHERE key = ?1",
+                    params![META_API_ORIGIN],
+                    |row| row.get(0),
+                )
+                .optional()?
+                .filter(|v: &String| !v.is_empty());
+            // До появления настройки все задачи писались на адрес по умолчанию
+            let owner = previous
+                .clone()
+                .unwrap_or_else(|| crate::endpoint::DEFAULT_API_BASE_URL.to_string());
+            conn.execute(
+                "UPDATE sync_queue SET api_origin = ?1 WHERE api_origin IS NULL",
+                params![owner],
+            )?;
+
+            let mut result = ApiOriginSwitch {
+                previous: previous.clone(),
+                changed: owner != origin,
+                ..Default::default()
+            };
+            if result.changed {
+                result.parked = conn.execute(
+                    "UPDATE sync_queue SET status = 'parked'
+                     WHERE status IN ('pending', 'failed') AND api_origin != ?1",
+                    params![origin],
+                )?;
+                result.restored = conn.execute(
+                    "UPDATE sync_queue SET status = 'pending' WHERE status = 'parked' AND api_origin = ?1",
+                    params![origin],
+                )?;
+                conn.execute("DELETE FROM projects_cache", [])?;
+                conn.execute("DELETE FROM tasks_cache", [])?;
+                conn.execute(
+                    "DELETE FROM app_meta WHERE key IN ('projects_cache_cursor', 'tasks_cache_cursor', 'projects_cache_full_at', 'client_config_json', 'client_config_etag', 'last_active_time_entry_id')",
+                    [],
+                )?;
             }
-        };
+            conn.execute(
+                "INSERT OR REPLACE INTO app_meta (key, value) VALUES (?1, ?2)",
+                params![META_API_ORIGIN, origin],
+            )?;
+            Ok(result)
+        })
+    }
 
-        if duplicate_check > 0 {
-            // Такая же задача уже в очереди - не добавляем дубликат
-            warn!(
-                "[DB] Duplicate task detected: {} with payload {} (skipping)",
-                entity_type,
-                if payload.len() > 50 {
-                    &payload[..50]
-                } else {
-                    payload
-                }
-            );
-            // Возвращаем ID существующей задачи по idempotency_key (payload в БД зашифрован)
-            let existing_id: i64 = match conn.query_row(
-                "SELECT id FROM sync_queue 
-                 WHERE idempotency_key = ?1 
-                 AND status = 'pending' 
-                 AND created_at > ?2
-                 ORDER BY created_at DESC 
-                 LIMIT 1",
-                params![idempotency_key, now - duplicate_window],
-                |row| row.get(0),
-            ) {
-                Ok(id) => {
-                    // ДОКАЗАНО: Дубликат найден - коммитим транзакцию и возвращаем ID
-                    conn.execute("COMMIT", []).map_err(|e| {
-                        error!(
-                            "[DB] Failed to commit transaction in enqueue_sync (duplicate): {}",
-                            e
-                        );
-                        let _ = conn.execute("ROLLBACK", []);
-                        e
-                    })?;
-                    id
-                }
+    /// Удалить blobs, на которые больше не ссылается ни одна pending/failed задача.
+    /// Вызывать после commit записи, которая отправила или отменила строки с этими blob_ref.
+    /// Проверка и удаление — в писателе под `lock_refs`: новая строка с тем же blob не проскочит
+    /// между ними.
+    fn release_blobs(&self, blob_refs: &[String]) {
+        let paths: Vec<(String, std::path::PathBuf)> = blob_refs
+            .iter()
+            .filter_map(|blob_ref| match self.blobs.path_for(blob_ref) {
+                Ok(path) => Some((blob_ref.clone(), path)),
                 Err(e) => {
-                    let _ = conn.execute("ROLLBACK", []);
-                    return Err(e);
+                    warn!("[DB] {}", e);
+                    None
                 }
-            };
-            return Ok(existing_id);
+            })
+            .collect();
+        if paths.is_empty() {
+            return;
+        }
+        let _refs = self.blobs.lock_refs();
+        let result = self.write(move |conn| {
+            for (blob_ref, path) in &paths {
+                let still_used: i32 = conn.query_row(
+                    "SELECT COUNT(*) FROM sync_queue
+                     WHERE blob_ref = ?1 AND status IN ('pending', 'failed', 'parked')",
+                    params![blob_ref],
+                    |row| row.get(0),
+                )?;
+                if still_used == 0 {
+                    if let Err(e) = BlobStore::remove_file(path) {
+                        warn!("[DB] Failed to remove blob {}: {}", blob_ref, e);
+                    }
+                }
+            }
+            Ok(())
+        });
+        if let Err(e) = result {
+            warn!("[DB] Failed to release blobs: {}", e);
         }
+    }
 
-        // Определяем приоритет задачи
-        let priority = TaskPriority::from_entity_type(entity_type);
-        let priority_value = priority as i32;
-
-        // GUARD: Проверка лимита очереди (10_000 задач)
-        let queue_size: i32 = match conn.query_row(
-            "SELECT COUNT(*) FROM sync_queue WHERE status IN ('pending', 'failed')",
-            [],
-            |row| row.get(0),
-        ) {
-            Ok(size) => size,
+    /// Startup GC: удалить blob-файлы без живых задач (сбой между записью blob и INSERT, старые версии)
+    pub(crate) fn sweep_orphan_blobs(&self) {
+        let referenced: std::collections::HashSet<String> = match self.read(|conn| {
+            let mut stmt = conn.prepare(
+                "SELECT DISTINCT blob_ref FROM sync_queue
+                 WHERE blob_ref IS NOT NULL AND status IN ('pending', 'failed', 'parked')",
+            )?;
+            let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+            Ok(rows.filter_map(|r| r.ok()).collect())
+        }) {
+            Ok(referenced) => referenced,
             Err(e) => {
-                let _ = conn.execute("ROLLBACK", []);
-                return Err(e);
+                warn!("[DB] Blob sweep skipped: {}", e);
+                return;
             }
         };
+        let removed = self.blobs.sweep_unreferenced(&referenced, BLOB_SWEEP_MIN_AGE);
+        if removed > 0 {
+            warn!("[DB] Removed {} orphaned queue blobs", removed);
+        }
+    }
+
+    /// Добавить задачу в очередь синхронизации
+    /// Защита от дублирования: не добавляет задачу, если такая же задача уже в очереди (pending) за последние 5 секунд
+    /// CRITICAL FIX: Использует явную транзакцию для атомарности
+    pub fn enqueue_sync(&self, entity_type: &str, payload: &str) -> SqliteResult<i64> {
+        self.enqueue_sync_with_blob(entity_type, payload, None)
+    }
+
+    /// enqueue_sync + ссылка на blob (payload должен ссылаться на тот же blob_ref).
+    /// Blob уже записан в BlobStore вызывающим кодом; строка очереди хранит только ссылку.
+    pub fn enqueue_sync_with_blob(
+        &self,
+        entity_type: &str,
+        payload: &str,
+        blob_ref: Option<&str>,
+    ) -> SqliteResult<i64> {
+        let duplicate_window = 5; // 5 секунд
 
-        if queue_size >= 10_000 {
-            // Очередь переполнена - не добавляем новые задачи (кроме critical)
-            if priority != TaskPriority::Critical {
+        // Idempotency key — на каждую операцию (UUIDv7); дубликаты ловит content fingerprint
+        let fingerprint = content_fingerprint(entity_type, payload);
+        let idempotency_key = new_idempotency_key();
+
+        let aad = payload_aad(entity_type, Some(&idempotency_key));
+        let encrypted_payload = self
+            .encryption
+            .encrypt_with_aad(payload, &aad)
+            .map_err(|e| {
+                error!("[DB] Encryption failed for payload: {}", e);
+                InvalidParameterName(format!("Encryption error: {}", e))
+            })?;
+        let payload_preview: String = payload.chars().take(50).collect();
+        let entity_type = entity_type.to_string();
+        let blob_ref = blob_ref.map(str::to_string);
+
+        let (id, dropped_blob_refs) = self.write(move |conn| {
+            let now = Utc::now().timestamp();
+            // CRITICAL FIX: Явная транзакция (savepoint) для атомарности; drop без commit — откат
+            let tx = conn.savepoint().map_err(|e| {
+                error!("[DB] Failed to begin transaction in enqueue_sync: {}", e);
+                e
+            })?;
+
+            // Проверяем, есть ли такая же задача в очереди (pending) за последние 5 секунд.
+            // Возвращаем ID существующей задачи по fingerprint (payload в БД зашифрован)
+            let existing_id: Option<i64> = tx
+                .query_row(
+                    "SELECT id FROM sync_queue 
+                     WHERE content_fingerprint = ?1 
+                     AND status = 'pending' 
+                     AND created_at > ?2
+                     ORDER BY created_at DESC 
+                     LIMIT 1",
+                    params![fingerprint, now - duplicate_window],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            if let Some(id) = existing_id {
+                // Такая же задача уже в очереди - не добавляем дубликат
                 warn!(
-                    "[DB] Queue limit reached ({} tasks), dropping non-critical task: {}",
-                    queue_size, entity_type
+                    "[DB] Duplicate task detected: {} with payload {} (skipping)",
+                    entity_type, payload_preview
                 );
-                let _ = conn.execute("ROLLBACK", []);
-                return Err(rusqlite::Error::SqliteFailure(
-                    rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_FULL),
-                    Some("Queue limit reached".to_string()),
-                ));
+                return Ok((id, Vec::new()));
             }
-            // Для critical задач удаляем самые старые normal задачи
-            let _ = conn.execute(
-                "DELETE FROM sync_queue 
-                 WHERE status = 'pending' 
-                 AND priority = 2 
-                 AND id IN (
-                     SELECT id FROM sync_queue 
-                     WHERE status = 'pending' AND priority = 2 
-                     ORDER BY created_at ASC 
-                     LIMIT 10
-                 )",
-                [],
-            );
-        }
 
-        // CRITICAL FIX: INSERT внутри транзакции с idempotency_key
-        let result = conn.execute(
-            "INSERT INTO sync_queue (entity_type, payload, status, created_at, priority, idempotency_key)
-     VALUES (?1, ?2, 'pending', ?3, ?4, ?5)",
-            params![entity_type, encrypted_payload, now, priority_value, idempotency_key],
-        );
+            // Определяем приоритет задачи
+            let priority = TaskPriority::from_entity_type(&entity_type);
+            let priority_value = priority as i32;
 
-        // CRITICAL FIX: Коммитим или откатываем транзакцию
-        match result {
-            Ok(_) => {
-                // ДОКАЗАНО: INSERT успешен - коммитим транзакцию
-                conn.execute("COMMIT", []).map_err(|e| {
-                    log_io_error_if_any("enqueue_sync commit", &e);
-                    error!("[DB] Failed to commit transaction in enqueue_sync: {}", e);
-                    let _ = conn.execute("ROLLBACK", []);
-                    e
-                })?;
-                Ok(conn.last_insert_rowid())
+            // GUARD: Проверка лимита очереди (10_000 задач)
+            let mut dropped_blob_refs: Vec<String> = Vec::new();
+            let queue_size: i32 = tx.query_row(
+                "SELECT COUNT(*) FROM sync_queue WHERE status IN ('pending', 'failed')",
+                [],
+                |row| row.get(0),
+            )?;
+
+            if queue_size >= 10_000 {
+                // Очередь переполнена - не добавляем новые задачи (кроме critical)
+                if priority != TaskPriority::Critical {
+                    warn!(
+                        "[DB] Queue limit reached ({} tasks), dropping non-critical task: {}",
+                        queue_size, entity_type
+                    );
+                    return Err(rusqlite::Error::SqliteFailure(
+                        rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_FULL),
+                        Some("Queue limit reached".to_string()),
+                    ));
+                }
+                // Для critical задач удаляем самые старые normal задачи
+                dropped_blob_refs = tx
+                    .prepare(
+                        "SELECT blob_ref FROM sync_queue
+                         WHERE status = 'pending' AND priority = 2
+                         ORDER BY created_at ASC
+                         LIMIT 10",
+                    )
+                    .and_then(|mut stmt| {
+                        stmt.query_map([], |row| row.get::<_, Option<String>>(0))
+                            .map(|rows| rows.filter_map(|r| r.ok().flatten()).collect())
+                    })
+                    .unwrap_or_default();
+                let _ = tx.execute(
+                    "DELETE FROM sync_queue 
+                     WHERE status = 'pending' 
+                     AND priority = 2 
+                     AND id IN (
+                         SELECT id FROM sync_queue 
+                         WHERE status = 'pending' AND priority = 2 
+                         ORDER BY created_at ASC 
+                         LIMIT 10
+                     )",
+                    [],
+                );
             }
-            Err(e) => {
+
+            // CRITICAL FIX: INSERT внутри транзакции с idempotency_key.
+            // clock_offset_ms — текущая оценка часов сервера (вне fingerprint и AAD), см. attach_client_time
+            tx.execute(
+                "INSERT INTO sync_queue (entity_type, payload, status, created_at, priority, idempotency_key, blob_ref, content_fingerprint, clock_offset_ms)
+     VALUES (?1, ?2, 'pending', ?3, ?4, ?5, ?6, ?7,
+             (SELECT CAST(value AS INTEGER) FROM app_meta WHERE key = ?8))",
+                params![
+                    entity_type,
+                    encrypted_payload,
+                    now,
+                    priority_value,
+                    idempotency_key,
+                    blob_ref,
+                    fingerprint,
+                    META_CLOCK_OFFSET
+                ],
+            )
+            .map_err(|e| {
                 log_io_error_if_any("enqueue_sync", &e);
                 error!(
                     "[DB] Failed to insert task in enqueue_sync: {}. Rolling back transaction.",
                     e
                 );
-                let _ = conn.execute("ROLLBACK", []);
-                Err(e)
-            }
-        }
+                e
+            })?;
+            let id = tx.last_insert_rowid();
+
+            // ДОКАЗАНО: INSERT успешен - коммитим транзакцию
+            tx.commit().map_err(|e| {
+                log_io_error_if_any("enqueue_sync commit", &e);
+                error!("[DB] Failed to commit transaction in enqueue_sync: {}", e);
+                e
+            })?;
+            Ok((id, dropped_blob_refs))
+        })?;
+        self.release_blobs(&dropped_blob_refs);
+        Ok(id)
     }
 
     /// Получить количество pending задач (для адаптивного batch)
@@ -543,29 +851,29 @@ impl Database {
         &self,
         limit: i32,
     ) -> SqliteResult<Vec<(i64, String, String)>> {
-        let conn = self.lock_conn()?;
-
-        let mut stmt = conn.prepare(
-            "SELECT id, entity_type, payload FROM sync_queue
-     WHERE status = 'pending'
-     ORDER BY created_at ASC
-     LIMIT ?1",
-        )?;
-
-        let rows = stmt.query_map(params![limit], |row| {
-            Ok((
-                row.get::<_, i64>(0)?,
-                row.get::<_, String>(1)?,
-                row.get::<_, String>(2)?,
-            ))
-        })?;
+        self.read(|conn| {
+            let mut stmt = conn.prepare(
+                "SELECT id, entity_type, payload FROM sync_queue
+         WHERE status = 'pending'
+         ORDER BY created_at ASC
+         LIMIT ?1",
+            )?;
 
-        let mut result = Vec::new();
-        for row in rows {
-            result.push(row?);
-        }
+            let rows = stmt.query_map(params![limit], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                ))
+            })?;
 
-        Ok(result)
+            let mut result = Vec::new();
+            for row in rows {
+                result.push(row?);
+            }
+
+            Ok(result)
+        })
     }
 
     /// Обновить статус задачи синхронизации
@@ -581,168 +889,495 @@ impl Database {
         retry_count: i32,
         error_message: Option<&str>,
     ) -> SqliteResult<()> {
-        let conn = self.lock_conn()?;
-        let now = Utc::now().timestamp();
-
-        if let Some(error) = error_message {
-            conn.execute(
-                "UPDATE sync_queue 
-         SET status = ?1, retry_count = ?2, last_retry_at = ?3, error_message = ?4
-         WHERE id = ?5",
-                params![status, retry_count, now, error, id],
-            )?;
-        } else {
-            conn.execute(
-                "UPDATE sync_queue 
-         SET status = ?1, retry_count = ?2, last_retry_at = ?3
-         WHERE id = ?4",
-                params![status, retry_count, now, id],
-            )?;
-        }
+        let status = status.to_string();
+        let error_message = error_message.map(str::to_string);
+        self.write(move |conn| {
+            let now = Utc::now().timestamp();
+
+            if let Some(error) = error_message {
+                conn.execute(
+                    "UPDATE sync_queue 
+             SET status = ?1, retry_count = ?2, last_retry_at = ?3, error_message = ?4
+             WHERE id = ?5",
+                    params![status, retry_count, now, error, id],
+                )?;
+            } else {
+                conn.execute(
+                    "UPDATE sync_queue 
+             SET status = ?1, retry_count = ?2, last_retry_at = ?3
+             WHERE id = ?4",
+                    params![status, retry_count, now, id],
+                )?;
+            }
 
-        Ok(())
+            Ok(())
+        })
     }
 
     /// Получить количество pending задач
     pub fn get_pending_count(&self) -> SqliteResult<i32> {
-        let conn = self.lock_conn()?;
-        let count: i32 = conn.query_row(
-            "SELECT COUNT(*) FROM sync_queue WHERE status = 'pending'",
-            [],
-            |row| row.get(0),
-        )?;
-        Ok(count)
+        self.read(|conn| {
+            let count: i32 = conn.query_row(
+                "SELECT COUNT(*) FROM sync_queue WHERE status = 'pending'",
+                [],
+                |row| row.get(0),
+            )?;
+            Ok(count)
+        })
     }
 
     /// Получить количество failed задач
     pub fn get_failed_count(&self) -> SqliteResult<i32> {
-        let conn = self.lock_conn()?;
-        let count: i32 = conn.query_row(
-            "SELECT COUNT(*) FROM sync_queue WHERE status = 'failed'",
-            [],
-            |row| row.get(0),
-        )?;
-        Ok(count)
+        self.read(|conn| {
+            let count: i32 = conn.query_row(
+                "SELECT COUNT(*) FROM sync_queue WHERE status = 'failed'",
+                [],
+                |row| row.get(0),
+            )?;
+            Ok(count)
+        })
     }
 
     /// Получить статистику очереди по типам задач
     pub fn get_queue_stats(&self) -> SqliteResult<QueueStats> {
-        let conn = self.lock_conn()?;
-
-        // Статистика по типам задач для pending
-        let mut stmt = conn.prepare(
-            "SELECT entity_type, COUNT(*) as count 
-             FROM sync_queue 
-             WHERE status = 'pending' 
-             GROUP BY entity_type",
-        )?;
-
-        let rows = stmt.query_map([], |row| {
-            Ok((row.get::<_, String>(0)?, row.get::<_, i32>(1)?))
-        })?;
+        self.read(|conn| {
+            // Статистика по типам задач для pending
+            let mut stmt = conn.prepare(
+                "SELECT entity_type, COUNT(*) as count 
+                 FROM sync_queue 
+                 WHERE status = 'pending' 
+                 GROUP BY entity_type",
+            )?;
 
-        let mut by_type: std::collections::HashMap<String, i32> = std::collections::HashMap::new();
-        for row in rows {
-            let (entity_type, count) = row?;
-            by_type.insert(entity_type, count);
-        }
+            let rows = stmt.query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, i32>(1)?))
+            })?;
+
+            let mut by_type: std::collections::HashMap<String, i32> =
+                std::collections::HashMap::new();
+            for row in rows {
+                let (entity_type, count) = row?;
+                by_type.insert(entity_type, count);
+            }
+
+            // Общее количество pending
+            let pending_count: i32 = conn.query_row(
+                "SELECT COUNT(*) FROM sync_queue WHERE status = 'pending'",
+                [],
+                |row| row.get(0),
+            )?;
+
+            // Общее количество failed
+            let failed_count: i32 = conn.query_row(
+                "SELECT COUNT(*) FROM sync_queue WHERE status = 'failed'",
+                [],
+                |row| row.get(0),
+            )?;
+
+            // Задачи другого сервера API (после смены адреса)
+            let parked_count: i32 = conn.query_row(
+                "SELECT COUNT(*) FROM sync_queue WHERE status = 'parked'",
+                [],
+                |row| row.get(0),
+            )?;
+
+            // Общее количество sent (успешно синхронизированных)
+            let sent_count: i32 = conn.query_row(
+                "SELECT COUNT(*) FROM sync_queue WHERE status = 'sent'",
+                [],
+                |row| row.get(0),
+            )?;
+
+            let read_counter = |key: &str| -> i64 {
+                conn.query_row(
+                    "SELECT value FROM app_meta WHERE key = ?1",
+                    params![key],
+                    |row| row.get::<_, String>(0),
+                )
+                .ok()
+                .and_then(|v| v.parse::<i64>().ok())
+                .unwrap_or(0)
+            };
+            let upload_bytes_raw = read_counter("upload_bytes_raw");
+            let upload_bytes_sent = read_counter("upload_bytes_sent");
+
+            Ok(QueueStats {
+                pending_count,
+                failed_count,
+                sent_count,
+                parked_count,
+                pending_by_type: by_type,
+                upload_bytes_raw,
+                upload_bytes_sent,
+                compression_saved_bytes: (upload_bytes_raw - upload_bytes_sent).max(0),
+            })
+        })
+    }
 
-        // Общее количество pending
-        let pending_count: i32 = conn.query_row(
-            "SELECT COUNT(*) FROM sync_queue WHERE status = 'pending'",
-            [],
-            |row| row.get(0),
-        )?;
-
-        // Общее количество failed
-        let failed_count: i32 = conn.query_row(
-            "SELECT COUNT(*) FROM sync_queue WHERE status = 'failed'",
-            [],
-            |row| row.get(0),
-        )?;
-
-        // Общее количество sent (успешно синхронизированных)
-        let sent_count: i32 = conn.query_row(
-            "SELECT COUNT(*) FROM sync_queue WHERE status = 'sent'",
-            [],
-            |row| row.get(0),
-        )?;
-
-        Ok(QueueStats {
-            pending_count,
-            failed_count,
-            sent_count,
-            pending_by_type: by_type,
+    /// Учесть отправленное тело запроса: raw (до сжатия) и sent (после Content-Encoding)
+    pub fn add_upload_bytes(&self, raw: u64, sent: u64) -> SqliteResult<()> {
+        self.write(move |conn| {
+            for (key, delta) in [("upload_bytes_raw", raw), ("upload_bytes_sent", sent)] {
+                conn.execute(
+                    "INSERT INTO app_meta (key, value) VALUES (?1, ?2)
+                     ON CONFLICT(key) DO UPDATE SET value = CAST(CAST(value AS INTEGER) + ?2 AS TEXT)",
+                    params![key, delta as i64],
+                )?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Записать метрики запуска синхронизации; старше MAX_SYNC_METRICS_ROWS удаляются
+    pub fn record_sync_metrics(&self, metrics: &SyncRunMetrics) -> SqliteResult<()> {
+        let metrics = metrics.clone();
+        self.write(move |conn| {
+            let failed_by_kind =
+                serde_json::to_string(&metrics.failed_by_kind).unwrap_or_else(|_| "{}".to_string());
+            conn.execute(
+                "INSERT INTO sync_metrics (started_at, duration_ms, attempted, succeeded, failed,
+                     failed_by_kind, bytes_sent, latency_p50_ms, latency_p95_ms)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                params![
+                    metrics.started_at,
+                    metrics.duration_ms as i64,
+                    metrics.attempted,
+                    metrics.succeeded,
+                    metrics.failed,
+                    failed_by_kind,
+                    metrics.bytes_sent as i64,
+                    metrics.latency_p50_ms.map(|v| v as i64),
+                    metrics.latency_p95_ms.map(|v| v as i64),
+                ],
+            )?;
+            conn.execute(
+                "DELETE FROM sync_metrics WHERE id <= (SELECT MAX(id) FROM sync_metrics) - ?1",
+                params![MAX_SYNC_METRICS_ROWS],
+            )?;
+            Ok(())
+        })
+    }
+
+    /// Последние `limit` запусков (новые первыми) и сводка по ним
+    pub fn get_sync_metrics(&self, limit: i64) -> SqliteResult<SyncMetricsReport> {
+        self.read(|conn| {
+            let mut stmt = conn.prepare(
+                "SELECT started_at, duration_ms, attempted, succeeded, failed, failed_by_kind,
+                        bytes_sent, latency_p50_ms, latency_p95_ms
+                 FROM sync_metrics ORDER BY id DESC LIMIT ?1",
+            )?;
+            let runs: Vec<SyncRunMetrics> = stmt
+                .query_map(params![limit.clamp(1, MAX_SYNC_METRICS_ROWS)], |row| {
+                    let failed_by_kind: String = row.get(5)?;
+                    Ok(SyncRunMetrics {
+                        started_at: row.get(0)?,
+                        duration_ms: row.get::<_, i64>(1)? as u64,
+                        attempted: row.get(2)?,
+                        succeeded: row.get(3)?,
+                        failed: row.get(4)?,
+                        failed_by_kind: serde_json::from_str(&failed_by_kind).unwrap_or_default(),
+                        bytes_sent: row.get::<_, i64>(6)? as u64,
+                        latency_p50_ms: row.get::<_, Option<i64>>(7)?.map(|v| v as u64),
+                        latency_p95_ms: row.get::<_, Option<i64>>(8)?.map(|v| v as u64),
+                    })
+                })?
+                .collect::<SqliteResult<_>>()?;
+
+            let mut report = SyncMetricsReport::default();
+            for run in &runs {
+                report.attempted += run.attempted;
+                report.succeeded += run.succeeded;
+                report.failed += run.failed;
+                report.bytes_sent += run.bytes_sent;
+                for (kind, count) in &run.failed_by_kind {
+                    *report.failed_by_kind.entry(kind.clone()).or_insert(0) += count;
+                }
+            }
+            let median = |values: Vec<u64>| -> Option<u64> {
+                let mut values = values;
+                values.sort_unstable();
+                values.get(values.len() / 2).copied()
+            };
+            report.latency_p50_ms = median(runs.iter().filter_map(|r| r.latency_p50_ms).collect());
+            report.latency_p95_ms = median(runs.iter().filter_map(|r| r.latency_p95_ms).collect());
+            report.runs = runs;
+            Ok(report)
+        })
+    }
+
+    /// Время постановки задачи в очередь (локальные часы, unix секунды) и оценка смещения
+    /// часов сервера на тот момент (NULL — неизвестна или задача записана до миграции 13)
+    pub fn get_task_client_time(&self, id: i64) -> SqliteResult<Option<(i64, Option<i64>)>> {
+        self.read(|conn| {
+            let mut stmt =
+                conn.prepare("SELECT created_at, clock_offset_ms FROM sync_queue WHERE id = ?1")?;
+            let mut rows = stmt.query(params![id])?;
+            if let Some(row) = rows.next()? {
+                return Ok(Some((row.get(0)?, row.get(1)?)));
+            }
+            Ok(None)
         })
     }
 
     /// Обновить payload задачи (для миграции ключа шифрования)
     pub fn update_sync_payload(&self, id: i64, encrypted_payload: &str) -> SqliteResult<()> {
-        let conn = self.lock_conn()?;
-        conn.execute(
-            "UPDATE sync_queue SET payload = ?1 WHERE id = ?2",
-            params![encrypted_payload, id],
-        )?;
-        Ok(())
+        let encrypted_payload = encrypted_payload.to_string();
+        self.write(move |conn| {
+            conn.execute(
+                "UPDATE sync_queue SET payload = ?1 WHERE id = ?2",
+                params![encrypted_payload, id],
+            )?;
+            Ok(())
+        })
     }
 
     /// Обновить статус задачи на "sent" (успешная синхронизация)
     /// PRODUCTION: Partial success - успешные задачи помечаются сразу
     pub fn mark_task_sent(&self, id: i64) -> SqliteResult<()> {
-        let conn = self.lock_conn()?;
-        conn.execute(
-            "UPDATE sync_queue SET status = 'sent' WHERE id = ?1",
-            params![id],
-        )?;
+        let blob_ref = self.write(move |conn| {
+            let blob_ref: Option<String> = conn
+                .query_row(
+                    "SELECT blob_ref FROM sync_queue WHERE id = ?1",
+                    params![id],
+                    |row| row.get(0),
+                )
+                .unwrap_or(None);
+            // Payload отправленной задачи больше не нужен (retention: drop_sent_payloads)
+            let drop_payload = Self::read_retention_policy(conn).drop_sent_payloads;
+            conn.execute(
+                &format!(
+                    "UPDATE sync_queue SET status = 'sent', sent_at = ?2,
+                        payload = CASE WHEN ?3 AND entity_type NOT IN {} THEN '' ELSE payload END
+                     WHERE id = ?1",
+                    SENT_PAYLOAD_KEPT_TYPES
+                ),
+                params![id, Utc::now().timestamp(), drop_payload],
+            )?;
+            Ok(blob_ref)
+        })?;
+        if let Some(blob_ref) = blob_ref {
+            self.release_blobs(&[blob_ref]);
+        }
         Ok(())
     }
 
+    fn read_retention_policy(conn: &Connection) -> RetentionPolicy {
+        let read = |key: &str| -> Option<String> {
+            conn.query_row(
+                "SELECT value FROM app_meta WHERE key = ?1",
+                params![key],
+                |row| row.get(0),
+            )
+            .ok()
+        };
+        let defaults = RetentionPolicy::default();
+        RetentionPolicy {
+            sent_retention_days: read("sync_retention_sent_days")
+                .and_then(|v| v.parse::<i64>().ok())
+                .map(|d| d.clamp(0, 365))
+                .unwrap_or(defaults.sent_retention_days),
+            drop_sent_payloads: read("sync_retention_drop_payloads")
+                .map(|v| v != "false" && v != "0")
+                .unwrap_or(defaults.drop_sent_payloads),
+        }
+    }
+
+    /// Политика хранения sent-задач (app_meta: sync_retention_sent_days, sync_retention_drop_payloads)
+    pub fn retention_policy(&self) -> SqliteResult<RetentionPolicy> {
+        self.read(|conn| Ok(Self::read_retention_policy(conn)))
+    }
+
+    /// Плановое обслуживание не чаще раза в MAINTENANCE_INTERVAL_SECS (учитывает перезапуски)
+    pub fn maintenance_due(&self) -> bool {
+        let last_run = self
+            .get_app_meta("maintenance_last_run_at")
+            .ok()
+            .flatten()
+            .and_then(|v| v.parse::<i64>().ok());
+        !matches!(last_run, Some(at) if Utc::now().timestamp() - at < MAINTENANCE_INTERVAL_SECS)
+    }
+
+    /// Путь к файлу БД
+    pub fn path(&self) -> &std::path::Path {
+        &self.path
+    }
+
+    /// Плановая резервная копия не чаще раза в BACKUP_INTERVAL_SECS (учитывает перезапуски)
+    pub fn backup_due(&self) -> bool {
+        let last_run = self
+            .get_app_meta(META_BACKUP_LAST_RUN)
+            .ok()
+            .flatten()
+            .and_then(|v| v.parse::<i64>().ok());
+        !matches!(last_run, Some(at) if Utc::now().timestamp() - at < crate::backup::BACKUP_INTERVAL_SECS)
+    }
+
+    /// Онлайн-копия БД (backup API на соединении чтения — писатель не ждёт), ротация BACKUP_KEEP
+    pub fn run_backup(&self) -> SqliteResult<crate::backup::BackupInfo> {
+        let key = self.encryption.database_key();
+        let info =
+            self.read(|conn| crate::backup::create_backup(conn, &self.path, key.as_deref()))?;
+        self.set_app_meta(META_BACKUP_LAST_RUN, &info.created_at.to_string())?;
+        Ok(info)
+    }
+
+    /// Перешифровать файл БД активным ключом (после ротации ключа; feature `sqlcipher`).
+    /// Соединения чтения переоткрываются с новым ключом. false — сборка без SQLCipher.
+    pub(crate) fn rekey_database(&self) -> SqliteResult<bool> {
+        let key = match self.encryption.database_key() {
+            Some(key) => key,
+            None => return Ok(false),
+        };
+        let writer_key = key.clone();
+        self.readers.rekey(&self.path, &key, || {
+            self.writer
+                .write_exclusive(move |conn| conn.pragma_update(None, "rekey", &writer_key))
+        })?;
+        Ok(true)
+    }
+
+    /// Размер файла БД + WAL (для отчёта об освобождённом месте)
+    fn database_file_bytes(conn: &Connection) -> u64 {
+        let path = match conn.path() {
+            Some(p) if !p.is_empty() => std::path::PathBuf::from(p),
+            _ => return 0,
+        };
+        let size = |p: &std::path::Path| std::fs::metadata(p).map(|m| m.len()).unwrap_or(0);
+        let mut wal = path.clone().into_os_string();
+        wal.push("-wal");
+        size(&path) + size(std::path::Path::new(&wal))
+    }
+
+    /// Обслуживание очереди: удалить payload отправленных задач (кроме SENT_PAYLOAD_KEPT_TYPES),
+    /// удалить sent-строки старше retention, incremental vacuum и wal_checkpoint(TRUNCATE).
+    /// Возвращает освобождённые байты.
+    pub fn run_maintenance(&self) -> SqliteResult<MaintenanceReport> {
+        let report = self.writer.write_exclusive(move |conn| {
+            let started = std::time::Instant::now();
+            let policy = Self::read_retention_policy(conn);
+            let bytes_before = Self::database_file_bytes(conn);
+
+            let payloads_dropped = if policy.drop_sent_payloads {
+                conn.execute(
+                    &format!(
+                        "UPDATE sync_queue SET payload = ''
+                         WHERE status = 'sent' AND payload != '' AND entity_type NOT IN {}",
+                        SENT_PAYLOAD_KEPT_TYPES
+                    ),
+                    [],
+                )?
+            } else {
+                0
+            };
+
+            let cutoff = Utc::now().timestamp() - policy.sent_retention_days * 24 * 60 * 60;
+            let rows_deleted = conn.execute(
+                "DELETE FROM sync_queue
+                 WHERE status = 'sent' AND COALESCE(sent_at, last_retry_at, created_at) < ?1",
+                params![cutoff],
+            )?;
+
+            // Перевод в INCREMENTAL при миграции мог не пройти — без него incremental_vacuum ничего
+            // не освобождает; повторяем при каждом обслуживании
+            let auto_vacuum_incremental = match crate::migrations::enable_incremental_vacuum(conn) {
+                Ok(()) => true,
+                Err(e) => {
+                    warn!(
+                        "[DB] Maintenance: failed to enable incremental auto_vacuum: {}",
+                        e
+                    );
+                    false
+                }
+            };
+
+            let free_pages: i64 = conn.query_row("PRAGMA freelist_count", [], |r| r.get(0))?;
+            conn.execute_batch("PRAGMA incremental_vacuum")?;
+            let free_pages_after: i64 =
+                conn.query_row("PRAGMA freelist_count", [], |r| r.get(0))?;
+
+            // (busy, log frames, checkpointed frames); busy=1 — читатель держит WAL, усечём в следующий раз
+            let (checkpoint_busy, _, _): (i64, i64, i64) =
+                conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |r| {
+                    Ok((r.get(0)?, r.get(1)?, r.get(2)?))
+                })?;
+
+            let bytes_after = Self::database_file_bytes(conn);
+            conn.execute(
+                "INSERT INTO app_meta (key, value) VALUES ('maintenance_last_run_at', ?1)
+                 ON CONFLICT(key) DO UPDATE SET value = ?1",
+                params![Utc::now().timestamp().to_string()],
+            )?;
+
+            Ok(MaintenanceReport {
+                payloads_dropped,
+                rows_deleted,
+                pages_freed: (free_pages - free_pages_after).max(0),
+                auto_vacuum_incremental,
+                wal_truncated: checkpoint_busy == 0,
+                bytes_before,
+                bytes_after,
+                reclaimed_bytes: bytes_before.saturating_sub(bytes_after),
+                duration_ms: started.elapsed().as_millis() as u64,
+            })
+        })?;
+        tracing::info!(
+            "[DB] Maintenance: {} payloads dropped, {} sent rows deleted, {} bytes reclaimed",
+            report.payloads_dropped,
+            report.rows_deleted,
+            report.reclaimed_bytes
+        );
+        Ok(report)
+    }
+
     /// Зарезервировать задачи для текущего sync run (обновить last_retry_at)
     /// Предотвращает повторный выбор тех же задач другим sync в течение backoff окна
     pub fn claim_tasks_for_sync(&self, ids: &[i64]) -> SqliteResult<()> {
         if ids.is_empty() {
             return Ok(());
         }
-        let conn = self.lock_conn()?;
-        let now = Utc::now().timestamp();
-        for id in ids {
-            conn.execute(
-                "UPDATE sync_queue SET last_retry_at = ?1 WHERE id = ?2",
-                params![now, id],
-            )?;
-        }
-        Ok(())
+        let ids = ids.to_vec();
+        self.write(move |conn| {
+            let now = Utc::now().timestamp();
+            for id in ids {
+                conn.execute(
+                    "UPDATE sync_queue SET last_retry_at = ?1 WHERE id = ?2",
+                    params![now, id],
+                )?;
+            }
+            Ok(())
+        })
     }
 
     /// Получить список failed задач с деталями
     pub fn get_failed_tasks(&self, limit: i32) -> SqliteResult<Vec<FailedTaskInfo>> {
-        let conn = self.lock_conn()?;
-        let mut stmt = conn.prepare(
-            "SELECT id, entity_type, payload, retry_count, created_at, last_retry_at, error_message 
-             FROM sync_queue 
-             WHERE status = 'failed' 
-             ORDER BY created_at DESC 
-             LIMIT ?1",
-        )?;
-
-        let rows = stmt.query_map(params![limit], |row| {
-            Ok(FailedTaskInfo {
-                id: row.get::<_, i64>(0)?,
-                entity_type: row.get::<_, String>(1)?,
-                payload: row.get::<_, String>(2)?,
-                retry_count: row.get::<_, i32>(3)?,
-                created_at: row.get::<_, i64>(4)?,
-                last_retry_at: row.get::<_, Option<i64>>(5)?,
-                error_message: row.get::<_, Option<String>>(6)?,
-            })
-        })?;
+        self.read(|conn| {
+            let mut stmt = conn.prepare(
+                "SELECT id, entity_type, payload, retry_count, created_at, last_retry_at, error_message 
+                 FROM sync_queue 
+                 WHERE status = 'failed' 
+                 ORDER BY created_at DESC 
+                 LIMIT ?1",
+            )?;
 
-        let mut result = Vec::new();
-        for row in rows {
-            result.push(row?);
-        }
+            let rows = stmt.query_map(params![limit], |row| {
+                Ok(FailedTaskInfo {
+                    id: row.get::<_, i64>(0)?,
+                    entity_type: row.get::<_, String>(1)?,
+                    payload: row.get::<_, String>(2)?,
+                    retry_count: row.get::<_, i32>(3)?,
+                    created_at: row.get::<_, i64>(4)?,
+                    last_retry_at: row.get::<_, Option<i64>>(5)?,
+                    error_message: row.get::<_, Option<String>>(6)?,
+                })
+            })?;
 
-        Ok(result)
+            let mut result = Vec::new();
+            for row in rows {
+                result.push(row?);
+            }
+
+            Ok(result)
+        })
     }
 
     /// Отменить противоположные операции для time entry (resume <-> pause)
@@ -753,8 +1388,6 @@ impl Database {
         operation: &str,
         _time_entry_id: &str,
     ) -> SqliteResult<usize> {
-        let conn = self.lock_conn()?;
-
         // Определяем противоположную операцию
         let opposite_operation = match operation {
             "resume" => "pause",
@@ -775,17 +1408,35 @@ impl Database {
         // Это безопасно, так как при синхронизации они будут обработаны корректно
 
         // ВАЖНО: Отменяем только недавние задачи (за последние 30 секунд), чтобы не отменить старые
-        let now = Utc::now().timestamp();
         let recent_window = 30; // 30 секунд
 
-        let count = conn.execute(
-            "UPDATE sync_queue 
-             SET status = 'cancelled' 
-             WHERE entity_type = ?1 
-             AND status = 'pending' 
-             AND created_at > ?2",
-            params![opposite_entity_type, now - recent_window],
-        )?;
+        let (count, cancelled_blob_refs) = self.write(move |conn| {
+            let now = Utc::now().timestamp();
+            let cancelled_blob_refs: Vec<String> = conn
+                .prepare(
+                    "SELECT blob_ref FROM sync_queue
+                     WHERE entity_type = ?1 AND status = 'pending' AND created_at > ?2
+                     AND blob_ref IS NOT NULL",
+                )
+                .and_then(|mut stmt| {
+                    stmt.query_map(params![opposite_entity_type, now - recent_window], |row| {
+                        row.get::<_, String>(0)
+                    })
+                    .map(|rows| rows.filter_map(|r| r.ok()).collect())
+                })
+                .unwrap_or_default();
+
+            let count = conn.execute(
+                "UPDATE sync_queue 
+                 SET status = 'cancelled' 
+                 WHERE entity_type = ?1 
+                 AND status = 'pending' 
+                 AND created_at > ?2",
+                params![opposite_entity_type, now - recent_window],
+            )?;
+            Ok((count, cancelled_blob_refs))
+        })?;
+        self.release_blobs(&cancelled_blob_refs);
 
         if count > 0 {
             warn!(
@@ -794,36 +1445,39 @@ impl Database {
             );
         }
 
-        Ok(count as usize)
+        Ok(count)
     }
 
     /// Очистить всю очередь синхронизации (safety valve для пользователей)
     pub fn clear_sync_queue(&self) -> SqliteResult<()> {
-        let conn = self.lock_conn()?;
-        conn.execute("DELETE FROM sync_queue", [])?;
+        self.write(|conn| conn.execute("DELETE FROM sync_queue", []))?;
+        if let Err(e) = self.blobs.remove_all() {
+            warn!("[DB] clear_sync_queue: failed to remove queued blobs: {}", e);
+        }
         Ok(())
     }
 
     /// Сбросить failed задачи обратно в pending для повторной попытки
     pub fn reset_failed_tasks(&self, limit: i32) -> SqliteResult<i32> {
-        let conn = self.lock_conn()?;
-        let now = Utc::now().timestamp();
+        self.write(move |conn| {
+            let now = Utc::now().timestamp();
 
-        // Сбрасываем retry_count в 0 и статус в 'pending' для failed задач
-        let count = conn.execute(
-            "UPDATE sync_queue 
-             SET status = 'pending', retry_count = 0, last_retry_at = ?1
-             WHERE status = 'failed' 
-             AND id IN (
-                 SELECT id FROM sync_queue 
+            // Сбрасываем retry_count в 0 и статус в 'pending' для failed задач
+            let count = conn.execute(
+                "UPDATE sync_queue 
+                 SET status = 'pending', retry_count = 0, last_retry_at = ?1
                  WHERE status = 'failed' 
-                 ORDER BY created_at ASC 
-                 LIMIT ?2
-             )",
-            params![now, limit],
-        )?;
+                 AND id IN (
+                     SELECT id FROM sync_queue 
+                     WHERE status = 'failed' 
+                     ORDER BY created_at ASC 
+                     LIMIT ?2
+                 )",
+                params![now, limit],
+            )?;
 
-        Ok(count as i32)
+            Ok(count as i32)
+        })
     }
 
     /// Получить задачи для повторной попытки (exponential backoff)
@@ -836,7 +1490,6 @@ impl Database {
         batch_size: i32,
         aggressive_retry: bool,
     ) -> SqliteResult<Vec<(i64, String, String, i32, Option<String>)>> {
-        let conn = self.lock_conn()?;
         let now = Utc::now().timestamp();
 
         // aggressive_retry: при online — 5 сек, чтобы сразу повторить после восстановления сети
@@ -862,33 +1515,33 @@ impl Database {
             backoff_sql
         );
 
-        let mut stmt = conn.prepare(&sql)?;
-
-        let raw_rows: Vec<(i64, String, String, i32, Option<String>)> = stmt
-            .query_map(params![max_retries, now, batch_size], |row| {
-                Ok((
-                    row.get::<_, i64>(0)?,
-                    row.get::<_, String>(1)?,
-                    row.get::<_, String>(2)?,
-                    row.get::<_, i32>(3)?,
-                    row.get::<_, Option<String>>(4)?,
-                ))
-            })?
-            .filter_map(|r| r.ok())
-            .collect();
-
-        drop(stmt);
-        drop(conn);
+        let raw_rows: Vec<(i64, String, String, i32, Option<String>)> = self.read(|conn| {
+            let mut stmt = conn.prepare(&sql)?;
+            let rows = stmt
+                .query_map(params![max_retries, now, batch_size], |row| {
+                    Ok((
+                        row.get::<_, i64>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, String>(2)?,
+                        row.get::<_, i32>(3)?,
+                        row.get::<_, Option<String>>(4)?,
+                    ))
+                })?
+                .filter_map(|r| r.ok())
+                .collect();
+            Ok(rows)
+        })?;
 
         let mut result = Vec::new();
+        let mut rejected = Vec::new();
         for (id, entity_type, encrypted_payload, retry_count, idempotency_key) in raw_rows {
-            match self
-                .encryption
-                .decrypt_with_legacy_fallback(&encrypted_payload)
-            {
+            // Payload привязан к строке (entity_type + idempotency key): чужой payload отклоняется
+            let aad = payload_aad(&entity_type, idempotency_key.as_deref());
+            match self.encryption.decrypt_with_aad(&encrypted_payload, &aad) {
                 Ok((payload, needs_migration)) => {
                     if needs_migration {
-                        if let Ok(new_encrypted) = self.encryption.encrypt(&payload) {
+                        let reencrypted = self.encryption.encrypt_with_aad(&payload, &aad);
+                        if let Ok(new_encrypted) = reencrypted {
                             if self.update_sync_payload(id, &new_encrypted).is_ok() {
                                 tracing::warn!(
                                     "[DB] Migration successful: task {} re-encrypted with new key",
@@ -901,13 +1554,210 @@ impl Database {
                 }
                 Err(e) => {
                     warn!(
-                        "[DB] Skipping task {}: decryption failed ({}). One broken task won't block the queue.",
+                        "[DB] Task {} marked failed: payload rejected ({}). One broken task won't block the queue.",
                         id, e
                     );
+                    rejected.push((id, format!("Payload rejected: {}", e)));
                 }
             }
         }
+        if !rejected.is_empty() {
+            if let Err(e) = self.reject_sync_payloads(rejected) {
+                warn!("[DB] Failed to mark rejected tasks failed: {}", e);
+            }
+        }
 
         Ok(result)
     }
+
+    /// Задачи, чей payload не прошёл проверку (чужая строка, другой entity_type/idempotency key,
+    /// не расшифровывается) → failed: pending они выбирались бы в каждом batch, не отправляясь
+    fn reject_sync_payloads(&self, rejected: Vec<(i64, String)>) -> SqliteResult<()> {
+        self.write(move |conn| {
+            let now = Utc::now().timestamp();
+            for (id, error) in &rejected {
+                conn.execute(
+                    "UPDATE sync_queue SET status = 'failed', last_retry_at = ?1, error_message = ?2
+                     WHERE id = ?3 AND status = 'pending'",
+                    params![now, error, id],
+                )?;
+            }
+            Ok(())
+        })
+    }
+
+    // ============================================
+    // PROJECTS / TASKS CACHE (offline start)
+    // ============================================
+
+    /// Upsert проектов из API. `full_sync` = ответ содержит полный список:
+    /// отсутствующие в нём проекты помечаются deleted (tombstone, чтобы offline start их отклонил).
+    pub fn upsert_cached_projects(
+        &self,
+        projects: &[CachedProject],
+        full_sync: bool,
+    ) -> SqliteResult<usize> {
+        let projects = projects.to_vec();
+        self.write(move |conn| {
+            let tx = conn.savepoint()?;
+            let now = Utc::now().timestamp();
+            if full_sync {
+                tx.execute("UPDATE projects_cache SET deleted = 1", [])?;
+            }
+            for p in &projects {
+                tx.execute(
+                    "INSERT INTO projects_cache
+                     (id, name, description, color, client_name, status, archived, deleted, updated_at, synced_at)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
+                     ON CONFLICT(id) DO UPDATE SET
+                        name = ?2, description = ?3, color = ?4, client_name = ?5, status = ?6,
+                        archived = ?7, deleted = ?8, updated_at = ?9, synced_at = ?10",
+                    params![
+                        p.id,
+                        p.name,
+                        p.description,
+                        p.color,
+                        p.client_name,
+                        p.status,
+                        p.archived,
+                        p.deleted,
+                        p.updated_at,
+                        now
+                    ],
+                )?;
+            }
+            tx.commit()?;
+            Ok(projects.len())
+        })
+    }
+
+    /// Upsert задач из API (семантика full_sync как у upsert_cached_projects)
+    pub fn upsert_cached_tasks(
+        &self,
+        tasks: &[CachedTask],
+        full_sync: bool,
+    ) -> SqliteResult<usize> {
+        let tasks = tasks.to_vec();
+        self.write(move |conn| {
+            let tx = conn.savepoint()?;
+            let now = Utc::now().timestamp();
+            if full_sync {
+                tx.execute("UPDATE tasks_cache SET deleted = 1", [])?;
+            }
+            for t in &tasks {
+                tx.execute(
+                    "INSERT INTO tasks_cache
+                     (id, project_id, name, status, archived, deleted, updated_at, synced_at)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+                     ON CONFLICT(id) DO UPDATE SET
+                        project_id = ?2, name = ?3, status = ?4, archived = ?5, deleted = ?6,
+                        updated_at = ?7, synced_at = ?8",
+                    params![
+                        t.id,
+                        t.project_id,
+                        t.name,
+                        t.status,
+                        t.archived,
+                        t.deleted,
+                        t.updated_at,
+                        now
+                    ],
+                )?;
+            }
+            tx.commit()?;
+            Ok(tasks.len())
+        })
+    }
+
+    fn project_from_row(row: &rusqlite::Row) -> SqliteResult<CachedProject> {
+        Ok(CachedProject {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            description: row.get(2)?,
+            color: row.get(3)?,
+            client_name: row.get(4)?,
+            status: row.get(5)?,
+            archived: row.get(6)?,
+            deleted: row.get(7)?,
+            updated_at: row.get(8)?,
+        })
+    }
+
+    fn task_from_row(row: &rusqlite::Row) -> SqliteResult<CachedTask> {
+        Ok(CachedTask {
+            id: row.get(0)?,
+            project_id: row.get(1)?,
+            name: row.get(2)?,
+            status: row.get(3)?,
+            archived: row.get(4)?,
+            deleted: row.get(5)?,
+            updated_at: row.get(6)?,
+        })
+    }
+
+    /// Проекты для выбора (без удалённых; архивные — по запросу)
+    pub fn list_cached_projects(&self, include_archived: bool) -> SqliteResult<Vec<CachedProject>> {
+        self.read(|conn| {
+            let mut stmt = conn.prepare(
+                "SELECT id, name, description, color, client_name, status, archived, deleted, updated_at
+                 FROM projects_cache
+                 WHERE deleted = 0 AND (archived = 0 OR ?1)
+                 ORDER BY name COLLATE NOCASE",
+            )?;
+            let rows = stmt.query_map(params![include_archived], Self::project_from_row)?;
+            rows.collect()
+        })
+    }
+
+    /// Поиск по имени проекта / клиента (подстрока, без учёта регистра для ASCII)
+    pub fn search_cached_projects(
+        &self,
+        query: &str,
+        limit: i32,
+    ) -> SqliteResult<Vec<CachedProject>> {
+        let escaped = query
+            .trim()
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_");
+        let pattern = format!("%{}%", escaped);
+        self.read(|conn| {
+            let mut stmt = conn.prepare(
+                "SELECT id, name, description, color, client_name, status, archived, deleted, updated_at
+                 FROM projects_cache
+                 WHERE deleted = 0 AND archived = 0
+                   AND (name LIKE ?1 ESCAPE '\\' OR client_name LIKE ?1 ESCAPE '\\')
+                 ORDER BY name COLLATE NOCASE
+                 LIMIT ?2",
+            )?;
+            let rows = stmt.query_map(params![pattern, limit], Self::project_from_row)?;
+            rows.collect()
+        })
+    }
+
+    /// Проект по id, включая deleted/archived (для валидации offline start)
+    pub fn get_cached_project(&self, id: &str) -> SqliteResult<Option<CachedProject>> {
+        self.read(|conn| {
+            let mut stmt = conn.prepare(
+                "SELECT id, name, description, color, client_name, status, archived, deleted, updated_at
+                 FROM projects_cache WHERE id = ?1",
+            )?;
+            let mut rows = stmt.query_map(params![id], Self::project_from_row)?;
+            rows.next().transpose()
+        })
+    }
+
+    /// Задачи проекта (или все), без удалённых и архивных
+    pub fn list_cached_tasks(&self, project_id: Option<&str>) -> SqliteResult<Vec<CachedTask>> {
+        self.read(|conn| {
+            let mut stmt = conn.prepare(
+                "SELECT id, project_id, name, status, archived, deleted, updated_at
+                 FROM tasks_cache
+                 WHERE deleted = 0 AND archived = 0 AND (?1 IS NULL OR project_id = ?1)
+                 ORDER BY name COLLATE NOCASE",
+            )?;
+            let rows = stmt.query_map(params![project_id], Self::task_from_row)?;
+            rows.collect()
+        })
+    }
 }
@@ -0,0 +1,369 @@
+//! SQLite access for `Database`: one writer thread and a small pool of read-only WAL connections.
+//! Writes are sent to the writer over a channel; commands queued at the same time are committed
+//! in one transaction (a savepoint per command, so one failing command does not undo the others).
+//! Reads never wait behind the writer: WAL readers see the last committed snapshot.
+//! With the `sqlcipher` feature every connection is keyed before its first statement.
+
+use rusqlite::Error::InvalidParameterName;
+use rusqlite::{params, Connection, DatabaseName, OpenFlags, Result as SqliteResult};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::sync::{Mutex, MutexGuard};
+use std::thread::JoinHandle;
+use std::time::Duration;
+use tracing::{error, warn};
+
+/// Соединений для чтения (UI-запросы, sync, статистика)
+pub const READ_POOL_SIZE: usize = 3;
+/// Сколько команд из очереди писателя объединяется в одну транзакцию
+const MAX_BATCH: usize = 64;
+/// Ожидание блокировки файла (другой процесс / checkpoint)
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Результат выполнения команды: ok — оставить изменения в транзакции пакета.
+/// deliver вызывается после commit пакета (Some — commit не удался).
+struct Outcome {
+    ok: bool,
+    deliver: Deliver,
+}
+
+type Deliver = Box<dyn FnOnce(Option<&rusqlite::Error>) + Send>;
+
+type Job = Box<dyn FnOnce(&mut Connection) -> Outcome + Send>;
+
+enum Command {
+    /// Может быть объединена с соседними командами в одну транзакцию
+    Write(Job),
+    /// Отдельно и вне транзакции (VACUUM, wal_checkpoint)
+    Exclusive(Job),
+}
+
+/// Копия ошибки для каждой команды пакета (код SQLite сохраняется — disk full / read-only)
+fn copy_error(e: &rusqlite::Error) -> rusqlite::Error {
+    match e {
+        rusqlite::Error::SqliteFailure(code, message) => {
+            rusqlite::Error::SqliteFailure(*code, message.clone())
+        }
+        other => InvalidParameterName(other.to_string()),
+    }
+}
+
+fn job<T, F>(f: F) -> (Job, mpsc::Receiver<SqliteResult<T>>)
+where
+    T: Send + 'static,
+    F: FnOnce(&mut Connection) -> SqliteResult<T> + Send + 'static,
+{
+    let (reply_tx, reply_rx) = mpsc::sync_channel(1);
+    let job: Job = Box::new(move |conn: &mut Connection| {
+        let result = f(conn);
+        let ok = result.is_ok();
+        Outcome {
+            ok,
+            deliver: Box::new(move |commit_error| {
+                let result = match commit_error {
+                    Some(e) if ok => Err(copy_error(e)),
+                    _ => result,
+                };
+                let _ = reply_tx.send(result);
+            }),
+        }
+    });
+    (job, reply_rx)
+}
+
+/// Поток-писатель: единственное соединение с правом записи
+pub(crate) struct DbWriter {
+    sender: Option<mpsc::Sender<Command>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl DbWriter {
+    pub fn spawn(conn: Connection) -> SqliteResult<Self> {
+        let (sender, receiver) = mpsc::channel();
+        let thread = std::thread::Builder::new()
+            .name("hubnity-db-writer".to_string())
+            .spawn(move || writer_loop(conn, receiver))
+            .map_err(|e| InvalidParameterName(format!("Failed to start DB writer: {}", e)))?;
+        Ok(Self {
+            sender: Some(sender),
+            thread: Some(thread),
+        })
+    }
+
+    /// Выполнить запись в потоке-писателе и дождаться commit
+    pub fn write<T, F>(&self, f: F) -> SqliteResult<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> SqliteResult<T> + Send + 'static,
+    {
+        let (job, reply) = job(f);
+        self.send(Command::Write(job), reply)
+    }
+
+    /// Запись вне транзакции пакета (VACUUM INTO, incremental_vacuum, wal_checkpoint)
+    pub fn write_exclusive<T, F>(&self, f: F) -> SqliteResult<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> SqliteResult<T> + Send + 'static,
+    {
+        let (job, reply) = job(f);
+        self.send(Command::Exclusive(job), reply)
+    }
+
+    fn send<T>(&self, command: Command, reply: mpsc::Receiver<SqliteResult<T>>) -> SqliteResult<T> {
+        let stopped = || InvalidParameterName("Database writer is not running".to_string());
+        self.sender
+            .as_ref()
+            .ok_or_else(stopped)?
+            .send(command)
+            .map_err(|_| stopped())?;
+        reply.recv().map_err(|_| stopped())?
+    }
+}
+
+impl Drop for DbWriter {
+    /// Дописать очередь и закрыть соединение до возврата (повторное открытие файла сразу после drop)
+    fn drop(&mut self) {
+        self.sender.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn writer_loop(mut conn: Connection, receiver: mpsc::Receiver<Command>) {
+    let mut pending: Option<Command> = None;
+    loop {
+        let first = match pending.take() {
+            Some(command) => command,
+            None => match receiver.recv() {
+                Ok(command) => command,
+                Err(_) => return,
+            },
+        };
+        let mut batch = match first {
+            Command::Exclusive(job) => {
+                let outcome = job(&mut conn);
+                (outcome.deliver)(None);
+                continue;
+            }
+            Command::Write(job) => vec![job],
+        };
+        while batch.len() < MAX_BATCH {
+            match receiver.try_recv() {
+                Ok(Command::Write(job)) => batch.push(job),
+                Ok(exclusive) => {
+                    pending = Some(exclusive);
+                    break;
+                }
+                Err(_) => break,
+            }
+        }
+        run_batch(&mut conn, batch);
+    }
+}
+
+fn run_batch(conn: &mut Connection, batch: Vec<Job>) {
+    if batch.len() == 1 || conn.execute_batch("BEGIN IMMEDIATE").is_err() {
+        for job in batch {
+            let outcome = job(conn);
+            (outcome.deliver)(None);
+        }
+        return;
+    }
+    let mut delivers = Vec::with_capacity(batch.len());
+    for job in batch {
+        if let Err(e) = conn.execute_batch("SAVEPOINT batch_command") {
+            warn!("[DB] Writer savepoint failed: {}", e);
+        }
+        let outcome = job(conn);
+        let end = if outcome.ok {
+            "RELEASE batch_command"
+        } else {
+            "ROLLBACK TO batch_command; RELEASE batch_command"
+        };
+        if let Err(e) = conn.execute_batch(end) {
+            warn!("[DB] Writer savepoint release failed: {}", e);
+        }
+        delivers.push(outcome.deliver);
+    }
+    let commit = conn.execute_batch("COMMIT");
+    if let Err(e) = &commit {
+        error!("[DB] Failed to commit write batch: {}", e);
+        let _ = conn.execute_batch("ROLLBACK");
+    }
+    for deliver in delivers {
+        deliver(commit.as_ref().err());
+    }
+}
+
+/// Пул read-only соединений (WAL: чтение не ждёт писателя)
+pub(crate) struct ReadPool {
+    conns: Vec<Mutex<Connection>>,
+    next: AtomicUsize,
+}
+
+impl ReadPool {
+    pub fn open(path: &Path, size: usize, key: Option<&str>) -> SqliteResult<Self> {
+        let flags = OpenFlags::SQLITE_OPEN_READ_ONLY
+            | OpenFlags::SQLITE_OPEN_NO_MUTEX
+            | OpenFlags::SQLITE_OPEN_URI;
+        let mut conns = Vec::with_capacity(size);
+        for _ in 0..size.max(1) {
+            let conn = Connection::open_with_flags(path, flags)?;
+            apply_key(&conn, key)?;
+            conn.busy_timeout(BUSY_TIMEOUT)?;
+            conns.push(Mutex::new(conn));
+        }
+        Ok(Self {
+            conns,
+            next: AtomicUsize::new(0),
+        })
+    }
+
+    /// Смена ключа SQLCipher: `rekey` выполняется, пока все соединения пула заняты,
+    /// затем они переоткрываются с новым ключом (старый ключ больше не читает файл)
+    pub fn rekey(
+        &self,
+        path: &Path,
+        key: &str,
+        rekey: impl FnOnce() -> SqliteResult<()>,
+    ) -> SqliteResult<()> {
+        let mut guards = Vec::with_capacity(self.conns.len());
+        for conn in &self.conns {
+            guards.push(conn.lock().unwrap_or_else(|e| e.into_inner()));
+        }
+        rekey()?;
+        let flags = OpenFlags::SQLITE_OPEN_READ_ONLY
+            | OpenFlags::SQLITE_OPEN_NO_MUTEX
+            | OpenFlags::SQLITE_OPEN_URI;
+        for guard in &mut guards {
+            let conn = Connection::open_with_flags(path, flags)?;
+            apply_key(&conn, Some(key))?;
+            conn.busy_timeout(BUSY_TIMEOUT)?;
+            **guard = conn;
+        }
+        Ok(())
+    }
+
+    /// Свободное соединение; если все заняты — ждём очередное по кругу
+    pub fn get(&self) -> SqliteResult<MutexGuard<'_, Connection>> {
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        for i in 0..self.conns.len() {
+            if let Ok(conn) = self.conns[(start + i) % self.conns.len()].try_lock() {
+                return Ok(conn);
+            }
+        }
+        self.conns[start % self.conns.len()].lock().map_err(|e| {
+            InvalidParameterName(format!(
+                "Database read mutex poisoned: {}. Please restart the application to recover.",
+                e
+            ))
+        })
+    }
+}
+
+/// Ключ SQLCipher и таймаут блокировки для соединения писателя
+pub(crate) fn configure_writer(conn: &Connection, key: Option<&str>) -> SqliteResult<()> {
+    apply_key(conn, key)?;
+    conn.busy_timeout(BUSY_TIMEOUT)
+}
+
+/// `PRAGMA key` — должен быть первым запросом соединения (None — файл не зашифрован)
+pub(crate) fn apply_key(conn: &Connection, key: Option<&str>) -> SqliteResult<()> {
+    match key {
+        Some(key) => conn.pragma_update(None, "key", key),
+        None => Ok(()),
+    }
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// Какой из ключей открывает файл (ротация ключа прервана до `rekey` — файл на прежнем ключе).
+/// None — ни один (файл повреждён или не зашифрован).
+pub(crate) fn find_database_key(path: &Path, keys: &[String]) -> Option<String> {
+    keys.iter()
+        .find(|key| {
+            Connection::open_with_flags(
+                path,
+                OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+            )
+            .and_then(|conn| {
+                apply_key(&conn, Some(key))?;
+                conn.query_row("SELECT COUNT(*) FROM sqlite_master", [], |r| {
+                    r.get::<_, i64>(0)
+                })
+            })
+            .is_ok()
+        })
+        .cloned()
+}
+
+/// Файл — обычная (незашифрованная) SQLite БД: заголовок "SQLite format 3\0"
+pub(crate) fn is_plaintext_database(path: &Path) -> bool {
+    use std::io::Read;
+    let mut header = [0u8; 16];
+    std::fs::File::open(path)
+        .and_then(|mut f| f.read_exact(&mut header))
+        .map(|_| &header == b"SQLite format 3\0")
+        .unwrap_or(false)
+}
+
+/// Однократный перевод незашифрованной БД в SQLCipher: sqlcipher_export во временный файл,
+/// проверка ключом, затем rename поверх исходного. До rename исходный файл не меняется,
+/// поэтому сбой на любом шаге оставляет рабочую (пусть и незашифрованную) БД.
+/// Возвращает true, если файл был перешифрован.
+pub(crate) fn encrypt_plaintext_database(path: &Path, key: &str) -> SqliteResult<bool> {
+    if !is_plaintext_database(path) {
+        return Ok(false);
+    }
+    let tmp = with_suffix(path, "-encrypting");
+    let tmp_str = tmp
+        .to_str()
+        .ok_or_else(|| InvalidParameterName(format!("Invalid path: {}", tmp.display())))?
+        .to_string();
+    let _ = std::fs::remove_file(&tmp);
+
+    {
+        let plain = Connection::open(path)?;
+        plain.busy_timeout(BUSY_TIMEOUT)?;
+        // Всё из WAL — в основной файл: после rename старый WAL удаляется
+        plain.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))?;
+        let user_version: i32 = plain.query_row("PRAGMA user_version", [], |r| r.get(0))?;
+        plain.execute(
+            "ATTACH DATABASE ?1 AS encrypted KEY ?2",
+            params![tmp_str, key],
+        )?;
+        plain.query_row("SELECT sqlcipher_export('encrypted')", [], |_| Ok(()))?;
+        plain.pragma_update(
+            Some(DatabaseName::Attached("encrypted")),
+            "user_version",
+            user_version,
+        )?;
+        plain.execute_batch("DETACH DATABASE encrypted")?;
+    }
+
+    {
+        let check = Connection::open(&tmp)?;
+        apply_key(&check, Some(key))?;
+        check.query_row("SELECT COUNT(*) FROM sqlite_master", [], |r| {
+            r.get::<_, i64>(0)
+        })?;
+    }
+
+    for suffix in ["-wal", "-shm"] {
+        let _ = std::fs::remove_file(with_suffix(path, suffix));
+    }
+    std::fs::rename(&tmp, path).map_err(|e| {
+        InvalidParameterName(format!(
+            "Failed to replace database with encrypted copy: {}",
+            e
+        ))
+    })?;
+    Ok(true)
+}
@@ -0,0 +1,170 @@
+//! API base URL resolution for self-hosted and staging deployments.
+//! Priority: managed policy file > `HUBNITY_API_URL` env var > user setting (app_meta) > built-in default.
+//! The active endpoint is recorded in app_meta; on a switch the queue of the previous server is parked
+//! (see `Database::switch_api_origin`) so its tasks are never replayed to another server.
+
+use crate::Database;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use tracing::{error, info};
+
+pub const DEFAULT_API_BASE_URL: &str = "https://app.automatonsoft.de/api";
+pub const ENV_API_URL: &str = "HUBNITY_API_URL";
+const META_API_URL: &str = "api_base_url";
+
+/// Откуда взят адрес API
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EndpointSource {
+    Policy,
+    Env,
+    Settings,
+    Default,
+}
+
+/// Активный адрес API (managed state, команда get_api_endpoint)
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ApiEndpoint {
+    pub url: String,
+    pub source: EndpointSource,
+    /// Policy/env перекрывают настройку пользователя — менять в UI нельзя
+    pub locked: bool,
+}
+
+/// Проверить и привести URL к каноническому виду (без завершающего `/`).
+/// https обязателен; http — только для localhost (локальная разработка).
+pub fn normalize_api_url(raw: &str) -> Result<String, String> {
+    let raw = raw.trim();
+    let url = reqwest::Url::parse(raw).map_err(|e| format!("Invalid API URL '{}': {}", raw, e))?;
+    let is_loopback = matches!(
+        url.host_str(),
+        Some("localhost") | Some("127.0.0.1") | Some("[::1]")
+    );
+    match url.scheme() {
+        "https" => {}
+        "http" if is_loopback => {}
+        scheme => {
+            return Err(format!(
+                "Invalid API URL '{}': scheme {} not allowed (https required)",
+                raw, scheme
+            ))
+        }
+    }
+    if !matches!(url.host_str(), Some(h) if !h.is_empty()) {
+        return Err(format!("Invalid API URL '{}': missing host", raw));
+    }
+    if !url.username().is_empty() || url.password().is_some() {
+        return Err(format!(
+            "Invalid API URL '{}': credentials not allowed",
+            raw
+        ));
+    }
+    if url.query().is_some() || url.fragment().is_some() {
+        return Err(format!(
+            "Invalid API URL '{}': query and fragment not allowed",
+            raw
+        ));
+    }
+    Ok(url.as_str().trim_end_matches('/').to_string())
+}
+
+/// Файл управляемой политики (MDM / GPO кладут его с правами администратора)
+pub fn policy_path() -> Option<PathBuf> {
+    #[cfg(target_os = "macos")]
+    {
+        Some(PathBuf::from(
+            "/Library/Application Support/Hubnity/policy.json",
+        ))
+    }
+    #[cfg(target_os = "windows")]
+    {
+        std::env::var_os("ProgramData")
+            .map(|dir| PathBuf::from(dir).join("Hubnity").join("policy.json"))
+    }
+    #[cfg(not(any(target_os = "macos", target_os = "windows")))]
+    {
+        Some(PathBuf::from("/etc/hubnity/policy.json"))
+    }
+}
+
+/// `{"apiBaseUrl": "https://..."}` из файла политики
+fn read_policy_url(path: &Path) -> Option<String> {
+    let content = std::fs::read_to_string(path).ok()?;
+    match serde_json::from_str::<serde_json::Value>(&content) {
+        Ok(policy) => policy
+            .get("apiBaseUrl")
+            .and_then(|v| v.as_str())
+            .map(|s| s.to_string()),
+        Err(e) => {
+            error!(
+                "[API] Policy file {} is not valid JSON: {}",
+                path.display(),
+                e
+            );
+            None
+        }
+    }
+}
+
+/// Определить адрес API. Невалидные значения пропускаются с ошибкой в логе (следующий источник).
+pub fn resolve(
+    db: &Database,
+    policy_file: Option<&Path>,
+    env_value: Option<String>,
+) -> ApiEndpoint {
+    let candidates = [
+        (
+            EndpointSource::Policy,
+            policy_file.and_then(read_policy_url),
+        ),
+        (EndpointSource::Env, env_value),
+        (
+            EndpointSource::Settings,
+            db.get_app_meta(META_API_URL).ok().flatten(),
+        ),
+    ];
+    for (source, value) in candidates {
+        let raw = match value {
+            Some(raw) if !raw.trim().is_empty() => raw,
+            _ => continue,
+        };
+        match normalize_api_url(&raw) {
+            Ok(url) => {
+                return ApiEndpoint {
+                    url,
+                    source,
+                    locked: matches!(source, EndpointSource::Policy | EndpointSource::Env),
+                }
+            }
+            Err(e) => error!("[API] Ignoring {:?} API URL: {}", source, e),
+        }
+    }
+    ApiEndpoint {
+        url: DEFAULT_API_BASE_URL.to_string(),
+        source: EndpointSource::Default,
+        locked: false,
+    }
+}
+
+/// resolve() с системным файлом политики и переменной окружения
+pub fn resolve_from_environment(db: &Database) -> ApiEndpoint {
+    resolve(
+        db,
+        policy_path().as_deref(),
+        std::env::var(ENV_API_URL).ok(),
+    )
+}
+
+/// Сохранить адрес из настроек (None — вернуть адрес по умолчанию). Применяется после перезапуска.
+pub fn save_user_url(db: &Database, url: Option<&str>) -> Result<Option<String>, String> {
+    let normalized = match url.map(str::trim).filter(|u| !u.is_empty()) {
+        Some(url) => Some(normalize_api_url(url)?),
+        None => None,
+    };
+    db.set_app_meta(META_API_URL, normalized.as_deref().unwrap_or(""))
+        .map_err(|e| format!("Failed to save API URL: {}", e))?;
+    if let Some(url) = &normalized {
+        info!("[API] API URL set to {} (applies after restart)", url);
+    }
+    Ok(normalized)
+}
@@ -0,0 +1,307 @@
+//! Shared factory for every reqwest client (sync, auth, connectivity probes, config/project fetches).
+//! Applies corporate network settings in one place: explicit HTTP/SOCKS proxy, system proxy env vars
+//! (HTTP_PROXY / HTTPS_PROXY / ALL_PROXY / NO_PROXY), bypass list and extra PEM root certificates.
+//! Settings live in app_meta (`http_client_settings`, JSON) and are applied when clients are built.
+
+use crate::Database;
+use serde::{Deserialize, Serialize};
+use std::error::Error as StdError;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+const META_HTTP_SETTINGS: &str = "http_client_settings";
+
+/// Сетевые настройки клиента (Settings → Network)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct HttpClientSettings {
+    /// Явный прокси для всех запросов: http://, https://, socks5://, socks5h://
+    /// (логин/пароль можно указать в URL или отдельными полями)
+    pub proxy_url: Option<String>,
+    pub proxy_username: Option<String>,
+    pub proxy_password: Option<String>,
+    /// Хосты без прокси, через запятую (формат NO_PROXY: `localhost,.corp.local,10.0.0.0/8`)
+    pub no_proxy: Option<String>,
+    /// Учитывать системные переменные HTTP_PROXY/HTTPS_PROXY/ALL_PROXY/NO_PROXY
+    pub use_system_proxy: bool,
+    /// Дополнительные корневые сертификаты (TLS-inspecting gateway): путь к PEM-файлу или PEM-текст
+    pub extra_ca_certs: Vec<String>,
+}
+
+impl Default for HttpClientSettings {
+    fn default() -> Self {
+        Self {
+            proxy_url: None,
+            proxy_username: None,
+            proxy_password: None,
+            no_proxy: None,
+            use_system_proxy: true,
+            extra_ca_certs: Vec::new(),
+        }
+    }
+}
+
+fn non_empty(value: &Option<String>) -> Option<&str> {
+    value.as_deref().map(str::trim).filter(|v| !v.is_empty())
+}
+
+impl HttpClientSettings {
+    /// Настройки из app_meta; отсутствуют или повреждены — значения по умолчанию
+    pub fn load(db: &Database) -> Self {
+        let mut settings: Self = match db.get_app_meta(META_HTTP_SETTINGS) {
+            Ok(Some(json)) if !json.is_empty() => serde_json::from_str(&json).unwrap_or_else(|e| {
+                warn!(
+                    "[HTTP] Stored network settings are invalid, using defaults: {}",
+                    e
+                );
+                Self::default()
+            }),
+            _ => Self::default(),
+        };
+        // Пароль прокси хранится зашифрованным (как токены)
+        if let Some(encrypted) = settings.proxy_password.take() {
+            match db.encryption.decrypt(&encrypted) {
+                Ok(password) => settings.proxy_password = Some(password),
+                Err(e) => warn!("[HTTP] Failed to decrypt proxy password: {}", e),
+            }
+        }
+        settings
+    }
+
+    /// В app_meta сохранён пароль прокси (зашифрован общим ключом)
+    pub(crate) fn has_stored_password(db: &Database) -> bool {
+        db.get_app_meta(META_HTTP_SETTINGS)
+            .ok()
+            .flatten()
+            .and_then(|json| serde_json::from_str::<Self>(&json).ok())
+            .map(|stored| non_empty(&stored.proxy_password).is_some())
+            .unwrap_or(false)
+    }
+
+    /// Перешифровать сохранённый пароль прокси активным ключом (ротация ключа); true — перешифрован
+    pub(crate) fn reencrypt_stored_password(db: &Database) -> Result<bool, String> {
+        let json = match db.get_app_meta(META_HTTP_SETTINGS) {
+            Ok(Some(json)) if !json.is_empty() => json,
+            Ok(_) => return Ok(false),
+            Err(e) => return Err(format!("Failed to read network settings: {}", e)),
+        };
+        let mut stored: Self = match serde_json::from_str(&json) {
+            Ok(stored) => stored,
+            Err(_) => return Ok(false),
+        };
+        let encrypted = match non_empty(&stored.proxy_password) {
+            Some(encrypted) if !db.encryption.is_current(encrypted) => encrypted.to_string(),
+            _ => return Ok(false),
+        };
+        let (password, _) = db.encryption.decrypt_with_legacy_fallback(&encrypted)?;
+        stored.proxy_password = Some(db.encryption.encrypt(&password)?);
+        let json = serde_json::to_string(&stored)
+            .map_err(|e| format!("Failed to serialize network settings: {}", e))?;
+        db.set_app_meta(META_HTTP_SETTINGS, &json)
+            .map_err(|e| format!("Failed to save network settings: {}", e))?;
+        Ok(true)
+    }
+
+    /// Проверить и сохранить (применяются к клиентам при следующем запуске)
+    pub fn save(&self, db: &Database) -> Result<(), String> {
+        self.validate()?;
+        let mut stored = self.clone();
+        if let Some(password) = non_empty(&self.proxy_password) {
+            stored.proxy_password = Some(db.encryption.encrypt(password)?);
+        } else {
+            stored.proxy_password = None;
+        }
+        let json = serde_json::to_string(&stored)
+            .map_err(|e| format!("Failed to serialize network settings: {}", e))?;
+        db.set_app_meta(META_HTTP_SETTINGS, &json)
+            .map_err(|e| format!("Failed to save network settings: {}", e))
+    }
+
+    /// Явный прокси: URL с поддерживаемой схемой
+    fn proxy(&self) -> Result<Option<reqwest::Proxy>, String> {
+        let url = match non_empty(&self.proxy_url) {
+            Some(url) => url,
+            None => return Ok(None),
+        };
+        let scheme = url.split("://").next().unwrap_or("").to_ascii_lowercase();
+        if !matches!(scheme.as_str(), "http" | "https" | "socks5" | "socks5h")
+            || !url.contains("://")
+        {
+            return Err(format!(
+                "Unsupported proxy URL '{}': expected http://, https://, socks5:// or socks5h://",
+                url
+            ));
+        }
+        let mut proxy =
+            reqwest::Proxy::all(url).map_err(|e| format!("Invalid proxy URL '{}': {}", url, e))?;
+        if let Some(username) = non_empty(&self.proxy_username) {
+            proxy = proxy.basic_auth(username, self.proxy_password.as_deref().unwrap_or(""));
+        }
+        // Свой список исключений; без него — NO_PROXY из окружения (если системный прокси разрешён)
+        let no_proxy = match non_empty(&self.no_proxy) {
+            Some(list) => reqwest::NoProxy::from_string(list),
+            None if self.use_system_proxy => reqwest::NoProxy::from_env(),
+            None => None,
+        };
+        Ok(Some(proxy.no_proxy(no_proxy)))
+    }
+
+    /// PEM-сертификаты из настроек (файл может содержать цепочку)
+    fn root_certificates(&self) -> Result<Vec<reqwest::Certificate>, String> {
+        let mut certs = Vec::new();
+        for entry in self
+            .extra_ca_certs
+            .iter()
+            .map(|e| e.trim())
+            .filter(|e| !e.is_empty())
+        {
+            let (pem, source) = if entry.starts_with("-----BEGIN") {
+                (entry.as_bytes().to_vec(), "inline PEM")
+            } else {
+                let pem = std::fs::read(entry)
+                    .map_err(|e| format!("Failed to read CA certificate '{}': {}", entry, e))?;
+                (pem, entry)
+            };
+            let bundle = reqwest::Certificate::from_pem_bundle(&pem)
+                .map_err(|e| format!("Invalid CA certificate ({}): {}", source, e))?;
+            if bundle.is_empty() {
+                return Err(format!("No certificates found in {}", source));
+            }
+            certs.extend(bundle);
+        }
+        Ok(certs)
+    }
+
+    /// Проверка без построения клиента (команда сохранения настроек)
+    pub fn validate(&self) -> Result<(), String> {
+        self.proxy()?;
+        self.root_certificates()?;
+        Ok(())
+    }
+
+    /// Явный прокси задан (ошибки соединения — скорее всего проблема прокси)
+    pub fn has_explicit_proxy(&self) -> bool {
+        non_empty(&self.proxy_url).is_some()
+    }
+}
+
+/// Единая точка создания reqwest::Client. Дёшево клонируется.
+#[derive(Clone, Default)]
+pub struct HttpClientFactory {
+    settings: Arc<HttpClientSettings>,
+}
+
+impl HttpClientFactory {
+    pub fn new(settings: HttpClientSettings) -> Self {
+        Self {
+            settings: Arc::new(settings),
+        }
+    }
+
+    pub fn settings(&self) -> &HttpClientSettings {
+        &self.settings
+    }
+
+    /// Builder с прокси и сертификатами; вызывающий добавляет таймауты и пр.
+    pub fn builder(&self) -> Result<reqwest::ClientBuilder, String> {
+        let mut builder = reqwest::Client::builder();
+        if !self.settings.use_system_proxy {
+            // Отключает чтение HTTP_PROXY/HTTPS_PROXY/ALL_PROXY; явный прокси ниже всё равно применяется
+            builder = builder.no_proxy();
+        }
+        if let Some(proxy) = self.settings.proxy()? {
+            builder = builder.proxy(proxy);
+        }
+        for cert in self.settings.root_certificates()? {
+            builder = builder.add_root_certificate(cert);
+        }
+        Ok(builder)
+    }
+
+    /// Клиент с таймаутом. Ошибка настроек логируется, клиент строится без них —
+    /// запросы тогда падают с понятной ошибкой вместо паники при старте.
+    pub fn build(&self, timeout: Duration) -> reqwest::Client {
+        let configured = self
+            .builder()
+            .and_then(|b| b.timeout(timeout).build().map_err(|e| e.to_string()));
+        match configured {
+            Ok(client) => client,
+            Err(e) => {
+                error!("[HTTP] Network settings not applied: {}", e);
+                reqwest::Client::builder()
+                    .timeout(timeout)
+                    .build()
+                    .unwrap_or_else(|_| reqwest::Client::new())
+            }
+        }
+    }
+
+    /// Класс ошибки транспорта с учётом настроек этого factory
+    pub fn classify(&self, error: &reqwest::Error) -> TransportErrorKind {
+        classify_error(error, self.settings.has_explicit_proxy())
+    }
+
+    /// Краткое описание для лога при старте (без учётных данных)
+    pub fn log_summary(&self) {
+        let s = &self.settings;
+        if s.has_explicit_proxy() || !s.extra_ca_certs.is_empty() || !s.use_system_proxy {
+            info!(
+                "[HTTP] Network settings: explicit_proxy={}, system_proxy={}, extra_ca_certs={}",
+                s.has_explicit_proxy(),
+                s.use_system_proxy,
+                s.extra_ca_certs.len()
+            );
+        }
+    }
+}
+
+/// Класс сетевой ошибки для SyncError
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransportErrorKind {
+    Proxy,
+    Tls,
+    Network,
+}
+
+/// Разбор цепочки source() ошибки reqwest: прокси (CONNECT-туннель, 407, SOCKS) или TLS (сертификат).
+/// При явном прокси любая ошибка соединения — это соединение с прокси (хосты из no_proxy не различаем).
+pub fn classify_error(error: &reqwest::Error, explicit_proxy: bool) -> TransportErrorKind {
+    let mut chain = Vec::new();
+    let mut source: Option<&(dyn StdError + 'static)> = Some(error);
+    while let Some(err) = source {
+        chain.push(err.to_string().to_ascii_lowercase());
+        source = err.source();
+    }
+    let text = chain.join(": ");
+    const PROXY_MARKERS: [&str; 4] = ["proxy", "tunnel", "socks", "407"];
+    const TLS_MARKERS: [&str; 7] = [
+        "certificate",
+        "tls",
+        "ssl",
+        "handshake",
+        "unknown issuer",
+        "unknownissuer",
+        "self signed",
+    ];
+    if PROXY_MARKERS.iter().any(|m| text.contains(m)) {
+        TransportErrorKind::Proxy
+    } else if TLS_MARKERS.iter().any(|m| text.contains(m)) {
+        TransportErrorKind::Tls
+    } else if explicit_proxy && error.is_connect() {
+        TransportErrorKind::Proxy
+    } else {
+        TransportErrorKind::Network
+    }
+}
+
+/// Полный текст ошибки с причинами (reqwest Display скрывает source)
+pub fn error_chain(error: &reqwest::Error) -> String {
+    let mut parts = vec![error.to_string()];
+    let mut source = error.source();
+    while let Some(err) = source {
+        parts.push(err.to_string());
+        source = err.source();
+    }
+    parts.join(": ")
+}
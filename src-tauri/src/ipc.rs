@@ -12,8 +12,26 @@ pub mod events {
     pub const REQUEST_IDLE_STATE: &str = "request-idle-state-for-idle-window";
     /// Emitted when sleep detected and timer auto-paused. Frontend can suppress activity for 30s.
     pub const SYSTEM_SLEEP_DETECTED: &str = "system-sleep-detected";
+    /// Sync batch started: { total }
+    pub const SYNC_STARTED: &str = "sync-started";
+    /// Task synced: { task_id, entity_type, index, total }
+    pub const SYNC_TASK_DONE: &str = "sync-task-done";
+    /// Task failed: { task_id, entity_type, index, total, error_class, error, will_retry }
+    pub const SYNC_TASK_FAILED: &str = "sync-task-failed";
+    /// Sync batch finished: { total, synced, failed, synced_by_type, failed_by_type, duration_ms }
+    pub const SYNC_FINISHED: &str = "sync-finished";
+    /// API connectivity changed: { state: "online" | "degraded" | "offline", previous, latency_ms }
+    pub const CONNECTIVITY_CHANGED: &str = "connectivity-changed";
+    /// Rust refreshed the access token: { access_token, refresh_token, expires_at }
+    pub const TOKENS_REFRESHED: &str = "auth-tokens-refreshed";
+    /// Logout finished: { revoked, queue_policy, flushed_tasks, remaining_tasks, keys_purged }
+    pub const LOGGED_OUT: &str = "auth-logged-out";
 }
 
+/// Emit callback for modules without an AppHandle (SyncManager is created in tests without Tauri).
+/// lib.rs wires it to `AppHandle::emit`.
+pub type EventSink = std::sync::Arc<dyn Fn(&str, serde_json::Value) + Send + Sync>;
+
 /// Tauri command names (Frontend invoke → Rust handler)
 /// Kept for API contract; frontend uses src/lib/ipc.ts. Rust handlers use fn names.
 #[allow(dead_code)]
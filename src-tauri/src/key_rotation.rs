@@ -0,0 +1,180 @@
+//! Rotation of the encryption key shared by all databases in the app data dir (profiles that are not
+//! loaded are opened for it, see `ProfileManager::all_databases`).
+//! The new key becomes active first while the previous keys stay readable (ciphertext carries
+//! the key id, see `TokenEncryption`). Then every queue payload, encrypted `app_meta` value and
+//! blob is re-encrypted — `ROTATION_BATCH` rows per writer transaction, so sync and the UI keep
+//! running — SQLCipher files are re-keyed and their backups replaced. Only after all of that
+//! succeeds are the previous keys removed from the key file and keyring: an interrupted rotation
+//! leaves everything readable and can simply be run again.
+
+use crate::auth::TokenEncryption;
+use crate::database::payload_aad;
+use crate::models::KeyRotationReport;
+use crate::Database;
+use rusqlite::params;
+use std::sync::{Arc, Mutex};
+use tracing::{info, warn};
+
+/// Строк за одну транзакцию писателя
+const ROTATION_BATCH: i64 = 200;
+
+/// Одна ротация за раз (команда может прийти повторно, пока идёт первая)
+static ROTATION: Mutex<()> = Mutex::new(());
+
+/// ((id, entity_type, idempotency_key), payload)
+type PayloadRow = ((i64, String, Option<String>), String);
+
+/// (ключ строки, прежний шифротекст, новый) для значений не активным ключом; второе — не расшифровались.
+/// `aad` — associated data строки (payload очереди): такие значения заодно привязываются к строке.
+fn reencrypt_values<K>(
+    encryption: &TokenEncryption,
+    rows: Vec<(K, String)>,
+    aad: impl Fn(&K) -> Option<Vec<u8>>,
+) -> (Vec<(K, String, String)>, usize) {
+    let mut updates = Vec::new();
+    let mut failed = 0;
+    for (key, old) in rows {
+        let aad = aad(&key);
+        let bound = encryption.is_bound(&old);
+        if encryption.is_current(&old) && (aad.is_none() || bound) {
+            continue;
+        }
+        let result = match &aad {
+            Some(aad) if bound => encryption.decrypt_with_aad(&old, aad),
+            _ => encryption.decrypt_with_legacy_fallback(&old),
+        };
+        match result.and_then(|(plaintext, _)| match &aad {
+            Some(aad) => encryption.encrypt_with_aad(&plaintext, aad),
+            None => encryption.encrypt(&plaintext),
+        }) {
+            Ok(new) => updates.push((key, old, new)),
+            Err(_) => failed += 1,
+        }
+    }
+    (updates, failed)
+}
+
+fn reencrypt_database(db: &Database, report: &mut KeyRotationReport) -> Result<(), String> {
+    let err =
+        |e: rusqlite::Error| format!("Key rotation failed for {}: {}", db.path().display(), e);
+
+    // Payload очереди: чанками по id, каждый чанк — одна транзакция писателя
+    let mut after = 0;
+    loop {
+        let rows: Vec<PayloadRow> = db
+            .read(|conn| {
+                let mut stmt = conn.prepare(
+                    "SELECT id, entity_type, idempotency_key, payload FROM sync_queue
+                     WHERE id > ?1 AND payload != '' ORDER BY id LIMIT ?2",
+                )?;
+                let rows = stmt.query_map(params![after, ROTATION_BATCH], |row| {
+                    Ok(((row.get(0)?, row.get(1)?, row.get(2)?), row.get(3)?))
+                })?;
+                rows.collect()
+            })
+            .map_err(err)?;
+        let last = match rows.last() {
+            Some(((id, _, _), _)) => *id,
+            None => break,
+        };
+        let (updates, failed) = reencrypt_values(&db.encryption, rows, |(_, entity_type, key)| {
+            Some(payload_aad(entity_type, key.as_deref()))
+        });
+        report.undecryptable += failed;
+        report.payloads_reencrypted += db
+            .write(move |conn| {
+                let tx = conn.savepoint()?;
+                let mut updated = 0;
+                for ((id, _, _), old, new) in &updates {
+                    // Строку изменили после чтения — новое значение уже зашифровано активным ключом
+                    updated += tx.execute(
+                        "UPDATE sync_queue SET payload = ?1 WHERE id = ?2 AND payload = ?3",
+                        params![new, id, old],
+                    )?;
+                }
+                tx.commit()?;
+                Ok(updated)
+            })
+            .map_err(err)?;
+        after = last;
+    }
+
+    // app_meta: секретом считается значение, которое расшифровывается известным ключом
+    let rows: Vec<(String, String)> = db
+        .read(|conn| {
+            let mut stmt = conn.prepare("SELECT key, value FROM app_meta WHERE value != ''")?;
+            let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+            rows.collect()
+        })
+        .map_err(err)?;
+    let (updates, _) = reencrypt_values(&db.encryption, rows, |_| None);
+    report.secrets_reencrypted += db
+        .write(move |conn| {
+            let tx = conn.savepoint()?;
+            let mut updated = 0;
+            for (key, old, new) in &updates {
+                updated += tx.execute(
+                    "UPDATE app_meta SET value = ?1 WHERE key = ?2 AND value = ?3",
+                    params![new, key, old],
+                )?;
+            }
+            tx.commit()?;
+            Ok(updated)
+        })
+        .map_err(err)?;
+    // Пароль прокси — внутри JSON сетевых настроек
+    match crate::http_client::HttpClientSettings::reencrypt_stored_password(db) {
+        Ok(true) => report.secrets_reencrypted += 1,
+        Ok(false) => {}
+        Err(e) => {
+            warn!("[AUTH] Key rotation: proxy password: {}", e);
+            report.undecryptable += 1;
+        }
+    }
+
+    let (blobs, failed) = db.blobs.reencrypt_all();
+    report.blobs_reencrypted += blobs;
+    report.undecryptable += failed;
+
+    if db.rekey_database().map_err(err)? {
+        report.databases_rekeyed += 1;
+    }
+    // Прежние копии зашифрованы прежним ключом — после его удаления их не восстановить
+    let backup = db.run_backup().map_err(err)?;
+    crate::backup::remove_backups_except(db.path(), &backup.file_name);
+    Ok(())
+}
+
+/// Ротация ключа для всех БД (они должны быть в одном каталоге — общий файл ключа)
+pub fn rotate_encryption_key(dbs: &[Arc<Database>]) -> Result<KeyRotationReport, String> {
+    let _guard = ROTATION.lock().unwrap_or_else(|e| e.into_inner());
+    let (first, others) = dbs
+        .split_first()
+        .ok_or_else(|| "No database to rotate the key for".to_string())?;
+
+    let key_id = first.encryption.rotate_key()?;
+    for db in others {
+        db.encryption.reload_keys()?;
+    }
+    let mut report = KeyRotationReport {
+        key_id: format!("{:08x}", key_id),
+        ..Default::default()
+    };
+    for db in dbs {
+        reencrypt_database(db, &mut report)?;
+    }
+
+    report.retired_keys = first.encryption.retire_previous_keys()?;
+    for db in others {
+        db.encryption.reload_keys()?;
+    }
+    info!(
+        "[AUTH] Key rotation done: {} payloads, {} secrets, {} blobs re-encrypted, {} undecryptable, {} keys retired",
+        report.payloads_reencrypted,
+        report.secrets_reencrypted,
+        report.blobs_reencrypted,
+        report.undecryptable,
+        report.retired_keys
+    );
+    Ok(report)
+}
@@ -5,26 +5,39 @@ use std::sync::OnceLock;
 use tauri::{AppHandle, Emitter, Listener, Manager, RunEvent};
 use tracing::{debug, error, info, warn};
 mod auth;
+mod backup;
+mod blob_store;
+mod client_config;
+mod clock;
 mod commands;
+mod connectivity;
 mod database;
+mod db_pool;
+mod endpoint;
+mod http_client;
 mod ipc;
+mod key_rotation;
 mod engine;
+mod migrations;
 mod models;
 mod monitor;
 mod network;
+mod oauth;
+mod profiles;
+mod project_cache;
+mod salvage;
 mod sync;
 use crate::engine::TimerEngine;
 use crate::monitor::ActivityMonitor;
-use crate::sync::SyncManager;
+use crate::profiles::ProfileManager;
 pub use crate::sync::TaskPriority;
 use commands::*;
 pub use database::Database;
-pub use network::check_online_status;
 pub use network::{extract_domain, extract_url_from_title};
 use std::sync::Arc;
 
 /// Panic recovery: persist TimerState when a non-fatal panic occurs.
-static PANIC_ENGINE: OnceLock<Arc<TimerEngine>> = OnceLock::new();
+static PANIC_PROFILES: OnceLock<Arc<ProfileManager>> = OnceLock::new();
 
 #[cfg(test)]
 mod tests;
@@ -34,6 +47,8 @@ struct SyncStatusResponse {
     pending_count: i32,
     failed_count: i32,
     is_online: bool,
+    /// online | degraded | offline (ConnectivityMonitor)
+    connectivity: crate::connectivity::ConnectivityState,
     #[serde(skip_serializing_if = "Option::is_none")]
     last_sync_at: Option<i64>,
 }
@@ -61,8 +76,8 @@ pub fn run() {
     // Panic recovery: attempt to persist TimerState before panic unwinds
     let default_hook = panic::take_hook();
     panic::set_hook(Box::new(move |info| {
-        if let Some(engine) = PANIC_ENGINE.get() {
-            if let Err(e) = engine.save_state() {
+        if let Some(profiles) = PANIC_PROFILES.get() {
+            if let Err(e) = profiles.save_all() {
                 eprintln!("[PANIC_RECOVERY] Failed to persist timer state: {}", e);
             } else {
                 eprintln!("[PANIC_RECOVERY] Timer state persisted before panic");
@@ -147,65 +162,102 @@ pub fn run() {
                 )
             })?;
 
-            // Auto-recovery from corrupted DB: on integrity/corruption failure, backup and retry once
+            // Auto-recovery from corrupted DB: on integrity/corruption failure, move the file aside,
+            // restore the newest backup that passes integrity_check (or start empty) and salvage
+            // unsent queue rows / time entries from the readable pages of the moved file
             let db = match Database::new(db_path_str) {
                 Ok(d) => Arc::new(d),
                 Err(e) => {
-                    let err_str = e.to_string();
-                    let is_corruption =
-                        err_str.contains("corruption") || err_str.contains("integrity");
-                    if !is_corruption || !db_path.exists() {
+                    if !crate::salvage::is_corruption_error(&e.to_string()) || !db_path.exists() {
                         return Err(Box::new(std::io::Error::new(
                             std::io::ErrorKind::Other,
                             format!("Failed to initialize database: {}", e),
                         )));
                     }
-                    let backup_path = app_data_dir.join(format!(
-                        "hubnity.db.corrupted.{}",
-                        chrono::Utc::now().timestamp()
-                    ));
-                    if let Err(rename_e) = std::fs::rename(&db_path, &backup_path) {
-                        warn!(
-                            "[DB] Failed to rename corrupted DB to {:?}: {}",
-                            backup_path, rename_e
-                        );
-                        return Err(Box::new(std::io::Error::new(
-                            std::io::ErrorKind::Other,
-                            format!("Database corrupted and could not backup: {}", e),
-                        )));
-                    }
-                    info!(
-                        "[DB] Corrupted DB backed up to {:?}, starting fresh",
-                        backup_path
-                    );
-                    let _ = app.handle().emit(crate::ipc::events::DB_RECOVERED, ());
-                    Arc::new(Database::new(db_path_str).map_err(|e2| {
-                        std::io::Error::new(
-                            std::io::ErrorKind::Other,
-                            format!("Failed to create fresh database: {}", e2),
-                        )
-                    })?)
+                    let (db, event) = crate::salvage::recover_corrupted(&db_path, None)
+                        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+                    let _ = app.handle().emit(crate::ipc::events::DB_RECOVERED, event);
+                    Arc::new(db)
                 }
             };
 
-            // Инициализируем TimerEngine с БД
-            let engine = TimerEngine::with_db(db.clone());
-            let engine_arc = Arc::new(engine);
+            // Адрес API: policy > HUBNITY_API_URL > настройка > default. При смене сервера его очередь
+            // откладывается (parked), кэши другого сервера очищаются — до load_cached и запуска sync.
+            let api_endpoint = crate::endpoint::resolve_from_environment(&db);
+            match db.switch_api_origin(&api_endpoint.url) {
+                Ok(switch) if switch.changed => warn!(
+                    "[API] API endpoint changed {:?} -> {} ({:?}): parked {} tasks, restored {}",
+                    switch.previous, api_endpoint.url, api_endpoint.source, switch.parked, switch.restored
+                ),
+                Ok(_) => info!("[API] Using API {} ({:?})", api_endpoint.url, api_endpoint.source),
+                Err(e) => error!("[API] Failed to record API endpoint: {}", e),
+            }
+
+            // Server-driven client config: сначала кэш из app_meta (работает offline), обновление — в sync loop
+            client_config::global().load_cached(&db);
+
+            // Инициализируем SyncManager (с app_version для X-App-Version header)
+            // sync_request_compression в app_meta: "off" | "gzip" | "auto" (по умолчанию auto)
+            let request_compression = db
+                .get_app_meta("sync_request_compression")
+                .ok()
+                .flatten()
+                .and_then(|v| crate::sync::RequestCompression::parse(&v))
+                .unwrap_or(crate::sync::RequestCompression::Auto);
+            // connectivity_health_url в app_meta — для корпоративных прокси/зеркал (по умолчанию {api}/health)
+            let health_check_url = db
+                .get_app_meta("connectivity_health_url")
+                .ok()
+                .flatten()
+                .filter(|v| !v.trim().is_empty());
+            // Прокси / дополнительные CA (Settings → Network) — один factory для всех HTTP-клиентов
+            let http = crate::http_client::HttpClientFactory::new(
+                crate::http_client::HttpClientSettings::load(&db),
+            );
+            http.log_summary();
+            let sync_config = crate::sync::SyncConfig {
+                api_base_url: api_endpoint.url.clone(),
+                app_version: app.package_info().version.to_string(),
+                request_compression,
+                health_check_url,
+                http,
+                ..Default::default()
+            };
+            // Профили: у каждого пользователя своя hubnity-<user>.db (таймер, очередь, кэши);
+            // hubnity.db — настройки приложения и current_user_id. Профили с диска загружаются сразу,
+            // чтобы их очереди синхронизировались и без повторного входа.
+            let profiles = Arc::new(ProfileManager::new(app_data_dir.clone(), db.clone(), sync_config));
+            let opened = profiles.open_existing();
+            let current_user_id = db
+                .get_app_meta("current_user_id")
+                .ok()
+                .flatten()
+                .unwrap_or_default();
+            if !current_user_id.is_empty() {
+                if let Err(e) = tauri::async_runtime::block_on(profiles.activate(&current_user_id)) {
+                    error!("[PROFILE] Failed to open profile of current user: {}", e);
+                }
+            }
+            info!(
+                "[PROFILE] {} profiles loaded, active {:?}",
+                opened,
+                profiles.active_user_id()
+            );
 
-            // Panic recovery: register engine for persist-on-panic
-            let _ = PANIC_ENGINE.set(engine_arc.clone());
+            // Panic recovery: register profiles for persist-on-panic
+            let _ = PANIC_PROFILES.set(profiles.clone());
 
             // Настраиваем обработчики sleep/wake (не сохраняет ссылку на engine)
-            setup_sleep_wake_handlers(app.handle().clone(), engine_arc.clone())?;
+            setup_sleep_wake_handlers(app.handle().clone(), profiles.engine())?;
 
             // CRITICAL FIX: Сохраняем состояние таймера при закрытии окна
             // Используем Tauri window close event для гарантированного сохранения
-            let engine_for_close = engine_arc.clone();
+            let profiles_for_close = profiles.clone();
             let app_handle = app.handle().clone();
             app_handle.listen("tauri://close-requested", move |_event| {
                 // ДОКАЗАНО: Это событие вызывается синхронно перед закрытием окна
                 // Сохраняем состояние таймера синхронно
-                if let Err(e) = engine_for_close.save_state() {
+                if let Err(e) = profiles_for_close.save_all() {
                     error!("[SHUTDOWN] Failed to save timer state on window close: {}", e);
                 } else {
                     info!("[SHUTDOWN] Timer state saved successfully on window close");
@@ -214,7 +266,7 @@ pub fn run() {
 
             // CRITICAL FIX: Периодическое сохранение состояния (каждые 30 секунд)
             // ДОКАЗАНО: Это гарантирует, что состояние сохранено даже при force quit
-            let engine_for_periodic = engine_arc.clone();
+            let profiles_for_periodic = profiles.clone();
             std::thread::spawn(move || {
                 // BUG FIX: Graceful degradation вместо process::exit(1)
                 // Если не удается создать runtime, логируем ошибку и выходим из потока
@@ -238,7 +290,7 @@ pub fn run() {
                     loop {
                         interval.tick().await;
                         // ДОКАЗАНО: Периодическое сохранение гарантирует актуальность состояния в БД
-                        if let Err(e) = engine_for_periodic.save_state() {
+                        if let Err(e) = profiles_for_periodic.save_all() {
                             warn!("[TIMER] Failed to save state periodically: {}", e);
                         } else {
                             debug!("[TIMER] State saved periodically");
@@ -248,7 +300,7 @@ pub fn run() {
             });
 
             // FIX: Один поток emit таймера (не создаётся при Active<->Idle). 1s + Skip при лагах.
-            let engine_for_emit = engine_arc.clone();
+            let profiles_for_emit = profiles.clone();
             let app_handle_for_emit = app.handle().clone();
             std::thread::spawn(move || {
                 let rt = match tokio::runtime::Runtime::new() {
@@ -278,7 +330,8 @@ pub fn run() {
                     interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
                     loop {
                         interval.tick().await;
-                        if let Ok(state) = engine_for_emit.get_state() {
+                        // Активный профиль — на каждом тике (меняется при входе другого пользователя)
+                        if let Ok(state) = profiles_for_emit.engine().get_state() {
                             let should_emit = matches!(
                                 state.state,
                                 TimerStateForAPI::Running { .. } | TimerStateForAPI::Paused
@@ -295,20 +348,35 @@ pub fn run() {
                 });
             });
 
-            // Управляем engine через Tauri State
-            // CRITICAL FIX: Используем Arc напрямую, так как он используется в других местах
-            // ДОКАЗАНО: Tauri State может работать с Arc<TimerEngine>, так как Arc: Send + Sync
-            app.manage(engine_arc);
+            // Команды берут TimerEngine / SyncManager активного профиля через Tauri State
+            app.manage(profiles.clone());
 
-            // Инициализируем SyncManager (с app_version для X-App-Version header)
-            let sync_config = crate::sync::SyncConfig {
-                app_version: app.package_info().version.to_string(),
-                ..Default::default()
-            };
-            let sync_manager = SyncManager::new_with_config(db.clone(), sync_config);
-            // CRITICAL FIX: Сохраняем ссылку на sync_manager ДО manage(), чтобы фоновая задача использовала тот же экземпляр
-            let sync_manager_bg = sync_manager.clone();
-            app.manage(sync_manager);
+            // Прогресс синхронизации → frontend (sync-started / sync-task-* / sync-finished)
+            let app_handle_for_sync = app.handle().clone();
+            profiles.set_event_sink(Arc::new(move |event: &str, payload: serde_json::Value| {
+                let _ = app_handle_for_sync.emit(event, payload);
+            }));
+            // Фоновая проба API health: переходы online/degraded/offline → connectivity-changed
+            let profiles_for_probe = profiles.clone();
+            std::thread::spawn(move || {
+                let rt = match tokio::runtime::Runtime::new() {
+                    Ok(rt) => rt,
+                    Err(e) => {
+                        error!("[NET] Failed to create runtime for connectivity probe: {}", e);
+                        return;
+                    }
+                };
+                rt.block_on(async {
+                    loop {
+                        let connectivity_bg = profiles_for_probe.sync_manager().connectivity.clone();
+                        connectivity_bg.probe().await;
+                        tokio::time::sleep(connectivity_bg.probe_interval()).await;
+                    }
+                });
+            });
+            // CRITICAL FIX: фоновая задача использует те же профили, что и команды
+            let profiles_bg = profiles.clone();
+            app.manage(api_endpoint);
 
             // CRITICAL FIX: Background sync с restart mechanism
             // ДОКАЗАНО: Thread автоматически перезапускается при панике или ошибке
@@ -338,28 +406,98 @@ pub fn run() {
                         tokio::time::sleep(tokio::time::Duration::from_millis(total_ms)).await;
 
                         info!("[SYNC] Starting background sync task");
-                        let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(60)); // Каждую минуту
+                        let mut last_remote_refresh: Option<std::time::Instant> = None;
                         loop {
-                            interval.tick().await;
+                            // Client config (If-None-Match) и кэш проектов — раз в REFRESH_INTERVAL; offline → остаётся кэш
+                            let remote_refresh_due = !matches!(
+                                last_remote_refresh,
+                                Some(t) if t.elapsed() < client_config::REFRESH_INTERVAL
+                            );
+                            let sync_manager_bg = profiles_bg.sync_manager();
+                            if remote_refresh_due {
+                                if let Ok(token) = sync_manager_bg.auth_manager.get_fresh_token().await {
+                                    last_remote_refresh = Some(std::time::Instant::now());
+                                    if let Err(e) = client_config::global()
+                                        .refresh(
+                                            profiles_bg.app_db(),
+                                            &sync_manager_bg.client,
+                                            &sync_manager_bg.api_base_url,
+                                            &token,
+                                        )
+                                        .await
+                                    {
+                                        warn!("[CONFIG] Client config refresh failed, using cached: {}", e);
+                                    }
+                                    if let Err(e) = project_cache::refresh(
+                                        &sync_manager_bg.db,
+                                        &sync_manager_bg.client,
+                                        &sync_manager_bg.api_base_url,
+                                        &token,
+                                        false,
+                                    )
+                                    .await
+                                    {
+                                        warn!("[PROJECTS] Project cache refresh failed, using cached: {}", e);
+                                    }
+                                }
+                            }
+
                             info!("[SYNC] Background sync tick, attempting sync...");
-                            match sync_manager_bg.sync_queue(5).await {
-                                Ok(count) => {
-                                    if count > 0 {
-                                        info!("[SYNC] Background sync: synced {} tasks", count);
-                                    } else {
-                                        debug!("[SYNC] Background sync: no tasks to sync");
+                            // Очереди всех профилей, каждая — токенами своего пользователя;
+                            // неактивные профили без задач не трогаем
+                            let active_user_id = profiles_bg.active_user_id();
+                            for profile in profiles_bg.profiles() {
+                                let is_active = profile.user_id == active_user_id;
+                                if !is_active
+                                    && profile.db.get_pending_count().unwrap_or(0) == 0
+                                    && !profile.db.maintenance_due()
+                                    && !profile.db.backup_due()
+                                {
+                                    continue;
+                                }
+                                match profile.sync_manager.sync_queue(5).await {
+                                    Ok(count) => {
+                                        if count > 0 {
+                                            info!("[SYNC] Background sync: synced {} tasks", count);
+                                        } else {
+                                            debug!("[SYNC] Background sync: no tasks to sync");
+                                        }
+                                    }
+                                    Err(e) => {
+                                        // Не логируем как error, если это просто отсутствие токенов
+                                        if e.contains("access token not set") {
+                                            warn!("[SYNC] Background sync skipped: {}", e);
+                                        } else {
+                                            error!("[SYNC] Background sync error: {}", e);
+                                            // ДОКАЗАНО: Ошибка не останавливает loop, sync продолжается
+                                        }
+                                    }
+                                }
+                                // Retention sent-задач + incremental vacuum (не чаще раза в 6 часов)
+                                if profile.sync_manager.db.maintenance_due() {
+                                    let db = profile.sync_manager.db.clone();
+                                    match tokio::task::spawn_blocking(move || db.run_maintenance()).await {
+                                        Ok(Ok(report)) => {
+                                            if report.reclaimed_bytes > 0 {
+                                                info!("[DB] Scheduled maintenance reclaimed {} bytes", report.reclaimed_bytes);
+                                            }
+                                        }
+                                        Ok(Err(e)) => warn!("[DB] Scheduled maintenance failed: {}", e),
+                                        Err(e) => warn!("[DB] Scheduled maintenance panicked: {}", e),
                                     }
                                 }
-                                Err(e) => {
-                                    // Не логируем как error, если это просто отсутствие токенов
-                                    if e.contains("access token not set") {
-                                        warn!("[SYNC] Background sync skipped: {}", e);
-                                    } else {
-                                        error!("[SYNC] Background sync error: {}", e);
-                                        // ДОКАЗАНО: Ошибка не останавливает loop, sync продолжается
+                                // Онлайн-копия БД, последние BACKUP_KEEP (не чаще раза в 6 часов)
+                                if profile.db.backup_due() {
+                                    let db = profile.db.clone();
+                                    match tokio::task::spawn_blocking(move || db.run_backup()).await {
+                                        Ok(Ok(backup)) => info!("[BACKUP] Created {} ({} bytes)", backup.file_name, backup.size_bytes),
+                                        Ok(Err(e)) => warn!("[BACKUP] Scheduled backup failed: {}", e),
+                                        Err(e) => warn!("[BACKUP] Scheduled backup panicked: {}", e),
                                     }
                                 }
                             }
+                            // Интервал из client config (по умолчанию каждую минуту)
+                            tokio::time::sleep(client_config::current().sync_interval()).await;
                         }
                     });
                     
@@ -380,9 +518,11 @@ pub fn run() {
             // Sync commands
             set_auth_tokens,
             get_current_user_id,
+            logout,
             sync_queue_now,
             get_sync_status,
             get_sync_queue_stats,
+            get_sync_metrics,
             clear_sync_queue,
             mark_task_sent_by_id,
             get_failed_tasks,
@@ -391,6 +531,25 @@ pub fn run() {
             get_last_time_entry_id,
             get_sleep_gap_threshold_minutes,
             set_sleep_gap_threshold_minutes,
+            list_cached_projects,
+            search_cached_projects,
+            list_cached_tasks,
+            refresh_project_cache,
+            run_db_maintenance,
+            list_backups,
+            restore_backup,
+            rotate_encryption_key,
+            get_sync_retention_days,
+            set_sync_retention_days,
+            get_network_settings,
+            set_network_settings,
+            get_api_endpoint,
+            set_api_endpoint,
+            get_oauth_settings,
+            set_oauth_settings,
+            login_with_sso,
+            start_device_login,
+            complete_device_login,
             // Timer Engine commands
             start_timer,
             pause_timer,
@@ -429,8 +588,8 @@ pub fn run() {
         .run(|app_handle, event| {
             // CHAOS AUDIT FIX: Graceful shutdown — persist timer state on exit
             if let RunEvent::ExitRequested { .. } = event {
-                if let Some(engine) = app_handle.try_state::<Arc<TimerEngine>>() {
-                    if let Err(e) = engine.save_state() {
+                if let Some(profiles) = app_handle.try_state::<Arc<ProfileManager>>() {
+                    if let Err(e) = profiles.save_all() {
                         error!("[SHUTDOWN] Failed to save timer state on exit: {}", e);
                     } else {
                         info!("[SHUTDOWN] Timer state saved successfully on exit");
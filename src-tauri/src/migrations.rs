@@ -0,0 +1,526 @@
+//! Schema migrations.
+//! Упорядоченный список шагов; каждый шаг выполняется в своей транзакции и проверяется
+//! post-condition'ом (ожидаемые колонки и индексы) до commit. Применённые шаги записываются в
+//! `schema_migrations`, `PRAGMA user_version` — номер последнего шага. БД новее приложения
+//! не открывается: старый код не должен писать в схему, которую не знает.
+
+use crate::auth::TokenEncryption;
+use crate::database::{content_fingerprint, new_idempotency_key, payload_aad};
+use chrono::Utc;
+use rusqlite::Error::InvalidParameterName;
+use rusqlite::{params, Connection, OptionalExtension, Result as SqliteResult};
+use std::collections::HashSet;
+use tracing::{info, warn};
+
+/// Текущая версия схемы (PRAGMA user_version) — номер последнего шага в MIGRATIONS
+pub const SCHEMA_VERSION: i32 = 13;
+
+/// Шаг миграции
+pub(crate) struct Migration {
+    pub version: i32,
+    pub name: &'static str,
+    /// Идемпотентен: повторный запуск (ремонт после сбоя) не должен падать
+    apply: fn(&Connection, &TokenEncryption) -> SqliteResult<()>,
+    /// Post-condition: колонки, которые должны существовать после шага
+    columns: &'static [(&'static str, &'static [&'static str])],
+    /// Post-condition: индексы
+    indexes: &'static [&'static str],
+    /// Вне транзакции после commit (VACUUM нельзя выполнить в транзакции); ошибка — только warn
+    after_commit: Option<fn(&Connection) -> SqliteResult<()>>,
+}
+
+pub(crate) const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "initial_schema",
+        apply: create_initial_schema,
+        columns: &[
+            (
+                "time_entries",
+                &[
+                    "id",
+                    "day",
+                    "accumulated_seconds",
+                    "state",
+                    "last_updated_at",
+                ],
+            ),
+            ("sync_queue", &["id", "entity_type", "payload", "status"]),
+            ("app_meta", &["key", "value"]),
+        ],
+        indexes: &["idx_sync_queue_status", "idx_time_entries_day"],
+        after_commit: None,
+    },
+    Migration {
+        version: 2,
+        name: "sync_queue_error_message",
+        apply: |conn, _| add_column(conn, "sync_queue", "error_message", "TEXT"),
+        columns: &[("sync_queue", &["error_message"])],
+        indexes: &[],
+        after_commit: None,
+    },
+    Migration {
+        version: 3,
+        name: "sync_queue_priority",
+        apply: |conn, _| add_column(conn, "sync_queue", "priority", "INTEGER DEFAULT 2"),
+        columns: &[("sync_queue", &["priority"])],
+        indexes: &[],
+        after_commit: None,
+    },
+    Migration {
+        version: 4,
+        name: "time_entries_started_at",
+        apply: |conn, _| add_column(conn, "time_entries", "started_at", "INTEGER"),
+        columns: &[("time_entries", &["started_at"])],
+        indexes: &[],
+        after_commit: None,
+    },
+    Migration {
+        version: 5,
+        name: "sync_queue_idempotency_key",
+        apply: |conn, _| add_column(conn, "sync_queue", "idempotency_key", "TEXT"),
+        columns: &[("sync_queue", &["idempotency_key"])],
+        indexes: &[],
+        after_commit: None,
+    },
+    // Скриншоты вынесены из payload в BlobStore
+    Migration {
+        version: 6,
+        name: "sync_queue_blob_ref",
+        apply: |conn, _| {
+            add_column(conn, "sync_queue", "blob_ref", "TEXT")?;
+            conn.execute(
+                "CREATE INDEX IF NOT EXISTS idx_sync_queue_blob_ref ON sync_queue(blob_ref)",
+                [],
+            )?;
+            Ok(())
+        },
+        columns: &[("sync_queue", &["blob_ref"])],
+        indexes: &["idx_sync_queue_blob_ref"],
+        after_commit: None,
+    },
+    // Кэш проектов/задач для offline start
+    Migration {
+        version: 7,
+        name: "project_cache",
+        apply: create_project_cache,
+        columns: &[
+            ("projects_cache", &["id", "name", "deleted", "synced_at"]),
+            (
+                "tasks_cache",
+                &["id", "project_id", "name", "deleted", "synced_at"],
+            ),
+        ],
+        indexes: &["idx_tasks_cache_project"],
+        after_commit: None,
+    },
+    // sent_at (retention) + auto_vacuum=INCREMENTAL: меняется только через VACUUM — один раз,
+    // дальше run_maintenance освобождает страницы
+    Migration {
+        version: 8,
+        name: "sync_queue_sent_at",
+        apply: |conn, _| add_column(conn, "sync_queue", "sent_at", "INTEGER"),
+        columns: &[("sync_queue", &["sent_at"])],
+        indexes: &[],
+        after_commit: Some(enable_incremental_vacuum),
+    },
+    // api_origin — сервер, для которого записана задача (NULL = активный сейчас), см. switch_api_origin
+    Migration {
+        version: 9,
+        name: "sync_queue_api_origin",
+        apply: |conn, _| add_column(conn, "sync_queue", "api_origin", "TEXT"),
+        columns: &[("sync_queue", &["api_origin"])],
+        indexes: &[],
+        after_commit: None,
+    },
+    // История запусков синхронизации (rolling, MAX_SYNC_METRICS_ROWS)
+    Migration {
+        version: 10,
+        name: "sync_metrics",
+        apply: create_sync_metrics,
+        columns: &[(
+            "sync_metrics",
+            &[
+                "id",
+                "started_at",
+                "duration_ms",
+                "failed_by_kind",
+                "bytes_sent",
+            ],
+        )],
+        indexes: &[],
+        after_commit: None,
+    },
+    // content_fingerprint — окно дубликатов отдельно от idempotency key (UUIDv7)
+    Migration {
+        version: 11,
+        name: "sync_queue_content_fingerprint",
+        apply: add_content_fingerprint,
+        columns: &[("sync_queue", &["content_fingerprint"])],
+        indexes: &["idx_sync_queue_fingerprint"],
+        after_commit: None,
+    },
+    // Payload очереди привязан к строке: AES-GCM associated data = entity_type + idempotency key
+    Migration {
+        version: 12,
+        name: "sync_queue_payload_aad",
+        apply: bind_queue_payloads,
+        columns: &[],
+        indexes: &[],
+        after_commit: None,
+    },
+    // Оценка смещения часов сервера на момент enqueue — clientTime не зависит от времени отправки
+    Migration {
+        version: 13,
+        name: "sync_queue_clock_offset",
+        apply: |conn, _| add_column(conn, "sync_queue", "clock_offset_ms", "INTEGER"),
+        columns: &[("sync_queue", &["clock_offset_ms"])],
+        indexes: &[],
+        after_commit: None,
+    },
+];
+
+fn create_initial_schema(conn: &Connection, _: &TokenEncryption) -> SqliteResult<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS time_entries (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            day TEXT NOT NULL,
+            accumulated_seconds INTEGER NOT NULL DEFAULT 0,
+            state TEXT NOT NULL,
+            last_updated_at INTEGER NOT NULL,
+            started_at INTEGER,
+            UNIQUE(day)
+        )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS sync_queue (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            entity_type TEXT NOT NULL,
+            payload TEXT NOT NULL,
+            status TEXT NOT NULL DEFAULT 'pending',
+            retry_count INTEGER NOT NULL DEFAULT 0,
+            created_at INTEGER NOT NULL,
+            last_retry_at INTEGER,
+            error_message TEXT,
+            priority INTEGER NOT NULL DEFAULT 2,
+            idempotency_key TEXT,
+            blob_ref TEXT
+        )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS app_meta (key TEXT PRIMARY KEY, value TEXT)",
+        [],
+    )?;
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS idx_sync_queue_status ON sync_queue(status)",
+        [],
+    )?;
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS idx_time_entries_day ON time_entries(day)",
+        [],
+    )?;
+    Ok(())
+}
+
+fn create_project_cache(conn: &Connection, _: &TokenEncryption) -> SqliteResult<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS projects_cache (
+            id TEXT PRIMARY KEY,
+            name TEXT NOT NULL,
+            description TEXT,
+            color TEXT,
+            client_name TEXT,
+            status TEXT,
+            archived INTEGER NOT NULL DEFAULT 0,
+            deleted INTEGER NOT NULL DEFAULT 0,
+            updated_at TEXT,
+            synced_at INTEGER NOT NULL
+        )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS tasks_cache (
+            id TEXT PRIMARY KEY,
+            project_id TEXT NOT NULL,
+            name TEXT NOT NULL,
+            status TEXT,
+            archived INTEGER NOT NULL DEFAULT 0,
+            deleted INTEGER NOT NULL DEFAULT 0,
+            updated_at TEXT,
+            synced_at INTEGER NOT NULL
+        )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS idx_tasks_cache_project ON tasks_cache(project_id)",
+        [],
+    )?;
+    Ok(())
+}
+
+/// auto_vacuum = INCREMENTAL (нужен VACUUM). Если VACUUM не прошёл (БД занята, нет места),
+/// run_maintenance повторяет перевод, пока режим не INCREMENTAL.
+pub(crate) fn enable_incremental_vacuum(conn: &Connection) -> SqliteResult<()> {
+    let auto_vacuum: i32 = conn.query_row("PRAGMA auto_vacuum", [], |r| r.get(0))?;
+    if auto_vacuum != 2 {
+        conn.pragma_update(None, "auto_vacuum", "INCREMENTAL")?;
+        conn.execute_batch("VACUUM")?;
+    }
+    Ok(())
+}
+
+fn create_sync_metrics(conn: &Connection, _: &TokenEncryption) -> SqliteResult<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS sync_metrics (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            started_at INTEGER NOT NULL,
+            duration_ms INTEGER NOT NULL,
+            attempted INTEGER NOT NULL,
+            succeeded INTEGER NOT NULL,
+            failed INTEGER NOT NULL,
+            failed_by_kind TEXT NOT NULL DEFAULT '{}',
+            bytes_sent INTEGER NOT NULL DEFAULT 0,
+            latency_p50_ms INTEGER,
+            latency_p95_ms INTEGER
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+/// Существующие ключи сохраняются (сервер мог уже видеть их при прерванной отправке);
+/// строкам без ключа выдаётся UUIDv7, fingerprint считается по расшифрованному payload.
+/// Прежний формат `{entity_type}-{hash}` совпадал у одинаковых операций: ключ остаётся у самой
+/// ранней строки, остальные неотправленные получают UUIDv7 — иначе сервер отбросит их как повтор.
+fn add_content_fingerprint(conn: &Connection, encryption: &TokenEncryption) -> SqliteResult<()> {
+    add_column(conn, "sync_queue", "content_fingerprint", "TEXT")?;
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS idx_sync_queue_fingerprint
+         ON sync_queue(content_fingerprint, status, created_at)",
+        [],
+    )?;
+    let rows: Vec<(i64, String, Option<String>, Option<String>)> = conn
+        .prepare(
+            "SELECT id, entity_type, payload, idempotency_key FROM sync_queue
+             WHERE content_fingerprint IS NULL OR idempotency_key IS NULL",
+        )?
+        .query_map([], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+        })?
+        .collect::<SqliteResult<_>>()?;
+    for (id, entity_type, encrypted_payload, key) in rows {
+        let fingerprint = encrypted_payload
+            .and_then(|p| encryption.decrypt(&p).ok())
+            .map(|payload| content_fingerprint(&entity_type, &payload));
+        let key = key.unwrap_or_else(new_idempotency_key);
+        conn.execute(
+            "UPDATE sync_queue SET content_fingerprint = ?1, idempotency_key = ?2 WHERE id = ?3",
+            params![fingerprint, key, id],
+        )?;
+    }
+
+    let shared: Vec<i64> = conn
+        .prepare(
+            "SELECT id FROM sync_queue AS q
+             WHERE status != 'sent'
+             AND EXISTS (SELECT 1 FROM sync_queue
+                         WHERE idempotency_key = q.idempotency_key AND id < q.id)",
+        )?
+        .query_map([], |row| row.get(0))?
+        .collect::<SqliteResult<_>>()?;
+    for id in &shared {
+        conn.execute(
+            "UPDATE sync_queue SET idempotency_key = ?1 WHERE id = ?2",
+            params![new_idempotency_key(), id],
+        )?;
+    }
+    if !shared.is_empty() {
+        info!(
+            "[DB] Migration: {} unsent tasks with a shared idempotency key re-keyed",
+            shared.len()
+        );
+    }
+    Ok(())
+}
+
+/// Перешифровать payload очереди в формат с associated data (database::payload_aad).
+/// Уже привязанные пропускаются (повтор шага). Нерасшифровываемые pending-задачи помечаются
+/// failed — привязать их нельзя, и decrypt_with_aad они никогда не пройдут.
+fn bind_queue_payloads(conn: &Connection, encryption: &TokenEncryption) -> SqliteResult<()> {
+    let rows: Vec<(i64, String, String, Option<String>)> = conn
+        .prepare(
+            "SELECT id, entity_type, payload, idempotency_key FROM sync_queue WHERE payload != ''",
+        )?
+        .query_map([], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+        })?
+        .collect::<SqliteResult<_>>()?;
+    let mut bound = 0;
+    let mut undecryptable = 0;
+    for (id, entity_type, payload, key) in rows {
+        if encryption.is_bound(&payload) {
+            continue;
+        }
+        let plaintext = match encryption.decrypt_with_legacy_fallback(&payload) {
+            Ok((plaintext, _)) => plaintext,
+            Err(e) => {
+                conn.execute(
+                    "UPDATE sync_queue SET status = 'failed', error_message = ?1
+                     WHERE id = ?2 AND status = 'pending'",
+                    params![format!("Payload rejected: {}", e), id],
+                )?;
+                undecryptable += 1;
+                continue;
+            }
+        };
+        let encrypted = encryption
+            .encrypt_with_aad(&plaintext, &payload_aad(&entity_type, key.as_deref()))
+            .map_err(InvalidParameterName)?;
+        conn.execute(
+            "UPDATE sync_queue SET payload = ?1 WHERE id = ?2",
+            params![encrypted, id],
+        )?;
+        bound += 1;
+    }
+    if bound + undecryptable > 0 {
+        info!(
+            "[DB] Migration: {} queue payloads bound to their rows, {} undecryptable marked failed",
+            bound, undecryptable
+        );
+    }
+    Ok(())
+}
+
+/// ALTER TABLE ADD COLUMN, если колонки ещё нет (ошибки ALTER не игнорируются)
+fn add_column(conn: &Connection, table: &str, column: &str, decl: &str) -> SqliteResult<()> {
+    if !has_column(conn, table, column)? {
+        conn.execute(
+            &format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, decl),
+            [],
+        )?;
+    }
+    Ok(())
+}
+
+fn has_column(conn: &Connection, table: &str, column: &str) -> SqliteResult<bool> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+    let names = stmt.query_map([], |row| row.get::<_, String>(1))?;
+    for name in names {
+        if name? == column {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+fn has_index(conn: &Connection, index: &str) -> SqliteResult<bool> {
+    conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE type = 'index' AND name = ?1)",
+        params![index],
+        |row| row.get(0),
+    )
+}
+
+impl Migration {
+    /// Post-condition шага: Err с описанием первой недостающей колонки или индекса
+    fn verify(&self, conn: &Connection) -> SqliteResult<()> {
+        for (table, columns) in self.columns {
+            for column in columns.iter() {
+                if !has_column(conn, table, column)? {
+                    return Err(InvalidParameterName(format!(
+                        "Migration {} ({}) check failed: column {}.{} is missing",
+                        self.version, self.name, table, column
+                    )));
+                }
+            }
+        }
+        for index in self.indexes {
+            if !has_index(conn, index)? {
+                return Err(InvalidParameterName(format!(
+                    "Migration {} ({}) check failed: index {} is missing",
+                    self.version, self.name, index
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Привести схему к SCHEMA_VERSION.
+/// Шаг считается применённым, если он записан в schema_migrations и не выше user_version.
+/// БД до появления schema_migrations: шаги до user_version, чей post-condition выполнен,
+/// только записываются; не прошедшие проверку (ALTER когда-то молча не сработал) выполняются заново.
+pub(crate) fn run(conn: &mut Connection, encryption: &TokenEncryption) -> SqliteResult<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS schema_migrations (
+            version INTEGER PRIMARY KEY,
+            name TEXT NOT NULL,
+            applied_at INTEGER NOT NULL
+        )",
+        [],
+    )?;
+    let user_version: i32 = conn.query_row("PRAGMA user_version", [], |r| r.get(0))?;
+    let recorded_max: Option<i32> = conn
+        .query_row("SELECT MAX(version) FROM schema_migrations", [], |r| {
+            r.get(0)
+        })
+        .optional()?
+        .flatten();
+    let current = user_version.max(recorded_max.unwrap_or(0));
+    if current > SCHEMA_VERSION {
+        return Err(InvalidParameterName(format!(
+            "Database schema version {} is newer than this app supports ({}). Update the application.",
+            current, SCHEMA_VERSION
+        )));
+    }
+    let recorded: HashSet<i32> = conn
+        .prepare("SELECT version FROM schema_migrations")?
+        .query_map([], |r| r.get(0))?
+        .collect::<SqliteResult<_>>()?;
+
+    let mut version = user_version;
+    for migration in MIGRATIONS {
+        let done = migration.version <= user_version;
+        if done && recorded.contains(&migration.version) {
+            continue;
+        }
+        let tx = conn.transaction()?;
+        let backfilled = done && migration.verify(&tx).is_ok();
+        if !backfilled {
+            (migration.apply)(&tx, encryption)?;
+            migration.verify(&tx)?;
+        }
+        tx.execute(
+            "INSERT OR REPLACE INTO schema_migrations (version, name, applied_at) VALUES (?1, ?2, ?3)",
+            params![migration.version, migration.name, Utc::now().timestamp()],
+        )?;
+        version = version.max(migration.version);
+        tx.pragma_update(None, "user_version", version)?;
+        tx.commit()?;
+        if backfilled {
+            continue;
+        }
+        if done {
+            warn!(
+                "[DB] Migration {} ({}) was incomplete, re-applied",
+                migration.version, migration.name
+            );
+        } else {
+            info!(
+                "[DB] Applied migration {} ({})",
+                migration.version, migration.name
+            );
+        }
+        if let Some(after_commit) = migration.after_commit {
+            if let Err(e) = after_commit(conn) {
+                warn!(
+                    "[DB] Migration {} ({}) post-commit step failed: {}",
+                    migration.version, migration.name, e
+                );
+            }
+        }
+    }
+    Ok(())
+}
@@ -6,7 +6,27 @@ pub struct QueueStats {
     pub pending_count: i32,
     pub failed_count: i32,
     pub sent_count: i32,
+    /// Задачи другого сервера API (после смены адреса не отправляются)
+    pub parked_count: i32,
     pub pending_by_type: HashMap<String, i32>,
+    /// Тела sync-запросов до сжатия (байты, накопительно)
+    pub upload_bytes_raw: i64,
+    /// Фактически отправлено (после Content-Encoding)
+    pub upload_bytes_sent: i64,
+    /// Сэкономлено сжатием: raw - sent
+    pub compression_saved_bytes: i64,
+}
+
+/// Результат Database::switch_api_origin
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ApiOriginSwitch {
+    /// Адрес API при прошлом запуске (None — первый запуск / старая версия)
+    pub previous: Option<String>,
+    pub changed: bool,
+    /// pending/failed задачи прежнего сервера, отложенные со статусом 'parked'
+    pub parked: usize,
+    /// parked-задачи этого сервера, возвращённые в pending
+    pub restored: usize,
 }
 
 /// Информация о failed задаче
@@ -28,6 +48,40 @@ pub struct TokenRefreshResult {
     pub refresh_token: Option<String>,
 }
 
+/// Payload события auth-tokens-refreshed
+#[derive(Serialize, Clone, Debug)]
+pub struct TokensRefreshedEvent {
+    pub access_token: String,
+    pub refresh_token: String,
+    /// `exp` нового access token (unix секунды), если это JWT
+    pub expires_at: Option<i64>,
+}
+
+/// Что делать с очередью синхронизации при выходе
+#[derive(serde::Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogoutQueuePolicy {
+    /// Отправить очередь и удалить локальные данные пользователя (если отправлено всё, иначе — как Keep)
+    Flush,
+    /// Оставить очередь (зашифрованной) до следующего входа
+    Keep,
+}
+
+/// Payload события auth-logged-out (и результат команды logout)
+#[derive(Serialize, Clone, Debug)]
+pub struct LoggedOutEvent {
+    /// Сервер подтвердил отзыв refresh token
+    pub revoked: bool,
+    /// Выполненная политика: Flush, отправивший не всё, становится Keep
+    pub queue_policy: LogoutQueuePolicy,
+    /// Отправлено перед выходом (Flush)
+    pub flushed_tasks: usize,
+    /// Неотправленных задач осталось в очереди (Keep или Flush, который отправил не всё)
+    pub remaining_tasks: usize,
+    /// Ключ шифрования удалён из keyring / fallback-файла
+    pub keys_purged: bool,
+}
+
 #[derive(Serialize)]
 pub struct ActiveWindowInfo {
     pub app_name: Option<String>,
@@ -35,3 +89,170 @@ pub struct ActiveWindowInfo {
     pub url: Option<String>,
     pub domain: Option<String>,
 }
+
+/// Payload события sync-started
+#[derive(Serialize, Clone, Debug)]
+pub struct SyncStartedEvent {
+    /// Сколько задач взято в этот batch
+    pub total: usize,
+}
+
+/// Payload событий sync-task-done / sync-task-failed
+#[derive(Serialize, Clone, Debug)]
+pub struct SyncTaskEvent {
+    pub task_id: i64,
+    pub entity_type: String,
+    /// Порядковый номер задачи в batch (1..=total)
+    pub index: usize,
+    pub total: usize,
+    /// Класс ошибки (SyncError::kind), только для failed
+    pub error_class: Option<String>,
+    pub error: Option<String>,
+    /// Задача останется pending и будет повторена (false — помечена failed)
+    pub will_retry: bool,
+}
+
+/// Payload события sync-finished
+#[derive(Serialize, Clone, Debug)]
+pub struct SyncFinishedEvent {
+    pub total: usize,
+    pub synced: usize,
+    pub failed: usize,
+    /// Остались pending без результата в этом запуске (сервер ответил 429/503 с Retry-After —
+    /// эта и все следующие задачи batch). synced + failed + deferred = total
+    pub deferred: usize,
+    pub synced_by_type: HashMap<String, i32>,
+    pub failed_by_type: HashMap<String, i32>,
+    pub duration_ms: u64,
+}
+
+/// Метрики одного запуска синхронизации (таблица sync_metrics)
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct SyncRunMetrics {
+    /// Unix time начала запуска
+    pub started_at: i64,
+    pub duration_ms: u64,
+    pub attempted: i32,
+    pub succeeded: i32,
+    pub failed: i32,
+    /// Неудачи по SyncError::kind() (network, proxy, tls, auth, server, http, ...)
+    pub failed_by_kind: HashMap<String, i32>,
+    /// Отправлено байт тел запросов (после сжатия)
+    pub bytes_sent: u64,
+    /// Латентность HTTP-запросов за запуск (None — запросов не было)
+    pub latency_p50_ms: Option<u64>,
+    pub latency_p95_ms: Option<u64>,
+}
+
+/// Ответ get_sync_metrics: последние запуски + сводка по ним
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct SyncMetricsReport {
+    /// Новые первыми
+    pub runs: Vec<SyncRunMetrics>,
+    pub attempted: i32,
+    pub succeeded: i32,
+    pub failed: i32,
+    pub failed_by_kind: HashMap<String, i32>,
+    pub bytes_sent: u64,
+    /// Медиана p50/p95 по запускам
+    pub latency_p50_ms: Option<u64>,
+    pub latency_p95_ms: Option<u64>,
+}
+
+/// Проект из локального кэша (offline выбор проекта)
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct CachedProject {
+    pub id: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub color: Option<String>,
+    pub client_name: Option<String>,
+    pub status: Option<String>,
+    pub archived: bool,
+    pub deleted: bool,
+    /// updatedAt с сервера (ISO) — курсор инкрементального обновления
+    pub updated_at: Option<String>,
+}
+
+/// Задача проекта из локального кэша
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct CachedTask {
+    pub id: String,
+    pub project_id: String,
+    pub name: String,
+    pub status: Option<String>,
+    pub archived: bool,
+    pub deleted: bool,
+    pub updated_at: Option<String>,
+}
+
+/// Результат обновления кэша проектов/задач
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct ProjectCacheRefresh {
+    pub projects_updated: usize,
+    pub tasks_updated: usize,
+    pub full_sync: bool,
+    /// false — сервер не поддерживает /tasks (404), кэш задач не обновлялся
+    pub tasks_supported: bool,
+}
+
+/// Payload события db-recovered-from-corruption
+#[derive(Serialize, Clone, Debug)]
+pub struct DbRecoveredEvent {
+    /// Куда перемещён повреждённый файл
+    pub corrupted_path: String,
+    /// Копия, из которой восстановлена БД; None — создана пустая
+    pub restored_backup: Option<crate::backup::BackupInfo>,
+    /// Что удалось перенести из повреждённого файла; None — файл не открылся
+    pub salvage: Option<SalvageReport>,
+}
+
+/// Результат переноса строк из повреждённой БД (salvage.rs)
+#[derive(Serialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct SalvageReport {
+    /// Неотправленные задачи очереди, добавленные в новую БД
+    pub sync_tasks_rescued: usize,
+    /// Уже были в новой БД (тот же idempotency_key — из резервной копии)
+    pub sync_tasks_skipped: usize,
+    pub time_entries_rescued: usize,
+    /// Строк, которые не удалось прочитать
+    pub unreadable_rows: usize,
+}
+
+/// Результат ротации ключа шифрования (rotate_encryption_key)
+#[derive(Serialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct KeyRotationReport {
+    /// Id нового ключа (hex, как в заголовке шифротекста)
+    pub key_id: String,
+    /// Payload очереди во всех БД профилей
+    pub payloads_reencrypted: usize,
+    /// Зашифрованные значения app_meta (refresh token, пароль прокси)
+    pub secrets_reencrypted: usize,
+    pub blobs_reencrypted: usize,
+    /// Не расшифровались ни одним ключом (были повреждены до ротации) — оставлены как есть
+    pub undecryptable: usize,
+    /// Файлы БД, перешифрованные SQLCipher (feature `sqlcipher`)
+    pub databases_rekeyed: usize,
+    /// Прежних ключей удалено из файла ключа и keyring
+    pub retired_keys: usize,
+}
+
+/// Отчёт обслуживания БД (retention + vacuum)
+#[derive(Serialize, Clone, Debug)]
+pub struct MaintenanceReport {
+    /// Payload отправленных задач, очищенных в этом запуске
+    pub payloads_dropped: usize,
+    /// Удалено sent-строк старше срока хранения
+    pub rows_deleted: usize,
+    /// Страниц, возвращённых incremental_vacuum
+    pub pages_freed: i64,
+    /// auto_vacuum = INCREMENTAL; false — перевод (VACUUM) снова не удался, место не освобождается
+    pub auto_vacuum_incremental: bool,
+    /// wal_checkpoint(TRUNCATE) прошёл без busy
+    pub wal_truncated: bool,
+    pub bytes_before: u64,
+    pub bytes_after: u64,
+    /// Освобождено на диске (БД + WAL)
+    pub reclaimed_bytes: u64,
+    pub duration_ms: u64,
+}
@@ -1,34 +1,3 @@
-use reqwest::Client;
-use std::time::Duration;
-
-// Проверка online статуса через легковесный HTTP запрос
-pub async fn check_online_status() -> bool {
-    let client = match Client::builder().timeout(Duration::from_secs(2)).build() {
-        Ok(client) => client,
-        Err(_) => return false,
-    };
-
-    match client
-        .get("https://www.cloudflare.com/cdn-cgi/trace")
-        .timeout(Duration::from_secs(2))
-        .send()
-        .await
-    {
-        Ok(response) => response.status().is_success(),
-        Err(_) => {
-            match client
-                .get("https://www.google.com/generate_204")
-                .timeout(Duration::from_secs(2))
-                .send()
-                .await
-            {
-                Ok(response) => response.status().is_success() || response.status().as_u16() == 204,
-                Err(_) => false,
-            }
-        }
-    }
-}
-
 pub fn extract_url_from_title(title: &str) -> (Option<String>, Option<String>) {
     if let Some(url_start) = title.find("http://") {
         if let Some(url_end) = title[url_start..].find(' ') {
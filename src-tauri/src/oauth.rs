@@ -0,0 +1,582 @@
+//! Native OAuth2 sign-in for SSO identity providers (next to `AuthManager`).
+//! Authorization code + PKCE (RFC 7636) with a loopback redirect listener (RFC 8252), or the
+//! device authorization grant (RFC 8628) for machines without a usable browser.
+//! Provider settings live in app_meta (`oauth_settings`, JSON); endpoints can be discovered from
+//! `{issuer}/.well-known/openid-configuration`. Tokens are handed to `AuthManager`, which stores
+//! the refresh token and refreshes against the provider's token endpoint.
+
+use crate::auth::AuthError;
+use crate::Database;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tracing::{info, warn};
+
+const META_OAUTH_SETTINGS: &str = "oauth_settings";
+const DEVICE_CODE_GRANT: &str = "urn:ietf:params:oauth:grant-type:device_code";
+/// Сколько ждать, пока пользователь войдёт в браузере
+pub const LOOPBACK_LOGIN_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+/// Сколько ждать запрос на принятом loopback-соединении
+const LOOPBACK_READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Настройки провайдера (Settings → Sign-in)
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct OAuthSettings {
+    /// OIDC issuer: пустые endpoint'ы берутся из discovery-документа
+    pub issuer: Option<String>,
+    pub authorization_endpoint: Option<String>,
+    pub token_endpoint: Option<String>,
+    pub device_authorization_endpoint: Option<String>,
+    /// RFC 7009: отзыв refresh token при выходе
+    pub revocation_endpoint: Option<String>,
+    /// OIDC userinfo: id пользователя, если token endpoint не вернул id_token
+    pub userinfo_endpoint: Option<String>,
+    pub client_id: String,
+    pub scopes: Vec<String>,
+}
+
+fn non_empty(value: &Option<String>) -> Option<&str> {
+    value.as_deref().map(str::trim).filter(|v| !v.is_empty())
+}
+
+impl OAuthSettings {
+    /// Настройки из app_meta; отсутствуют или повреждены — значения по умолчанию (SSO выключен)
+    pub fn load(db: &Database) -> Self {
+        match db.get_app_meta(META_OAUTH_SETTINGS) {
+            Ok(Some(json)) if !json.is_empty() => serde_json::from_str(&json).unwrap_or_else(|e| {
+                warn!("[OAUTH] Stored OAuth settings are invalid: {}", e);
+                Self::default()
+            }),
+            _ => Self::default(),
+        }
+    }
+
+    pub fn save(&self, db: &Database) -> Result<(), String> {
+        self.validate()?;
+        let json = serde_json::to_string(self)
+            .map_err(|e| format!("Failed to serialize OAuth settings: {}", e))?;
+        db.set_app_meta(META_OAUTH_SETTINGS, &json)
+            .map_err(|e| format!("Failed to save OAuth settings: {}", e))
+    }
+
+    /// client_id обязателен; endpoint'ы — https (http только для localhost), либо issuer для discovery
+    pub fn validate(&self) -> Result<(), String> {
+        if self.client_id.trim().is_empty() {
+            return Err("OAuth client ID is required".to_string());
+        }
+        let endpoints = [
+            &self.issuer,
+            &self.authorization_endpoint,
+            &self.token_endpoint,
+            &self.device_authorization_endpoint,
+            &self.revocation_endpoint,
+            &self.userinfo_endpoint,
+        ];
+        for url in endpoints.into_iter().filter_map(non_empty) {
+            crate::endpoint::normalize_api_url(url)?;
+        }
+        if non_empty(&self.issuer).is_none() && non_empty(&self.token_endpoint).is_none() {
+            return Err("OAuth issuer or token endpoint is required".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn is_configured(&self) -> bool {
+        self.validate().is_ok()
+    }
+
+    /// Заполнить пустые endpoint'ы из `{issuer}/.well-known/openid-configuration`
+    pub async fn discover(&self, client: &reqwest::Client) -> Result<Self, String> {
+        let issuer = match non_empty(&self.issuer) {
+            Some(issuer) => issuer.trim_end_matches('/'),
+            None => return Ok(self.clone()),
+        };
+        let url = format!("{}/.well-known/openid-configuration", issuer);
+        let response = client
+            .get(&url)
+            .send()
+            .await
+            .map_err(|e| format!("OIDC discovery failed: {}", e))?;
+        if !response.status().is_success() {
+            return Err(format!("OIDC discovery failed: HTTP {}", response.status()));
+        }
+        let doc: serde_json::Value = response
+            .json()
+            .await
+            .map_err(|e| format!("Invalid OIDC discovery document: {}", e))?;
+        let field = |name: &str| doc[name].as_str().map(|s| s.to_string());
+        let mut resolved = self.clone();
+        for (target, name) in [
+            (
+                &mut resolved.authorization_endpoint,
+                "authorization_endpoint",
+            ),
+            (&mut resolved.token_endpoint, "token_endpoint"),
+            (
+                &mut resolved.device_authorization_endpoint,
+                "device_authorization_endpoint",
+            ),
+            (&mut resolved.revocation_endpoint, "revocation_endpoint"),
+            (&mut resolved.userinfo_endpoint, "userinfo_endpoint"),
+        ] {
+            if non_empty(target).is_none() {
+                *target = field(name);
+            }
+        }
+        Ok(resolved)
+    }
+
+    fn endpoint(&self, value: &Option<String>, name: &str) -> Result<String, String> {
+        non_empty(value)
+            .map(|s| s.to_string())
+            .ok_or_else(|| format!("OAuth {} is not configured", name))
+    }
+
+    /// Параметры refresh для AuthManager
+    pub fn refresh_client(&self) -> Result<OAuthRefresh, String> {
+        Ok(OAuthRefresh {
+            token_endpoint: self.endpoint(&self.token_endpoint, "token endpoint")?,
+            client_id: self.client_id.trim().to_string(),
+            revocation_endpoint: non_empty(&self.revocation_endpoint).map(|s| s.to_string()),
+        })
+    }
+
+    fn scope(&self) -> String {
+        self.scopes.join(" ")
+    }
+}
+
+/// Куда AuthManager отправляет grant_type=refresh_token для токенов от провайдера
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OAuthRefresh {
+    pub token_endpoint: String,
+    pub client_id: String,
+    #[serde(default)]
+    pub revocation_endpoint: Option<String>,
+}
+
+/// Ответ token endpoint
+#[derive(Debug, Clone, Deserialize)]
+pub struct OAuthTokens {
+    pub access_token: String,
+    pub refresh_token: Option<String>,
+    pub expires_in: Option<i64>,
+    /// OIDC (scope openid): claim `sub` — id пользователя, см. resolve_user_id
+    pub id_token: Option<String>,
+}
+
+/// Ответ device authorization endpoint (показывается пользователю)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceAuthorization {
+    pub device_code: String,
+    pub user_code: String,
+    pub verification_uri: String,
+    pub verification_uri_complete: Option<String>,
+    pub expires_in: u64,
+    #[serde(default = "default_poll_interval")]
+    pub interval: u64,
+}
+
+fn default_poll_interval() -> u64 {
+    5
+}
+
+fn base64_url(bytes: &[u8]) -> String {
+    base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes)
+}
+
+fn random_token() -> String {
+    base64_url(&rand::random::<[u8; 32]>())
+}
+
+/// PKCE verifier (43 символа base64url) и S256 challenge
+#[derive(Debug, Clone)]
+pub struct Pkce {
+    pub verifier: String,
+    pub challenge: String,
+}
+
+impl Pkce {
+    pub fn generate() -> Self {
+        let verifier = random_token();
+        let challenge = base64_url(&Sha256::digest(verifier.as_bytes()));
+        Self {
+            verifier,
+            challenge,
+        }
+    }
+}
+
+/// URL страницы входа провайдера
+pub fn authorization_url(
+    settings: &OAuthSettings,
+    redirect_uri: &str,
+    state: &str,
+    pkce: &Pkce,
+) -> Result<String, String> {
+    let endpoint = settings.endpoint(&settings.authorization_endpoint, "authorization endpoint")?;
+    let mut url = reqwest::Url::parse(&endpoint)
+        .map_err(|e| format!("Invalid authorization endpoint: {}", e))?;
+    url.query_pairs_mut()
+        .append_pair("response_type", "code")
+        .append_pair("client_id", settings.client_id.trim())
+        .append_pair("redirect_uri", redirect_uri)
+        .append_pair("scope", &settings.scope())
+        .append_pair("state", state)
+        .append_pair("code_challenge", &pkce.challenge)
+        .append_pair("code_challenge_method", "S256");
+    Ok(url.to_string())
+}
+
+/// Ошибка token endpoint (RFC 6749 §5.2)
+#[derive(Debug, Clone, PartialEq)]
+enum TokenError {
+    /// `error` из тела ответа (authorization_pending, slow_down, access_denied, ...)
+    OAuth(String),
+    Other(String),
+}
+
+impl std::fmt::Display for TokenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TokenError::OAuth(code) => write!(f, "OAuth error: {}", code),
+            TokenError::Other(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+async fn request_token(
+    client: &reqwest::Client,
+    token_endpoint: &str,
+    form: &[(&str, &str)],
+) -> Result<OAuthTokens, TokenError> {
+    let response = client
+        .post(token_endpoint)
+        .header("Accept", "application/json")
+        .form(form)
+        .send()
+        .await
+        .map_err(|e| TokenError::Other(crate::http_client::error_chain(&e)))?;
+    let status = response.status();
+    let body: serde_json::Value = response.json().await.unwrap_or_default();
+    if !status.is_success() {
+        return Err(match body["error"].as_str() {
+            Some(code) => TokenError::OAuth(code.to_string()),
+            None => TokenError::Other(format!("Token endpoint returned HTTP {}", status)),
+        });
+    }
+    serde_json::from_value(body)
+        .map_err(|e| TokenError::Other(format!("Invalid token response: {}", e)))
+}
+
+/// grant_type=refresh_token у провайдера (вызывает AuthManager)
+pub async fn refresh(
+    client: &reqwest::Client,
+    refresh: &OAuthRefresh,
+    refresh_token: &str,
+) -> Result<OAuthTokens, AuthError> {
+    request_token(
+        client,
+        &refresh.token_endpoint,
+        &[
+            ("grant_type", "refresh_token"),
+            ("refresh_token", refresh_token),
+            ("client_id", &refresh.client_id),
+        ],
+    )
+    .await
+    .map_err(|e| match e {
+        TokenError::OAuth(code) => AuthError::OAuth(code),
+        TokenError::Other(msg) => AuthError::Network(msg),
+    })
+}
+
+/// Отозвать refresh token у провайдера (RFC 7009); без revocation endpoint — Ok(false)
+pub async fn revoke(
+    client: &reqwest::Client,
+    refresh: &OAuthRefresh,
+    refresh_token: &str,
+) -> Result<bool, String> {
+    let endpoint = match &refresh.revocation_endpoint {
+        Some(endpoint) => endpoint,
+        None => return Ok(false),
+    };
+    let response = client
+        .post(endpoint)
+        .form(&[
+            ("token", refresh_token),
+            ("token_type_hint", "refresh_token"),
+            ("client_id", &refresh.client_id),
+        ])
+        .send()
+        .await
+        .map_err(|e| crate::http_client::error_chain(&e))?;
+    if !response.status().is_success() {
+        return Err(format!(
+            "Revocation endpoint returned HTTP {}",
+            response.status()
+        ));
+    }
+    Ok(true)
+}
+
+/// Ответ браузеру после редиректа
+async fn respond(socket: &mut tokio::net::TcpStream, status: &str, message: &str) {
+    let body = format!(
+        "<!doctype html><html><body><p>{}</p></body></html>",
+        message
+    );
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    let _ = socket.write_all(response.as_bytes()).await;
+}
+
+/// Заголовки запроса (до пустой строки, не больше 64 KiB)
+async fn read_request_head(socket: &mut tokio::net::TcpStream) -> Vec<u8> {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 4096];
+    while !buf.windows(4).any(|w| w == b"\r\n\r\n") && buf.len() < 64 * 1024 {
+        match socket.read(&mut chunk).await {
+            Ok(0) | Err(_) => break,
+            Ok(n) => buf.extend_from_slice(&chunk[..n]),
+        }
+    }
+    buf
+}
+
+/// Ждать редирект на /callback; прочие запросы (favicon) получают 404.
+/// Соединение без запроса (preconnect браузера, чужой процесс) закрывается по LOOPBACK_READ_TIMEOUT,
+/// запрос с чужим state получает 400 — ожидание настоящего редиректа продолжается.
+async fn wait_for_code(listener: &TcpListener, expected_state: &str) -> Result<String, String> {
+    loop {
+        let (mut socket, _) = listener
+            .accept()
+            .await
+            .map_err(|e| format!("Loopback listener failed: {}", e))?;
+        let buf = match tokio::time::timeout(LOOPBACK_READ_TIMEOUT, read_request_head(&mut socket))
+            .await
+        {
+            Ok(buf) => buf,
+            Err(_) => {
+                warn!("[OAUTH] Loopback connection sent no request, closing");
+                continue;
+            }
+        };
+        let head = String::from_utf8_lossy(&buf);
+        let target = head.split_whitespace().nth(1).unwrap_or("/").to_string();
+        let url = match reqwest::Url::parse(&format!("http://127.0.0.1{}", target)) {
+            Ok(url) if url.path() == "/callback" => url,
+            _ => {
+                respond(&mut socket, "404 Not Found", "Not found").await;
+                continue;
+            }
+        };
+        let param = |name: &str| {
+            url.query_pairs()
+                .find(|(k, _)| k == name)
+                .map(|(_, v)| v.into_owned())
+        };
+        if param("state").as_deref() != Some(expected_state) {
+            respond(
+                &mut socket,
+                "400 Bad Request",
+                "Sign-in failed: state mismatch.",
+            )
+            .await;
+            warn!("[OAUTH] Callback with unexpected state ignored");
+            continue;
+        }
+        if let Some(error) = param("error") {
+            respond(
+                &mut socket,
+                "400 Bad Request",
+                "Sign-in was cancelled or failed.",
+            )
+            .await;
+            let description = param("error_description").unwrap_or_default();
+            return Err(format!("Sign-in failed: {} {}", error, description)
+                .trim()
+                .to_string());
+        }
+        return match param("code") {
+            Some(code) => {
+                respond(
+                    &mut socket,
+                    "200 OK",
+                    "Signed in to Hubnity. You can close this window.",
+                )
+                .await;
+                Ok(code)
+            }
+            None => {
+                respond(&mut socket, "400 Bad Request", "Sign-in failed: no code.").await;
+                Err("Authorization response has no code".to_string())
+            }
+        };
+    }
+}
+
+/// Authorization code + PKCE через loopback-редирект: `open_url` открывает страницу входа в браузере
+pub async fn login_with_loopback<F>(
+    settings: &OAuthSettings,
+    client: &reqwest::Client,
+    open_url: F,
+    timeout: Duration,
+) -> Result<OAuthTokens, String>
+where
+    F: FnOnce(&str) -> Result<(), String>,
+{
+    let token_endpoint = settings.endpoint(&settings.token_endpoint, "token endpoint")?;
+    // Порт выбирает ОС; провайдеры разрешают любой порт для loopback (RFC 8252 §7.3)
+    let listener = TcpListener::bind("127.0.0.1:0")
+        .await
+        .map_err(|e| format!("Failed to start loopback listener: {}", e))?;
+    let port = listener
+        .local_addr()
+        .map_err(|e| format!("Failed to start loopback listener: {}", e))?
+        .port();
+    let redirect_uri = format!("http://127.0.0.1:{}/callback", port);
+    let pkce = Pkce::generate();
+    let state = random_token();
+    open_url(&authorization_url(settings, &redirect_uri, &state, &pkce)?)?;
+    info!("[OAUTH] Waiting for browser sign-in on port {}", port);
+
+    let code = tokio::time::timeout(timeout, wait_for_code(&listener, &state))
+        .await
+        .map_err(|_| "Sign-in timed out".to_string())??;
+    request_token(
+        client,
+        &token_endpoint,
+        &[
+            ("grant_type", "authorization_code"),
+            ("code", &code),
+            ("redirect_uri", &redirect_uri),
+            ("client_id", settings.client_id.trim()),
+            ("code_verifier", &pkce.verifier),
+        ],
+    )
+    .await
+    .map_err(|e| e.to_string())
+}
+
+/// Начать device flow: код для пользователя и адрес, где его ввести
+pub async fn start_device_authorization(
+    settings: &OAuthSettings,
+    client: &reqwest::Client,
+) -> Result<DeviceAuthorization, String> {
+    let endpoint = settings.endpoint(
+        &settings.device_authorization_endpoint,
+        "device authorization endpoint",
+    )?;
+    let response = client
+        .post(&endpoint)
+        .header("Accept", "application/json")
+        .form(&[
+            ("client_id", settings.client_id.trim()),
+            ("scope", &settings.scope()),
+        ])
+        .send()
+        .await
+        .map_err(|e| format!("Device authorization failed: {}", e))?;
+    if !response.status().is_success() {
+        return Err(format!(
+            "Device authorization failed: HTTP {}",
+            response.status()
+        ));
+    }
+    response
+        .json()
+        .await
+        .map_err(|e| format!("Invalid device authorization response: {}", e))
+}
+
+/// Опрашивать token endpoint, пока пользователь не подтвердит вход (RFC 8628 §3.4–3.5)
+pub async fn poll_device_token(
+    settings: &OAuthSettings,
+    client: &reqwest::Client,
+    device: &DeviceAuthorization,
+) -> Result<OAuthTokens, String> {
+    let token_endpoint = settings.endpoint(&settings.token_endpoint, "token endpoint")?;
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(device.expires_in);
+    let mut interval = Duration::from_secs(device.interval);
+    loop {
+        if tokio::time::Instant::now() >= deadline {
+            return Err("Device code expired".to_string());
+        }
+        tokio::time::sleep(interval).await;
+        let result = request_token(
+            client,
+            &token_endpoint,
+            &[
+                ("grant_type", DEVICE_CODE_GRANT),
+                ("device_code", &device.device_code),
+                ("client_id", settings.client_id.trim()),
+            ],
+        )
+        .await;
+        match result {
+            Ok(tokens) => return Ok(tokens),
+            Err(TokenError::OAuth(code)) if code == "authorization_pending" => {}
+            Err(TokenError::OAuth(code)) if code == "slow_down" => {
+                interval += Duration::from_secs(5);
+            }
+            Err(TokenError::OAuth(code)) if code == "access_denied" => {
+                return Err("Sign-in was denied".to_string());
+            }
+            Err(TokenError::OAuth(code)) if code == "expired_token" => {
+                return Err("Device code expired".to_string());
+            }
+            Err(e) => return Err(e.to_string()),
+        }
+    }
+}
+
+/// Id вошедшего пользователя — профиль, в который сохраняются токены: claim `sub` из id_token
+/// (получен напрямую от token endpoint по TLS, подпись не проверяется — OIDC Core §3.1.3.7),
+/// иначе `sub` из ответа userinfo endpoint
+pub async fn resolve_user_id(
+    settings: &OAuthSettings,
+    client: &reqwest::Client,
+    tokens: &OAuthTokens,
+) -> Result<String, String> {
+    if let Some(sub) = tokens
+        .id_token
+        .as_deref()
+        .and_then(crate::auth::jwt_subject)
+    {
+        return Ok(sub);
+    }
+    let endpoint = non_empty(&settings.userinfo_endpoint).ok_or_else(|| {
+        "Cannot identify the signed-in user: no id_token and no userinfo endpoint".to_string()
+    })?;
+    let response = client
+        .get(endpoint)
+        .bearer_auth(&tokens.access_token)
+        .header("Accept", "application/json")
+        .send()
+        .await
+        .map_err(|e| format!("Userinfo request failed: {}", e))?;
+    if !response.status().is_success() {
+        return Err(format!(
+            "Userinfo request failed: HTTP {}",
+            response.status()
+        ));
+    }
+    let info: serde_json::Value = response
+        .json()
+        .await
+        .map_err(|e| format!("Invalid userinfo response: {}", e))?;
+    info["sub"]
+        .as_str()
+        .filter(|sub| !sub.is_empty())
+        .map(str::to_string)
+        .ok_or_else(|| "Userinfo response has no subject".to_string())
+}
@@ -0,0 +1,518 @@
+//! User profiles with isolated databases.
+//! Each user id gets its own `hubnity-<user>.db` (and `blobs-<user>/`) in the app data dir, with
+//! its own `TimerEngine` and `SyncManager` — so a user switch on a shared machine no longer deletes
+//! the previous user's time entries and unsent queue. `hubnity.db` keeps app-wide settings
+//! (API endpoint, network, SSO, `current_user_id`) and serves as the profile before the first login.
+//! Inactive profiles stay loaded: their queues keep syncing with their own stored refresh tokens.
+//! A corrupted profile DB is recovered like `hubnity.db` (see `salvage::recover_corrupted`).
+
+use crate::engine::{TimerEngine, TimerStateForAPI};
+use crate::ipc::EventSink;
+use crate::models::DbRecoveredEvent;
+use crate::sync::{SyncConfig, SyncManager};
+use crate::Database;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use tracing::{info, warn};
+
+/// Владелец БД профиля (имя файла для нестандартных id — хэш)
+const META_PROFILE_USER_ID: &str = "profile_user_id";
+const PROFILE_DB_PREFIX: &str = "hubnity-";
+
+/// Состояние одного пользователя
+#[derive(Clone)]
+pub struct Profile {
+    /// "" — профиль до первого входа (hubnity.db)
+    pub user_id: String,
+    pub db: Arc<Database>,
+    pub engine: Arc<TimerEngine>,
+    pub sync_manager: SyncManager,
+}
+
+impl Profile {
+    fn new(user_id: String, db: Arc<Database>, config: &SyncConfig) -> Self {
+        let engine = Arc::new(TimerEngine::with_db(db.clone()));
+        let sync_manager = SyncManager::new_with_config(db.clone(), config.clone());
+        Self {
+            user_id,
+            db,
+            engine,
+            sync_manager,
+        }
+    }
+}
+
+/// Часть имени файла профиля: безопасный id как есть, иначе первые 16 байт SHA-256
+fn profile_file_stem(user_id: &str) -> String {
+    let safe = !user_id.is_empty()
+        && user_id.len() <= 64
+        && user_id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_');
+    if safe {
+        user_id.to_string()
+    } else {
+        hex::encode(&Sha256::digest(user_id.as_bytes())[..16])
+    }
+}
+
+pub fn profile_db_path(dir: &Path, user_id: &str) -> PathBuf {
+    dir.join(format!(
+        "{}{}.db",
+        PROFILE_DB_PREFIX,
+        profile_file_stem(user_id)
+    ))
+}
+
+/// Файлы БД приложения в каталоге: hubnity.db и hubnity-<user>.db (без карантина и копий)
+pub fn database_files(dir: &Path) -> Vec<PathBuf> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|path| {
+            path.file_name()
+                .and_then(|n| n.to_str())
+                .map(|n| {
+                    n == "hubnity.db" || (n.starts_with(PROFILE_DB_PREFIX) && n.ends_with(".db"))
+                })
+                .unwrap_or(false)
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+/// Пауза таймера профиля, который перестаёт быть активным: команды таймера идут к активному
+/// профилю, и остановить его из UI уже нельзя. На сервер ставится pause записи (если её id известен).
+fn pause_running_timer(profile: &Profile) {
+    let running = matches!(
+        profile.engine.get_state().map(|s| s.state),
+        Ok(TimerStateForAPI::Running { .. })
+    );
+    if !running {
+        return;
+    }
+    if let Err(e) = profile.engine.pause() {
+        warn!(
+            "[PROFILE] Failed to pause timer of {:?}: {}",
+            profile.user_id, e
+        );
+        return;
+    }
+    let entry_id = profile
+        .db
+        .get_app_meta("last_active_time_entry_id")
+        .ok()
+        .flatten()
+        .filter(|id| !id.is_empty() && !id.starts_with("temp-"))
+        .or_else(|| {
+            profile
+                .db
+                .get_last_time_entry_id_from_queue()
+                .ok()
+                .flatten()
+        });
+    if let Some(id) = entry_id {
+        if let Err(e) = profile.sync_manager.enqueue_time_entry(
+            "pause",
+            serde_json::json!({ "id": id }),
+            String::new(),
+            None,
+        ) {
+            warn!(
+                "[PROFILE] Failed to queue pause for {:?}: {}",
+                profile.user_id, e
+            );
+        }
+    }
+    info!(
+        "[PROFILE] Paused timer of inactive profile {:?}",
+        profile.user_id
+    );
+}
+
+/// Загруженные профили и активный (тот, с которым работают команды и frontend)
+pub struct ProfileManager {
+    dir: PathBuf,
+    app_db: Arc<Database>,
+    config: SyncConfig,
+    /// Профиль до первого входа (hubnity.db)
+    default: Profile,
+    /// Профили пользователей по user id
+    profiles: Mutex<HashMap<String, Profile>>,
+    active: RwLock<String>,
+    /// Sink событий frontend — только у SyncManager активного профиля
+    event_sink: RwLock<Option<EventSink>>,
+    /// Восстановленные БД профилей до появления event_sink (open_existing при старте)
+    recovered: Mutex<Vec<DbRecoveredEvent>>,
+}
+
+impl ProfileManager {
+    /// `app_db` — hubnity.db в `dir`; активен профиль до первого входа
+    pub fn new(dir: PathBuf, app_db: Arc<Database>, config: SyncConfig) -> Self {
+        let default = Profile::new(String::new(), app_db.clone(), &config);
+        Self {
+            dir,
+            app_db,
+            config,
+            default,
+            profiles: Mutex::new(HashMap::new()),
+            active: RwLock::new(String::new()),
+            event_sink: RwLock::new(None),
+            recovered: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// hubnity.db: настройки приложения и current_user_id
+    pub fn app_db(&self) -> &Arc<Database> {
+        &self.app_db
+    }
+
+    pub fn active_user_id(&self) -> String {
+        self.active
+            .read()
+            .map(|guard| guard.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn active(&self) -> Profile {
+        self.loaded(&self.active_user_id())
+            .unwrap_or_else(|| self.default.clone())
+    }
+
+    fn loaded(&self, user_id: &str) -> Option<Profile> {
+        if user_id.is_empty() {
+            return Some(self.default.clone());
+        }
+        self.profiles
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(user_id)
+            .cloned()
+    }
+
+    pub fn sync_manager(&self) -> SyncManager {
+        self.active().sync_manager
+    }
+
+    pub fn engine(&self) -> Arc<TimerEngine> {
+        self.active().engine
+    }
+
+    /// Все загруженные профили (активный первым)
+    pub fn profiles(&self) -> Vec<Profile> {
+        let active = self.active_user_id();
+        let mut all: Vec<Profile> = self
+            .profiles
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .values()
+            .cloned()
+            .collect();
+        all.push(self.default.clone());
+        all.sort_by_key(|p| (p.user_id != active, p.user_id.clone()));
+        all
+    }
+
+    pub fn set_event_sink(&self, sink: EventSink) {
+        if let Ok(mut guard) = self.event_sink.write() {
+            *guard = Some(sink.clone());
+        }
+        let recovered =
+            std::mem::take(&mut *self.recovered.lock().unwrap_or_else(|e| e.into_inner()));
+        for event in recovered {
+            self.report_recovery(event);
+        }
+        self.active().sync_manager.set_event_sink(sink);
+    }
+
+    /// db-recovered-from-corruption для frontend (или отложить до set_event_sink)
+    fn report_recovery(&self, event: DbRecoveredEvent) {
+        let sink = self.event_sink.read().ok().and_then(|guard| guard.clone());
+        match (sink, serde_json::to_value(&event)) {
+            (Some(sink), Ok(payload)) => sink(crate::ipc::events::DB_RECOVERED, payload),
+            (None, _) => self
+                .recovered
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .push(event),
+            (Some(_), Err(e)) => warn!("[PROFILE] Failed to serialize recovery event: {}", e),
+        }
+    }
+
+    /// Сохранить состояние таймеров всех профилей (закрытие окна, выход, panic).
+    /// Ошибка одного профиля не мешает сохранить остальные; возвращается последняя.
+    pub fn save_all(&self) -> Result<(), String> {
+        let mut result = Ok(());
+        for profile in self.profiles() {
+            if let Err(e) = profile.engine.save_state() {
+                result = Err(format!("profile {:?}: {}", profile.user_id, e));
+            }
+        }
+        result
+    }
+
+    /// БД, кроме БД профиля `user_id`, которым ещё нужен общий ключ шифрования (см.
+    /// `Database::depends_on_key`). Незагруженный файл профиля без открытия не проверить — считается
+    /// зависящим. С SQLCipher ключом зашифрован и файл самого профиля.
+    pub fn key_dependents(&self, user_id: &str) -> Vec<PathBuf> {
+        let own = self.loaded(user_id).map(|p| p.db.path().to_path_buf());
+        let loaded: Vec<Arc<Database>> = self.profiles().into_iter().map(|p| p.db).collect();
+        let same_file = |a: &Path, b: &Path| a.file_name() == b.file_name();
+        database_files(&self.dir)
+            .into_iter()
+            .filter(|path| {
+                if own.as_deref().is_some_and(|own| same_file(own, path)) {
+                    return self.app_db.encryption.database_key().is_some();
+                }
+                match loaded.iter().find(|db| same_file(db.path(), path)) {
+                    Some(db) => db.depends_on_key().unwrap_or(true),
+                    None => true,
+                }
+            })
+            .collect()
+    }
+
+    /// Перечитать ключ шифрования во всех загруженных БД (ключ заменён через экземпляр одной из них,
+    /// см. `TokenEncryption::purge_key_material`)
+    pub fn reload_keys(&self) -> Result<(), String> {
+        self.app_db.encryption.reload_keys()?;
+        for profile in self.profiles() {
+            profile.db.encryption.reload_keys()?;
+        }
+        Ok(())
+    }
+
+    /// БД всех профилей на диске: загруженные и остальные `hubnity-*.db` (открываются только для
+    /// вызывающего — ротация ключа, иначе их данные остались бы на удаляемом ключе).
+    /// Файл, который не открывается, — ошибка: ротацию нельзя завершать без него.
+    pub fn all_databases(&self) -> Result<Vec<Arc<Database>>, String> {
+        let mut dbs: Vec<Arc<Database>> = self.profiles().into_iter().map(|p| p.db).collect();
+        for path in database_files(&self.dir) {
+            if dbs
+                .iter()
+                .any(|db| db.path().file_name() == path.file_name())
+            {
+                continue;
+            }
+            // hubnity.db — app_db, всегда загружена
+            let stem = match path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .and_then(|n| n.strip_prefix(PROFILE_DB_PREFIX))
+                .and_then(|n| n.strip_suffix(".db"))
+            {
+                Some(stem) => stem.to_string(),
+                None => continue,
+            };
+            let db = self
+                .open_db(&stem)
+                .map_err(|e| format!("{}: {}", path.display(), e))?;
+            dbs.push(Arc::new(db));
+        }
+        Ok(dbs)
+    }
+
+    /// Загрузить профили с диска (`hubnity-*.db`), чтобы их очереди синхронизировались и без входа
+    pub fn open_existing(&self) -> usize {
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                warn!("[PROFILE] Failed to list profiles: {}", e);
+                return 0;
+            }
+        };
+        let mut opened = 0;
+        for path in entries.filter_map(|e| e.ok()).map(|e| e.path()) {
+            match self.open_profile_file(&path) {
+                Ok(Some(user_id)) => {
+                    info!("[PROFILE] Loaded profile {:?}", user_id);
+                    opened += 1;
+                }
+                Ok(None) => {}
+                Err(e) => warn!("[PROFILE] Skipping {}: {}", path.display(), e),
+            }
+        }
+        opened
+    }
+
+    fn open_profile_file(&self, path: &Path) -> Result<Option<String>, String> {
+        let stem = match path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .and_then(|n| n.strip_prefix(PROFILE_DB_PREFIX))
+            .and_then(|n| n.strip_suffix(".db"))
+        {
+            Some(stem) => stem.to_string(),
+            None => return Ok(None),
+        };
+        let db = self.open_db(&stem)?;
+        let owner = db
+            .get_app_meta(META_PROFILE_USER_ID)
+            .map_err(|e| format!("Failed to read profile owner: {}", e))?;
+        let user_id = match owner {
+            Some(id) if !id.is_empty() && profile_file_stem(&id) == stem => id,
+            _ => return Ok(None),
+        };
+        let mut profiles = self.profiles.lock().unwrap_or_else(|e| e.into_inner());
+        if !profiles.contains_key(&user_id) {
+            let profile = self.profile_from_db(&user_id, db);
+            profiles.insert(user_id.clone(), profile);
+        }
+        Ok(Some(user_id))
+    }
+
+    /// Открыть БД профиля; повреждённая — в карантин, восстановление из копии и salvage
+    fn open_db(&self, stem: &str) -> Result<Database, String> {
+        let path = self.dir.join(format!("{}{}.db", PROFILE_DB_PREFIX, stem));
+        let path_str = path
+            .to_str()
+            .ok_or_else(|| format!("Invalid profile path: {}", path.display()))?;
+        let blob_dir = self.dir.join(format!("blobs-{}", stem));
+        match Database::with_blob_dir(path_str, &blob_dir) {
+            Ok(db) => Ok(db),
+            Err(e) if crate::salvage::is_corruption_error(&e.to_string()) && path.exists() => {
+                warn!("[PROFILE] {} is corrupted: {}", path.display(), e);
+                let (db, event) = crate::salvage::recover_corrupted(&path, Some(&blob_dir))?;
+                self.report_recovery(event);
+                Ok(db)
+            }
+            Err(e) => Err(format!("Failed to open profile database: {}", e)),
+        }
+    }
+
+    fn open_profile(&self, user_id: &str) -> Result<Profile, String> {
+        let db = self.open_db(&profile_file_stem(user_id))?;
+        db.set_app_meta(META_PROFILE_USER_ID, user_id)
+            .map_err(|e| format!("Failed to record profile owner: {}", e))?;
+        Ok(self.profile_from_db(user_id, db))
+    }
+
+    fn profile_from_db(&self, user_id: &str, db: Database) -> Profile {
+        match db.switch_api_origin(&self.config.api_base_url) {
+            Ok(switch) if switch.changed => info!(
+                "[PROFILE] {:?}: API endpoint changed, parked {} tasks, restored {}",
+                user_id, switch.parked, switch.restored
+            ),
+            Ok(_) => {}
+            Err(e) => warn!(
+                "[PROFILE] {:?}: failed to record API endpoint: {}",
+                user_id, e
+            ),
+        }
+        Profile::new(user_id.to_string(), Arc::new(db), &self.config)
+    }
+
+    /// Данные hubnity.db (до профилей) принадлежат вошедшему ранее пользователю (или неизвестному,
+    /// если он вышел) — переносятся в его профиль, а не удаляются.
+    fn migrate_legacy_data(&self, user_id: &str) -> Result<bool, String> {
+        let path = profile_db_path(&self.dir, user_id);
+        if path.exists() || !self.app_db.has_user_data().unwrap_or(false) {
+            return Ok(false);
+        }
+        let owner = self
+            .app_db
+            .get_app_meta("current_user_id")
+            .map_err(|e| format!("Failed to get current user: {}", e))?
+            .unwrap_or_default();
+        if !owner.is_empty() && owner != user_id {
+            return Ok(false);
+        }
+        let _ = self.default.engine.save_state();
+        self.app_db
+            .copy_to(&path)
+            .map_err(|e| format!("Failed to copy data to profile: {}", e))?;
+        let legacy_blobs = self.dir.join("blobs");
+        let blob_dir = self
+            .dir
+            .join(format!("blobs-{}", profile_file_stem(user_id)));
+        if legacy_blobs.exists() && !blob_dir.exists() {
+            if let Err(e) = std::fs::rename(&legacy_blobs, &blob_dir) {
+                warn!("[PROFILE] Failed to move queued screenshots: {}", e);
+            }
+        }
+        info!("[PROFILE] Moved existing data to profile {:?}", user_id);
+        Ok(true)
+    }
+
+    /// Вход пользователя: его профиль становится активным, в hubnity.db записывается current_user_id.
+    /// Запись — после activate: migrate_legacy_data определяет владельца данных по прежнему значению.
+    pub async fn sign_in(&self, user_id: &str) -> Result<Profile, String> {
+        let profile = self.activate(user_id).await?;
+        let current = self
+            .app_db
+            .get_app_meta("current_user_id")
+            .map_err(|e| format!("Failed to get current user: {}", e))?;
+        if current.as_deref() != Some(user_id) {
+            self.app_db
+                .set_app_meta("current_user_id", user_id)
+                .map_err(|e| format!("Failed to set current user: {}", e))?;
+        }
+        Ok(profile)
+    }
+
+    /// Сделать профиль пользователя активным (открыть или создать его БД).
+    /// Прежний профиль остаётся загруженным: таймер поставлен на паузу и сохранён, очередь
+    /// синхронизируется в фоне.
+    pub async fn activate(&self, user_id: &str) -> Result<Profile, String> {
+        let mut migrated = false;
+        let profile = match self.loaded(user_id) {
+            Some(profile) => profile,
+            None => {
+                migrated = self.migrate_legacy_data(user_id)?;
+                let profile = self.open_profile(user_id)?;
+                self.profiles
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .entry(user_id.to_string())
+                    .or_insert(profile)
+                    .clone()
+            }
+        };
+        if migrated {
+            // Данные и refresh token теперь в профиле — в hubnity.db их не оставляем
+            let default = &self.default;
+            default
+                .db
+                .clear_user_data()
+                .map_err(|e| format!("Failed to clear migrated data: {}", e))?;
+            default
+                .sync_manager
+                .auth_manager
+                .set_tokens(None, None)
+                .await;
+            if let Err(e) = default.engine.reset_state() {
+                warn!("[PROFILE] Failed to reset default timer: {}", e);
+            }
+        }
+
+        let previous = match self.active.write() {
+            Ok(mut active) => std::mem::replace(&mut *active, user_id.to_string()),
+            Err(_) => return Err("Profile state poisoned".to_string()),
+        };
+        if previous != user_id {
+            if let Some(old) = self.loaded(&previous) {
+                pause_running_timer(&old);
+                if let Err(e) = old.engine.save_state() {
+                    warn!(
+                        "[PROFILE] Failed to save timer state for {:?}: {}",
+                        previous, e
+                    );
+                }
+                old.sync_manager.clear_event_sink();
+            }
+            let sink = self.event_sink.read().ok().and_then(|guard| guard.clone());
+            if let Some(sink) = sink {
+                profile.sync_manager.set_event_sink(sink);
+            }
+            info!("[PROFILE] Active profile {:?} -> {:?}", previous, user_id);
+        }
+        Ok(profile)
+    }
+}
@@ -0,0 +1,207 @@
+//! Local cache of projects and tasks (GET /projects, GET /tasks) for offline project selection.
+//! Incremental refresh by `updatedSince` cursor; periodic full refresh marks vanished rows deleted.
+
+use crate::models::{CachedProject, CachedTask, ProjectCacheRefresh};
+use crate::Database;
+use chrono::Utc;
+use tracing::{debug, info};
+
+const META_PROJECTS_CURSOR: &str = "projects_cache_cursor";
+const META_TASKS_CURSOR: &str = "tasks_cache_cursor";
+const META_FULL_AT: &str = "projects_cache_full_at";
+
+/// Полное обновление (с пометкой исчезнувших как deleted) — не реже раза в сутки
+const FULL_SYNC_INTERVAL_SECS: i64 = 24 * 60 * 60;
+
+fn json_id(value: &serde_json::Value) -> Option<String> {
+    match value {
+        serde_json::Value::String(s) if !s.is_empty() => Some(s.clone()),
+        serde_json::Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+fn json_str(value: &serde_json::Value, key: &str) -> Option<String> {
+    value
+        .get(key)
+        .and_then(|v| v.as_str())
+        .map(|s| s.to_string())
+}
+
+fn is_archived(item: &serde_json::Value, status: Option<&str>) -> bool {
+    item.get("archived").and_then(|v| v.as_bool()) == Some(true)
+        || item.get("isArchived").and_then(|v| v.as_bool()) == Some(true)
+        || status.is_some_and(|s| s.eq_ignore_ascii_case("archived"))
+}
+
+fn is_deleted(item: &serde_json::Value) -> bool {
+    item.get("deleted").and_then(|v| v.as_bool()) == Some(true)
+        || item.get("deletedAt").is_some_and(|v| !v.is_null())
+}
+
+/// Проект из ответа API (поля как в src/lib/api.ts Project)
+pub fn parse_project(item: &serde_json::Value) -> Option<CachedProject> {
+    let status = json_str(item, "status");
+    Some(CachedProject {
+        id: json_id(item.get("id")?)?,
+        name: json_str(item, "name")?,
+        description: json_str(item, "description"),
+        color: json_str(item, "color"),
+        client_name: json_str(item, "clientName"),
+        archived: is_archived(item, status.as_deref()),
+        deleted: is_deleted(item),
+        updated_at: json_str(item, "updatedAt"),
+        status,
+    })
+}
+
+/// Задача из ответа API (projectId или вложенный project.id; name или title)
+pub fn parse_task(item: &serde_json::Value) -> Option<CachedTask> {
+    let status = json_str(item, "status");
+    let project_id = item.get("projectId").and_then(json_id).or_else(|| {
+        item.get("project")
+            .and_then(|p| p.get("id"))
+            .and_then(json_id)
+    })?;
+    Some(CachedTask {
+        id: json_id(item.get("id")?)?,
+        project_id,
+        name: json_str(item, "name").or_else(|| json_str(item, "title"))?,
+        archived: is_archived(item, status.as_deref()),
+        deleted: is_deleted(item),
+        updated_at: json_str(item, "updatedAt"),
+        status,
+    })
+}
+
+/// Список из ответа: массив или обёртка { data: [...] } / { items: [...] }
+fn response_items(body: &serde_json::Value) -> Vec<serde_json::Value> {
+    body.as_array()
+        .or_else(|| body.get("data").and_then(|v| v.as_array()))
+        .or_else(|| body.get("items").and_then(|v| v.as_array()))
+        .cloned()
+        .unwrap_or_default()
+}
+
+/// Максимальный updatedAt (ISO 8601 сравнивается лексикографически) — следующий курсор
+fn max_cursor<'a>(
+    previous: Option<String>,
+    updated: impl Iterator<Item = Option<&'a String>>,
+) -> Option<String> {
+    updated.flatten().cloned().chain(previous).max()
+}
+
+/// GET списка; Ok(None) — endpoint не поддерживается (404)
+async fn fetch_items(
+    client: &reqwest::Client,
+    url: &str,
+    access_token: &str,
+    cursor: Option<&str>,
+) -> Result<Option<Vec<serde_json::Value>>, String> {
+    let mut query: Vec<(&str, &str)> = vec![("includeArchived", "true")];
+    if let Some(cursor) = cursor {
+        query.push(("updatedSince", cursor));
+        query.push(("includeDeleted", "true"));
+    }
+    let response = client
+        .get(url)
+        .query(&query)
+        .header("Authorization", format!("Bearer {}", access_token))
+        .header("Accept", "application/json")
+        .send()
+        .await
+        .map_err(|e| format!("Request {} failed: {}", url, e))?;
+    let status = response.status();
+    if status == reqwest::StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    if !status.is_success() {
+        return Err(format!("Request {} failed: HTTP {}", url, status.as_u16()));
+    }
+    let body: serde_json::Value = response
+        .json()
+        .await
+        .map_err(|e| format!("Invalid response from {}: {}", url, e))?;
+    Ok(Some(response_items(&body)))
+}
+
+/// Обновить кэш проектов и задач. `force_full` — полный список (ручное обновление).
+pub async fn refresh(
+    db: &Database,
+    client: &reqwest::Client,
+    api_base_url: &str,
+    access_token: &str,
+    force_full: bool,
+) -> Result<ProjectCacheRefresh, String> {
+    let now = Utc::now().timestamp();
+    let meta = |key: &str| {
+        db.get_app_meta(key)
+            .ok()
+            .flatten()
+            .filter(|v| !v.is_empty())
+    };
+    let last_full_at = meta(META_FULL_AT).and_then(|v| v.parse::<i64>().ok());
+    let full_sync =
+        force_full || !matches!(last_full_at, Some(at) if now - at < FULL_SYNC_INTERVAL_SECS);
+    let projects_cursor = if full_sync {
+        None
+    } else {
+        meta(META_PROJECTS_CURSOR)
+    };
+    let tasks_cursor = if full_sync {
+        None
+    } else {
+        meta(META_TASKS_CURSOR)
+    };
+
+    let mut result = ProjectCacheRefresh {
+        full_sync,
+        ..Default::default()
+    };
+
+    let projects_url = format!("{}/projects", api_base_url);
+    let items = fetch_items(
+        client,
+        &projects_url,
+        access_token,
+        projects_cursor.as_deref(),
+    )
+    .await?
+    .ok_or_else(|| format!("Request {} failed: HTTP 404", projects_url))?;
+    let projects: Vec<CachedProject> = items.iter().filter_map(parse_project).collect();
+    result.projects_updated = db
+        .upsert_cached_projects(&projects, full_sync)
+        .map_err(|e| format!("Failed to cache projects: {}", e))?;
+    if let Some(cursor) = max_cursor(
+        projects_cursor,
+        projects.iter().map(|p| p.updated_at.as_ref()),
+    ) {
+        let _ = db.set_app_meta(META_PROJECTS_CURSOR, &cursor);
+    }
+
+    let tasks_url = format!("{}/tasks", api_base_url);
+    match fetch_items(client, &tasks_url, access_token, tasks_cursor.as_deref()).await? {
+        Some(items) => {
+            let tasks: Vec<CachedTask> = items.iter().filter_map(parse_task).collect();
+            result.tasks_supported = true;
+            result.tasks_updated = db
+                .upsert_cached_tasks(&tasks, full_sync)
+                .map_err(|e| format!("Failed to cache tasks: {}", e))?;
+            if let Some(cursor) =
+                max_cursor(tasks_cursor, tasks.iter().map(|t| t.updated_at.as_ref()))
+            {
+                let _ = db.set_app_meta(META_TASKS_CURSOR, &cursor);
+            }
+        }
+        None => debug!("[PROJECTS] /tasks not supported by server, skipping task cache"),
+    }
+
+    if full_sync {
+        let _ = db.set_app_meta(META_FULL_AT, &now.to_string());
+    }
+    info!(
+        "[PROJECTS] Cache refreshed (full={}): {} projects, {} tasks",
+        full_sync, result.projects_updated, result.tasks_updated
+    );
+    Ok(result)
+}
@@ -0,0 +1,393 @@
+//! Salvage of a quarantined (corrupted) database.
+//! `integrity_check` fails for the whole file, but usually only a few pages are damaged. The file is
+//! opened read-only and `sync_queue` / `time_entries` are read in rowid chunks; a chunk that hits a
+//! broken page is retried row by row, so one bad page costs only the rows stored on it.
+//! Unsent tasks are re-enqueued into the fresh database with their idempotency keys (the server
+//! deduplicates a task that was in fact sent), encrypted payloads are copied as is; payloads from a
+//! file that predates migration 12 are bound to their row (see `database::payload_aad`) on the way.
+//! `app_meta` values missing in the new database (settings, profile owner, refresh token) are copied
+//! too. `recover_corrupted` is the whole path: quarantine, restore a backup, salvage.
+//! `salvage_queue_into` carries the queue of a database replaced by a user-chosen backup over into
+//! the restored one (time entries stay as restored).
+
+use crate::auth::TokenEncryption;
+use crate::database::payload_aad;
+use crate::db_pool::{apply_key, is_plaintext_database};
+use crate::models::{DbRecoveredEvent, SalvageReport};
+use crate::Database;
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension, Result as SqliteResult, Row};
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+/// Строк за один запрос; при ошибке чанк читается построчно
+const SALVAGE_CHUNK: i64 = 100;
+
+/// Задача очереди из повреждённой БД
+#[derive(Debug, Clone)]
+struct SalvagedTask {
+    entity_type: String,
+    payload: String,
+    status: String,
+    retry_count: i32,
+    created_at: i64,
+    priority: i32,
+    idempotency_key: Option<String>,
+    blob_ref: Option<String>,
+    content_fingerprint: Option<String>,
+    api_origin: Option<String>,
+    clock_offset_ms: Option<i64>,
+}
+
+#[derive(Debug, Clone)]
+struct SalvagedEntry {
+    day: String,
+    accumulated_seconds: i64,
+    state: String,
+    last_updated_at: i64,
+    started_at: Option<i64>,
+}
+
+/// Колонка, если она есть в схеме повреждённой БД (файл мог не дойти до последних миграций)
+fn column_or(conn: &Connection, table: &str, column: &str, default: &str) -> String {
+    let exists = conn
+        .query_row(
+            "SELECT 1 FROM pragma_table_info(?1) WHERE name = ?2",
+            params![table, column],
+            |_| Ok(()),
+        )
+        .optional()
+        .ok()
+        .flatten()
+        .is_some();
+    if exists {
+        column.to_string()
+    } else {
+        default.to_string()
+    }
+}
+
+/// Верхняя граница rowid: MAX(rowid), иначе sqlite_sequence (AUTOINCREMENT)
+fn max_rowid(conn: &Connection, table: &str) -> Option<i64> {
+    let max = conn
+        .query_row(&format!("SELECT MAX(rowid) FROM {}", table), [], |r| {
+            r.get::<_, Option<i64>>(0)
+        })
+        .ok()
+        .flatten();
+    let seq = conn
+        .query_row(
+            "SELECT seq FROM sqlite_sequence WHERE name = ?1",
+            params![table],
+            |r| r.get::<_, i64>(0),
+        )
+        .ok();
+    max.into_iter().chain(seq).max()
+}
+
+/// Прочитать строки `select ... WHERE rowid BETWEEN ?1 AND ?2` по чанкам.
+/// Возвращает (строки, нечитаемых rowid).
+fn read_chunked<T>(
+    conn: &Connection,
+    table: &str,
+    select: &str,
+    map: impl Fn(&Row) -> SqliteResult<T>,
+) -> (Vec<T>, usize) {
+    let max = match max_rowid(conn, table) {
+        Some(max) => max,
+        None => {
+            warn!("[SALVAGE] {}: table is unreadable", table);
+            return (Vec::new(), 0);
+        }
+    };
+    let read = |from: i64, to: i64| -> SqliteResult<Vec<T>> {
+        let mut stmt = conn.prepare(&format!("{} WHERE rowid BETWEEN ?1 AND ?2", select))?;
+        let rows = stmt.query_map(params![from, to], &map)?;
+        rows.collect()
+    };
+
+    let mut result = Vec::new();
+    let mut unreadable = 0;
+    let mut from = 1;
+    while from <= max {
+        let to = from + SALVAGE_CHUNK - 1;
+        match read(from, to) {
+            Ok(rows) => result.extend(rows),
+            Err(_) => {
+                for rowid in from..=to.min(max) {
+                    match read(rowid, rowid) {
+                        Ok(rows) => result.extend(rows),
+                        Err(_) => unreadable += 1,
+                    }
+                }
+            }
+        }
+        from = to + 1;
+    }
+    (result, unreadable)
+}
+
+fn read_tasks(conn: &Connection) -> (Vec<SalvagedTask>, usize) {
+    let select = format!(
+        "SELECT entity_type, payload, status, retry_count, created_at, {}, {}, {}, {}, {}, {}
+         FROM sync_queue",
+        column_or(conn, "sync_queue", "priority", "2"),
+        column_or(conn, "sync_queue", "idempotency_key", "NULL"),
+        column_or(conn, "sync_queue", "blob_ref", "NULL"),
+        column_or(conn, "sync_queue", "content_fingerprint", "NULL"),
+        column_or(conn, "sync_queue", "api_origin", "NULL"),
+        column_or(conn, "sync_queue", "clock_offset_ms", "NULL"),
+    );
+    let (tasks, unreadable) = read_chunked(conn, "sync_queue", &select, |row| {
+        Ok(SalvagedTask {
+            entity_type: row.get(0)?,
+            payload: row.get(1)?,
+            status: row.get(2)?,
+            retry_count: row.get(3)?,
+            created_at: row.get(4)?,
+            priority: row.get(5)?,
+            idempotency_key: row.get(6)?,
+            blob_ref: row.get(7)?,
+            content_fingerprint: row.get(8)?,
+            api_origin: row.get(9)?,
+            clock_offset_ms: row.get(10)?,
+        })
+    });
+    let unsent = tasks
+        .into_iter()
+        .filter(|t| matches!(t.status.as_str(), "pending" | "failed" | "parked"))
+        .collect();
+    (unsent, unreadable)
+}
+
+fn read_entries(conn: &Connection) -> (Vec<SalvagedEntry>, usize) {
+    let select = format!(
+        "SELECT day, accumulated_seconds, state, last_updated_at, {} FROM time_entries",
+        column_or(conn, "time_entries", "started_at", "NULL"),
+    );
+    read_chunked(conn, "time_entries", &select, |row| {
+        Ok(SalvagedEntry {
+            day: row.get(0)?,
+            accumulated_seconds: row.get(1)?,
+            state: row.get(2)?,
+            last_updated_at: row.get(3)?,
+            started_at: row.get(4)?,
+        })
+    })
+}
+
+fn read_meta(conn: &Connection) -> (Vec<(String, Option<String>)>, usize) {
+    read_chunked(conn, "app_meta", "SELECT key, value FROM app_meta", |row| {
+        Ok((row.get(0)?, row.get(1)?))
+    })
+}
+
+/// Перенести читаемые неотправленные задачи, записи таймера и недостающие app_meta из `corrupted` в `db`.
+/// Задачи с idempotency_key, который уже есть в `db` (восстановлена из копии), не дублируются;
+/// запись дня заменяется, только если в повреждённой БД она новее.
+pub fn salvage_into(corrupted: &Path, db: &Database) -> Result<SalvageReport, String> {
+    salvage(corrupted, db, true)
+}
+
+/// То же для БД, заменённой копией по restore_backup: неотправленные задачи и недостающие app_meta,
+/// без записей таймера — их состояние берётся из восстановленной копии
+pub fn salvage_queue_into(replaced: &Path, db: &Database) -> Result<SalvageReport, String> {
+    salvage(replaced, db, false)
+}
+
+fn salvage(source: &Path, db: &Database, with_entries: bool) -> Result<SalvageReport, String> {
+    let conn = Connection::open_with_flags(
+        source,
+        OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+    )
+    .map_err(|e| format!("Failed to open quarantined database: {}", e))?;
+    if !is_plaintext_database(source) {
+        apply_key(&conn, db.encryption.database_key().as_deref())
+            .map_err(|e| format!("Failed to key quarantined database: {}", e))?;
+    }
+
+    let (mut tasks, unreadable_tasks) = read_tasks(&conn);
+    let (entries, unreadable_entries) = if with_entries {
+        read_entries(&conn)
+    } else {
+        (Vec::new(), 0)
+    };
+    let (meta, unreadable_meta) = read_meta(&conn);
+    drop(conn);
+
+    // Файл до миграции 12: payload ещё не привязан к строке. Нерасшифровываемый копируется
+    // как есть — get_retry_tasks его отклонит, как и в исходной БД.
+    for task in &mut tasks {
+        if task.payload.is_empty() || db.encryption.is_bound(&task.payload) {
+            continue;
+        }
+        let aad = payload_aad(&task.entity_type, task.idempotency_key.as_deref());
+        if let Ok(bound) = db
+            .encryption
+            .decrypt_with_legacy_fallback(&task.payload)
+            .and_then(|(plaintext, _)| db.encryption.encrypt_with_aad(&plaintext, &aad))
+        {
+            task.payload = bound;
+        }
+    }
+
+    let (mut report, meta_rescued) = db
+        .write(move |conn| {
+            let tx = conn.savepoint()?;
+            let mut report = SalvageReport::default();
+            for task in &tasks {
+                let exists = match &task.idempotency_key {
+                    Some(key) => tx
+                        .query_row(
+                            "SELECT 1 FROM sync_queue WHERE idempotency_key = ?1",
+                            params![key],
+                            |_| Ok(()),
+                        )
+                        .optional()?
+                        .is_some(),
+                    None => false,
+                };
+                if exists {
+                    report.sync_tasks_skipped += 1;
+                    continue;
+                }
+                tx.execute(
+                    "INSERT INTO sync_queue (entity_type, payload, status, retry_count, created_at,
+                         priority, idempotency_key, blob_ref, content_fingerprint, api_origin,
+                         clock_offset_ms)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+                    params![
+                        task.entity_type,
+                        task.payload,
+                        task.status,
+                        task.retry_count,
+                        task.created_at,
+                        task.priority,
+                        task.idempotency_key,
+                        task.blob_ref,
+                        task.content_fingerprint,
+                        task.api_origin,
+                        task.clock_offset_ms,
+                    ],
+                )?;
+                report.sync_tasks_rescued += 1;
+            }
+            for entry in &entries {
+                report.time_entries_rescued += tx.execute(
+                    "INSERT INTO time_entries (day, accumulated_seconds, state, last_updated_at, started_at)
+                     VALUES (?1, ?2, ?3, ?4, ?5)
+                     ON CONFLICT(day) DO UPDATE SET
+                        accumulated_seconds = excluded.accumulated_seconds,
+                        state = excluded.state,
+                        last_updated_at = excluded.last_updated_at,
+                        started_at = excluded.started_at
+                     WHERE excluded.last_updated_at > time_entries.last_updated_at",
+                    params![
+                        entry.day,
+                        entry.accumulated_seconds,
+                        entry.state,
+                        entry.last_updated_at,
+                        entry.started_at,
+                    ],
+                )?;
+            }
+            // Значения восстановленной копии не перезаписываются
+            let mut meta_rescued = 0;
+            for (key, value) in &meta {
+                meta_rescued += tx.execute(
+                    "INSERT OR IGNORE INTO app_meta (key, value) VALUES (?1, ?2)",
+                    params![key, value],
+                )?;
+            }
+            tx.commit()?;
+            Ok((report, meta_rescued))
+        })
+        .map_err(|e| format!("Failed to store salvaged rows: {}", e))?;
+    report.unreadable_rows = unreadable_tasks + unreadable_entries + unreadable_meta;
+    info!(
+        "[SALVAGE] Rescued {} queued tasks ({} already present), {} time entries, {} app_meta values; {} rows unreadable",
+        report.sync_tasks_rescued,
+        report.sync_tasks_skipped,
+        report.time_entries_rescued,
+        meta_rescued,
+        report.unreadable_rows
+    );
+    Ok(report)
+}
+
+/// Ошибка открытия БД, после которой файл переносится в карантин
+pub fn is_corruption_error(error: &str) -> bool {
+    error.contains("corruption") || error.contains("integrity")
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> OsString {
+    let mut path = path.as_os_str().to_os_string();
+    path.push(suffix);
+    path
+}
+
+/// Повреждённая БД: файл (вместе с WAL) → `<name>.corrupted.<ts>`, восстановить новейшую копию,
+/// проходящую integrity_check (или начать с пустой), и перенести читаемые неотправленные задачи и
+/// записи таймера. Orphan-blobs удаляются только после salvage — иначе пропали бы скриншоты
+/// спасённых задач.
+pub fn recover_corrupted(
+    db_path: &Path,
+    blob_dir: Option<&Path>,
+) -> Result<(Database, DbRecoveredEvent), String> {
+    let path_str = db_path
+        .to_str()
+        .ok_or_else(|| format!("Invalid database path: {}", db_path.display()))?;
+    let quarantined = PathBuf::from(with_suffix(
+        db_path,
+        &format!(".corrupted.{}", chrono::Utc::now().timestamp()),
+    ));
+    fs::rename(db_path, &quarantined).map_err(|e| {
+        format!(
+            "Database corrupted and could not be moved to {}: {}",
+            quarantined.display(),
+            e
+        )
+    })?;
+    // WAL повреждённой БД — вместе с ней (в нём последние записи для salvage);
+    // рядом с новой БД он бы применился к чужому файлу
+    let _ = fs::rename(
+        with_suffix(db_path, "-wal"),
+        with_suffix(&quarantined, "-wal"),
+    );
+    let _ = fs::remove_file(with_suffix(db_path, "-shm"));
+
+    let db_key = TokenEncryption::new(db_path.parent())
+        .ok()
+        .and_then(|encryption| encryption.database_key());
+    let restored_backup = crate::backup::restore_newest_good(db_path, db_key.as_deref());
+    match &restored_backup {
+        Some(backup) => info!(
+            "[DB] Corrupted DB moved to {:?}, restored backup {}",
+            quarantined, backup.file_name
+        ),
+        None => info!(
+            "[DB] Corrupted DB moved to {:?}, no usable backup, starting fresh",
+            quarantined
+        ),
+    }
+
+    let db = Database::open_for_recovery(path_str, blob_dir)
+        .map_err(|e| format!("Failed to create fresh database: {}", e))?;
+    let salvage = match salvage_into(&quarantined, &db) {
+        Ok(report) => {
+            db.sweep_orphan_blobs();
+            Some(report)
+        }
+        Err(e) => {
+            warn!("[DB] Salvage of corrupted DB failed: {}", e);
+            None
+        }
+    };
+    let event = DbRecoveredEvent {
+        corrupted_path: quarantined.to_string_lossy().into_owned(),
+        restored_backup,
+        salvage,
+    };
+    Ok((db, event))
+}
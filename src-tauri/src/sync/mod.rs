@@ -1,13 +1,21 @@
-use crate::auth::AuthManager;
+use crate::auth::{AuthConfig, AuthManager};
+use crate::clock::ClockOffset;
+use crate::connectivity::{ConnectivityConfig, ConnectivityMonitor, ConnectivityState};
 use crate::database::enqueue_error_to_user_message;
+use crate::http_client::{HttpClientFactory, TransportErrorKind};
+use crate::ipc::EventSink;
 #[cfg(test)]
 use crate::models::TokenRefreshResult;
+use crate::models::{
+    LoggedOutEvent, LogoutQueuePolicy, SyncFinishedEvent, SyncRunMetrics, SyncStartedEvent,
+    SyncTaskEvent,
+};
 use crate::Database;
 use scopeguard::guard;
 use std::fmt;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
-use std::time::Duration;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
 use tracing::{debug, error, info, warn};
 
 /// Ошибки синхронизации (для разбора и логирования)
@@ -16,7 +24,13 @@ pub enum SyncError {
     ParsePayload(String),
     Auth(String),
     Network(String),
+    /// Прокси недоступен / отклонил CONNECT / требует авторизацию (407)
+    Proxy(String),
+    /// Ошибка TLS (недоверенный сертификат, TLS-inspecting gateway без CA в настройках)
+    Tls(String),
     Http { status: u16, message: String },
+    /// 429/503 с Retry-After: задача не отправлена и не считается неудачной попыткой
+    RateLimited(Duration),
     UnknownOperation(String),
     Db(String),
 }
@@ -27,13 +41,92 @@ impl fmt::Display for SyncError {
             SyncError::ParsePayload(s) => write!(f, "Parse payload: {}", s),
             SyncError::Auth(s) => write!(f, "Auth: {}", s),
             SyncError::Network(s) => write!(f, "Network: {}", s),
+            SyncError::Proxy(s) => write!(f, "Proxy: {}", s),
+            SyncError::Tls(s) => write!(f, "TLS: {}", s),
             SyncError::Http { status, message } => write!(f, "HTTP {}: {}", status, message),
+            SyncError::RateLimited(delay) => write!(f, "Rate limited, retry after {:?}", delay),
             SyncError::UnknownOperation(s) => write!(f, "Unknown operation: {}", s),
             SyncError::Db(s) => write!(f, "DB: {}", s),
         }
     }
 }
 
+impl SyncError {
+    /// Класс ошибки для UI (error_class в sync-task-failed)
+    pub fn kind(&self) -> &'static str {
+        match self {
+            SyncError::ParsePayload(_) => "parse",
+            SyncError::Auth(_) => "auth",
+            SyncError::Network(_) => "network",
+            SyncError::Proxy(_) => "proxy",
+            SyncError::Tls(_) => "tls",
+            SyncError::Http { status, .. } if *status >= 500 => "server",
+            SyncError::Http { .. } => "http",
+            SyncError::RateLimited(_) => "rate_limited",
+            SyncError::UnknownOperation(_) => "unknown_operation",
+            SyncError::Db(_) => "db",
+        }
+    }
+
+    /// Ошибка транспорта reqwest → Proxy / Tls / Network
+    pub fn from_transport(error: &reqwest::Error, http: &HttpClientFactory) -> Self {
+        let message = crate::http_client::error_chain(error);
+        match http.classify(error) {
+            TransportErrorKind::Proxy => SyncError::Proxy(message),
+            TransportErrorKind::Tls => SyncError::Tls(message),
+            TransportErrorKind::Network => SyncError::Network(message),
+        }
+    }
+}
+
+/// 429 без Retry-After
+const DEFAULT_RATE_LIMIT_DELAY: Duration = Duration::from_secs(30);
+/// Верхняя граница Retry-After (ошибочный заголовок не должен остановить синхронизацию надолго)
+const MAX_RATE_LIMIT_DELAY: Duration = Duration::from_secs(60 * 60);
+
+/// Retry-After: секунды или HTTP-date (RFC 9110 §10.2.3)
+pub(crate) fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let at = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    let secs = (at.timestamp() - chrono::Utc::now().timestamp()).max(0);
+    Some(Duration::from_secs(secs as u64))
+}
+
+/// Nearest-rank percentile по отсортированной выборке
+pub(crate) fn percentile(sorted: &[u64], p: usize) -> Option<u64> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let rank = (p * sorted.len()).div_ceil(100).max(1);
+    sorted.get(rank.min(sorted.len()) - 1).copied()
+}
+
+/// Сжатие тел sync-запросов (Content-Encoding)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestCompression {
+    /// Никогда не сжимать
+    Off,
+    /// Всегда gzip (сервер заведомо поддерживает)
+    Gzip,
+    /// gzip только после того, как сервер объявил поддержку (Accept-Encoding в ответе, RFC 7694)
+    Auto,
+}
+
+impl RequestCompression {
+    /// Значение из app_meta / настроек: "off" | "gzip" | "auto"
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "off" | "none" | "identity" => Some(Self::Off),
+            "gzip" => Some(Self::Gzip),
+            "auto" => Some(Self::Auto),
+            _ => None,
+        }
+    }
+}
+
 /// Конфигурация синхронизации (api_base_url, таймауты, app_version)
 #[derive(Clone)]
 pub struct SyncConfig {
@@ -41,18 +134,45 @@ pub struct SyncConfig {
     pub http_timeout_secs: u64,
     /// App version sent in X-App-Version header for debugging version skew
     pub app_version: String,
+    /// Сжатие тел запросов; Auto — только если сервер объявил поддержку gzip
+    pub request_compression: RequestCompression,
+    /// Health endpoint для ConnectivityMonitor (None — {api_base_url}/health)
+    pub health_check_url: Option<String>,
+    /// Прокси и дополнительные CA — общие для всех HTTP-клиентов
+    pub http: HttpClientFactory,
 }
 
 impl Default for SyncConfig {
     fn default() -> Self {
         Self {
-            api_base_url: "https://app.automatonsoft.de/api".to_string(),
+            api_base_url: crate::endpoint::DEFAULT_API_BASE_URL.to_string(),
             http_timeout_secs: 120,
             app_version: env!("CARGO_PKG_VERSION").to_string(),
+            request_compression: RequestCompression::Auto,
+            health_check_url: None,
+            http: HttpClientFactory::default(),
         }
     }
 }
 
+/// gzip-кодирование тела запроса
+pub(crate) fn gzip_encode(data: &[u8]) -> std::io::Result<Vec<u8>> {
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    let mut encoder = GzEncoder::new(Vec::with_capacity(data.len() / 2), Compression::default());
+    encoder.write_all(data)?;
+    encoder.finish()
+}
+
+/// Размер тела запроса: до сжатия и отправляемый
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct UploadSize {
+    pub raw: usize,
+    pub sent: usize,
+}
+
 /// Приоритет задачи синхронизации (используется sync и database)
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum TaskPriority {
@@ -84,6 +204,23 @@ pub struct SyncManager {
     pub(crate) is_syncing: Arc<AtomicBool>,
     pub(crate) client: reqwest::Client,
     pub(crate) app_version: String,
+    pub(crate) request_compression: RequestCompression,
+    /// Сервер объявил Accept-Encoding: gzip (RFC 7694) — для RequestCompression::Auto
+    pub(crate) server_accepts_gzip: Arc<AtomicBool>,
+    /// Прогресс синхронизации → frontend (общий для всех клонов)
+    pub(crate) event_sink: Arc<RwLock<Option<EventSink>>>,
+    /// Кэшированное состояние связи с API (вместо inline-проб внешних хостов)
+    pub(crate) connectivity: Arc<ConnectivityMonitor>,
+    /// Factory, из которого построены client и клиенты AuthManager/ConnectivityMonitor
+    pub(crate) http: HttpClientFactory,
+    /// 429/503 + Retry-After: до этого момента очередь не отправляется (общий для всех клонов)
+    pub(crate) rate_limited_until: Arc<Mutex<Option<Instant>>>,
+    /// Байты тел запросов (после сжатия) — дельта за запуск идёт в sync_metrics
+    pub(crate) bytes_sent: Arc<AtomicU64>,
+    /// Латентность HTTP-запросов текущего запуска (мс)
+    pub(crate) request_latencies_ms: Arc<Mutex<Vec<u64>>>,
+    /// Смещение часов сервера (по заголовкам Date) — для clientTime в time-entry payload
+    pub(crate) clock: Arc<ClockOffset>,
 }
 
 impl SyncManager {
@@ -94,17 +231,144 @@ impl SyncManager {
     }
 
     pub fn new_with_config(db: Arc<Database>, config: SyncConfig) -> Self {
-        let client = reqwest::Client::builder()
-            .timeout(Duration::from_secs(config.http_timeout_secs))
-            .build()
-            .unwrap_or_else(|_| reqwest::Client::new());
+        let client = config
+            .http
+            .build(Duration::from_secs(config.http_timeout_secs));
+        let event_sink = Arc::new(RwLock::new(None));
+        let mut connectivity_config = ConnectivityConfig::for_api(&config.api_base_url);
+        if let Some(url) = config.health_check_url.clone() {
+            connectivity_config.health_url = url;
+        }
+        let connectivity = Arc::new(ConnectivityMonitor::new(
+            connectivity_config,
+            &config.http,
+            event_sink.clone(),
+        ));
+        let clock = Arc::new(ClockOffset::load(&db));
+        let auth_manager = Arc::new(AuthManager::new_with_config(AuthConfig {
+            api_base_url: config.api_base_url.clone(),
+            http: config.http.clone(),
+            event_sink: event_sink.clone(),
+            token_store: Some(db.clone()),
+            ..Default::default()
+        }));
         Self {
             db,
             api_base_url: config.api_base_url.clone(),
-            auth_manager: Arc::new(AuthManager::new(config.api_base_url)),
+            auth_manager,
             is_syncing: Arc::new(AtomicBool::new(false)),
             client,
             app_version: config.app_version.clone(),
+            request_compression: config.request_compression,
+            server_accepts_gzip: Arc::new(AtomicBool::new(false)),
+            event_sink,
+            connectivity,
+            http: config.http,
+            rate_limited_until: Arc::new(Mutex::new(None)),
+            bytes_sent: Arc::new(AtomicU64::new(0)),
+            request_latencies_ms: Arc::new(Mutex::new(Vec::new())),
+            clock,
+        }
+    }
+
+    /// Подключить отправку событий прогресса (sync-started / sync-task-* / sync-finished)
+    pub fn set_event_sink(&self, sink: EventSink) {
+        if let Ok(mut guard) = self.event_sink.write() {
+            *guard = Some(sink);
+        }
+    }
+
+    /// Отключить события (неактивный профиль: его токены и прогресс не должны попасть во frontend)
+    pub fn clear_event_sink(&self) {
+        if let Ok(mut guard) = self.event_sink.write() {
+            *guard = None;
+        }
+    }
+
+    fn emit_event<T: serde::Serialize>(&self, event: &str, payload: &T) {
+        let sink = match self.event_sink.read() {
+            Ok(guard) => guard.clone(),
+            Err(_) => return,
+        };
+        if let Some(sink) = sink {
+            match serde_json::to_value(payload) {
+                Ok(value) => sink(event, value),
+                Err(e) => debug!("[SYNC] Failed to serialize {} event: {}", event, e),
+            }
+        }
+    }
+
+    /// Тела меньше этого не сжимаем — заголовок gzip съест выигрыш
+    const MIN_COMPRESS_BYTES: usize = 512;
+
+    fn gzip_enabled(&self) -> bool {
+        match self.request_compression {
+            RequestCompression::Off => false,
+            RequestCompression::Gzip => true,
+            RequestCompression::Auto => self.server_accepts_gzip.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Установить тело запроса, при необходимости сжав его (Content-Encoding: gzip).
+    /// Возвращает размер тела до/после сжатия — учитывается в QueueStats только после успешной отправки.
+    fn with_body(
+        &self,
+        request: reqwest::RequestBuilder,
+        body: String,
+        allow_compression: bool,
+    ) -> (reqwest::RequestBuilder, UploadSize) {
+        let raw_len = body.len();
+        if allow_compression && raw_len >= Self::MIN_COMPRESS_BYTES && self.gzip_enabled() {
+            match gzip_encode(body.as_bytes()) {
+                Ok(encoded) if encoded.len() < raw_len => {
+                    debug!(
+                        "[SYNC] Request body gzip: {} -> {} bytes",
+                        raw_len,
+                        encoded.len()
+                    );
+                    let size = UploadSize {
+                        raw: raw_len,
+                        sent: encoded.len(),
+                    };
+                    return (
+                        request.header("Content-Encoding", "gzip").body(encoded),
+                        size,
+                    );
+                }
+                Ok(_) => {}
+                Err(e) => warn!("[SYNC] gzip failed, sending uncompressed: {}", e),
+            }
+        }
+        let size = UploadSize {
+            raw: raw_len,
+            sent: raw_len,
+        };
+        (request.body(body), size)
+    }
+
+    /// Учесть тело запроса, принятого сервером (повторы, 401/415 и ошибки не считаются)
+    fn record_upload_bytes(&self, size: UploadSize) {
+        self.bytes_sent
+            .fetch_add(size.sent as u64, Ordering::Relaxed);
+        if let Err(e) = self.db.add_upload_bytes(size.raw as u64, size.sent as u64) {
+            debug!("[SYNC] Failed to record upload bytes: {}", e);
+        }
+    }
+
+    /// RFC 7694: Accept-Encoding в ответе объявляет кодировки, которые сервер принимает в запросах
+    fn observe_server_encoding(&self, response: &reqwest::Response) {
+        if let Some(value) = response
+            .headers()
+            .get(reqwest::header::ACCEPT_ENCODING)
+            .and_then(|v| v.to_str().ok())
+        {
+            let accepts_gzip = value
+                .split(',')
+                .any(|coding| coding.split(';').next().map(str::trim) == Some("gzip"));
+            let previous = self.server_accepts_gzip.swap(accepts_gzip, Ordering::Relaxed);
+            if previous != accepts_gzip {
+                info!("[SYNC] Server request gzip support: {}", accepts_gzip);
+            }
         }
     }
 
@@ -174,6 +438,12 @@ impl SyncManager {
         // PRODUCTION: Токены НЕ сохраняются в payload
         // Они будут получаться через AuthManager.get_fresh_token() при синхронизации
 
+        let payload = if operation == "start" {
+            self.prepare_start_payload(payload)?
+        } else {
+            payload
+        };
+
         let payload_str = serde_json::to_string(&payload)
             .map_err(|e| format!("Failed to serialize payload: {}", e))?;
 
@@ -182,8 +452,70 @@ impl SyncManager {
             .map_err(|e| enqueue_error_to_user_message(&e))
     }
 
+    /// Проверить start-payload по кэшу проектов (offline start):
+    /// удалённый/архивный проект отклоняется сразу, а не после N неудачных retry.
+    /// Неизвестный проект пропускаем — кэш мог устареть, решит сервер.
+    /// Недостающие userId/description дополняются детерминированно (не ломает 5-сек dedup).
+    fn prepare_start_payload(
+        &self,
+        mut payload: serde_json::Value,
+    ) -> Result<serde_json::Value, String> {
+        let project_id = payload
+            .get("projectId")
+            .and_then(|v| v.as_str())
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .ok_or_else(|| "Missing projectId in start payload".to_string())?;
+
+        let project = match self.db.get_cached_project(&project_id) {
+            Ok(project) => project,
+            Err(e) => {
+                warn!("[SYNC] Project cache lookup failed: {}", e);
+                None
+            }
+        };
+        match &project {
+            Some(p) if p.deleted => {
+                return Err(format!("Project \"{}\" has been deleted", p.name));
+            }
+            Some(p) if p.archived => {
+                return Err(format!("Project \"{}\" is archived", p.name));
+            }
+            Some(_) => {}
+            None => debug!(
+                "[SYNC] Project {} not in local cache, enqueueing start as is",
+                project_id
+            ),
+        }
+
+        let obj = match payload.as_object_mut() {
+            Some(obj) => obj,
+            None => return Err("Start payload must be a JSON object".to_string()),
+        };
+        obj.insert("projectId".to_string(), serde_json::json!(project_id));
+        let has_user = obj
+            .get("userId")
+            .and_then(|v| v.as_str())
+            .is_some_and(|s| !s.is_empty());
+        if !has_user {
+            if let Ok(Some(user_id)) = self.db.get_app_meta("current_user_id") {
+                if !user_id.is_empty() {
+                    obj.insert("userId".to_string(), serde_json::json!(user_id));
+                }
+            }
+        }
+        if let (None, Some(p)) = (obj.get("description"), &project) {
+            obj.insert(
+                "description".to_string(),
+                serde_json::json!(format!("Work on project {}", p.name)),
+            );
+        }
+        Ok(payload)
+    }
+
     /// Добавить скриншот в очередь синхронизации
     /// PRODUCTION: Токены НЕ сохраняются в payload
+    /// Изображение пишется один раз в BlobStore (зашифрованный файл); в очереди — только imageRef
     pub fn enqueue_screenshot(
         &self,
         png_data: Vec<u8>,
@@ -191,48 +523,65 @@ impl SyncManager {
         _access_token: String, // Не используется - оставлен для обратной совместимости
         _refresh_token: Option<String>, // Не используется - оставлен для обратной совместимости
     ) -> Result<i64, String> {
-        use base64::{engine::general_purpose, Engine as _};
-
-        // Конвертируем в base64
-        let base64_string = general_purpose::STANDARD.encode(&png_data);
-        let image_data = format!("data:image/jpeg;base64,{}", base64_string);
+        let image_len = png_data.len();
+        // До INSERT: release_blobs прежней строки с тем же blob не удалит файл из-под новой
+        let _refs = self.db.blobs.lock_refs();
+        let blob_ref = self
+            .db
+            .blobs
+            .put(&png_data)
+            .map_err(|e| format!("Failed to store screenshot: {}", e))?;
 
         // PRODUCTION: Токены НЕ сохраняются в payload
         let payload = serde_json::json!({
-            "imageData": image_data,
+            "imageRef": blob_ref,
             "timeEntryId": time_entry_id,
         });
 
         let payload_str = serde_json::to_string(&payload)
             .map_err(|e| format!("Failed to serialize payload: {}", e))?;
 
-        // Log payload size — helps debug "Unterminated string in JSON" (truncation in DB/encryption/HTTP)
-        let payload_len = payload_str.len();
         info!(
-            "[SYNC] Enqueue screenshot: payload_len={} bytes, imageData_len={} bytes",
-            payload_len,
-            image_data.len()
+            "[SYNC] Enqueue screenshot: image_len={} bytes, imageRef={}",
+            image_len, blob_ref
         );
-        if payload_len > 5 * 1024 * 1024 {
-            warn!(
-                "[SYNC] Large screenshot payload {} MB — may hit SQLite/encryption/server limits",
-                payload_len / (1024 * 1024)
-            );
-        }
 
         self.db
-            .enqueue_sync("screenshot", &payload_str)
+            .enqueue_sync_with_blob("screenshot", &payload_str, Some(&blob_ref))
             .map_err(|e| enqueue_error_to_user_message(&e))
     }
 
+    /// imageData для запроса: из BlobStore (imageRef) или legacy-payload со встроенным base64
+    fn resolve_screenshot_image_data(
+        &self,
+        payload_json: &serde_json::Value,
+    ) -> Result<String, SyncError> {
+        use base64::{engine::general_purpose, Engine as _};
+
+        if let Some(image_data) = payload_json["imageData"].as_str() {
+            return Ok(image_data.to_string());
+        }
+        let blob_ref = payload_json["imageRef"].as_str().ok_or_else(|| {
+            SyncError::UnknownOperation("Missing imageData/imageRef in payload".into())
+        })?;
+        let bytes = self.db.blobs.get(blob_ref).map_err(|e| {
+            SyncError::ParsePayload(format!("Screenshot blob {} unreadable: {}", blob_ref, e))
+        })?;
+        Ok(format!(
+            "data:image/jpeg;base64,{}",
+            general_purpose::STANDARD.encode(&bytes)
+        ))
+    }
+
     /// Построить и отправить HTTP-запрос для time_entry операции
-    fn send_time_entry_request(
+    pub(crate) fn send_time_entry_request(
         &self,
         operation: &str,
         payload_json: &serde_json::Value,
         access_token: &str,
         idempotency_key: Option<&str>,
-    ) -> Result<reqwest::RequestBuilder, SyncError> {
+        allow_compression: bool,
+    ) -> Result<(reqwest::RequestBuilder, UploadSize), SyncError> {
         let url = match operation {
             "start" => format!("{}/time-entries", self.api_base_url),
             "pause" => {
@@ -279,12 +628,52 @@ impl SyncManager {
         if let Some(key) = idempotency_key {
             request = request.header("X-Idempotency-Key", key);
         }
-        // start: body = payload (projectId, userId, description). pause/resume/stop: id в URL, тело пустое (API часто не ожидает body)
-        let body = match operation {
-            "start" => payload_json.clone(),
+        // start: body = payload (projectId, userId, description). pause/resume/stop: id в URL,
+        // в теле только clientTime (если есть)
+        let body = match (operation, payload_json.get("clientTime")) {
+            ("start", _) => payload_json.clone(),
+            (_, Some(client_time)) => serde_json::json!({ "clientTime": client_time }),
             _ => serde_json::json!({}),
         };
-        Ok(request.json(&body))
+        let body_str = serde_json::to_string(&body)
+            .map_err(|e| SyncError::ParsePayload(format!("Time entry JSON serialize: {}", e)))?;
+        Ok(self.with_body(request, body_str, allow_compression))
+    }
+
+    /// Обновить оценку смещения часов по заголовку Date ответа
+    fn observe_server_clock(&self, response: &reqwest::Response, sent_at_ms: i64) {
+        let date = match response
+            .headers()
+            .get(reqwest::header::DATE)
+            .and_then(|v| v.to_str().ok())
+        {
+            Some(date) => date,
+            None => return,
+        };
+        let received_at_ms = chrono::Utc::now().timestamp_millis();
+        let estimate = self.clock.observe(date, sent_at_ms, received_at_ms);
+        if estimate.is_some() {
+            self.clock.persist(&self.db);
+        }
+    }
+
+    /// clientTime: время постановки в очередь по локальным часам и по оценке часов сервера на тот же
+    /// момент (clock_offset_ms строки). Добавляется при отправке, а не при enqueue — payload в очереди
+    /// (и idempotency key) не меняется. Оценки при enqueue нет — текущая.
+    fn attach_client_time(&self, task_id: i64, payload_json: &mut serde_json::Value) {
+        let task = self.db.get_task_client_time(task_id).ok().flatten();
+        let local_ms = task
+            .map(|(created_at, _)| created_at * 1000)
+            .unwrap_or_else(|| chrono::Utc::now().timestamp_millis());
+        let offset = task
+            .and_then(|(_, offset)| offset)
+            .or_else(|| self.clock.offset_ms());
+        if let Some(obj) = payload_json.as_object_mut() {
+            obj.insert(
+                "clientTime".to_string(),
+                ClockOffset::stamp_with_offset(local_ms, offset),
+            );
+        }
     }
 
     /// Max screenshot JSON body size (bytes). Many servers (nginx, etc.) default to 1MB.
@@ -292,15 +681,14 @@ impl SyncManager {
     const MAX_SCREENSHOT_JSON_BYTES: usize = 10 * 1024 * 1024; // 10 MB
 
     /// Построить и отправить HTTP-запрос для screenshot
-    fn send_screenshot_request(
+    pub(crate) fn send_screenshot_request(
         &self,
         payload_json: &serde_json::Value,
         access_token: &str,
         idempotency_key: Option<&str>,
-    ) -> Result<reqwest::RequestBuilder, SyncError> {
-        let image_data = payload_json["imageData"]
-            .as_str()
-            .ok_or_else(|| SyncError::UnknownOperation("Missing imageData in payload".into()))?;
+        allow_compression: bool,
+    ) -> Result<(reqwest::RequestBuilder, UploadSize), SyncError> {
+        let image_data = self.resolve_screenshot_image_data(payload_json)?;
         let time_entry_id = payload_json["timeEntryId"]
             .as_str()
             .ok_or_else(|| SyncError::UnknownOperation("Missing timeEntryId in payload".into()))?;
@@ -338,7 +726,7 @@ impl SyncManager {
             request = request.header("X-Idempotency-Key", key);
         }
         // Use body_str directly — single serialization, and we've validated it for logging
-        Ok(request.body(body_str))
+        Ok(self.with_body(request, body_str, allow_compression))
     }
 
     /// Синхронизировать одну задачу из очереди
@@ -352,7 +740,7 @@ impl SyncManager {
         payload: String,
         idempotency_key: Option<String>,
     ) -> Result<bool, SyncError> {
-        let payload_json: serde_json::Value = serde_json::from_str(&payload).map_err(|e| {
+        let mut payload_json: serde_json::Value = serde_json::from_str(&payload).map_err(|e| {
             let msg = format!(
                 "{} (payload_len={} bytes, entity_type={})",
                 e,
@@ -367,24 +755,25 @@ impl SyncManager {
             }
             SyncError::ParsePayload(msg)
         })?;
+        if entity_type.starts_with("time_entry_") {
+            self.attach_client_time(task_id, &mut payload_json);
+        }
 
+        // Истекающий токен обновляется до запроса (single-flight в AuthManager)
         let mut access_token = self
             .auth_manager
-            .get_access_token()
-            .await
-            .map_err(|e| SyncError::Auth(e.to_string()))?;
-
-        let mut refresh_token = self
-            .auth_manager
-            .get_refresh_token()
+            .get_fresh_token()
             .await
             .map_err(|e| SyncError::Auth(e.to_string()))?;
 
         let mut retry_with_refresh = true;
+        let mut allow_compression = true;
         let idempotency_key_ref = idempotency_key.as_deref();
 
         loop {
-            let response_result = if entity_type.starts_with("time_entry_") {
+            let request_started = Instant::now();
+            let sent_at_ms = chrono::Utc::now().timestamp_millis();
+            let (builder, upload) = if entity_type.starts_with("time_entry_") {
                 let operation = entity_type
                     .strip_prefix("time_entry_")
                     .ok_or_else(|| {
@@ -393,80 +782,112 @@ impl SyncManager {
                             entity_type
                         ))
                     })?;
-                let builder = self.send_time_entry_request(
+                self.send_time_entry_request(
                     operation,
                     &payload_json,
                     &access_token,
                     idempotency_key_ref,
-                )?;
-                builder.send().await
+                    allow_compression,
+                )?
             } else if entity_type == "screenshot" {
-                let builder = self.send_screenshot_request(
+                self.send_screenshot_request(
                     &payload_json,
                     &access_token,
                     idempotency_key_ref,
-                )?;
-                builder.send().await
+                    allow_compression,
+                )?
             } else {
                 return Err(SyncError::UnknownOperation(format!(
                     "Unknown entity type: {}",
                     entity_type
                 )));
             };
+            let response_result = builder.send().await;
+
+            if let Ok(mut latencies) = self.request_latencies_ms.lock() {
+                latencies.push(request_started.elapsed().as_millis() as u64);
+            }
 
             match response_result {
                 Ok(response) => {
                     let status = response.status();
+                    self.observe_server_encoding(&response);
+                    self.observe_server_clock(&response, sent_at_ms);
+                    self.connectivity.observe(
+                        if status.is_server_error() {
+                            ConnectivityState::Degraded
+                        } else {
+                            ConnectivityState::Online
+                        },
+                        None,
+                    );
+
+                    // 415 на сжатое тело: сервер не принимает gzip — повторяем без сжатия
+                    if status == 415 && allow_compression && self.gzip_enabled() {
+                        warn!(
+                            "[SYNC] Server rejected compressed body (415) for task {}, retrying uncompressed",
+                            task_id
+                        );
+                        self.server_accepts_gzip.store(false, Ordering::Relaxed);
+                        allow_compression = false;
+                        continue;
+                    }
 
-                    // Если 401 и есть refresh_token, обновляем токен
+                    // 401: обновляем токен (single-flight — параллельные задачи ждут один refresh)
                     if status == 401 && retry_with_refresh {
-                        if let Some(refresh) = refresh_token.as_ref() {
-                            info!(
-                                "[SYNC] Token expired (401), refreshing token for task {}",
-                                task_id
-                            );
-
-                            match self.auth_manager.refresh_token(refresh).await {
-                                Ok(token_result) => {
-                                    // Обновляем токены в AuthManager
-                                    access_token = token_result.access_token.clone();
-                                    if let Some(new_refresh) = token_result.refresh_token {
-                                        refresh_token = Some(new_refresh.clone());
-                                    }
-
-                                    // PRODUCTION: Сохраняем новые токены в AuthManager (не в payload)
-                                    self.auth_manager
-                                        .set_tokens(
-                                            Some(access_token.clone()),
-                                            refresh_token.clone(),
-                                        )
-                                        .await;
-
-                                    retry_with_refresh = false; // Только одна попытка обновления
-                                    continue; // Повторяем запрос с новым токеном
-                                }
-                                Err(e) => {
-                                    let err = SyncError::Auth(e.to_string());
-                                    warn!(
-                                        "[SYNC] Failed to refresh token for task {}: {}",
-                                        task_id, err
-                                    );
-                                    return Err(err);
-                                }
+                        info!(
+                            "[SYNC] Token expired (401), refreshing token for task {}",
+                            task_id
+                        );
+                        let refreshed = self
+                            .auth_manager
+                            .refresh_access_token(Some(&access_token))
+                            .await;
+                        match refreshed {
+                            Ok(fresh) => {
+                                access_token = fresh;
+                                retry_with_refresh = false; // Только одна попытка обновления
+                                continue; // Повторяем запрос с новым токеном
+                            }
+                            Err(e) => {
+                                let err = SyncError::Auth(e.to_string());
+                                warn!(
+                                    "[SYNC] Failed to refresh token for task {}: {}",
+                                    task_id, err
+                                );
+                                return Err(err);
                             }
-                        } else {
-                            let err = SyncError::Auth(
-                                "Token expired (401) but no refresh token available".into(),
-                            );
-                            warn!("[SYNC] {} for task {}", err, task_id);
-                            return Err(err);
                         }
                     }
 
                     let status_code = status.as_u16();
+                    // Сервер просит подождать: 429 (по умолчанию 30 сек) или 503 с Retry-After
+                    if status_code == 429 || status_code == 503 {
+                        let retry_after = response
+                            .headers()
+                            .get(reqwest::header::RETRY_AFTER)
+                            .and_then(|v| v.to_str().ok())
+                            .and_then(parse_retry_after);
+                        let delay = match (status_code, retry_after) {
+                            (_, Some(delay)) => Some(delay),
+                            (429, None) => Some(DEFAULT_RATE_LIMIT_DELAY),
+                            _ => None,
+                        };
+                        if let Some(delay) = delay {
+                            self.defer_sync(delay);
+                            return Err(SyncError::RateLimited(delay));
+                        }
+                    }
                     if status.is_success() {
+                        self.record_upload_bytes(upload);
                         return Ok(true);
                     }
+                    // 407 от HTTP-прокси (plain HTTP без CONNECT): нужны учётные данные прокси
+                    if status == reqwest::StatusCode::PROXY_AUTHENTICATION_REQUIRED {
+                        return Err(SyncError::Proxy(
+                            "Proxy authentication required (407)".into(),
+                        ));
+                    }
                     let body = response.text().await.unwrap_or_default();
                     // State-already-achieved: server says desired state is already there, drop task to stop retries
                     if status_code == 400
@@ -479,6 +900,7 @@ impl SyncManager {
                             "[SYNC] Task {} HTTP 400 state-already-achieved, dropping task",
                             task_id
                         );
+                        self.record_upload_bytes(upload);
                         return Ok(true);
                     }
                     if status_code == 400 && !body.is_empty() {
@@ -495,16 +917,54 @@ impl SyncManager {
                     });
                 }
                 Err(e) => {
-                    return Err(SyncError::Network(e.to_string()));
+                    if e.is_connect() || e.is_timeout() {
+                        self.connectivity.observe(ConnectivityState::Offline, None);
+                    }
+                    let err = SyncError::from_transport(&e, &self.http);
+                    if !matches!(err, SyncError::Network(_)) {
+                        warn!("[SYNC] Task {} failed: {}", task_id, err);
+                    }
+                    return Err(err);
                 }
             }
         }
     }
 
+    /// Не отправлять очередь `delay` (Retry-After). Более поздний срок не сокращается.
+    fn defer_sync(&self, delay: Duration) {
+        let until = Instant::now() + delay.min(MAX_RATE_LIMIT_DELAY);
+        if let Ok(mut guard) = self.rate_limited_until.lock() {
+            if !matches!(*guard, Some(current) if current >= until) {
+                warn!(
+                    "[SYNC] Server asked to back off, deferring sync for {:?}",
+                    delay
+                );
+                *guard = Some(until);
+            }
+        }
+    }
+
+    /// Сколько ещё ждать по Retry-After (None — можно отправлять)
+    pub fn rate_limit_remaining(&self) -> Option<Duration> {
+        let until = (*self.rate_limited_until.lock().ok()?)?;
+        until
+            .checked_duration_since(Instant::now())
+            .filter(|d| !d.is_zero())
+    }
+
     /// Внутренний метод синхронизации (single-flight)
     /// PRODUCTION: Все точки входа сходятся здесь
     async fn run_sync_internal(&self, max_retries: i32) -> Result<usize, SyncError> {
-        match self.auth_manager.get_access_token().await {
+        if let Some(remaining) = self.rate_limit_remaining() {
+            debug!(
+                "[SYNC] Rate limited by server, skipping sync for {:?}",
+                remaining
+            );
+            return Ok(0);
+        }
+
+        // Без access token, но с сохранённым refresh token — get_fresh_token получит новый
+        match self.auth_manager.get_fresh_token().await {
             Ok(token) => {
                 debug!("[SYNC] Token available, length: {}", token.len());
             }
@@ -560,7 +1020,8 @@ impl SyncManager {
         );
 
         // При online — aggressive retry (5 сек), чтобы сразу повторить после восстановления сети
-        let is_online = crate::network::check_online_status().await;
+        // Состояние из ConnectivityMonitor (кэш; проба API health только если устарело)
+        let is_online = self.connectivity.is_online().await;
         let aggressive_retry = is_online;
 
         let tasks = self
@@ -587,13 +1048,56 @@ impl SyncManager {
 
         let mut synced_count = 0;
         let mut failed_in_batch = 0;
+        // Остались pending без результата в этом запуске (429/503 Retry-After, сбой mark_task_sent)
+        let mut deferred_count = 0;
         let mut by_type_synced: std::collections::HashMap<String, i32> =
             std::collections::HashMap::new();
         let mut by_type_failed: std::collections::HashMap<String, i32> =
             std::collections::HashMap::new();
 
+        let total = tasks.len();
+        let started_at = std::time::Instant::now();
+        let run_started_at = chrono::Utc::now().timestamp();
+        let bytes_sent_before = self.bytes_sent.load(Ordering::Relaxed);
+        let mut failed_by_kind: std::collections::HashMap<String, i32> =
+            std::collections::HashMap::new();
+        if let Ok(mut latencies) = self.request_latencies_ms.lock() {
+            latencies.clear();
+        }
+
+        self.emit_event(
+            crate::ipc::events::SYNC_STARTED,
+            &SyncStartedEvent { total },
+        );
+
         // PRODUCTION: Network I/O OUTSIDE any lock - lock held only for DB ops
-        for (id, entity_type, payload, retry_count, idempotency_key) in tasks {
+        for (index, (id, entity_type, payload, retry_count, idempotency_key)) in
+            tasks.into_iter().enumerate()
+        {
+            // Retry-After посреди батча: остальные задачи ждут (claim уже сдвинул last_retry_at)
+            if self.rate_limit_remaining().is_some() {
+                info!(
+                    "[SYNC] Rate limited, deferring {} remaining tasks",
+                    total - index
+                );
+                deferred_count += total - index;
+                break;
+            }
+            let task_event = |error: Option<(&str, String)>, will_retry: bool| {
+                let (error_class, error) = match error {
+                    Some((class, message)) => (Some(class.to_string()), Some(message)),
+                    None => (None, None),
+                };
+                SyncTaskEvent {
+                    task_id: id,
+                    entity_type: entity_type.clone(),
+                    index: index + 1,
+                    total,
+                    error_class,
+                    error,
+                    will_retry,
+                }
+            };
             info!(
                 "[SYNC] Processing task {}: {} (retry {})",
                 id, entity_type, retry_count
@@ -650,6 +1154,10 @@ impl SyncManager {
                     if marked {
                         // ДОКАЗАНО: Задача помечена как sent - увеличиваем счетчики
                         synced_count += 1;
+                        self.emit_event(
+                            crate::ipc::events::SYNC_TASK_DONE,
+                            &task_event(None, false),
+                        );
                         *by_type_synced.entry(entity_type.clone()).or_insert(0) += 1;
                         if let Err(e) = self.db.set_app_meta(
                             "last_sync_at",
@@ -666,6 +1174,7 @@ impl SyncManager {
                             "[SYNC] Task {} remains pending after HTTP success due to mark_task_sent failure. Manual intervention may be required.",
                             id
                         );
+                        deferred_count += 1;
                     }
                 }
                 Ok(false) => {
@@ -673,8 +1182,16 @@ impl SyncManager {
                     failed_in_batch += 1;
                     *by_type_failed.entry(entity_type.clone()).or_insert(0) += 1;
                     let new_retry_count = retry_count + 1;
+                    *failed_by_kind.entry("http".to_string()).or_insert(0) += 1;
                     let error_msg =
                         format!("Server error (4xx/5xx) after {} retries", new_retry_count);
+                    self.emit_event(
+                        crate::ipc::events::SYNC_TASK_FAILED,
+                        &task_event(
+                            Some(("http", error_msg.clone())),
+                            new_retry_count < max_retries,
+                        ),
+                    );
                     if new_retry_count >= max_retries {
                         self.db
                             .update_sync_status_with_error(
@@ -698,11 +1215,27 @@ impl SyncManager {
                         );
                     }
                 }
+                Err(SyncError::RateLimited(delay)) => {
+                    // Сервер просит подождать — не неудача: retry_count не растёт, задача остаётся pending
+                    deferred_count += 1;
+                    info!(
+                        "[SYNC] Task {} deferred by server rate limit ({:?})",
+                        id, delay
+                    );
+                }
                 Err(e) => {
                     failed_in_batch += 1;
                     *by_type_failed.entry(entity_type.clone()).or_insert(0) += 1;
+                    *failed_by_kind.entry(e.kind().to_string()).or_insert(0) += 1;
                     let new_retry_count = retry_count + 1;
                     let error_msg = e.to_string();
+                    self.emit_event(
+                        crate::ipc::events::SYNC_TASK_FAILED,
+                        &task_event(
+                            Some((e.kind(), error_msg.clone())),
+                            new_retry_count < max_retries,
+                        ),
+                    );
                     if new_retry_count >= max_retries {
                         self.db
                             .update_sync_status_with_error(
@@ -760,6 +1293,45 @@ impl SyncManager {
             info!("[SYNC] Sync completed: {}", log_parts.join(", "));
         }
 
+        let mut latencies = self
+            .request_latencies_ms
+            .lock()
+            .map(|mut l| std::mem::take(&mut *l))
+            .unwrap_or_default();
+        latencies.sort_unstable();
+        let metrics = SyncRunMetrics {
+            started_at: run_started_at,
+            duration_ms: started_at.elapsed().as_millis() as u64,
+            attempted: (synced_count + failed_in_batch) as i32,
+            succeeded: synced_count as i32,
+            failed: failed_in_batch as i32,
+            failed_by_kind,
+            bytes_sent: self
+                .bytes_sent
+                .load(Ordering::Relaxed)
+                .saturating_sub(bytes_sent_before),
+            latency_p50_ms: percentile(&latencies, 50),
+            latency_p95_ms: percentile(&latencies, 95),
+        };
+        if metrics.attempted > 0 {
+            if let Err(e) = self.db.record_sync_metrics(&metrics) {
+                warn!("[SYNC] Failed to record sync metrics: {}", e);
+            }
+        }
+
+        self.emit_event(
+            crate::ipc::events::SYNC_FINISHED,
+            &SyncFinishedEvent {
+                total,
+                synced: synced_count,
+                failed: failed_in_batch,
+                deferred: deferred_count,
+                synced_by_type: by_type_synced,
+                failed_by_type: by_type_failed,
+                duration_ms: started_at.elapsed().as_millis() as u64,
+            },
+        );
+
         Ok(synced_count)
     }
 
@@ -784,4 +1356,89 @@ impl SyncManager {
             .await
             .map_err(|e| e.to_string())
     }
+
+    /// Отправить всю очередь (выход с Flush): дождаться идущей синхронизации, затем запуски подряд,
+    /// пока очередь не опустеет или запуск ничего не отправит (offline, backoff, Retry-After)
+    async fn flush_queue(&self, max_retries: i32) -> usize {
+        // Ok(0) от sync_queue при идущей фоновой синхронизации не значит «нечего отправлять»
+        while self
+            .is_syncing
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        let _guard = guard((), |_| {
+            self.is_syncing.store(false, Ordering::Release);
+        });
+
+        let mut flushed = 0;
+        loop {
+            match self.run_sync_internal(max_retries).await {
+                Ok(0) => break,
+                Ok(synced) => flushed += synced,
+                Err(e) => {
+                    warn!("[AUTH] Logout flush failed: {}", e);
+                    break;
+                }
+            }
+        }
+        flushed
+    }
+
+    /// Выход пользователя: Flush — отправить очередь, пока токены действительны, и удалить локальные данные;
+    /// если отправлено не всё (offline, 5xx/429), выход выполняется как Keep — очередь остаётся до следующего входа. Токены отзываются и стираются,
+    /// `purge_keys` заменяет ключ шифрования новым (только с Flush — иначе очередь не расшифровать после перезапуска);
+    /// ключ общий для всех БД — вызывающий проверяет `ProfileManager::key_dependents`.
+    pub async fn logout(
+        &self,
+        queue_policy: LogoutQueuePolicy,
+        purge_keys: bool,
+    ) -> Result<LoggedOutEvent, String> {
+        if purge_keys && queue_policy == LogoutQueuePolicy::Keep {
+            return Err("Cannot purge the encryption key while keeping the sync queue".to_string());
+        }
+        let mut event = LoggedOutEvent {
+            revoked: false,
+            queue_policy,
+            flushed_tasks: 0,
+            remaining_tasks: 0,
+            keys_purged: false,
+        };
+        if queue_policy == LogoutQueuePolicy::Flush {
+            event.flushed_tasks = self.flush_queue(5).await;
+        }
+        event.revoked = self.auth_manager.sign_out().await;
+
+        let stats = self
+            .db
+            .get_queue_stats()
+            .map_err(|e| format!("Failed to get queue stats: {}", e))?;
+        event.remaining_tasks =
+            (stats.pending_count + stats.failed_count + stats.parked_count).max(0) as usize;
+        if queue_policy == LogoutQueuePolicy::Flush {
+            if event.remaining_tasks == 0 {
+                self.db
+                    .clear_user_data()
+                    .map_err(|e| format!("Failed to clear user data: {}", e))?;
+            } else {
+                // Неотправленное не удаляем молча: очередь (и ключ) остаются до следующего входа
+                warn!(
+                    "[AUTH] Logout flush left {} unsent tasks, keeping the queue",
+                    event.remaining_tasks
+                );
+                event.queue_policy = LogoutQueuePolicy::Keep;
+            }
+        }
+        if purge_keys && event.queue_policy == LogoutQueuePolicy::Flush {
+            self.db.encryption.purge_key_material()?;
+            event.keys_purged = true;
+        }
+        info!(
+            "[AUTH] Logged out: policy {:?}, flushed {}, remaining {}, keys purged {}",
+            event.queue_policy, event.flushed_tasks, event.remaining_tasks, event.keys_purged
+        );
+        self.emit_event(crate::ipc::events::LOGGED_OUT, &event);
+        Ok(event)
+    }
 }
@@ -15,6 +15,21 @@ mod tests {
         TokenEncryption::new(Some(dir.path())).expect("Failed to create encryption")
     }
 
+    /// Payload задачи очереди: зашифрован с associated data своей строки
+    fn decrypt_queue_payload(db: &Database, id: i64) -> Result<String, String> {
+        let (entity_type, key, payload): (String, Option<String>, String) = db
+            .test_conn()
+            .query_row(
+                "SELECT entity_type, idempotency_key, payload FROM sync_queue WHERE id = ?1",
+                [id],
+                |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)),
+            )
+            .map_err(|e| e.to_string())?;
+        db.encryption
+            .decrypt_with_aad(&payload, &payload_aad(&entity_type, key.as_deref()))
+            .map(|(plaintext, _)| plaintext)
+    }
+
     #[test]
     fn test_token_encryption_decryption() {
         let encryption = test_encryption_with_fallback();
@@ -1110,7 +1125,7 @@ mod tests {
             let (db, _temp_dir) = create_test_db();
 
             // Проверяем, что таблицы созданы
-            let conn = db.conn.lock().unwrap();
+            let conn = db.test_conn();
             let mut stmt = conn
                 .prepare("SELECT name FROM sqlite_master WHERE type='table'")
                 .unwrap();
@@ -1364,7 +1379,7 @@ mod tests {
             // Тест, что WAL mode включен
             let (db, _temp_dir) = create_test_db();
 
-            let conn = db.conn.lock().unwrap();
+            let conn = db.test_conn();
             let mut stmt = conn.prepare("PRAGMA journal_mode").unwrap();
             let journal_mode: String = stmt.query_row([], |row| row.get(0)).unwrap();
 
@@ -1382,7 +1397,7 @@ mod tests {
             // Тест, что Foreign keys включены
             let (db, _temp_dir) = create_test_db();
 
-            let conn = db.conn.lock().unwrap();
+            let conn = db.test_conn();
             let mut stmt = conn.prepare("PRAGMA foreign_keys").unwrap();
             let foreign_keys: i32 = stmt.query_row([], |row| row.get(0)).unwrap();
 
@@ -1416,6 +1431,532 @@ mod tests {
             assert_eq!(loaded_accumulated, 7200); // Последнее значение
             assert_eq!(loaded_state, "paused"); // Последнее значение
         }
+
+        fn queue_key(db: &Database, id: i64) -> String {
+            db.test_conn()
+                .query_row(
+                    "SELECT idempotency_key FROM sync_queue WHERE id = ?1",
+                    [id],
+                    |row| row.get(0),
+                )
+                .unwrap()
+        }
+
+        #[test]
+        fn test_idempotency_key_per_operation_and_fingerprint_dedup() {
+            let (db, _temp_dir) = create_test_db();
+
+            let first = db
+                .enqueue_sync("time_entry_pause", r#"{"id": "e1", "projectId": "p1"}"#)
+                .unwrap();
+            // Тот же payload с другим порядком полей — дубликат в 5-секундном окне
+            let duplicate = db
+                .enqueue_sync("time_entry_pause", r#"{"projectId": "p1", "id": "e1"}"#)
+                .unwrap();
+            assert_eq!(duplicate, first);
+
+            let key = queue_key(&db, first);
+            assert_eq!(key.len(), 36);
+            assert_eq!(&key[14..15], "7", "UUIDv7 expected, got {}", key);
+
+            // После отправки та же операция — новая задача с новым ключом
+            db.mark_task_sent(first).unwrap();
+            let second = db
+                .enqueue_sync("time_entry_pause", r#"{"id": "e1", "projectId": "p1"}"#)
+                .unwrap();
+            assert_ne!(second, first);
+            assert_ne!(queue_key(&db, second), key);
+        }
+
+        #[test]
+        fn test_migration_keeps_legacy_keys_and_backfills_fingerprints() {
+            let temp_dir = TempDir::new().unwrap();
+            let db_path = temp_dir.path().join("test.db");
+            let payload = r#"{"id": "e1"}"#;
+            {
+                let db = Database::new(db_path.to_str().unwrap()).unwrap();
+                let legacy = db.enqueue_sync("time_entry_stop", payload).unwrap();
+                let keyless = db.enqueue_sync("time_entry_pause", payload).unwrap();
+                // Тот же stop позже, вне окна дубликатов: прежний ключ совпадал с первым
+                let repeated = db
+                    .enqueue_sync("time_entry_stop", r#"{"id": "e2"}"#)
+                    .unwrap();
+                // Payload v10 ещё не привязан к строке
+                let unbound = db.encryption.encrypt(payload).unwrap();
+                let conn = db.test_conn();
+                conn.execute(
+                    "UPDATE sync_queue SET content_fingerprint = NULL, payload = ?4,
+                     idempotency_key = CASE id WHEN ?2 THEN NULL ELSE 'time_entry_stop-1a2b' END,
+                     created_at = CASE id WHEN ?3 THEN 0 ELSE created_at END
+                     WHERE id IN (?1, ?2, ?3)",
+                    params![legacy, keyless, repeated, unbound],
+                )
+                .unwrap();
+                conn.pragma_update(None, "user_version", 10).unwrap();
+            }
+
+            let db = Database::new(db_path.to_str().unwrap()).unwrap();
+            assert_eq!(queue_key(&db, 1), "time_entry_stop-1a2b");
+            assert_eq!(&queue_key(&db, 2)[14..15], "7");
+            // Повтор получил свой ключ — сервер не отбросит его как уже применённый
+            assert_ne!(queue_key(&db, 3), queue_key(&db, 1));
+            assert_eq!(&queue_key(&db, 3)[14..15], "7");
+            assert_eq!(db.get_retry_tasks(5, 10, false).unwrap().len(), 3);
+            // Fingerprint восстановлен: повтор в окне — дубликат
+            assert_eq!(db.enqueue_sync("time_entry_stop", payload).unwrap(), 1);
+        }
+
+        #[test]
+        fn test_migration_binds_queue_payloads_to_rows() {
+            let temp_dir = TempDir::new().unwrap();
+            let db_path = temp_dir.path().join("test.db");
+            let payload = r#"{"id": "e1"}"#;
+            {
+                let db = Database::new(db_path.to_str().unwrap()).unwrap();
+                db.enqueue_sync("time_entry_stop", payload).unwrap();
+                db.enqueue_sync("time_entry_pause", r#"{"id": "e2"}"#)
+                    .unwrap();
+                let unbound = db.encryption.encrypt(payload).unwrap();
+                let conn = db.test_conn();
+                conn.execute(
+                    "UPDATE sync_queue SET payload = ?1 WHERE id = 1",
+                    [&unbound],
+                )
+                .unwrap();
+                conn.execute("UPDATE sync_queue SET payload = 'garbage' WHERE id = 2", [])
+                    .unwrap();
+                conn.pragma_update(None, "user_version", 11).unwrap();
+            }
+
+            let db = Database::new(db_path.to_str().unwrap()).unwrap();
+            let stored: String = db
+                .test_conn()
+                .query_row("SELECT payload FROM sync_queue WHERE id = 1", [], |r| {
+                    r.get(0)
+                })
+                .unwrap();
+            assert!(db.encryption.is_bound(&stored));
+            // Нерасшифровываемый payload не отправляется: задача помечена failed
+            let tasks = db.get_retry_tasks(5, 10, false).unwrap();
+            assert_eq!(tasks.len(), 1);
+            assert_eq!(tasks[0].0, 1);
+            assert_eq!(tasks[0].2, payload);
+            let failed = db.get_failed_tasks(10).unwrap();
+            assert_eq!(failed.len(), 1);
+            assert_eq!(failed[0].id, 2);
+            assert!(failed[0]
+                .error_message
+                .as_deref()
+                .unwrap()
+                .starts_with("Payload rejected"));
+        }
+
+        #[test]
+        fn test_payload_moved_to_another_row_is_rejected() {
+            let (db, _temp_dir) = create_test_db();
+            let victim = db
+                .enqueue_sync("time_entry_stop", r#"{"id": "e1"}"#)
+                .unwrap();
+            let other = db
+                .enqueue_sync("time_entry_pause", r#"{"id": "e2"}"#)
+                .unwrap();
+            let conn = db.test_conn();
+            let stolen: String = conn
+                .query_row(
+                    "SELECT payload FROM sync_queue WHERE id = ?1",
+                    [victim],
+                    |r| r.get(0),
+                )
+                .unwrap();
+            // Чужой payload в другой строке
+            conn.execute(
+                "UPDATE sync_queue SET payload = ?1 WHERE id = ?2",
+                params![stolen, other],
+            )
+            .unwrap();
+            // Свой payload, но задача выдаёт себя за другой тип
+            let retyped = db
+                .enqueue_sync("time_entry_resume", r#"{"id": "e3"}"#)
+                .unwrap();
+            conn.execute(
+                "UPDATE sync_queue SET entity_type = 'time_entry_stop' WHERE id = ?1",
+                [retyped],
+            )
+            .unwrap();
+            drop(conn);
+
+            let tasks = db.get_retry_tasks(5, 10, false).unwrap();
+            let ids: Vec<i64> = tasks.iter().map(|t| t.0).collect();
+            assert_eq!(ids, vec![victim]);
+            assert_eq!(tasks[0].2, r#"{"id": "e1"}"#);
+
+            // Отклонённые задачи — failed с причиной, больше не выбираются
+            let mut failed = db.get_failed_tasks(10).unwrap();
+            failed.sort_by_key(|t| t.id);
+            let failed_ids: Vec<i64> = failed.iter().map(|t| t.id).collect();
+            assert_eq!(failed_ids, vec![other, retyped]);
+            for task in &failed {
+                let error = task.error_message.as_deref().unwrap();
+                assert!(error.starts_with("Payload rejected"), "{}", error);
+            }
+            let tasks = db.get_retry_tasks(5, 10, false).unwrap();
+            assert_eq!(tasks.len(), 1);
+        }
+
+        /// Схема выпущенных версий 1..=11 так, как её создавал старый run_migrations
+        const LEGACY_SCHEMA: &[&str] = &[
+            "CREATE TABLE time_entries (
+                id INTEGER PRIMARY KEY AUTOINCREMENT, day TEXT NOT NULL,
+                accumulated_seconds INTEGER NOT NULL DEFAULT 0, state TEXT NOT NULL,
+                last_updated_at INTEGER NOT NULL, UNIQUE(day));
+             CREATE TABLE sync_queue (
+                id INTEGER PRIMARY KEY AUTOINCREMENT, entity_type TEXT NOT NULL, payload TEXT NOT NULL,
+                status TEXT NOT NULL DEFAULT 'pending', retry_count INTEGER NOT NULL DEFAULT 0,
+                created_at INTEGER NOT NULL, last_retry_at INTEGER);
+             CREATE TABLE app_meta (key TEXT PRIMARY KEY, value TEXT);
+             CREATE INDEX idx_sync_queue_status ON sync_queue(status);
+             CREATE INDEX idx_time_entries_day ON time_entries(day);",
+            "ALTER TABLE sync_queue ADD COLUMN error_message TEXT;",
+            "ALTER TABLE sync_queue ADD COLUMN priority INTEGER DEFAULT 2;",
+            "ALTER TABLE time_entries ADD COLUMN started_at INTEGER;",
+            "ALTER TABLE sync_queue ADD COLUMN idempotency_key TEXT;",
+            "ALTER TABLE sync_queue ADD COLUMN blob_ref TEXT;
+             CREATE INDEX idx_sync_queue_blob_ref ON sync_queue(blob_ref);",
+            "CREATE TABLE projects_cache (
+                id TEXT PRIMARY KEY, name TEXT NOT NULL, description TEXT, color TEXT,
+                client_name TEXT, status TEXT, archived INTEGER NOT NULL DEFAULT 0,
+                deleted INTEGER NOT NULL DEFAULT 0, updated_at TEXT, synced_at INTEGER NOT NULL);
+             CREATE TABLE tasks_cache (
+                id TEXT PRIMARY KEY, project_id TEXT NOT NULL, name TEXT NOT NULL, status TEXT,
+                archived INTEGER NOT NULL DEFAULT 0, deleted INTEGER NOT NULL DEFAULT 0,
+                updated_at TEXT, synced_at INTEGER NOT NULL);
+             CREATE INDEX idx_tasks_cache_project ON tasks_cache(project_id);",
+            "ALTER TABLE sync_queue ADD COLUMN sent_at INTEGER;",
+            "ALTER TABLE sync_queue ADD COLUMN api_origin TEXT;",
+            "CREATE TABLE sync_metrics (
+                id INTEGER PRIMARY KEY AUTOINCREMENT, started_at INTEGER NOT NULL,
+                duration_ms INTEGER NOT NULL, attempted INTEGER NOT NULL, succeeded INTEGER NOT NULL,
+                failed INTEGER NOT NULL, failed_by_kind TEXT NOT NULL DEFAULT '{}',
+                bytes_sent INTEGER NOT NULL DEFAULT 0, latency_p50_ms INTEGER, latency_p95_ms INTEGER);",
+            "ALTER TABLE sync_queue ADD COLUMN content_fingerprint TEXT;
+             CREATE INDEX idx_sync_queue_fingerprint ON sync_queue(content_fingerprint, status, created_at);",
+            // v12 — только перешифровка payload, схема не меняется
+            "",
+        ];
+
+        fn applied_migrations(db: &Database) -> Vec<i32> {
+            let conn = db.test_conn();
+            let mut stmt = conn
+                .prepare("SELECT version FROM schema_migrations ORDER BY version")
+                .unwrap();
+            let versions = stmt
+                .query_map([], |r| r.get(0))
+                .unwrap()
+                .map(|r| r.unwrap())
+                .collect();
+            versions
+        }
+
+        fn user_version(db: &Database) -> i32 {
+            let conn = db.test_conn();
+            conn.query_row("PRAGMA user_version", [], |r| r.get(0))
+                .unwrap()
+        }
+
+        #[test]
+        fn test_upgrade_from_every_historical_version() {
+            use crate::migrations::{MIGRATIONS, SCHEMA_VERSION};
+            assert_eq!(LEGACY_SCHEMA.len() as i32, SCHEMA_VERSION - 1);
+            let all: Vec<i32> = MIGRATIONS.iter().map(|m| m.version).collect();
+            assert_eq!(all, (1..=SCHEMA_VERSION).collect::<Vec<_>>());
+
+            for version in 0..SCHEMA_VERSION {
+                let temp_dir = TempDir::new().unwrap();
+                let db_path = temp_dir.path().join("test.db");
+                {
+                    let conn = rusqlite::Connection::open(&db_path).unwrap();
+                    for sql in &LEGACY_SCHEMA[..version as usize] {
+                        conn.execute_batch(sql).unwrap();
+                    }
+                    if version >= 1 {
+                        conn.execute_batch(
+                            "INSERT INTO time_entries (day, accumulated_seconds, state, last_updated_at)
+                             VALUES ('2026-01-05', 3600, 'stopped', 0);
+                             INSERT INTO sync_queue (entity_type, payload, created_at)
+                             VALUES ('time_entry_stop', 'legacy', 0);",
+                        )
+                        .unwrap();
+                    }
+                    if version >= 11 {
+                        // v11 всегда заполнял ключ (UUIDv7 при enqueue, backfill в миграции 11)
+                        conn.execute_batch(
+                            "UPDATE sync_queue SET idempotency_key = 'time_entry_stop-legacy'",
+                        )
+                        .unwrap();
+                    }
+                    conn.pragma_update(None, "user_version", version).unwrap();
+                }
+
+                let db = Database::new(db_path.to_str().unwrap())
+                    .unwrap_or_else(|e| panic!("upgrade from v{} failed: {}", version, e));
+                assert_eq!(user_version(&db), SCHEMA_VERSION, "from v{}", version);
+                assert_eq!(applied_migrations(&db), all, "from v{}", version);
+                if version >= 1 {
+                    assert_eq!(
+                        db.load_timer_state().unwrap().map(|s| s.1),
+                        Some(3600),
+                        "from v{}",
+                        version
+                    );
+                    let key: Option<String> = db
+                        .test_conn()
+                        .query_row(
+                            "SELECT idempotency_key FROM sync_queue WHERE id = 1",
+                            [],
+                            |r| r.get(0),
+                        )
+                        .unwrap();
+                    assert!(key.is_some(), "from v{}", version);
+                }
+                // Новая схема полностью рабочая
+                db.enqueue_sync("time_entry_pause", r#"{"id": "e1"}"#)
+                    .unwrap();
+                drop(db);
+                // Повторное открытие — без изменений
+                let db = Database::new(db_path.to_str().unwrap()).unwrap();
+                assert_eq!(applied_migrations(&db), all);
+            }
+        }
+
+        #[test]
+        fn test_migration_reapplies_step_that_silently_failed() {
+            let temp_dir = TempDir::new().unwrap();
+            let db_path = temp_dir.path().join("test.db");
+            {
+                let db = Database::new(db_path.to_str().unwrap()).unwrap();
+                // БД старого run_migrations: user_version = 11, но ALTER для api_origin не сработал
+                let conn = db.test_conn();
+                conn.execute_batch(
+                    "DROP TABLE schema_migrations;
+                     ALTER TABLE sync_queue DROP COLUMN api_origin;",
+                )
+                .unwrap();
+            }
+
+            let db = Database::new(db_path.to_str().unwrap()).unwrap();
+            assert_eq!(applied_migrations(&db).len(), 13);
+            let has_api_origin: bool = db
+                .test_conn()
+                .query_row(
+                    "SELECT EXISTS(SELECT 1 FROM pragma_table_info('sync_queue') WHERE name = 'api_origin')",
+                    [],
+                    |r| r.get(0),
+                )
+                .unwrap();
+            assert!(has_api_origin);
+            db.enqueue_sync("time_entry_pause", r#"{"id": "e1"}"#)
+                .unwrap();
+        }
+
+        #[test]
+        fn test_refuses_database_newer_than_app() {
+            let temp_dir = TempDir::new().unwrap();
+            let db_path = temp_dir.path().join("test.db");
+            {
+                let db = Database::new(db_path.to_str().unwrap()).unwrap();
+                db.test_conn()
+                    .pragma_update(None, "user_version", 99)
+                    .unwrap();
+            }
+
+            let err = match Database::new(db_path.to_str().unwrap()) {
+                Ok(_) => panic!("newer schema must not open"),
+                Err(e) => e.to_string(),
+            };
+            assert!(err.contains("newer"), "{}", err);
+            // Файл не тронут — новая версия приложения откроет его
+            let conn = rusqlite::Connection::open(&db_path).unwrap();
+            let key = TokenEncryption::new(Some(temp_dir.path()))
+                .unwrap()
+                .database_key();
+            crate::db_pool::apply_key(&conn, key.as_deref()).unwrap();
+            let version: i32 = conn
+                .query_row("PRAGMA user_version", [], |r| r.get(0))
+                .unwrap();
+            assert_eq!(version, 99);
+        }
+
+        #[test]
+        fn test_reads_do_not_wait_for_writer() {
+            let (db, _temp_dir) = create_test_db();
+            db.set_app_meta("k", "v").unwrap();
+            let db = Arc::new(db);
+
+            // Писатель занят (долгая транзакция sync) — чтение из пула отвечает сразу
+            let (release_tx, release_rx) = std::sync::mpsc::channel::<()>();
+            let writer_db = db.clone();
+            let writer = std::thread::spawn(move || {
+                writer_db.write(move |conn| {
+                    let tx = conn.savepoint()?;
+                    tx.execute("UPDATE app_meta SET value = 'busy' WHERE key = 'k'", [])?;
+                    let _ = release_rx.recv();
+                    tx.commit()
+                })
+            });
+            std::thread::sleep(std::time::Duration::from_millis(50));
+
+            let started = Instant::now();
+            assert_eq!(db.get_app_meta("k").unwrap(), Some("v".to_string()));
+            assert_eq!(db.get_pending_count().unwrap(), 0);
+            assert!(started.elapsed() < std::time::Duration::from_secs(1));
+
+            release_tx.send(()).unwrap();
+            writer.join().unwrap().unwrap();
+            assert_eq!(db.get_app_meta("k").unwrap(), Some("busy".to_string()));
+        }
+
+        #[test]
+        fn test_failed_write_in_batch_keeps_other_writes() {
+            let (db, _temp_dir) = create_test_db();
+            let db = Arc::new(db);
+
+            // Держим писателя, чтобы следующие команды попали в один пакет
+            let (release_tx, release_rx) = std::sync::mpsc::channel::<()>();
+            let blocker_db = db.clone();
+            let blocker = std::thread::spawn(move || {
+                blocker_db.write(move |_| {
+                    let _ = release_rx.recv();
+                    Ok(())
+                })
+            });
+            std::thread::sleep(std::time::Duration::from_millis(50));
+
+            let mut handles = Vec::new();
+            for i in 0..4 {
+                let db = db.clone();
+                handles.push(std::thread::spawn(move || {
+                    if i == 2 {
+                        db.write(|conn| {
+                            conn.execute(
+                                "INSERT INTO app_meta (key, value) VALUES ('bad', 'x')",
+                                [],
+                            )?;
+                            conn.execute("INSERT INTO no_such_table VALUES (1)", [])?;
+                            Ok(())
+                        })
+                    } else {
+                        db.set_app_meta(&format!("key_{}", i), "ok")
+                    }
+                }));
+            }
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            release_tx.send(()).unwrap();
+            blocker.join().unwrap().unwrap();
+
+            let results: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+            assert!(results[2].is_err());
+            for i in [0, 1, 3] {
+                assert!(results[i].is_ok(), "write {} failed: {:?}", i, results[i]);
+                assert_eq!(
+                    db.get_app_meta(&format!("key_{}", i)).unwrap(),
+                    Some("ok".to_string())
+                );
+            }
+            // Частичные изменения упавшей команды откачены
+            assert_eq!(db.get_app_meta("bad").unwrap(), None);
+        }
+
+        #[test]
+        fn test_concurrent_enqueue_from_threads() {
+            let (db, _temp_dir) = create_test_db();
+            let db = Arc::new(db);
+
+            let handles: Vec<_> = (0..8)
+                .map(|t| {
+                    let db = db.clone();
+                    std::thread::spawn(move || {
+                        for i in 0..25 {
+                            let payload = format!(r#"{{"thread":{},"i":{}}}"#, t, i);
+                            db.enqueue_sync("time_entry_start", &payload).unwrap();
+                        }
+                    })
+                })
+                .collect();
+            for handle in handles {
+                handle.join().unwrap();
+            }
+
+            assert_eq!(db.get_pending_count().unwrap(), 200);
+            let tasks = db.get_pending_sync_tasks(500).unwrap();
+            let ids: std::collections::HashSet<i64> = tasks.iter().map(|t| t.0).collect();
+            assert_eq!(ids.len(), 200);
+        }
+
+        #[cfg(feature = "sqlcipher")]
+        #[test]
+        fn test_database_with_unknown_key_is_not_reported_as_corrupted() {
+            let other_dir = TempDir::new().unwrap();
+            let other_path = other_dir.path().join("hubnity.db");
+            {
+                let db = Database::new(other_path.to_str().unwrap()).unwrap();
+                db.save_timer_state("2026-01-05", 3600, "stopped", None)
+                    .unwrap();
+                db.test_conn()
+                    .query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))
+                    .unwrap();
+            }
+            // Файл, зашифрованный ключом другого каталога (ключ удалён или чужой)
+            let temp_dir = TempDir::new().unwrap();
+            let db_path = temp_dir.path().join("hubnity.db");
+            std::fs::copy(&other_path, &db_path).unwrap();
+
+            let err = Database::new(db_path.to_str().unwrap())
+                .err()
+                .unwrap()
+                .to_string();
+            assert!(err.contains("key mismatch"), "{}", err);
+            assert!(!crate::salvage::is_corruption_error(&err));
+            assert!(db_path.exists());
+        }
+
+        #[cfg(feature = "sqlcipher")]
+        #[test]
+        fn test_plaintext_database_is_encrypted_on_open() {
+            let temp_dir = TempDir::new().unwrap();
+            let db_path = temp_dir.path().join("test.db");
+            {
+                // БД прежней сборки без SQLCipher
+                let conn = rusqlite::Connection::open(&db_path).unwrap();
+                for sql in LEGACY_SCHEMA {
+                    conn.execute_batch(sql).unwrap();
+                }
+                conn.execute_batch(
+                    "INSERT INTO time_entries (day, accumulated_seconds, state, last_updated_at)
+                     VALUES ('2026-01-05', 3600, 'stopped', 0);",
+                )
+                .unwrap();
+                conn.pragma_update(None, "user_version", LEGACY_SCHEMA.len() as i32)
+                    .unwrap();
+            }
+
+            let db = Database::new(db_path.to_str().unwrap()).unwrap();
+            let (day, seconds, _, _) = db.load_timer_state().unwrap().unwrap();
+            assert_eq!((day.as_str(), seconds), ("2026-01-05", 3600));
+            drop(db);
+
+            let header = std::fs::read(&db_path).unwrap();
+            assert_ne!(&header[..16], b"SQLite format 3\0");
+            assert!(!temp_dir.path().join("test.db-encrypting").exists());
+            // Без ключа файл не читается
+            let conn = rusqlite::Connection::open(&db_path).unwrap();
+            let unreadable = conn.query_row("SELECT COUNT(*) FROM sqlite_master", [], |r| {
+                r.get::<_, i64>(0)
+            });
+            assert!(unreadable.is_err());
+            // Повторное открытие — без повторной миграции
+            let db = Database::new(db_path.to_str().unwrap()).unwrap();
+            assert!(db.load_timer_state().unwrap().is_some());
+        }
     }
 
     // Тесты для SyncManager
@@ -1497,10 +2038,7 @@ mod tests {
 
             let encrypted_payload = &tasks[0].2;
 
-            let decrypted = sync_manager
-                .db
-                .encryption
-                .decrypt(encrypted_payload)
+            let decrypted = decrypt_queue_payload(&sync_manager.db, tasks[0].0)
                 .unwrap_or_else(|_| encrypted_payload.clone());
 
             // PRODUCTION: Парсим payload и проверяем, что токены НЕ сохранены
@@ -1731,7 +2269,7 @@ mod tests {
                 // Для retry_count=1 задержка = 2 минуты, для retry_count=2 задержка = 4 минуты,
                 // для retry_count=3 задержка = 8 минут, для retry_count=4 задержка = 16 минут
                 // Устанавливаем на 20 минут назад, чтобы покрыть все случаи
-                let conn = sync_manager.db.conn.lock().unwrap();
+                let conn = sync_manager.db.test_conn();
                 let twenty_minutes_ago = now - (20 * 60);
                 conn.execute(
                     "UPDATE sync_queue SET last_retry_at = ?1 WHERE id = ?2",
@@ -2024,8 +2562,8 @@ mod tests {
         }
 
         #[test]
-        fn test_enqueue_screenshot_base64_encoding() {
-            // Тест, что screenshot корректно кодируется в base64
+        fn test_enqueue_screenshot_stores_blob_reference() {
+            // Скриншот пишется в BlobStore, в payload очереди — только imageRef
             let (sync_manager, _temp_dir) = create_test_sync_manager();
 
             let png_data = vec![0x01, 0x02, 0x03, 0x04];
@@ -2036,30 +2574,25 @@ mod tests {
                 .enqueue_screenshot(png_data.clone(), time_entry_id, access_token, None)
                 .unwrap();
 
-            // Проверяем, что payload содержит base64-encoded данные
             let tasks = sync_manager.db.get_pending_sync_tasks(10).unwrap();
             let task = tasks.iter().find(|(id, _, _)| *id == queue_id);
             assert!(task.is_some(), "Task should be found");
 
-            if let Some((_, _, payload)) = task {
-                let decrypted_payload = sync_manager
-                    .db
-                    .encryption
-                    .decrypt(&payload)
+            if let Some((id, _, _)) = task {
+                let decrypted_payload = decrypt_queue_payload(&sync_manager.db, *id)
                     .expect("Payload must be decrypted successfully");
                 let payload_json: serde_json::Value = serde_json::from_str(&decrypted_payload)
                     .expect("Decrypted payload must be a valid JSON");
-                let image_data = payload_json["imageData"].as_str().unwrap();
-
-                // Проверяем формат: data:image/jpeg;base64,...
                 assert!(
-                    image_data.starts_with("data:image/jpeg;base64,"),
-                    "Image data should be base64 encoded"
+                    payload_json.get("imageData").is_none(),
+                    "Image bytes must not be stored in sync_queue.payload"
+                );
+                let image_ref = payload_json["imageRef"].as_str().unwrap();
+                assert_eq!(
+                    sync_manager.db.blobs.get(image_ref).unwrap(),
+                    png_data,
+                    "Blob must round-trip to the original image"
                 );
-
-                // Проверяем, что base64 данные присутствуют
-                let base64_part = image_data.strip_prefix("data:image/jpeg;base64,").unwrap();
-                assert!(!base64_part.is_empty(), "Base64 data should not be empty");
             }
         }
 
@@ -2163,6 +2696,229 @@ mod tests {
                 );
             }
         }
+
+        #[test]
+        fn test_request_compression_parse() {
+            use crate::sync::RequestCompression;
+            assert_eq!(
+                RequestCompression::parse("off"),
+                Some(RequestCompression::Off)
+            );
+            assert_eq!(RequestCompression::parse(" GZIP "), Some(RequestCompression::Gzip));
+            assert_eq!(RequestCompression::parse("auto"), Some(RequestCompression::Auto));
+            assert_eq!(RequestCompression::parse("brotli"), None);
+        }
+
+        fn sync_manager_with_compression(
+            compression: crate::sync::RequestCompression,
+        ) -> (SyncManager, TempDir) {
+            let temp_dir = TempDir::new().unwrap();
+            let db_path = temp_dir.path().join("test.db");
+            let db = Arc::new(Database::new(db_path.to_str().unwrap()).unwrap());
+            let config = crate::sync::SyncConfig {
+                request_compression: compression,
+                ..Default::default()
+            };
+            (SyncManager::new_with_config(db, config), temp_dir)
+        }
+
+        fn large_start_payload() -> serde_json::Value {
+            serde_json::json!({
+                "projectId": "project-123",
+                "description": "a".repeat(4096),
+            })
+        }
+
+        #[test]
+        fn test_gzip_request_body_when_forced() {
+            use flate2::read::GzDecoder;
+            use std::io::Read;
+
+            let (sync_manager, _temp_dir) =
+                sync_manager_with_compression(crate::sync::RequestCompression::Gzip);
+            let payload = large_start_payload();
+            let (request, size) = sync_manager
+                .send_time_entry_request("start", &payload, "token", Some("key-1"), true)
+                .unwrap();
+            let request = request.build().unwrap();
+
+            assert_eq!(
+                request.headers().get("Content-Encoding").unwrap(),
+                "gzip",
+                "Large body must be sent gzip-encoded"
+            );
+            let body = request.body().unwrap().as_bytes().unwrap();
+            let mut decoded = String::new();
+            GzDecoder::new(body).read_to_string(&mut decoded).unwrap();
+            let decoded: serde_json::Value = serde_json::from_str(&decoded).unwrap();
+            assert_eq!(decoded, payload);
+
+            let raw_len = serde_json::to_string(&payload).unwrap().len();
+            assert_eq!(size.raw, raw_len);
+            assert_eq!(size.sent, body.len());
+            // Учитывается только после ответа сервера
+            let stats = sync_manager.db.get_queue_stats().unwrap();
+            assert_eq!(stats.upload_bytes_sent, 0);
+        }
+
+        #[test]
+        fn test_request_body_not_compressed_when_disabled_or_small() {
+            let (off_manager, _off_dir) =
+                sync_manager_with_compression(crate::sync::RequestCompression::Off);
+            let request = off_manager
+                .send_time_entry_request("start", &large_start_payload(), "token", None, true)
+                .unwrap()
+                .0
+                .build()
+                .unwrap();
+            assert!(request.headers().get("Content-Encoding").is_none());
+
+            // Маленькое тело не сжимаем даже при Gzip
+            let (gzip_manager, _gzip_dir) =
+                sync_manager_with_compression(crate::sync::RequestCompression::Gzip);
+            let request = gzip_manager
+                .send_time_entry_request(
+                    "start",
+                    &serde_json::json!({"projectId": "1"}),
+                    "token",
+                    None,
+                    true,
+                )
+                .unwrap()
+                .0
+                .build()
+                .unwrap();
+            assert!(request.headers().get("Content-Encoding").is_none());
+
+            // Fallback после 415: allow_compression = false
+            let request = gzip_manager
+                .send_time_entry_request("start", &large_start_payload(), "token", None, false)
+                .unwrap()
+                .0
+                .build()
+                .unwrap();
+            assert!(request.headers().get("Content-Encoding").is_none());
+            let stats = gzip_manager.db.get_queue_stats().unwrap();
+            assert_eq!(stats.compression_saved_bytes, 0);
+        }
+
+        #[test]
+        fn test_auto_compression_waits_for_server_support() {
+            let (sync_manager, _temp_dir) =
+                sync_manager_with_compression(crate::sync::RequestCompression::Auto);
+            let request = sync_manager
+                .send_time_entry_request("start", &large_start_payload(), "token", None, true)
+                .unwrap()
+                .0
+                .build()
+                .unwrap();
+            assert!(
+                request.headers().get("Content-Encoding").is_none(),
+                "Auto must not compress before the server advertises gzip"
+            );
+
+            sync_manager
+                .server_accepts_gzip
+                .store(true, std::sync::atomic::Ordering::Relaxed);
+            let request = sync_manager
+                .send_time_entry_request("start", &large_start_payload(), "token", None, true)
+                .unwrap()
+                .0
+                .build()
+                .unwrap();
+            assert_eq!(request.headers().get("Content-Encoding").unwrap(), "gzip");
+        }
+
+        #[test]
+        fn test_sync_error_kind() {
+            use crate::sync::SyncError;
+            assert_eq!(SyncError::Network("timeout".into()).kind(), "network");
+            assert_eq!(SyncError::Auth("expired".into()).kind(), "auth");
+            assert_eq!(
+                SyncError::Http {
+                    status: 503,
+                    message: String::new()
+                }
+                .kind(),
+                "server"
+            );
+            assert_eq!(
+                SyncError::Http {
+                    status: 422,
+                    message: String::new()
+                }
+                .kind(),
+                "http"
+            );
+            assert_eq!(
+                SyncError::RateLimited(std::time::Duration::from_secs(30)).kind(),
+                "rate_limited"
+            );
+        }
+
+        #[tokio::test]
+        async fn test_sync_queue_emits_progress_events() {
+            let temp_dir = TempDir::new().unwrap();
+            let db_path = temp_dir.path().join("test.db");
+            let db = Arc::new(Database::new(db_path.to_str().unwrap()).unwrap());
+            // Порт 9 (discard) на localhost — соединение отклоняется сразу, задача падает с network
+            let config = crate::sync::SyncConfig {
+                api_base_url: "http://127.0.0.1:9/api".to_string(),
+                http_timeout_secs: 2,
+                ..Default::default()
+            };
+            let sync_manager = SyncManager::new_with_config(db, config);
+            set_test_tokens(&sync_manager).await;
+
+            let events: Arc<std::sync::Mutex<Vec<(String, serde_json::Value)>>> =
+                Arc::new(std::sync::Mutex::new(Vec::new()));
+            let events_sink = events.clone();
+            sync_manager.set_event_sink(Arc::new(
+                move |event: &str, payload: serde_json::Value| {
+                    events_sink
+                        .lock()
+                        .unwrap()
+                        .push((event.to_string(), payload));
+                },
+            ));
+
+            sync_manager
+                .enqueue_time_entry(
+                    "start",
+                    serde_json::json!({"projectId": "123"}),
+                    "test_access_token".to_string(),
+                    None,
+                )
+                .unwrap();
+
+            sync_manager.sync_queue(5).await.unwrap();
+
+            // connectivity-changed (пассивное наблюдение offline) здесь не проверяем
+            let events: Vec<(String, serde_json::Value)> = events
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|(n, _)| n.starts_with("sync-"))
+                .cloned()
+                .collect();
+            let names: Vec<&str> = events.iter().map(|(n, _)| n.as_str()).collect();
+            assert_eq!(
+                names,
+                vec![
+                    crate::ipc::events::SYNC_STARTED,
+                    crate::ipc::events::SYNC_TASK_FAILED,
+                    crate::ipc::events::SYNC_FINISHED,
+                ]
+            );
+            assert_eq!(events[0].1["total"], 1);
+            assert_eq!(events[1].1["entity_type"], "time_entry_start");
+            assert_eq!(events[1].1["index"], 1);
+            assert_eq!(events[1].1["error_class"], "network");
+            assert_eq!(events[1].1["will_retry"], true);
+            assert_eq!(events[2].1["synced"], 0);
+            assert_eq!(events[2].1["failed"], 1);
+            assert_eq!(events[2].1["failed_by_type"]["time_entry_start"], 1);
+        }
     }
 
     // Тесты для Database retry механизма
@@ -2197,7 +2953,7 @@ mod tests {
             // Проверяем, что функция не паникует и возвращает корректный результат
 
             // Устанавливаем last_retry_at на 2 минуты назад (больше чем 1 минута для retry_count=0)
-            let conn = db.conn.lock().unwrap();
+            let conn = db.test_conn();
             let two_minutes_ago = now - (2 * 60);
             conn.execute(
                 "UPDATE sync_queue SET last_retry_at = ?1 WHERE id = ?2",
@@ -2234,7 +2990,7 @@ mod tests {
 
             // Устанавливаем last_retry_at на 30 секунд назад для всех
             // Это должно быть достаточно для retry_count=0 (10 сек) и retry_count=1 (20 сек), но недостаточно для retry_count=2 (40 сек)
-            let conn = db.conn.lock().unwrap();
+            let conn = db.test_conn();
             let thirty_seconds_ago = now - 30;
             conn.execute(
                 "UPDATE sync_queue SET last_retry_at = ?1",
@@ -2294,7 +3050,7 @@ mod tests {
 
             // Проверяем, что last_retry_at обновлен
             let last_retry_at: Option<i64> = {
-                let conn = db.conn.lock().unwrap();
+                let conn = db.test_conn();
                 let mut stmt = conn
                     .prepare("SELECT last_retry_at FROM sync_queue WHERE id = ?1")
                     .unwrap();
@@ -2362,7 +3118,7 @@ mod tests {
             let (db, _temp_dir) = create_test_db();
 
             // Удаляем таблицу напрямую через SQL
-            let conn = db.conn.lock().unwrap();
+            let conn = db.test_conn();
             conn.execute("DROP TABLE IF EXISTS time_entries", [])
                 .unwrap();
             drop(conn);
@@ -2392,7 +3148,7 @@ mod tests {
             let (db, _temp_dir) = create_test_db();
 
             // Вставляем невалидные данные напрямую в БД
-            let conn = db.conn.lock().unwrap();
+            let conn = db.test_conn();
             // Вставляем строку вместо числа для accumulated_seconds (это невозможно через API, но возможно при corruption)
             // SQLite типизирован слабо, поэтому это может пройти, но при чтении может быть проблема
             conn.execute(
@@ -2514,4 +3270,3755 @@ mod tests {
             assert_eq!(loaded1, loaded2);
         }
     }
+
+    // Тесты для BlobStore (скриншоты очереди на диске)
+    #[cfg(test)]
+    mod blob_store_tests {
+        use super::*;
+        use tempfile::TempDir;
+
+        fn create_test_sync_manager() -> (SyncManager, TempDir) {
+            let temp_dir = TempDir::new().unwrap();
+            let db_path = temp_dir.path().join("test.db");
+            let db = Arc::new(Database::new(db_path.to_str().unwrap()).unwrap());
+            (SyncManager::new(db), temp_dir)
+        }
+
+        fn blob_ref_of(sync_manager: &SyncManager, queue_id: i64) -> String {
+            let conn = sync_manager.db.test_conn();
+            conn.query_row(
+                "SELECT blob_ref FROM sync_queue WHERE id = ?1",
+                params![queue_id],
+                |row| row.get(0),
+            )
+            .unwrap()
+        }
+
+        #[test]
+        fn test_blob_is_encrypted_on_disk() {
+            let (sync_manager, temp_dir) = create_test_sync_manager();
+            let image = b"JPEG-PLAINTEXT-MARKER".to_vec();
+            let queue_id = sync_manager
+                .enqueue_screenshot(image.clone(), "entry".into(), String::new(), None)
+                .unwrap();
+            let blob_ref = blob_ref_of(&sync_manager, queue_id);
+
+            let path = temp_dir.path().join("blobs").join(format!("{}.blob", blob_ref));
+            let on_disk = std::fs::read(&path).expect("blob file must exist");
+            assert!(
+                !on_disk.windows(image.len()).any(|w| w == image.as_slice()),
+                "Blob must not be stored in plaintext"
+            );
+        }
+
+        #[test]
+        fn test_mark_task_sent_removes_blob() {
+            let (sync_manager, _temp_dir) = create_test_sync_manager();
+            let queue_id = sync_manager
+                .enqueue_screenshot(vec![1, 2, 3], "entry".into(), String::new(), None)
+                .unwrap();
+            let blob_ref = blob_ref_of(&sync_manager, queue_id);
+            assert!(sync_manager.db.blobs.get(&blob_ref).is_ok());
+
+            sync_manager.db.mark_task_sent(queue_id).unwrap();
+            assert!(
+                sync_manager.db.blobs.get(&blob_ref).is_err(),
+                "Blob must be removed once the task is sent"
+            );
+        }
+
+        #[test]
+        fn test_shared_blob_kept_while_referenced() {
+            let (sync_manager, _temp_dir) = create_test_sync_manager();
+            let first = sync_manager
+                .enqueue_screenshot(vec![9, 9, 9], "entry-a".into(), String::new(), None)
+                .unwrap();
+            let second = sync_manager
+                .enqueue_screenshot(vec![9, 9, 9], "entry-b".into(), String::new(), None)
+                .unwrap();
+            let blob_ref = blob_ref_of(&sync_manager, first);
+            assert_eq!(blob_ref, blob_ref_of(&sync_manager, second));
+
+            sync_manager.db.mark_task_sent(first).unwrap();
+            assert!(
+                sync_manager.db.blobs.get(&blob_ref).is_ok(),
+                "Blob still referenced by a pending task must survive"
+            );
+            sync_manager.db.mark_task_sent(second).unwrap();
+            assert!(sync_manager.db.blobs.get(&blob_ref).is_err());
+        }
+
+        #[test]
+        fn test_identical_screenshot_enqueued_while_previous_is_sent() {
+            let (sync_manager, _temp_dir) = create_test_sync_manager();
+            let image = vec![7u8; 1024];
+            let mut previous = sync_manager
+                .enqueue_screenshot(image.clone(), "entry-0".into(), String::new(), None)
+                .unwrap();
+            // Тот же кадр (заблокированный экран) ставится, пока прежний помечается sent
+            for i in 1..30 {
+                let sender = sync_manager.clone();
+                let sent = std::thread::spawn(move || sender.db.mark_task_sent(previous).unwrap());
+                let next = sync_manager
+                    .enqueue_screenshot(image.clone(), format!("entry-{}", i), String::new(), None)
+                    .unwrap();
+                sent.join().unwrap();
+                let blob_ref = blob_ref_of(&sync_manager, next);
+                assert!(
+                    sync_manager.db.blobs.get(&blob_ref).is_ok(),
+                    "Blob of pending task {} must survive",
+                    next
+                );
+                previous = next;
+            }
+        }
+
+        #[test]
+        fn test_clear_sync_queue_removes_blobs() {
+            let (sync_manager, _temp_dir) = create_test_sync_manager();
+            let queue_id = sync_manager
+                .enqueue_screenshot(vec![4, 5, 6], "entry".into(), String::new(), None)
+                .unwrap();
+            let blob_ref = blob_ref_of(&sync_manager, queue_id);
+
+            sync_manager.db.clear_sync_queue().unwrap();
+            assert!(sync_manager.db.blobs.get(&blob_ref).is_err());
+        }
+
+        #[test]
+        fn test_clear_user_data_removes_blobs() {
+            let (sync_manager, _temp_dir) = create_test_sync_manager();
+            let queue_id = sync_manager
+                .enqueue_screenshot(vec![7, 8], "entry".into(), String::new(), None)
+                .unwrap();
+            let blob_ref = blob_ref_of(&sync_manager, queue_id);
+
+            sync_manager.db.clear_user_data().unwrap();
+            assert!(sync_manager.db.blobs.get(&blob_ref).is_err());
+            assert_eq!(sync_manager.db.get_pending_count().unwrap(), 0);
+        }
+
+        #[test]
+        fn test_blob_store_rejects_invalid_reference() {
+            let (sync_manager, _temp_dir) = create_test_sync_manager();
+            assert!(sync_manager.db.blobs.get("../test.db").is_err());
+            assert!(sync_manager.db.blobs.remove("../../etc/passwd").is_err());
+        }
+
+        #[test]
+        fn test_startup_sweep_keeps_fresh_orphans() {
+            // Blob пишется до INSERT — свежий файл без строки очереди не должен удаляться
+            let temp_dir = TempDir::new().unwrap();
+            let db_path = temp_dir.path().join("test.db");
+            let blob_ref = {
+                let db = Database::new(db_path.to_str().unwrap()).unwrap();
+                db.blobs.put(b"orphan").unwrap()
+            };
+            let db = Database::new(db_path.to_str().unwrap()).unwrap();
+            assert!(db.blobs.get(&blob_ref).is_ok());
+        }
+    }
+
+    // Тесты для server-driven client config (ETag cache)
+    #[cfg(test)]
+    mod client_config_tests {
+        use super::*;
+        use crate::client_config::{ClientConfig, ClientConfigStore, RefreshOutcome};
+        use tempfile::TempDir;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        fn create_test_db() -> (Database, TempDir) {
+            let temp_dir = TempDir::new().unwrap();
+            let db_path = temp_dir.path().join("test.db");
+            let db = Database::new(db_path.to_str().unwrap()).unwrap();
+            (db, temp_dir)
+        }
+
+        /// Одно соединение: читает запрос, отвечает `response`, возвращает текст запроса
+        async fn serve_once(listener: &tokio::net::TcpListener, response: &str) -> String {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = vec![0u8; 8192];
+            let n = socket.read(&mut buf).await.unwrap();
+            socket.write_all(response.as_bytes()).await.unwrap();
+            socket.shutdown().await.ok();
+            String::from_utf8_lossy(&buf[..n]).to_lowercase()
+        }
+
+        #[test]
+        fn test_partial_document_uses_defaults() {
+            let config: ClientConfig =
+                serde_json::from_str(r#"{"idleThresholdSecs": 120, "unknownField": true}"#)
+                    .unwrap();
+            assert_eq!(config.idle_threshold_secs, 120);
+            assert_eq!(
+                config.poll_interval_secs,
+                ClientConfig::default().poll_interval_secs
+            );
+            assert_eq!(config.screenshot_max_width, 1280);
+        }
+
+        #[test]
+        fn test_sanitized_clamps_bad_values() {
+            let config = ClientConfig {
+                idle_threshold_secs: 0,
+                poll_interval_secs: 0,
+                screenshot_max_width: 100_000,
+                screenshot_max_height: 1,
+                screenshot_max_jpeg_bytes: 1,
+                sync_interval_secs: 1,
+            }
+            .sanitized();
+            assert_eq!(config.idle_threshold_secs, 10);
+            assert_eq!(config.poll_interval_secs, 1);
+            assert_eq!(config.screenshot_max_width, 3840);
+            assert_eq!(config.screenshot_max_height, 240);
+            assert_eq!(config.screenshot_max_jpeg_bytes, 50 * 1024);
+            assert_eq!(config.sync_interval_secs, 10);
+        }
+
+        #[tokio::test]
+        async fn test_refresh_caches_document_and_sends_if_none_match() {
+            let (db, _temp_dir) = create_test_db();
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let base_url = format!("http://{}/api", listener.local_addr().unwrap());
+            let client = reqwest::Client::new();
+            let store = ClientConfigStore::new();
+
+            let body = r#"{"idleThresholdSecs":90,"syncIntervalSecs":30}"#;
+            let ok_response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nETag: \"v1\"\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            let (request, outcome) = tokio::join!(
+                serve_once(&listener, &ok_response),
+                store.refresh(&db, &client, &base_url, "token")
+            );
+            assert!(request.starts_with("get /api/client-config"));
+            assert!(!request.contains("if-none-match"));
+            assert_eq!(outcome.unwrap(), RefreshOutcome::Updated);
+            assert_eq!(store.get().idle_threshold_secs, 90);
+            assert_eq!(store.get().sync_interval_secs, 30);
+
+            let not_modified =
+                "HTTP/1.1 304 Not Modified\r\nETag: \"v1\"\r\nConnection: close\r\n\r\n";
+            let (request, outcome) = tokio::join!(
+                serve_once(&listener, not_modified),
+                store.refresh(&db, &client, &base_url, "token")
+            );
+            assert!(request.contains("if-none-match: \"v1\""));
+            assert_eq!(outcome.unwrap(), RefreshOutcome::NotModified);
+
+            // Новый запуск offline: применяется закэшированный документ
+            let restarted = ClientConfigStore::new();
+            assert!(restarted.load_cached(&db));
+            assert_eq!(restarted.get().idle_threshold_secs, 90);
+        }
+
+        #[tokio::test]
+        async fn test_refresh_failure_keeps_current_config() {
+            let (db, _temp_dir) = create_test_db();
+            let store = ClientConfigStore::new();
+            store.set(ClientConfig {
+                sync_interval_secs: 120,
+                ..Default::default()
+            });
+            let client = reqwest::Client::builder()
+                .timeout(std::time::Duration::from_secs(2))
+                .build()
+                .unwrap();
+
+            let result = store
+                .refresh(&db, &client, "http://127.0.0.1:9/api", "token")
+                .await;
+            assert!(result.is_err());
+            assert_eq!(store.get().sync_interval_secs, 120);
+            assert!(!store.load_cached(&db), "Nothing cached after failed fetch");
+        }
+    }
+
+    // Тесты для кэша проектов/задач (offline start)
+    #[cfg(test)]
+    mod project_cache_tests {
+        use super::*;
+        use crate::models::{CachedProject, CachedTask};
+        use tempfile::TempDir;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        fn create_test_sync_manager() -> (SyncManager, TempDir) {
+            let temp_dir = TempDir::new().unwrap();
+            let db_path = temp_dir.path().join("test.db");
+            let db = Arc::new(Database::new(db_path.to_str().unwrap()).unwrap());
+            (SyncManager::new(db), temp_dir)
+        }
+
+        fn project(id: &str, name: &str) -> CachedProject {
+            CachedProject {
+                id: id.to_string(),
+                name: name.to_string(),
+                description: None,
+                color: None,
+                client_name: None,
+                status: Some("ACTIVE".to_string()),
+                archived: false,
+                deleted: false,
+                updated_at: Some("2026-01-01T00:00:00.000Z".to_string()),
+            }
+        }
+
+        #[test]
+        fn test_parse_project_flags() {
+            let active = crate::project_cache::parse_project(&serde_json::json!({
+                "id": "p1", "name": "Site", "clientName": "ACME", "status": "ACTIVE",
+                "updatedAt": "2026-01-02T00:00:00Z"
+            }))
+            .unwrap();
+            assert_eq!(active.client_name.as_deref(), Some("ACME"));
+            assert!(!active.archived && !active.deleted);
+
+            let archived = crate::project_cache::parse_project(
+                &serde_json::json!({"id": 7, "name": "Old", "status": "ARCHIVED"}),
+            )
+            .unwrap();
+            assert_eq!(archived.id, "7");
+            assert!(archived.archived);
+
+            let deleted = crate::project_cache::parse_project(&serde_json::json!({
+                "id": "p3", "name": "Gone", "deletedAt": "2026-01-03T00:00:00Z"
+            }))
+            .unwrap();
+            assert!(deleted.deleted);
+
+            let without_id = serde_json::json!({"name": "x"});
+            assert!(crate::project_cache::parse_project(&without_id).is_none());
+
+            let task = crate::project_cache::parse_task(
+                &serde_json::json!({"id": "t1", "title": "Design", "project": {"id": "p1"}}),
+            )
+            .unwrap();
+            assert_eq!(task.project_id, "p1");
+            assert_eq!(task.name, "Design");
+        }
+
+        #[test]
+        fn test_full_sync_marks_missing_projects_deleted() {
+            let (sync_manager, _temp_dir) = create_test_sync_manager();
+            let db = &sync_manager.db;
+            db.upsert_cached_projects(&[project("p1", "Alpha"), project("p2", "Beta")], true)
+                .unwrap();
+
+            // Инкрементальное обновление не трогает остальные строки
+            let mut archived = project("p2", "Beta");
+            archived.archived = true;
+            db.upsert_cached_projects(&[archived], false).unwrap();
+            let visible = db.list_cached_projects(false).unwrap();
+            assert_eq!(visible.len(), 1);
+            assert_eq!(visible[0].id, "p1");
+            assert_eq!(db.list_cached_projects(true).unwrap().len(), 2);
+
+            // Полный список без p1 → p1 помечен deleted, но остаётся tombstone
+            db.upsert_cached_projects(&[project("p2", "Beta")], true)
+                .unwrap();
+            let visible = db.list_cached_projects(true).unwrap();
+            assert_eq!(visible.len(), 1);
+            assert_eq!(visible[0].id, "p2");
+            assert!(db.get_cached_project("p1").unwrap().unwrap().deleted);
+        }
+
+        #[test]
+        fn test_search_and_tasks() {
+            let (sync_manager, _temp_dir) = create_test_sync_manager();
+            let db = &sync_manager.db;
+            let mut site = project("p1", "Website");
+            site.client_name = Some("ACME Corp".to_string());
+            db.upsert_cached_projects(&[site, project("p2", "100%_done")], true)
+                .unwrap();
+
+            assert_eq!(db.search_cached_projects("web", 10).unwrap().len(), 1);
+            assert_eq!(db.search_cached_projects("acme", 10).unwrap()[0].id, "p1");
+            // % и _ ищутся буквально
+            assert_eq!(db.search_cached_projects("%_", 10).unwrap()[0].id, "p2");
+            assert!(db.search_cached_projects("_e", 10).unwrap().is_empty());
+
+            let task = |id: &str, project_id: &str| CachedTask {
+                id: id.to_string(),
+                project_id: project_id.to_string(),
+                name: format!("Task {}", id),
+                status: None,
+                archived: false,
+                deleted: false,
+                updated_at: None,
+            };
+            db.upsert_cached_tasks(&[task("t1", "p1"), task("t2", "p2")], true)
+                .unwrap();
+            assert_eq!(db.list_cached_tasks(Some("p1")).unwrap().len(), 1);
+            assert_eq!(db.list_cached_tasks(None).unwrap().len(), 2);
+        }
+
+        #[test]
+        fn test_offline_start_validated_against_cache() {
+            let (sync_manager, _temp_dir) = create_test_sync_manager();
+            let mut archived = project("p2", "Legacy");
+            archived.archived = true;
+            let mut deleted = project("p3", "Removed");
+            deleted.deleted = true;
+            sync_manager
+                .db
+                .upsert_cached_projects(&[project("p1", "Website"), archived, deleted], false)
+                .unwrap();
+            sync_manager
+                .db
+                .set_app_meta("current_user_id", "user-1")
+                .unwrap();
+
+            let enqueue = |payload: serde_json::Value| {
+                sync_manager.enqueue_time_entry("start", payload, String::new(), None)
+            };
+            assert!(enqueue(serde_json::json!({"projectId": "p2"}))
+                .unwrap_err()
+                .contains("archived"));
+            assert!(enqueue(serde_json::json!({"projectId": "p3"}))
+                .unwrap_err()
+                .contains("deleted"));
+            assert!(enqueue(serde_json::json!({"description": "x"}))
+                .unwrap_err()
+                .contains("Missing projectId"));
+            // Неизвестный проект (кэш устарел) — не блокируем
+            assert!(enqueue(serde_json::json!({"projectId": "p-new"})).is_ok());
+
+            let queue_id = enqueue(serde_json::json!({"projectId": "p1"})).unwrap();
+            let tasks = sync_manager.db.get_pending_sync_tasks(10).unwrap();
+            assert!(tasks.iter().any(|t| t.0 == queue_id));
+            let payload = decrypt_queue_payload(&sync_manager.db, queue_id).unwrap();
+            let payload: serde_json::Value = serde_json::from_str(&payload).unwrap();
+            assert_eq!(payload["userId"], "user-1");
+            assert_eq!(payload["description"], "Work on project Website");
+        }
+
+        #[tokio::test]
+        async fn test_refresh_from_api_without_tasks_endpoint() {
+            let (sync_manager, _temp_dir) = create_test_sync_manager();
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let base_url = format!("http://{}/api", listener.local_addr().unwrap());
+
+            let server = async {
+                let mut requests = Vec::new();
+                let body = r#"[{"id":"p1","name":"Website","updatedAt":"2026-02-01T00:00:00Z"}]"#;
+                let responses = [
+                    format!(
+                        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        body.len(),
+                        body
+                    ),
+                    "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                        .to_string(),
+                ];
+                for response in responses {
+                    let (mut socket, _) = listener.accept().await.unwrap();
+                    let mut buf = vec![0u8; 8192];
+                    let n = socket.read(&mut buf).await.unwrap();
+                    socket.write_all(response.as_bytes()).await.unwrap();
+                    socket.shutdown().await.ok();
+                    requests.push(String::from_utf8_lossy(&buf[..n]).to_string());
+                }
+                requests
+            };
+            let client = reqwest::Client::new();
+            let (requests, result) = tokio::join!(
+                server,
+                crate::project_cache::refresh(&sync_manager.db, &client, &base_url, "token", false)
+            );
+            let result = result.unwrap();
+            assert!(result.full_sync, "First refresh has no cursor → full");
+            assert_eq!(result.projects_updated, 1);
+            assert!(!result.tasks_supported);
+            assert!(requests[0].starts_with("GET /api/projects?includeArchived=true "));
+            assert!(requests[1].starts_with("GET /api/tasks"));
+            assert_eq!(
+                sync_manager
+                    .db
+                    .get_app_meta("projects_cache_cursor")
+                    .unwrap()
+                    .as_deref(),
+                Some("2026-02-01T00:00:00Z")
+            );
+            let cached = sync_manager.db.list_cached_projects(false).unwrap();
+            assert_eq!(cached.len(), 1);
+        }
+    }
+
+    // Тесты для retention / обслуживания sync_queue
+    #[cfg(test)]
+    mod maintenance_tests {
+        use super::*;
+        use tempfile::TempDir;
+
+        fn create_test_db() -> (Database, TempDir) {
+            let temp_dir = TempDir::new().unwrap();
+            let db_path = temp_dir.path().join("test.db");
+            let db = Database::new(db_path.to_str().unwrap()).unwrap();
+            (db, temp_dir)
+        }
+
+        fn payload_of(db: &Database, id: i64) -> String {
+            let conn = db.test_conn();
+            conn.query_row(
+                "SELECT payload FROM sync_queue WHERE id = ?1",
+                [id],
+                |row| row.get(0),
+            )
+            .unwrap()
+        }
+
+        #[test]
+        fn test_new_database_uses_incremental_auto_vacuum() {
+            let (db, _temp_dir) = create_test_db();
+            let conn = db.test_conn();
+            let mode: i32 = conn
+                .query_row("PRAGMA auto_vacuum", [], |r| r.get(0))
+                .unwrap();
+            assert_eq!(mode, 2, "auto_vacuum must be INCREMENTAL");
+        }
+
+        #[test]
+        fn test_maintenance_retries_incremental_auto_vacuum() {
+            let (db, _temp_dir) = create_test_db();
+            // Как после миграции, чей VACUUM не прошёл
+            db.test_conn()
+                .execute_batch("PRAGMA auto_vacuum = NONE; VACUUM")
+                .unwrap();
+            let mode = |db: &Database| -> i32 {
+                db.test_conn()
+                    .query_row("PRAGMA auto_vacuum", [], |r| r.get(0))
+                    .unwrap()
+            };
+            assert_eq!(mode(&db), 0);
+
+            let report = db.run_maintenance().unwrap();
+            assert!(report.auto_vacuum_incremental);
+            assert_eq!(mode(&db), 2);
+        }
+
+        #[test]
+        fn test_mark_task_sent_drops_payload() {
+            let (db, _temp_dir) = create_test_db();
+            let id = db
+                .enqueue_sync("time_entry_start", r#"{"projectId":"p1"}"#)
+                .unwrap();
+            db.mark_task_sent(id).unwrap();
+            assert_eq!(payload_of(&db, id), "");
+
+            // Политика без удаления payload
+            db.set_app_meta("sync_retention_drop_payloads", "false")
+                .unwrap();
+            let id = db
+                .enqueue_sync("time_entry_stop", r#"{"id":"e1"}"#)
+                .unwrap();
+            db.mark_task_sent(id).unwrap();
+            assert!(!payload_of(&db, id).is_empty());
+        }
+
+        #[test]
+        fn test_sent_time_entry_ops_keep_payload_for_id_fallback() {
+            let (db, _temp_dir) = create_test_db();
+            let id = db
+                .enqueue_sync("time_entry_pause", r#"{"id":"entry-7"}"#)
+                .unwrap();
+            db.mark_task_sent(id).unwrap();
+            db.run_maintenance().unwrap();
+
+            assert!(!payload_of(&db, id).is_empty());
+            assert_eq!(
+                db.get_last_time_entry_id_from_queue().unwrap().as_deref(),
+                Some("entry-7")
+            );
+
+            // После retention строка удаляется вместе с payload
+            db.test_conn()
+                .execute(
+                    "UPDATE sync_queue SET sent_at = ?1 WHERE id = ?2",
+                    rusqlite::params![Utc::now().timestamp() - 8 * 24 * 60 * 60, id],
+                )
+                .unwrap();
+            assert_eq!(db.run_maintenance().unwrap().rows_deleted, 1);
+            assert_eq!(db.get_last_time_entry_id_from_queue().unwrap(), None);
+        }
+
+        #[test]
+        fn test_run_maintenance_prunes_old_sent_rows() {
+            let (db, _temp_dir) = create_test_db();
+            db.set_app_meta("sync_retention_drop_payloads", "false")
+                .unwrap();
+            let big_payload = serde_json::json!({ "data": "x".repeat(64 * 1024) }).to_string();
+            let mut old_ids = Vec::new();
+            for i in 0..20 {
+                let id = db
+                    .enqueue_sync(&format!("screenshot_{}", i), &big_payload)
+                    .unwrap();
+                db.mark_task_sent(id).unwrap();
+                old_ids.push(id);
+            }
+            let recent_sent = db
+                .enqueue_sync("time_entry_start", r#"{"projectId":"p1"}"#)
+                .unwrap();
+            db.mark_task_sent(recent_sent).unwrap();
+            let pending = db
+                .enqueue_sync("time_entry_stop", r#"{"id":"e1"}"#)
+                .unwrap();
+            {
+                let conn = db.test_conn();
+                let old = Utc::now().timestamp() - 8 * 24 * 60 * 60;
+                for id in &old_ids {
+                    conn.execute(
+                        "UPDATE sync_queue SET sent_at = ?1 WHERE id = ?2",
+                        rusqlite::params![old, id],
+                    )
+                    .unwrap();
+                }
+            }
+            db.set_app_meta("sync_retention_drop_payloads", "true")
+                .unwrap();
+
+            let report = db.run_maintenance().unwrap();
+            assert_eq!(report.rows_deleted, old_ids.len());
+            assert_eq!(report.payloads_dropped, 21);
+            assert!(report.pages_freed > 0, "incremental_vacuum must free pages");
+            assert!(report.wal_truncated);
+            assert!(
+                report.reclaimed_bytes > 0,
+                "reclaimed {} (before {}, after {})",
+                report.reclaimed_bytes,
+                report.bytes_before,
+                report.bytes_after
+            );
+
+            let stats = db.get_queue_stats().unwrap();
+            assert_eq!(stats.sent_count, 1, "Recent sent row is kept for 7 days");
+            assert_eq!(stats.pending_count, 1);
+            assert!(
+                !payload_of(&db, pending).is_empty(),
+                "Pending payload untouched"
+            );
+            assert!(!db.maintenance_due());
+        }
+
+        #[test]
+        fn test_retention_days_override() {
+            let (db, _temp_dir) = create_test_db();
+            assert_eq!(db.retention_policy().unwrap().sent_retention_days, 7);
+            db.set_app_meta("sync_retention_sent_days", "0").unwrap();
+            let id = db
+                .enqueue_sync("time_entry_start", r#"{"projectId":"p1"}"#)
+                .unwrap();
+            db.mark_task_sent(id).unwrap();
+            {
+                let conn = db.test_conn();
+                conn.execute(
+                    "UPDATE sync_queue SET sent_at = sent_at - 1 WHERE id = ?1",
+                    [id],
+                )
+                .unwrap();
+            }
+            assert_eq!(db.run_maintenance().unwrap().rows_deleted, 1);
+        }
+    }
+
+    mod connectivity_tests {
+        use crate::connectivity::{ConnectivityConfig, ConnectivityMonitor, ConnectivityState};
+        use crate::ipc::EventSink;
+        use std::sync::{Arc, Mutex, RwLock};
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        type Events = Arc<Mutex<Vec<(String, serde_json::Value)>>>;
+
+        fn monitor(api: &str) -> (ConnectivityMonitor, Events) {
+            let events: Events = Arc::new(Mutex::new(Vec::new()));
+            let captured = events.clone();
+            let sink: EventSink = Arc::new(move |event: &str, payload: serde_json::Value| {
+                captured.lock().unwrap().push((event.to_string(), payload));
+            });
+            let monitor = ConnectivityMonitor::new(
+                ConnectivityConfig::for_api(api),
+                &crate::http_client::HttpClientFactory::default(),
+                Arc::new(RwLock::new(Some(sink))),
+            );
+            (monitor, events)
+        }
+
+        /// Health endpoint на локальном порту: одно соединение, ответ со статусом `status`
+        async fn serve_health(status: &'static str) -> String {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let api = format!("http://{}/api", listener.local_addr().unwrap());
+            tokio::spawn(async move {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut buf = vec![0u8; 4096];
+                let _ = socket.read(&mut buf).await;
+                let response = format!(
+                    "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    status
+                );
+                socket.write_all(response.as_bytes()).await.unwrap();
+                socket.shutdown().await.ok();
+            });
+            api
+        }
+
+        #[test]
+        fn test_health_url_from_api_base() {
+            let config = ConnectivityConfig::for_api("https://api.example.com/api/");
+            assert_eq!(config.health_url, "https://api.example.com/api/health");
+        }
+
+        #[test]
+        fn test_hysteresis_requires_consecutive_observations() {
+            let (monitor, events) = monitor("http://127.0.0.1:9/api");
+            assert_eq!(monitor.state(), None);
+
+            // Первое наблюдение применяется сразу
+            assert_eq!(
+                monitor.observe(ConnectivityState::Online, Some(20)),
+                ConnectivityState::Online
+            );
+            // Одиночный сбой не переключает состояние
+            assert_eq!(
+                monitor.observe(ConnectivityState::Offline, None),
+                ConnectivityState::Online
+            );
+            // Серия прервана успешным ответом — счётчик сбрасывается
+            assert_eq!(
+                monitor.observe(ConnectivityState::Online, Some(20)),
+                ConnectivityState::Online
+            );
+            assert_eq!(
+                monitor.observe(ConnectivityState::Offline, None),
+                ConnectivityState::Online
+            );
+            assert_eq!(
+                monitor.observe(ConnectivityState::Offline, None),
+                ConnectivityState::Offline
+            );
+
+            let events = events.lock().unwrap();
+            assert_eq!(events.len(), 2, "Initial state + one transition");
+            assert_eq!(events[0].0, crate::ipc::events::CONNECTIVITY_CHANGED);
+            assert_eq!(events[0].1["state"], "online");
+            assert!(events[0].1["previous"].is_null());
+            assert_eq!(events[1].1["state"], "offline");
+            assert_eq!(events[1].1["previous"], "online");
+        }
+
+        #[tokio::test]
+        async fn test_probe_unreachable_host_is_offline() {
+            let (monitor, _events) = monitor("http://127.0.0.1:9/api");
+            assert_eq!(monitor.probe().await, ConnectivityState::Offline);
+            assert!(
+                !monitor.is_online().await,
+                "Cached offline state within TTL"
+            );
+        }
+
+        #[tokio::test]
+        async fn test_probe_classifies_server_errors_as_degraded() {
+            let api = serve_health("503 Service Unavailable").await;
+            let (monitor, _events) = monitor(&api);
+            assert_eq!(monitor.probe().await, ConnectivityState::Degraded);
+            assert!(monitor.is_online().await, "Degraded still allows sync");
+        }
+
+        #[tokio::test]
+        async fn test_probe_any_client_response_is_online() {
+            // Нет /health на сервере — 404, но хост доступен
+            let api = serve_health("404 Not Found").await;
+            let (monitor, _events) = monitor(&api);
+            assert_eq!(monitor.probe().await, ConnectivityState::Online);
+        }
+    }
+
+    mod http_client_tests {
+        use super::*;
+        use crate::http_client::{HttpClientFactory, HttpClientSettings, TransportErrorKind};
+        use crate::sync::{SyncConfig, SyncError};
+        use std::time::Duration;
+        use tempfile::TempDir;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        fn create_test_db() -> (Database, TempDir) {
+            let temp_dir = TempDir::new().unwrap();
+            let db_path = temp_dir.path().join("test.db");
+            let db = Database::new(db_path.to_str().unwrap()).unwrap();
+            (db, temp_dir)
+        }
+
+        /// Локальный "прокси": одно соединение, возвращает первую строку запроса
+        async fn serve_proxy_once(response: &'static str) -> (String, tokio::task::JoinHandle<String>) {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let url = format!("http://{}", listener.local_addr().unwrap());
+            let handle = tokio::spawn(async move {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut buf = vec![0u8; 8192];
+                let n = socket.read(&mut buf).await.unwrap();
+                socket.write_all(response.as_bytes()).await.unwrap();
+                socket.shutdown().await.ok();
+                String::from_utf8_lossy(&buf[..n])
+                    .lines()
+                    .next()
+                    .unwrap_or("")
+                    .to_string()
+            });
+            (url, handle)
+        }
+
+        fn proxy_settings(proxy_url: &str) -> HttpClientSettings {
+            HttpClientSettings {
+                proxy_url: Some(proxy_url.to_string()),
+                use_system_proxy: false,
+                ..Default::default()
+            }
+        }
+
+        #[test]
+        fn test_validate_rejects_bad_proxy_and_ca() {
+            assert!(HttpClientSettings::default().validate().is_ok());
+            assert!(proxy_settings("socks5h://proxy.corp:1080")
+                .validate()
+                .is_ok());
+            assert!(proxy_settings("ftp://proxy.corp:21").validate().is_err());
+            assert!(proxy_settings("proxy.corp:3128").validate().is_err());
+
+            let missing_file = HttpClientSettings {
+                extra_ca_certs: vec!["/nonexistent/corp-root.pem".into()],
+                ..Default::default()
+            };
+            assert!(missing_file
+                .validate()
+                .unwrap_err()
+                .contains("corp-root.pem"));
+            let garbage = HttpClientSettings {
+                extra_ca_certs: vec![
+                    "-----BEGIN CERTIFICATE-----\nnot base64\n-----END CERTIFICATE-----".into(),
+                ],
+                ..Default::default()
+            };
+            assert!(garbage.validate().is_err());
+        }
+
+        #[test]
+        fn test_settings_roundtrip_encrypts_proxy_password() {
+            let (db, _temp_dir) = create_test_db();
+            assert_eq!(HttpClientSettings::load(&db), HttpClientSettings::default());
+
+            let settings = HttpClientSettings {
+                proxy_username: Some("alice".into()),
+                proxy_password: Some("s3cret-pass".into()),
+                no_proxy: Some("localhost,.corp.local".into()),
+                ..proxy_settings("http://proxy.corp:3128")
+            };
+            settings.save(&db).unwrap();
+
+            let raw = db.get_app_meta("http_client_settings").unwrap().unwrap();
+            assert!(
+                !raw.contains("s3cret-pass"),
+                "Password must not be stored in plain text"
+            );
+            assert_eq!(HttpClientSettings::load(&db), settings);
+
+            // Невалидные настройки не сохраняются
+            assert!(proxy_settings("gopher://x").save(&db).is_err());
+            assert_eq!(HttpClientSettings::load(&db), settings);
+        }
+
+        #[tokio::test]
+        async fn test_requests_go_through_explicit_proxy() {
+            let (proxy_url, handle) =
+                serve_proxy_once("HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok")
+                    .await;
+            let client =
+                HttpClientFactory::new(proxy_settings(&proxy_url)).build(Duration::from_secs(5));
+            let response = client
+                .get("http://api.example.invalid/api/health")
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), 200);
+            assert_eq!(
+                handle.await.unwrap(),
+                "GET http://api.example.invalid/api/health HTTP/1.1",
+                "Plain HTTP via proxy uses absolute-form request line"
+            );
+        }
+
+        #[tokio::test]
+        async fn test_no_proxy_bypasses_proxy() {
+            let (target, handle) =
+                serve_proxy_once("HTTP/1.1 204 No Content\r\nConnection: close\r\n\r\n").await;
+            let settings = HttpClientSettings {
+                no_proxy: Some("127.0.0.1".into()),
+                ..proxy_settings("http://127.0.0.1:9")
+            };
+            let client = HttpClientFactory::new(settings).build(Duration::from_secs(5));
+            let response = client
+                .get(format!("{}/health", target))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), 204);
+            assert_eq!(handle.await.unwrap(), "GET /health HTTP/1.1");
+        }
+
+        #[tokio::test]
+        async fn test_proxy_failures_classified_as_proxy_errors() {
+            // Прокси не слушает: ошибка соединения при явном прокси
+            let factory = HttpClientFactory::new(proxy_settings("http://127.0.0.1:9"));
+            let err = factory
+                .build(Duration::from_secs(5))
+                .get("http://api.example.invalid/api")
+                .send()
+                .await
+                .unwrap_err();
+            assert_eq!(factory.classify(&err), TransportErrorKind::Proxy);
+
+            // Без прокси тот же отказ соединения — обычная сетевая ошибка
+            let direct = HttpClientFactory::default();
+            let err = direct
+                .build(Duration::from_secs(5))
+                .get("http://127.0.0.1:9/api")
+                .send()
+                .await
+                .unwrap_err();
+            assert_eq!(direct.classify(&err), TransportErrorKind::Network);
+            assert!(matches!(
+                SyncError::from_transport(&err, &direct),
+                SyncError::Network(_)
+            ));
+
+            // CONNECT отклонён (407) — ошибка туннеля
+            let (proxy_url, _handle) = serve_proxy_once(
+                "HTTP/1.1 407 Proxy Authentication Required\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            )
+            .await;
+            let factory = HttpClientFactory::new(proxy_settings(&proxy_url));
+            let err = factory
+                .build(Duration::from_secs(5))
+                .get("https://api.example.invalid/api")
+                .send()
+                .await
+                .unwrap_err();
+            let sync_err = SyncError::from_transport(&err, &factory);
+            assert!(
+                matches!(sync_err, SyncError::Proxy(_)),
+                "got {:?}",
+                sync_err
+            );
+            assert_eq!(sync_err.kind(), "proxy");
+        }
+
+        #[tokio::test]
+        async fn test_sync_task_reports_proxy_auth_required() {
+            let temp_dir = TempDir::new().unwrap();
+            let db_path = temp_dir.path().join("test.db");
+            let db = Arc::new(Database::new(db_path.to_str().unwrap()).unwrap());
+            let (proxy_url, handle) = serve_proxy_once(
+                "HTTP/1.1 407 Proxy Authentication Required\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            )
+            .await;
+            let config = SyncConfig {
+                api_base_url: "http://api.example.invalid/api".to_string(),
+                http_timeout_secs: 5,
+                http: HttpClientFactory::new(proxy_settings(&proxy_url)),
+                ..Default::default()
+            };
+            let sync_manager = SyncManager::new_with_config(db, config);
+            sync_manager
+                .auth_manager
+                .set_tokens(Some("token".into()), None)
+                .await;
+
+            let result = sync_manager
+                .sync_task(
+                    1,
+                    "time_entry_start".to_string(),
+                    r#"{"projectId":"p1"}"#.to_string(),
+                    None,
+                )
+                .await;
+            assert!(
+                matches!(result, Err(SyncError::Proxy(_))),
+                "got {:?}",
+                result
+            );
+            assert!(handle
+                .await
+                .unwrap()
+                .starts_with("POST http://api.example.invalid/api/"));
+        }
+    }
+
+    mod endpoint_tests {
+        use super::*;
+        use crate::endpoint::{
+            normalize_api_url, resolve, save_user_url, EndpointSource, DEFAULT_API_BASE_URL,
+        };
+        use crate::models::CachedProject;
+        use tempfile::TempDir;
+
+        const STAGING: &str = "https://staging.example.com/api";
+
+        fn create_test_db() -> (Database, TempDir) {
+            let temp_dir = TempDir::new().unwrap();
+            let db_path = temp_dir.path().join("test.db");
+            let db = Database::new(db_path.to_str().unwrap()).unwrap();
+            (db, temp_dir)
+        }
+
+        #[test]
+        fn test_normalize_api_url() {
+            assert_eq!(
+                normalize_api_url(" https://staging.example.com/api/ ").unwrap(),
+                STAGING
+            );
+            assert_eq!(
+                normalize_api_url("http://localhost:3000/api").unwrap(),
+                "http://localhost:3000/api"
+            );
+            assert!(
+                normalize_api_url("http://onprem.corp/api").is_err(),
+                "Plain http only for localhost"
+            );
+            assert!(normalize_api_url("ftp://example.com/api").is_err());
+            assert!(normalize_api_url("https://user:pw@example.com/api").is_err());
+            assert!(normalize_api_url("https://example.com/api?tenant=1").is_err());
+            assert!(normalize_api_url("not a url").is_err());
+        }
+
+        #[test]
+        fn test_resolve_priority_policy_env_settings_default() {
+            let (db, temp_dir) = create_test_db();
+            let endpoint = resolve(&db, None, None);
+            assert_eq!(endpoint.url, DEFAULT_API_BASE_URL);
+            assert_eq!(endpoint.source, EndpointSource::Default);
+            assert!(!endpoint.locked);
+
+            save_user_url(&db, Some("https://onprem.corp/api/")).unwrap();
+            let endpoint = resolve(&db, None, None);
+            assert_eq!(endpoint.url, "https://onprem.corp/api");
+            assert_eq!(endpoint.source, EndpointSource::Settings);
+
+            let endpoint = resolve(&db, None, Some(STAGING.to_string()));
+            assert_eq!(endpoint.source, EndpointSource::Env);
+            assert!(endpoint.locked);
+
+            let policy = temp_dir.path().join("policy.json");
+            std::fs::write(&policy, r#"{"apiBaseUrl": "https://policy.corp/api"}"#).unwrap();
+            let endpoint = resolve(&db, Some(&policy), Some(STAGING.to_string()));
+            assert_eq!(endpoint.url, "https://policy.corp/api");
+            assert_eq!(endpoint.source, EndpointSource::Policy);
+
+            // Невалидная политика пропускается — следующий источник
+            std::fs::write(&policy, r#"{"apiBaseUrl": "http://policy.corp/api"}"#).unwrap();
+            let endpoint = resolve(&db, Some(&policy), Some(STAGING.to_string()));
+            assert_eq!(endpoint.source, EndpointSource::Env);
+
+            // Сброс настройки — снова default
+            save_user_url(&db, None).unwrap();
+            assert_eq!(resolve(&db, None, None).source, EndpointSource::Default);
+            assert!(save_user_url(&db, Some("http://onprem.corp")).is_err());
+        }
+
+        #[test]
+        fn test_switch_api_origin_parks_other_server_queue() {
+            let (db, _temp_dir) = create_test_db();
+            // Задача из версии без api_origin — принадлежит адресу по умолчанию
+            let legacy = db
+                .enqueue_sync("time_entry_start", r#"{"projectId":"p1"}"#)
+                .unwrap();
+            let switch = db.switch_api_origin(DEFAULT_API_BASE_URL).unwrap();
+            assert!(!switch.changed);
+            assert_eq!(db.get_queue_stats().unwrap().pending_count, 1);
+
+            db.upsert_cached_projects(
+                &[CachedProject {
+                    id: "p1".into(),
+                    name: "Production project".into(),
+                    description: None,
+                    color: None,
+                    client_name: None,
+                    status: None,
+                    archived: false,
+                    deleted: false,
+                    updated_at: None,
+                }],
+                true,
+            )
+            .unwrap();
+
+            let switch = db.switch_api_origin(STAGING).unwrap();
+            assert!(switch.changed);
+            assert_eq!(switch.previous.as_deref(), Some(DEFAULT_API_BASE_URL));
+            assert_eq!(switch.parked, 1);
+            let stats = db.get_queue_stats().unwrap();
+            assert_eq!(
+                stats.pending_count, 0,
+                "Production task must not be sent to staging"
+            );
+            assert_eq!(stats.parked_count, 1);
+            assert!(db.list_cached_projects(true).unwrap().is_empty());
+
+            let staging_task = db
+                .enqueue_sync("time_entry_stop", r#"{"id":"e1"}"#)
+                .unwrap();
+
+            // Обратно на production: его задача возвращается, задача staging откладывается
+            let switch = db.switch_api_origin(DEFAULT_API_BASE_URL).unwrap();
+            assert_eq!((switch.parked, switch.restored), (1, 1));
+            let pending: Vec<i64> = db
+                .get_pending_sync_tasks(10)
+                .unwrap()
+                .into_iter()
+                .map(|(id, _, _)| id)
+                .collect();
+            assert_eq!(pending, vec![legacy]);
+            assert!(!pending.contains(&staging_task));
+        }
+    }
+
+    /// In-process stand-in for the Hubnity API: scripted responses per route, recorded requests.
+    /// Every response closes the connection, so each request is parsed independently.
+    mod mock_api {
+        use std::collections::{HashMap, VecDeque};
+        use std::io::Read;
+        use std::sync::{Arc, Mutex};
+        use std::time::Duration;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio::net::{TcpListener, TcpStream};
+
+        #[derive(Clone, Debug)]
+        pub struct RecordedRequest {
+            pub method: String,
+            /// Путь без префикса /api (с query)
+            pub path: String,
+            pub headers: HashMap<String, String>,
+            /// Тело (gzip уже распакован)
+            pub body: String,
+        }
+
+        impl RecordedRequest {
+            pub fn header(&self, name: &str) -> Option<&str> {
+                self.headers
+                    .get(&name.to_ascii_lowercase())
+                    .map(|v| v.as_str())
+            }
+        }
+
+        #[derive(Clone, Debug)]
+        pub struct MockResponse {
+            status: u16,
+            headers: Vec<(String, String)>,
+            body: String,
+            body_delay: Option<Duration>,
+            truncate_at: Option<usize>,
+        }
+
+        impl MockResponse {
+            pub fn status(status: u16) -> Self {
+                Self {
+                    status,
+                    headers: Vec::new(),
+                    body: String::new(),
+                    body_delay: None,
+                    truncate_at: None,
+                }
+            }
+
+            pub fn json(status: u16, body: serde_json::Value) -> Self {
+                Self {
+                    body: body.to_string(),
+                    ..Self::status(status)
+                }
+                .header("Content-Type", "application/json")
+            }
+
+            pub fn text(status: u16, body: &str) -> Self {
+                Self {
+                    body: body.to_string(),
+                    ..Self::status(status)
+                }
+            }
+
+            pub fn header(mut self, name: &str, value: &str) -> Self {
+                self.headers.push((name.to_string(), value.to_string()));
+                self
+            }
+
+            /// Заголовки сразу, тело — через `delay`
+            pub fn slow_body(mut self, delay: Duration) -> Self {
+                self.body_delay = Some(delay);
+                self
+            }
+
+            /// Оборвать соединение после `bytes` байт ответа (включая status line)
+            pub fn truncated(mut self, bytes: usize) -> Self {
+                self.truncate_at = Some(bytes);
+                self
+            }
+
+            fn head(&self) -> String {
+                let mut head = format!("HTTP/1.1 {} Mock\r\n", self.status);
+                for (name, value) in &self.headers {
+                    head.push_str(&format!("{}: {}\r\n", name, value));
+                }
+                head.push_str(&format!(
+                    "Content-Length: {}\r\nConnection: close\r\n\r\n",
+                    self.body.len()
+                ));
+                head
+            }
+        }
+
+        #[derive(Default)]
+        struct State {
+            scripts: HashMap<(String, String), VecDeque<MockResponse>>,
+            requests: Vec<RecordedRequest>,
+        }
+
+        pub struct MockApi {
+            pub base_url: String,
+            state: Arc<Mutex<State>>,
+            server: tokio::task::JoinHandle<()>,
+        }
+
+        impl MockApi {
+            pub async fn start() -> Self {
+                let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+                let base_url = format!("http://{}/api", listener.local_addr().unwrap());
+                let state = Arc::new(Mutex::new(State::default()));
+                let server_state = state.clone();
+                let server = tokio::spawn(async move {
+                    while let Ok((socket, _)) = listener.accept().await {
+                        tokio::spawn(handle(socket, server_state.clone()));
+                    }
+                });
+                Self {
+                    base_url,
+                    state,
+                    server,
+                }
+            }
+
+            /// Следующий ответ на `method path` (FIFO). Без сценария — 200 `{}`.
+            pub fn on(&self, method: &str, path: &str, response: MockResponse) {
+                self.state
+                    .lock()
+                    .unwrap()
+                    .scripts
+                    .entry((method.to_string(), path.to_string()))
+                    .or_default()
+                    .push_back(response);
+            }
+
+            /// Запросы в порядке поступления (health-пробы не записываются)
+            pub fn requests(&self) -> Vec<RecordedRequest> {
+                self.state.lock().unwrap().requests.clone()
+            }
+        }
+
+        impl Drop for MockApi {
+            fn drop(&mut self) {
+                self.server.abort();
+            }
+        }
+
+        async fn read_request(socket: &mut TcpStream) -> Option<RecordedRequest> {
+            let mut buf = Vec::new();
+            let mut chunk = [0u8; 8192];
+            let head_end = loop {
+                let n = socket.read(&mut chunk).await.ok()?;
+                if n == 0 {
+                    return None;
+                }
+                buf.extend_from_slice(&chunk[..n]);
+                if let Some(pos) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+                    break pos + 4;
+                }
+            };
+            let head = String::from_utf8_lossy(&buf[..head_end]).to_string();
+            let mut lines = head.split("\r\n");
+            let mut request_line = lines.next()?.split(' ');
+            let method = request_line.next()?.to_string();
+            let target = request_line.next()?.to_string();
+            let headers: HashMap<String, String> = lines
+                .filter_map(|line| line.split_once(':'))
+                .map(|(k, v)| (k.trim().to_ascii_lowercase(), v.trim().to_string()))
+                .collect();
+            let content_length: usize = headers
+                .get("content-length")
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(0);
+            while buf.len() < head_end + content_length {
+                let n = socket.read(&mut chunk).await.ok()?;
+                if n == 0 {
+                    break;
+                }
+                buf.extend_from_slice(&chunk[..n]);
+            }
+            let raw_body = &buf[head_end..];
+            let body = if headers.get("content-encoding").map(|v| v.as_str()) == Some("gzip") {
+                let mut decoded = String::new();
+                flate2::read::GzDecoder::new(raw_body)
+                    .read_to_string(&mut decoded)
+                    .ok()?;
+                decoded
+            } else {
+                String::from_utf8_lossy(raw_body).to_string()
+            };
+            Some(RecordedRequest {
+                method,
+                path: target.strip_prefix("/api").unwrap_or(&target).to_string(),
+                headers,
+                body,
+            })
+        }
+
+        async fn handle(mut socket: TcpStream, state: Arc<Mutex<State>>) {
+            let request = match read_request(&mut socket).await {
+                Some(request) => request,
+                None => return,
+            };
+            let route = request.path.split('?').next().unwrap_or("").to_string();
+            let response = if route == "/health" {
+                MockResponse::status(200)
+            } else {
+                let mut state = state.lock().unwrap();
+                let scripted = state
+                    .scripts
+                    .get_mut(&(request.method.clone(), route))
+                    .and_then(|queue| queue.pop_front());
+                state.requests.push(request);
+                scripted.unwrap_or_else(|| MockResponse::json(200, serde_json::json!({})))
+            };
+
+            let head = response.head();
+            let mut raw = head.clone().into_bytes();
+            raw.extend_from_slice(response.body.as_bytes());
+            if let Some(limit) = response.truncate_at {
+                let _ = socket.write_all(&raw[..limit.min(raw.len())]).await;
+            } else if let Some(delay) = response.body_delay {
+                let _ = socket.write_all(head.as_bytes()).await;
+                tokio::time::sleep(delay).await;
+                let _ = socket.write_all(response.body.as_bytes()).await;
+            } else {
+                let _ = socket.write_all(&raw).await;
+            }
+            let _ = socket.shutdown().await;
+        }
+    }
+
+    mod sync_integration_tests {
+        use super::mock_api::{MockApi, MockResponse};
+        use super::*;
+        use std::sync::Arc;
+        use std::time::Duration;
+        use tempfile::TempDir;
+
+        /// SyncManager на БД по пути (повторное открытие — как перезапуск приложения)
+        fn sync_manager_at(api: &MockApi, db_path: &std::path::Path) -> SyncManager {
+            let db = Arc::new(Database::new(db_path.to_str().unwrap()).unwrap());
+            let config = crate::sync::SyncConfig {
+                api_base_url: api.base_url.clone(),
+                http_timeout_secs: 1,
+                ..Default::default()
+            };
+            SyncManager::new_with_config(db, config)
+        }
+
+        async fn sync_manager_for(api: &MockApi) -> (SyncManager, TempDir) {
+            let temp_dir = TempDir::new().unwrap();
+            let sync_manager = sync_manager_at(api, &temp_dir.path().join("test.db"));
+            sync_manager
+                .auth_manager
+                .set_tokens(Some("access-1".into()), Some("refresh-1".into()))
+                .await;
+            (sync_manager, temp_dir)
+        }
+
+        /// (id, status, retry_count, idempotency_key) по порядку id
+        fn queue_rows(db: &Database) -> Vec<(i64, String, i32, Option<String>)> {
+            let conn = db.test_conn();
+            let mut stmt = conn
+                .prepare("SELECT id, status, retry_count, idempotency_key FROM sync_queue ORDER BY id")
+                .unwrap();
+            let rows = stmt
+                .query_map([], |row| {
+                    Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+                })
+                .unwrap()
+                .map(|r| r.unwrap())
+                .collect();
+            rows
+        }
+
+        fn enqueue(sync_manager: &SyncManager, operation: &str, payload: serde_json::Value) -> i64 {
+            sync_manager
+                .enqueue_time_entry(operation, payload, String::new(), None)
+                .unwrap()
+        }
+
+        #[tokio::test]
+        async fn test_sync_queue_sends_by_priority_with_headers_and_idempotency_keys() {
+            let api = MockApi::start().await;
+            let (sync_manager, _temp_dir) = sync_manager_for(&api).await;
+            let pause = enqueue(&sync_manager, "pause", serde_json::json!({"id": "entry-1"}));
+            let start = enqueue(
+                &sync_manager,
+                "start",
+                serde_json::json!({"projectId": "p1"}),
+            );
+
+            assert_eq!(sync_manager.sync_queue(5).await.unwrap(), 2);
+
+            let requests = api.requests();
+            let routes: Vec<(&str, &str)> = requests
+                .iter()
+                .map(|r| (r.method.as_str(), r.path.as_str()))
+                .collect();
+            // start (critical) раньше pause (high), хотя pause поставлен первым
+            assert_eq!(
+                routes,
+                vec![
+                    ("POST", "/time-entries"),
+                    ("PUT", "/time-entries/entry-1/pause")
+                ]
+            );
+            let rows = queue_rows(&sync_manager.db);
+            let key_of = |id: i64| rows.iter().find(|r| r.0 == id).unwrap().3.clone();
+            assert_eq!(
+                requests[0].header("x-idempotency-key"),
+                key_of(start).as_deref()
+            );
+            assert_eq!(
+                requests[1].header("x-idempotency-key"),
+                key_of(pause).as_deref()
+            );
+            for request in &requests {
+                assert_eq!(request.header("authorization"), Some("Bearer access-1"));
+                assert_eq!(
+                    request.header("x-app-version"),
+                    Some(env!("CARGO_PKG_VERSION"))
+                );
+            }
+            let body: serde_json::Value = serde_json::from_str(&requests[0].body).unwrap();
+            assert_eq!(body["projectId"], "p1");
+            assert!(rows.iter().all(|r| r.1 == "sent"));
+        }
+
+        #[tokio::test]
+        async fn test_401_refreshes_token_and_retries() {
+            let api = MockApi::start().await;
+            let (sync_manager, _temp_dir) = sync_manager_for(&api).await;
+            api.on("POST", "/time-entries", MockResponse::status(401));
+            api.on(
+                "POST",
+                "/auth/refresh",
+                MockResponse::json(
+                    200,
+                    serde_json::json!({"access_token": "access-2", "refresh_token": "refresh-2"}),
+                ),
+            );
+            enqueue(
+                &sync_manager,
+                "start",
+                serde_json::json!({"projectId": "p1"}),
+            );
+
+            assert_eq!(sync_manager.sync_queue(5).await.unwrap(), 1);
+
+            let requests = api.requests();
+            let paths: Vec<&str> = requests.iter().map(|r| r.path.as_str()).collect();
+            assert_eq!(
+                paths,
+                vec!["/time-entries", "/auth/refresh", "/time-entries"]
+            );
+            let refresh: serde_json::Value = serde_json::from_str(&requests[1].body).unwrap();
+            assert_eq!(refresh["refresh_token"], "refresh-1");
+            assert_eq!(requests[2].header("authorization"), Some("Bearer access-2"));
+            assert_eq!(
+                requests[0].header("x-idempotency-key"),
+                requests[2].header("x-idempotency-key"),
+                "Retry after refresh must reuse the idempotency key"
+            );
+            assert_eq!(
+                sync_manager
+                    .auth_manager
+                    .get_refresh_token()
+                    .await
+                    .unwrap()
+                    .as_deref(),
+                Some("refresh-2")
+            );
+            assert_eq!(queue_rows(&sync_manager.db)[0].1, "sent");
+        }
+
+        /// JWT с заданным exp (подпись не проверяется клиентом)
+        fn jwt_with_exp(exp: i64) -> String {
+            use base64::Engine;
+            let encode = |v: serde_json::Value| {
+                base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(v.to_string())
+            };
+            format!(
+                "{}.{}.signature",
+                encode(serde_json::json!({"alg": "HS256", "typ": "JWT"})),
+                encode(serde_json::json!({"sub": "user-1", "exp": exp}))
+            )
+        }
+
+        #[tokio::test]
+        async fn test_concurrent_401s_share_one_refresh_and_notify_frontend() {
+            let api = MockApi::start().await;
+            let (sync_manager, _temp_dir) = sync_manager_for(&api).await;
+            let events: Arc<std::sync::Mutex<Vec<(String, serde_json::Value)>>> =
+                Arc::new(std::sync::Mutex::new(Vec::new()));
+            let events_sink = events.clone();
+            sync_manager.set_event_sink(Arc::new(move |event: &str, payload| {
+                events_sink
+                    .lock()
+                    .unwrap()
+                    .push((event.to_string(), payload));
+            }));
+            api.on(
+                "PUT",
+                "/time-entries/entry-1/pause",
+                MockResponse::status(401),
+            );
+            api.on(
+                "PUT",
+                "/time-entries/entry-2/pause",
+                MockResponse::status(401),
+            );
+            // Второй refresh получил бы `{}` (без access_token) и упал бы
+            api.on(
+                "POST",
+                "/auth/refresh",
+                MockResponse::json(
+                    200,
+                    serde_json::json!({"access_token": "access-2", "refresh_token": "refresh-2"}),
+                ),
+            );
+
+            let payload = |id: &str| serde_json::json!({ "id": id }).to_string();
+            let (first, second) = tokio::join!(
+                sync_manager.sync_task(1, "time_entry_pause".into(), payload("entry-1"), None),
+                sync_manager.sync_task(2, "time_entry_pause".into(), payload("entry-2"), None)
+            );
+            assert!(first.unwrap() && second.unwrap());
+
+            let requests = api.requests();
+            let refreshes = requests
+                .iter()
+                .filter(|r| r.path == "/auth/refresh")
+                .count();
+            assert_eq!(refreshes, 1, "Refresh must be single-flight");
+            let retried: Vec<_> = requests
+                .iter()
+                .filter(|r| r.header("authorization") == Some("Bearer access-2"))
+                .collect();
+            assert_eq!(retried.len(), 2);
+
+            let events = events.lock().unwrap();
+            let refreshed: Vec<_> = events
+                .iter()
+                .filter(|(name, _)| name == "auth-tokens-refreshed")
+                .collect();
+            assert_eq!(refreshed.len(), 1);
+            assert_eq!(refreshed[0].1["access_token"], "access-2");
+            assert_eq!(refreshed[0].1["refresh_token"], "refresh-2");
+        }
+
+        #[tokio::test]
+        async fn test_token_refreshed_before_expiry() {
+            let api = MockApi::start().await;
+            let (sync_manager, _temp_dir) = sync_manager_for(&api).await;
+            let expiring = jwt_with_exp(chrono::Utc::now().timestamp() + 30);
+            sync_manager
+                .auth_manager
+                .set_tokens(Some(expiring), Some("refresh-1".into()))
+                .await;
+            let fresh = jwt_with_exp(chrono::Utc::now().timestamp() + 3600);
+            api.on(
+                "POST",
+                "/auth/refresh",
+                MockResponse::json(200, serde_json::json!({ "access_token": fresh })),
+            );
+            enqueue(&sync_manager, "stop", serde_json::json!({"id": "entry-1"}));
+
+            assert_eq!(sync_manager.sync_queue(5).await.unwrap(), 1);
+
+            let requests = api.requests();
+            let paths: Vec<&str> = requests.iter().map(|r| r.path.as_str()).collect();
+            assert_eq!(paths, vec!["/auth/refresh", "/time-entries/entry-1/stop"]);
+            let bearer = format!("Bearer {}", fresh);
+            assert_eq!(requests[1].header("authorization"), Some(bearer.as_str()));
+            // Сервер не ротировал refresh token — прежний сохраняется
+            assert_eq!(
+                sync_manager
+                    .auth_manager
+                    .get_refresh_token()
+                    .await
+                    .unwrap()
+                    .as_deref(),
+                Some("refresh-1")
+            );
+        }
+
+        #[tokio::test]
+        async fn test_refresh_token_survives_restart_and_flushes_queue() {
+            let api = MockApi::start().await;
+            let temp_dir = TempDir::new().unwrap();
+            let db_path = temp_dir.path().join("test.db");
+            {
+                let sync_manager = sync_manager_at(&api, &db_path);
+                sync_manager
+                    .auth_manager
+                    .set_tokens(Some("access-1".into()), Some("refresh-1".into()))
+                    .await;
+                enqueue(&sync_manager, "stop", serde_json::json!({"id": "entry-1"}));
+                let stored = sync_manager
+                    .db
+                    .get_app_meta("auth_refresh_token")
+                    .unwrap()
+                    .unwrap();
+                assert!(!stored.contains("refresh-1"), "Stored encrypted");
+            }
+            api.on(
+                "POST",
+                "/auth/refresh",
+                MockResponse::json(
+                    200,
+                    serde_json::json!({"access_token": "access-2", "refresh_token": "refresh-2"}),
+                ),
+            );
+
+            // Перезапуск: webview ещё не вызвал set_auth_tokens
+            let sync_manager = sync_manager_at(&api, &db_path);
+            assert_eq!(sync_manager.sync_queue(5).await.unwrap(), 1);
+
+            let requests = api.requests();
+            let paths: Vec<&str> = requests.iter().map(|r| r.path.as_str()).collect();
+            assert_eq!(paths, vec!["/auth/refresh", "/time-entries/entry-1/stop"]);
+            assert_eq!(requests[1].header("authorization"), Some("Bearer access-2"));
+            let restarted = sync_manager_at(&api, &db_path);
+            assert_eq!(
+                restarted
+                    .auth_manager
+                    .get_refresh_token()
+                    .await
+                    .unwrap()
+                    .as_deref(),
+                Some("refresh-2"),
+                "Rotated refresh token must be persisted"
+            );
+        }
+
+        #[tokio::test]
+        async fn test_superseded_refresh_token_from_frontend_is_ignored() {
+            let api = MockApi::start().await;
+            let (sync_manager, _temp_dir) = sync_manager_for(&api).await;
+            let events: Arc<std::sync::Mutex<Vec<String>>> =
+                Arc::new(std::sync::Mutex::new(Vec::new()));
+            let events_sink = events.clone();
+            sync_manager.set_event_sink(Arc::new(move |event: &str, _payload| {
+                events_sink.lock().unwrap().push(event.to_string());
+            }));
+            api.on(
+                "POST",
+                "/auth/refresh",
+                MockResponse::json(
+                    200,
+                    serde_json::json!({"access_token": "access-2", "refresh_token": "refresh-2"}),
+                ),
+            );
+            let auth = &sync_manager.auth_manager;
+            auth.refresh_access_token(Some("access-1")).await.unwrap();
+
+            // Frontend пропустил событие и прислал старую пару
+            auth.set_tokens(Some("access-1".into()), Some("refresh-1".into()))
+                .await;
+            assert_eq!(auth.get_access_token().await.unwrap(), "access-2");
+            assert_eq!(
+                auth.get_refresh_token().await.unwrap().as_deref(),
+                Some("refresh-2")
+            );
+            let refreshed = events
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|e| *e == "auth-tokens-refreshed")
+                .count();
+            assert_eq!(refreshed, 2, "Event repeated for the stale frontend copy");
+
+            // Новый вход (другая пара) применяется как обычно
+            auth.set_tokens(Some("access-9".into()), Some("refresh-9".into()))
+                .await;
+            assert_eq!(auth.get_access_token().await.unwrap(), "access-9");
+        }
+
+        #[tokio::test]
+        async fn test_rejected_refresh_token_is_cleared() {
+            let api = MockApi::start().await;
+            let (sync_manager, _temp_dir) = sync_manager_for(&api).await;
+            api.on("POST", "/auth/refresh", MockResponse::status(401));
+
+            let auth = &sync_manager.auth_manager;
+            assert!(auth.refresh_access_token(Some("access-1")).await.is_err());
+            assert_eq!(auth.get_refresh_token().await.unwrap(), None);
+            assert_eq!(
+                sync_manager
+                    .db
+                    .get_app_meta("auth_refresh_token")
+                    .unwrap()
+                    .as_deref(),
+                Some("")
+            );
+        }
+
+        #[test]
+        fn test_jwt_expiry() {
+            assert_eq!(
+                crate::auth::jwt_expiry(&jwt_with_exp(1_700_000_000)),
+                Some(1_700_000_000)
+            );
+            assert_eq!(crate::auth::jwt_expiry("opaque-token"), None);
+            assert_eq!(crate::auth::jwt_expiry("a.!!!.c"), None);
+        }
+
+        #[tokio::test]
+        async fn test_400_state_already_achieved_drops_task() {
+            let api = MockApi::start().await;
+            let (sync_manager, _temp_dir) = sync_manager_for(&api).await;
+            api.on(
+                "PUT",
+                "/time-entries/entry-1/stop",
+                MockResponse::json(
+                    400,
+                    serde_json::json!({"message": "Time entry is already stopped"}),
+                ),
+            );
+            api.on(
+                "PUT",
+                "/time-entries/entry-2/stop",
+                MockResponse::json(400, serde_json::json!({"message": "Validation failed"})),
+            );
+            let achieved = enqueue(&sync_manager, "stop", serde_json::json!({"id": "entry-1"}));
+            let invalid = enqueue(&sync_manager, "stop", serde_json::json!({"id": "entry-2"}));
+
+            assert_eq!(sync_manager.sync_queue(5).await.unwrap(), 1);
+
+            let rows = queue_rows(&sync_manager.db);
+            let row = |id: i64| rows.iter().find(|r| r.0 == id).unwrap().clone();
+            assert_eq!(row(achieved).1, "sent");
+            assert_eq!((row(invalid).1.as_str(), row(invalid).2), ("pending", 1));
+        }
+
+        #[tokio::test]
+        async fn test_429_retry_after_defers_rest_of_queue() {
+            let api = MockApi::start().await;
+            let (sync_manager, _temp_dir) = sync_manager_for(&api).await;
+            api.on(
+                "POST",
+                "/time-entries",
+                MockResponse::status(429).header("Retry-After", "120"),
+            );
+            enqueue(
+                &sync_manager,
+                "start",
+                serde_json::json!({"projectId": "p1"}),
+            );
+            enqueue(&sync_manager, "pause", serde_json::json!({"id": "entry-1"}));
+            let finished: Arc<std::sync::Mutex<Vec<serde_json::Value>>> =
+                Arc::new(std::sync::Mutex::new(Vec::new()));
+            let finished_sink = finished.clone();
+            sync_manager.set_event_sink(Arc::new(move |event: &str, payload| {
+                if event == crate::ipc::events::SYNC_FINISHED {
+                    finished_sink.lock().unwrap().push(payload);
+                }
+            }));
+
+            assert_eq!(sync_manager.sync_queue(5).await.unwrap(), 0);
+            assert_eq!(api.requests().len(), 1, "Pause must wait for Retry-After");
+            // Итог сходится с total из sync-started: отложенная задача учтена
+            let summary = finished.lock().unwrap()[0].clone();
+            assert_eq!(summary["total"], 2);
+            assert_eq!(summary["synced"], 0);
+            assert_eq!(summary["failed"], 0, "Rate limit is not a failed attempt");
+            assert_eq!(summary["deferred"], 2);
+            let remaining = sync_manager.rate_limit_remaining().unwrap();
+            assert!(remaining > Duration::from_secs(100) && remaining <= Duration::from_secs(120));
+            let rows = queue_rows(&sync_manager.db);
+            assert_eq!((rows[0].1.as_str(), rows[0].2), ("pending", 0));
+            assert_eq!((rows[1].1.as_str(), rows[1].2), ("pending", 0));
+
+            // Следующий запуск до истечения Retry-After не делает запросов
+            assert_eq!(sync_manager.sync_queue(5).await.unwrap(), 0);
+            assert_eq!(api.requests().len(), 1);
+        }
+
+        #[tokio::test]
+        async fn test_retried_task_upload_bytes_counted_once() {
+            let api = MockApi::start().await;
+            let (sync_manager, _temp_dir) = sync_manager_for(&api).await;
+            api.on(
+                "PUT",
+                "/time-entries/entry-1/stop",
+                MockResponse::status(500),
+            );
+            enqueue(&sync_manager, "stop", serde_json::json!({"id": "entry-1"}));
+
+            assert_eq!(sync_manager.sync_queue(5).await.unwrap(), 0);
+            assert_eq!(
+                sync_manager.db.get_queue_stats().unwrap().upload_bytes_sent,
+                0,
+                "Failed attempt is not counted"
+            );
+            sync_manager
+                .db
+                .test_conn()
+                .execute("UPDATE sync_queue SET last_retry_at = NULL", [])
+                .unwrap();
+            assert_eq!(sync_manager.sync_queue(5).await.unwrap(), 1);
+
+            let requests = api.requests();
+            assert_eq!(requests.len(), 2);
+            let body_len = requests[1].body.len() as i64;
+            let stats = sync_manager.db.get_queue_stats().unwrap();
+            assert_eq!(stats.upload_bytes_raw, body_len);
+            assert_eq!(stats.upload_bytes_sent, body_len);
+            let report = sync_manager.db.get_sync_metrics(10).unwrap();
+            assert_eq!(report.bytes_sent, body_len as u64);
+        }
+
+        #[tokio::test]
+        async fn test_repeated_429_never_fails_task() {
+            let api = MockApi::start().await;
+            let (sync_manager, _temp_dir) = sync_manager_for(&api).await;
+            let max_retries = 2;
+            for _ in 0..max_retries + 2 {
+                api.on(
+                    "PUT",
+                    "/time-entries/entry-1/stop",
+                    MockResponse::status(429).header("Retry-After", "0"),
+                );
+            }
+            let id = enqueue(&sync_manager, "stop", serde_json::json!({"id": "entry-1"}));
+
+            for _ in 0..max_retries + 2 {
+                assert_eq!(sync_manager.sync_queue(max_retries).await.unwrap(), 0);
+                let rows = queue_rows(&sync_manager.db);
+                assert_eq!((rows[0].1.as_str(), rows[0].2), ("pending", 0));
+                sync_manager
+                    .db
+                    .test_conn()
+                    .execute("UPDATE sync_queue SET last_retry_at = NULL", [])
+                    .unwrap();
+            }
+            assert_eq!(api.requests().len(), (max_retries + 2) as usize);
+            assert!(sync_manager.db.get_failed_tasks(10).unwrap().is_empty());
+
+            // Ограничение снято — задача отправляется
+            assert_eq!(sync_manager.sync_queue(max_retries).await.unwrap(), 1);
+            let rows = queue_rows(&sync_manager.db);
+            assert_eq!((rows[0].0, rows[0].1.as_str()), (id, "sent"));
+        }
+
+        #[tokio::test]
+        async fn test_sync_run_records_metrics() {
+            let api = MockApi::start().await;
+            let (sync_manager, _temp_dir) = sync_manager_for(&api).await;
+            api.on(
+                "PUT",
+                "/time-entries/entry-2/stop",
+                MockResponse::json(400, serde_json::json!({"message": "Validation failed"})),
+            );
+            enqueue(&sync_manager, "stop", serde_json::json!({"id": "entry-1"}));
+            enqueue(&sync_manager, "stop", serde_json::json!({"id": "entry-2"}));
+
+            assert_eq!(sync_manager.sync_queue(5).await.unwrap(), 1);
+
+            let report = sync_manager.db.get_sync_metrics(10).unwrap();
+            assert_eq!(report.runs.len(), 1);
+            let run = &report.runs[0];
+            assert_eq!((run.attempted, run.succeeded, run.failed), (2, 1, 1));
+            assert_eq!(run.failed_by_kind.get("http"), Some(&1));
+            assert!(run.bytes_sent > 0);
+            assert!(run.latency_p50_ms.is_some() && run.latency_p95_ms >= run.latency_p50_ms);
+        }
+
+        #[tokio::test]
+        async fn test_time_entry_body_carries_server_corrected_client_time() {
+            let api = MockApi::start().await;
+            let (sync_manager, _temp_dir) = sync_manager_for(&api).await;
+            // Часы сервера на 10 минут впереди
+            let server_now = chrono::Utc::now() + chrono::Duration::minutes(10);
+            api.on(
+                "PUT",
+                "/time-entries/entry-1/pause",
+                MockResponse::json(200, serde_json::json!({})).header("Date", &server_now.to_rfc2822()),
+            );
+            enqueue(&sync_manager, "pause", serde_json::json!({"id": "entry-1"}));
+            enqueue(
+                &sync_manager,
+                "resume",
+                serde_json::json!({"id": "entry-1"}),
+            );
+
+            assert_eq!(sync_manager.sync_queue(5).await.unwrap(), 2);
+
+            let requests = api.requests();
+            let first: serde_json::Value = serde_json::from_str(&requests[0].body).unwrap();
+            assert!(first["clientTime"]["localAt"].is_string());
+            assert_eq!(first["clientTime"]["clockSkewed"], false, "No estimate yet");
+            // Вторая задача отправлена уже с оценкой по Date первого ответа
+            let second: serde_json::Value = serde_json::from_str(&requests[1].body).unwrap();
+            let offset = second["clientTime"]["offsetMs"].as_i64().unwrap();
+            assert!((595_000..=602_000).contains(&offset), "offset {}", offset);
+            assert_eq!(second["clientTime"]["clockSkewed"], true);
+            let stored = sync_manager
+                .db
+                .get_app_meta("server_clock_offset_ms")
+                .unwrap()
+                .unwrap();
+            assert_eq!(stored.parse::<i64>().unwrap(), offset);
+        }
+
+        #[tokio::test]
+        async fn test_client_time_uses_offset_captured_at_enqueue() {
+            let api = MockApi::start().await;
+            let (sync_manager, _temp_dir) = sync_manager_for(&api).await;
+            api.on(
+                "PUT",
+                "/time-entries/entry-1/pause",
+                MockResponse::json(200, serde_json::json!({})),
+            );
+            // При enqueue сервер был на 30 секунд впереди
+            sync_manager
+                .db
+                .set_app_meta("server_clock_offset_ms", "30000")
+                .unwrap();
+            enqueue(&sync_manager, "pause", serde_json::json!({"id": "entry-1"}));
+            // До отправки оценка сменилась: часы переставили, сервер теперь на 10 минут впереди
+            let now_ms = chrono::Utc::now().timestamp_millis();
+            let server_now = chrono::Utc::now() + chrono::Duration::minutes(10);
+            assert!(sync_manager
+                .clock
+                .observe(&server_now.to_rfc2822(), now_ms, now_ms)
+                .is_some());
+            sync_manager.clock.persist(&sync_manager.db);
+
+            assert_eq!(sync_manager.sync_queue(5).await.unwrap(), 1);
+
+            let requests = api.requests();
+            let body: serde_json::Value = serde_json::from_str(&requests[0].body).unwrap();
+            let client_time = &body["clientTime"];
+            assert_eq!(client_time["offsetMs"], 30_000);
+            assert_eq!(client_time["clockSkewed"], false);
+            let local_at =
+                chrono::DateTime::parse_from_rfc3339(client_time["localAt"].as_str().unwrap())
+                    .unwrap();
+            let corrected_at =
+                chrono::DateTime::parse_from_rfc3339(client_time["correctedAt"].as_str().unwrap())
+                    .unwrap();
+            assert_eq!((corrected_at - local_at).num_milliseconds(), 30_000);
+        }
+
+        #[test]
+        fn test_parse_retry_after() {
+            assert_eq!(
+                crate::sync::parse_retry_after("30"),
+                Some(Duration::from_secs(30))
+            );
+            assert_eq!(
+                crate::sync::parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+                Some(Duration::ZERO),
+                "Date in the past means retry now"
+            );
+            assert_eq!(crate::sync::parse_retry_after("soon"), None);
+        }
+
+        #[tokio::test]
+        async fn test_slow_body_times_out_and_task_retries() {
+            let api = MockApi::start().await;
+            let (sync_manager, _temp_dir) = sync_manager_for(&api).await;
+            api.on(
+                "POST",
+                "/time-entries",
+                MockResponse::json(500, serde_json::json!({"message": "slow"}))
+                    .slow_body(Duration::from_secs(3)),
+            );
+            enqueue(
+                &sync_manager,
+                "start",
+                serde_json::json!({"projectId": "p1"}),
+            );
+
+            // Тело ошибки дольше http_timeout (1 сек) — чтение прерывается, синхронизация не зависает
+            let started = std::time::Instant::now();
+            assert_eq!(sync_manager.sync_queue(5).await.unwrap(), 0);
+            assert!(started.elapsed() < Duration::from_secs(3));
+            let rows = queue_rows(&sync_manager.db);
+            assert_eq!((rows[0].1.as_str(), rows[0].2), ("pending", 1));
+        }
+
+        #[tokio::test]
+        async fn test_truncated_response_is_retried_with_same_key() {
+            let api = MockApi::start().await;
+            let (sync_manager, _temp_dir) = sync_manager_for(&api).await;
+            // Обрыв посреди заголовков — сервер мог успеть создать запись
+            api.on(
+                "POST",
+                "/time-entries",
+                MockResponse::json(201, serde_json::json!({"id": "entry-1"})).truncated(20),
+            );
+            enqueue(
+                &sync_manager,
+                "start",
+                serde_json::json!({"projectId": "p1"}),
+            );
+
+            assert_eq!(sync_manager.sync_queue(5).await.unwrap(), 0);
+            assert_eq!(queue_rows(&sync_manager.db)[0].1, "pending");
+
+            // Повтор (claim сдвинул last_retry_at — сбрасываем, чтобы не ждать backoff)
+            sync_manager
+                .db
+                .test_conn()
+                .execute("UPDATE sync_queue SET last_retry_at = NULL", [])
+                .unwrap();
+            assert_eq!(sync_manager.sync_queue(5).await.unwrap(), 1);
+            let requests = api.requests();
+            assert_eq!(requests.len(), 2);
+            assert_eq!(
+                requests[0].header("x-idempotency-key"),
+                requests[1].header("x-idempotency-key"),
+                "Server deduplicates the replay by idempotency key"
+            );
+            assert_eq!(queue_rows(&sync_manager.db)[0].1, "sent");
+        }
+
+        #[tokio::test]
+        async fn test_logout_keep_revokes_tokens_and_keeps_queue() {
+            let api = MockApi::start().await;
+            let (sync_manager, _temp_dir) = sync_manager_for(&api).await;
+            enqueue(&sync_manager, "pause", serde_json::json!({"id": "entry-1"}));
+            let events: Arc<std::sync::Mutex<Vec<String>>> =
+                Arc::new(std::sync::Mutex::new(Vec::new()));
+            let events_sink = events.clone();
+            sync_manager.set_event_sink(Arc::new(move |event: &str, _payload| {
+                events_sink.lock().unwrap().push(event.to_string());
+            }));
+
+            let event = sync_manager
+                .logout(crate::models::LogoutQueuePolicy::Keep, false)
+                .await
+                .unwrap();
+            assert!(event.revoked);
+            assert_eq!(event.remaining_tasks, 1);
+
+            let requests = api.requests();
+            assert_eq!(requests.len(), 1, "Queue must not be sent on keep");
+            assert_eq!(requests[0].path, "/auth/logout");
+            assert_eq!(requests[0].header("authorization"), Some("Bearer access-1"));
+            let body: serde_json::Value = serde_json::from_str(&requests[0].body).unwrap();
+            assert_eq!(body["refreshToken"], "refresh-1");
+
+            let auth = &sync_manager.auth_manager;
+            assert!(auth.get_access_token().await.is_err());
+            assert_eq!(auth.get_refresh_token().await.unwrap(), None);
+            assert_eq!(queue_rows(&sync_manager.db).len(), 1);
+            assert!(events
+                .lock()
+                .unwrap()
+                .contains(&crate::ipc::events::LOGGED_OUT.to_string()));
+
+            // Устаревшая копия токенов из frontend после выхода не восстанавливает сессию
+            auth.set_tokens(Some("access-1".into()), Some("refresh-1".into()))
+                .await;
+            assert!(auth.get_access_token().await.is_err());
+        }
+
+        #[tokio::test]
+        async fn test_logout_flush_sends_queue_and_purges_local_data() {
+            let api = MockApi::start().await;
+            let (sync_manager, temp_dir) = sync_manager_for(&api).await;
+            enqueue(&sync_manager, "pause", serde_json::json!({"id": "entry-1"}));
+            enqueue(
+                &sync_manager,
+                "resume",
+                serde_json::json!({"id": "entry-2"}),
+            );
+            // Сервер недоступен для отзыва — локальный выход всё равно выполняется
+            api.on("POST", "/auth/logout", MockResponse::status(503));
+            let key_file = temp_dir.path().join(".hubnity_encryption_key");
+            let old_key = std::fs::read_to_string(&key_file).ok();
+
+            let event = sync_manager
+                .logout(crate::models::LogoutQueuePolicy::Flush, true)
+                .await
+                .unwrap();
+            assert!(!event.revoked);
+            assert_eq!(event.queue_policy, crate::models::LogoutQueuePolicy::Flush);
+            assert_eq!(event.flushed_tasks, 2);
+            assert_eq!(event.remaining_tasks, 0);
+            assert!(event.keys_purged);
+            assert!(queue_rows(&sync_manager.db).is_empty());
+            assert!(sync_manager.auth_manager.get_access_token().await.is_err());
+            // Прежний ключ удалён, вместо него записан новый
+            if let Some(old_key) = old_key {
+                assert_ne!(std::fs::read_to_string(&key_file).unwrap(), old_key);
+            }
+        }
+
+        // С SQLCipher выход с удалением ключа отклоняется: файл БД зашифрован тем же ключом
+        #[cfg(not(feature = "sqlcipher"))]
+        #[tokio::test]
+        async fn test_queue_written_after_key_purge_decrypts_after_restart() {
+            let api = MockApi::start().await;
+            let (sync_manager, _temp_dir) = sync_manager_for(&api).await;
+            let event = sync_manager
+                .logout(crate::models::LogoutQueuePolicy::Flush, true)
+                .await
+                .unwrap();
+            assert!(event.keys_purged);
+
+            // Следующий пользователь работает до перезапуска
+            let id = enqueue(&sync_manager, "pause", serde_json::json!({"id": "entry-1"}));
+            let db_path = sync_manager.db.path().to_path_buf();
+            drop(sync_manager);
+
+            let db = Database::new(db_path.to_str().unwrap()).unwrap();
+            let payload = decrypt_queue_payload(&db, id).unwrap();
+            assert!(payload.contains("entry-1"));
+            assert_eq!(db.get_retry_tasks(5, 10, false).unwrap().len(), 1);
+        }
+
+        #[tokio::test]
+        async fn test_logout_flush_sends_every_batch_after_running_sync() {
+            let api = MockApi::start().await;
+            let (sync_manager, _temp_dir) = sync_manager_for(&api).await;
+            // 12 задач — batch size 5, нужно три запуска
+            for i in 0..12 {
+                enqueue(
+                    &sync_manager,
+                    "stop",
+                    serde_json::json!({"id": format!("entry-{}", i)}),
+                );
+            }
+            // Фоновая синхронизация ещё идёт
+            sync_manager
+                .is_syncing
+                .store(true, std::sync::atomic::Ordering::Release);
+            let background = sync_manager.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_millis(200)).await;
+                background
+                    .is_syncing
+                    .store(false, std::sync::atomic::Ordering::Release);
+            });
+
+            let event = sync_manager
+                .logout(crate::models::LogoutQueuePolicy::Flush, true)
+                .await
+                .unwrap();
+            assert_eq!(event.queue_policy, crate::models::LogoutQueuePolicy::Flush);
+            assert_eq!(event.flushed_tasks, 12);
+            assert_eq!(event.remaining_tasks, 0);
+            assert!(event.keys_purged);
+            let stops = api
+                .requests()
+                .iter()
+                .filter(|r| r.path.ends_with("/stop"))
+                .count();
+            assert_eq!(stops, 12);
+            assert!(queue_rows(&sync_manager.db).is_empty());
+        }
+
+        #[tokio::test]
+        async fn test_logout_flush_keeps_queue_that_could_not_be_sent() {
+            let api = MockApi::start().await;
+            let (sync_manager, temp_dir) = sync_manager_for(&api).await;
+            enqueue(&sync_manager, "pause", serde_json::json!({"id": "entry-1"}));
+            enqueue(
+                &sync_manager,
+                "resume",
+                serde_json::json!({"id": "entry-2"}),
+            );
+            api.on(
+                "PUT",
+                "/time-entries/entry-2/resume",
+                MockResponse::status(500),
+            );
+            sync_manager
+                .db
+                .save_timer_state("2026-01-05", 3600, "stopped", None)
+                .unwrap();
+            let key_file = temp_dir.path().join(".hubnity_encryption_key");
+            let had_key_file = key_file.exists();
+
+            let event = sync_manager
+                .logout(crate::models::LogoutQueuePolicy::Flush, true)
+                .await
+                .unwrap();
+            // 5xx: неотправленное не удаляется — выход выполнен как Keep
+            assert_eq!(event.queue_policy, crate::models::LogoutQueuePolicy::Keep);
+            assert_eq!(event.flushed_tasks, 1);
+            assert_eq!(event.remaining_tasks, 1);
+            assert!(!event.keys_purged);
+            assert_eq!(key_file.exists(), had_key_file);
+            let unsent: Vec<i64> = queue_rows(&sync_manager.db)
+                .into_iter()
+                .filter(|r| r.1 != "sent")
+                .map(|r| r.0)
+                .collect();
+            assert_eq!(unsent.len(), 1);
+            assert!(sync_manager.db.load_timer_state().unwrap().is_some());
+            assert!(sync_manager.auth_manager.get_access_token().await.is_err());
+            // Очередь расшифровывается и после выхода
+            assert!(decrypt_queue_payload(&sync_manager.db, unsent[0]).is_ok());
+        }
+
+        #[tokio::test]
+        async fn test_logout_keep_cannot_purge_keys() {
+            let api = MockApi::start().await;
+            let (sync_manager, _temp_dir) = sync_manager_for(&api).await;
+            let result = sync_manager
+                .logout(crate::models::LogoutQueuePolicy::Keep, true)
+                .await;
+            assert!(result.is_err());
+            assert!(api.requests().is_empty());
+            assert_eq!(
+                sync_manager.auth_manager.get_access_token().await.unwrap(),
+                "access-1"
+            );
+        }
+    }
+
+    mod sync_metrics_tests {
+        use super::*;
+        use crate::models::SyncRunMetrics;
+        use tempfile::TempDir;
+
+        fn create_test_db() -> (Database, TempDir) {
+            let temp_dir = TempDir::new().unwrap();
+            let db_path = temp_dir.path().join("test.db");
+            let db = Database::new(db_path.to_str().unwrap()).unwrap();
+            (db, temp_dir)
+        }
+
+        fn run(started_at: i64, failed_kind: Option<&str>, p50: u64, p95: u64) -> SyncRunMetrics {
+            let mut failed_by_kind = std::collections::HashMap::new();
+            if let Some(kind) = failed_kind {
+                failed_by_kind.insert(kind.to_string(), 1);
+            }
+            SyncRunMetrics {
+                started_at,
+                duration_ms: 100,
+                attempted: 2,
+                succeeded: if failed_kind.is_some() { 1 } else { 2 },
+                failed: i32::from(failed_kind.is_some()),
+                failed_by_kind,
+                bytes_sent: 1000,
+                latency_p50_ms: Some(p50),
+                latency_p95_ms: Some(p95),
+            }
+        }
+
+        #[test]
+        fn test_report_aggregates_recent_runs_newest_first() {
+            let (db, _temp_dir) = create_test_db();
+            db.record_sync_metrics(&run(1, Some("network"), 10, 50))
+                .unwrap();
+            db.record_sync_metrics(&run(2, None, 20, 60)).unwrap();
+            db.record_sync_metrics(&run(3, Some("network"), 30, 70))
+                .unwrap();
+            db.record_sync_metrics(&run(4, Some("server"), 40, 80))
+                .unwrap();
+
+            let report = db.get_sync_metrics(3).unwrap();
+            let started: Vec<i64> = report.runs.iter().map(|r| r.started_at).collect();
+            assert_eq!(started, vec![4, 3, 2]);
+            assert_eq!(
+                (report.attempted, report.succeeded, report.failed),
+                (6, 4, 2)
+            );
+            assert_eq!(report.bytes_sent, 3000);
+            assert_eq!(report.failed_by_kind.get("network"), Some(&1));
+            assert_eq!(report.failed_by_kind.get("server"), Some(&1));
+            assert_eq!(report.latency_p50_ms, Some(30));
+            assert_eq!(report.latency_p95_ms, Some(70));
+            assert_eq!(report.runs[1], run(3, Some("network"), 30, 70));
+        }
+
+        #[test]
+        fn test_history_is_capped() {
+            let (db, _temp_dir) = create_test_db();
+            let extra = 5;
+            for i in 0..crate::database::MAX_SYNC_METRICS_ROWS + extra {
+                db.record_sync_metrics(&run(i, None, 1, 1)).unwrap();
+            }
+            let count: i64 = db
+                .test_conn()
+                .query_row("SELECT COUNT(*) FROM sync_metrics", [], |row| row.get(0))
+                .unwrap();
+            assert_eq!(count, crate::database::MAX_SYNC_METRICS_ROWS);
+            let oldest = db
+                .get_sync_metrics(crate::database::MAX_SYNC_METRICS_ROWS)
+                .unwrap()
+                .runs
+                .last()
+                .unwrap()
+                .started_at;
+            assert_eq!(oldest, extra);
+        }
+
+        #[test]
+        fn test_percentile_nearest_rank() {
+            let samples: Vec<u64> = (1..=20).collect();
+            assert_eq!(crate::sync::percentile(&samples, 50), Some(10));
+            assert_eq!(crate::sync::percentile(&samples, 95), Some(19));
+            assert_eq!(crate::sync::percentile(&[7], 95), Some(7));
+            assert_eq!(crate::sync::percentile(&[], 50), None);
+        }
+    }
+
+    mod clock_tests {
+        use super::*;
+        use crate::clock::ClockOffset;
+        use tempfile::TempDir;
+
+        const SENT_AT_MS: i64 = 1_700_000_000_000;
+
+        /// Date-заголовок с часами сервера, сдвинутыми на `offset_secs` от момента отправки
+        fn http_date(offset_secs: i64) -> String {
+            chrono::DateTime::from_timestamp(SENT_AT_MS / 1000 + offset_secs, 0)
+                .unwrap()
+                .to_rfc2822()
+        }
+
+        #[test]
+        fn test_offset_is_smoothed_over_samples() {
+            let clock = ClockOffset::new(None);
+            // RTT 200 мс: наблюдение = +10 с + 0,5 с (усечение Date) - 100 мс
+            assert_eq!(
+                clock.observe(&http_date(10), SENT_AT_MS, SENT_AT_MS + 200),
+                Some(10_400)
+            );
+            assert_eq!(
+                clock.observe(&http_date(20), SENT_AT_MS, SENT_AT_MS + 200),
+                Some(12_400)
+            );
+            assert!(!clock.is_skewed());
+        }
+
+        #[test]
+        fn test_slow_and_invalid_samples_ignored() {
+            let clock = ClockOffset::new(Some(1_000));
+            assert_eq!(
+                clock.observe(&http_date(30), SENT_AT_MS, SENT_AT_MS + 6_000),
+                None
+            );
+            assert_eq!(clock.observe("not a date", SENT_AT_MS, SENT_AT_MS), None);
+            assert_eq!(clock.offset_ms(), Some(1_000));
+        }
+
+        #[test]
+        fn test_clock_jump_resets_estimate() {
+            let clock = ClockOffset::new(Some(0));
+            assert_eq!(
+                clock.observe(&http_date(3_600), SENT_AT_MS, SENT_AT_MS),
+                Some(3_600_500)
+            );
+            assert!(clock.is_skewed());
+        }
+
+        #[test]
+        fn test_stamp_and_persistence() {
+            let temp_dir = TempDir::new().unwrap();
+            let db = Database::new(temp_dir.path().join("test.db").to_str().unwrap()).unwrap();
+            assert_eq!(ClockOffset::load(&db).offset_ms(), None);
+            let unknown = ClockOffset::new(None).stamp(SENT_AT_MS);
+            assert_eq!(unknown["correctedAt"], serde_json::Value::Null);
+            assert_eq!(unknown["clockSkewed"], false);
+
+            ClockOffset::new(Some(-120_000)).persist(&db);
+            let clock = ClockOffset::load(&db);
+            assert_eq!(clock.offset_ms(), Some(-120_000));
+            let stamp = clock.stamp(SENT_AT_MS);
+            assert_eq!(stamp["localAt"], "2023-11-14T22:13:20.000Z");
+            assert_eq!(stamp["correctedAt"], "2023-11-14T22:11:20.000Z");
+            assert_eq!(stamp["offsetMs"], -120_000);
+            assert_eq!(stamp["clockSkewed"], true);
+        }
+    }
+
+    mod oauth_tests {
+        use super::mock_api::{MockApi, MockResponse};
+        use super::*;
+        use crate::oauth::{OAuthSettings, OAuthTokens};
+        use std::time::Duration;
+        use tempfile::TempDir;
+
+        /// Провайдер на MockApi: endpoint'ы под /api/oauth/...
+        fn settings_for(api: &MockApi) -> OAuthSettings {
+            OAuthSettings {
+                authorization_endpoint: Some(format!("{}/oauth/authorize", api.base_url)),
+                token_endpoint: Some(format!("{}/oauth/token", api.base_url)),
+                device_authorization_endpoint: Some(format!("{}/oauth/device", api.base_url)),
+                client_id: "desktop".to_string(),
+                scopes: vec!["openid".to_string(), "offline_access".to_string()],
+                ..Default::default()
+            }
+        }
+
+        /// Поля x-www-form-urlencoded тела (или query)
+        fn form_field(encoded: &str, name: &str) -> Option<String> {
+            reqwest::Url::parse(&format!("http://localhost/?{}", encoded))
+                .unwrap()
+                .query_pairs()
+                .find(|(k, _)| k == name)
+                .map(|(_, v)| v.into_owned())
+        }
+
+        #[test]
+        fn test_settings_validation() {
+            let mut settings = OAuthSettings {
+                issuer: Some("https://id.example.com".to_string()),
+                client_id: "desktop".to_string(),
+                ..Default::default()
+            };
+            assert!(settings.validate().is_ok());
+
+            settings.client_id = " ".to_string();
+            assert!(settings.validate().is_err());
+
+            settings.client_id = "desktop".to_string();
+            settings.token_endpoint = Some("http://id.example.com/token".to_string());
+            assert!(settings.validate().is_err(), "http only for loopback");
+
+            settings.issuer = None;
+            settings.token_endpoint = None;
+            assert!(
+                settings.validate().is_err(),
+                "issuer or token endpoint required"
+            );
+            assert!(!OAuthSettings::default().is_configured());
+        }
+
+        #[test]
+        fn test_settings_persisted_in_app_meta() {
+            let temp_dir = TempDir::new().unwrap();
+            let db = Database::new(temp_dir.path().join("test.db").to_str().unwrap()).unwrap();
+            assert_eq!(OAuthSettings::load(&db), OAuthSettings::default());
+
+            let settings = OAuthSettings {
+                issuer: Some("https://id.example.com".to_string()),
+                client_id: "desktop".to_string(),
+                scopes: vec!["openid".to_string()],
+                ..Default::default()
+            };
+            settings.save(&db).unwrap();
+            assert_eq!(OAuthSettings::load(&db), settings);
+
+            let invalid = OAuthSettings::default();
+            assert!(invalid.save(&db).is_err());
+            assert_eq!(OAuthSettings::load(&db), settings);
+        }
+
+        #[tokio::test]
+        async fn test_discovery_fills_missing_endpoints() {
+            let api = MockApi::start().await;
+            api.on(
+                "GET",
+                "/.well-known/openid-configuration",
+                MockResponse::json(
+                    200,
+                    serde_json::json!({
+                        "authorization_endpoint": "https://id.example.com/authorize",
+                        "token_endpoint": "https://id.example.com/token",
+                        "device_authorization_endpoint": "https://id.example.com/device",
+                        "userinfo_endpoint": "https://id.example.com/userinfo",
+                    }),
+                ),
+            );
+            let settings = OAuthSettings {
+                issuer: Some(format!("{}/", api.base_url)),
+                token_endpoint: Some("https://id.example.com/custom-token".to_string()),
+                client_id: "desktop".to_string(),
+                ..Default::default()
+            };
+
+            let resolved = settings.discover(&reqwest::Client::new()).await.unwrap();
+            assert_eq!(
+                resolved.authorization_endpoint.as_deref(),
+                Some("https://id.example.com/authorize")
+            );
+            // Явно заданный endpoint не перезаписывается
+            assert_eq!(
+                resolved.token_endpoint.as_deref(),
+                Some("https://id.example.com/custom-token")
+            );
+            assert_eq!(
+                resolved.device_authorization_endpoint.as_deref(),
+                Some("https://id.example.com/device")
+            );
+            assert_eq!(
+                resolved.userinfo_endpoint.as_deref(),
+                Some("https://id.example.com/userinfo")
+            );
+        }
+
+        #[tokio::test]
+        async fn test_loopback_login_exchanges_code_with_pkce_verifier() {
+            let api = MockApi::start().await;
+            api.on(
+                "POST",
+                "/oauth/token",
+                MockResponse::json(
+                    200,
+                    serde_json::json!({
+                        "access_token": "sso-access",
+                        "refresh_token": "sso-refresh",
+                        "expires_in": 3600,
+                    }),
+                ),
+            );
+            let settings = settings_for(&api);
+            let challenge = std::sync::Arc::new(std::sync::Mutex::new(String::new()));
+            let seen_challenge = challenge.clone();
+
+            let tokens = crate::oauth::login_with_loopback(
+                &settings,
+                &reqwest::Client::new(),
+                |url| {
+                    // «Браузер»: провайдер сразу редиректит на redirect_uri с code и state
+                    let query = reqwest::Url::parse(url)
+                        .unwrap()
+                        .query()
+                        .unwrap()
+                        .to_string();
+                    assert_eq!(form_field(&query, "client_id").as_deref(), Some("desktop"));
+                    assert_eq!(
+                        form_field(&query, "scope").as_deref(),
+                        Some("openid offline_access")
+                    );
+                    assert_eq!(
+                        form_field(&query, "code_challenge_method").as_deref(),
+                        Some("S256")
+                    );
+                    *seen_challenge.lock().unwrap() = form_field(&query, "code_challenge").unwrap();
+                    let redirect = format!(
+                        "{}?code=auth-code&state={}",
+                        form_field(&query, "redirect_uri").unwrap(),
+                        form_field(&query, "state").unwrap()
+                    );
+                    tokio::spawn(async move {
+                        let favicon = redirect.replace("/callback", "/favicon.ico");
+                        let response = reqwest::get(&favicon).await.unwrap();
+                        assert_eq!(response.status(), 404);
+                        let response = reqwest::get(&redirect).await.unwrap();
+                        assert_eq!(response.status(), 200);
+                    });
+                    Ok(())
+                },
+                Duration::from_secs(5),
+            )
+            .await
+            .unwrap();
+            assert_eq!(tokens.access_token, "sso-access");
+            assert_eq!(tokens.refresh_token.as_deref(), Some("sso-refresh"));
+
+            let requests = api.requests();
+            let token_request = requests.iter().find(|r| r.path == "/oauth/token").unwrap();
+            assert_eq!(
+                form_field(&token_request.body, "grant_type").as_deref(),
+                Some("authorization_code")
+            );
+            assert_eq!(
+                form_field(&token_request.body, "code").as_deref(),
+                Some("auth-code")
+            );
+            let verifier = form_field(&token_request.body, "code_verifier").unwrap();
+            use base64::Engine;
+            use sha2::Digest;
+            let expected = base64::engine::general_purpose::URL_SAFE_NO_PAD
+                .encode(sha2::Sha256::digest(verifier.as_bytes()));
+            assert_eq!(*challenge.lock().unwrap(), expected);
+        }
+
+        #[tokio::test]
+        async fn test_loopback_login_rejects_state_mismatch() {
+            let api = MockApi::start().await;
+            api.on(
+                "POST",
+                "/oauth/token",
+                MockResponse::json(200, serde_json::json!({"access_token": "sso-access"})),
+            );
+            let tokens = crate::oauth::login_with_loopback(
+                &settings_for(&api),
+                &reqwest::Client::new(),
+                |url| {
+                    let query = reqwest::Url::parse(url)
+                        .unwrap()
+                        .query()
+                        .unwrap()
+                        .to_string();
+                    let redirect_uri = form_field(&query, "redirect_uri").unwrap();
+                    let forged = format!("{}?code=forged-code&state=forged", redirect_uri);
+                    let redirect = format!(
+                        "{}?code=auth-code&state={}",
+                        redirect_uri,
+                        form_field(&query, "state").unwrap()
+                    );
+                    tokio::spawn(async move {
+                        // Чужой state — 400, но вход продолжает ждать настоящий редирект
+                        let response = reqwest::get(&forged).await.unwrap();
+                        assert_eq!(response.status(), 400);
+                        let response = reqwest::get(&redirect).await.unwrap();
+                        assert_eq!(response.status(), 200);
+                    });
+                    Ok(())
+                },
+                Duration::from_secs(5),
+            )
+            .await
+            .unwrap();
+            assert_eq!(tokens.access_token, "sso-access");
+            let exchanged: Vec<_> = api
+                .requests()
+                .into_iter()
+                .filter(|r| r.path == "/oauth/token")
+                .map(|r| form_field(&r.body, "code"))
+                .collect();
+            assert_eq!(exchanged, vec![Some("auth-code".to_string())]);
+        }
+
+        #[tokio::test]
+        async fn test_loopback_login_survives_idle_connection() {
+            let api = MockApi::start().await;
+            api.on(
+                "POST",
+                "/oauth/token",
+                MockResponse::json(200, serde_json::json!({"access_token": "sso-access"})),
+            );
+            let tokens = crate::oauth::login_with_loopback(
+                &settings_for(&api),
+                &reqwest::Client::new(),
+                |url| {
+                    let query = reqwest::Url::parse(url)
+                        .unwrap()
+                        .query()
+                        .unwrap()
+                        .to_string();
+                    let redirect_uri = form_field(&query, "redirect_uri").unwrap();
+                    let port = reqwest::Url::parse(&redirect_uri).unwrap().port().unwrap();
+                    let redirect = format!(
+                        "{}?code=auth-code&state={}",
+                        redirect_uri,
+                        form_field(&query, "state").unwrap()
+                    );
+                    tokio::spawn(async move {
+                        // Соединение без запроса (preconnect) не блокирует listener до конца входа
+                        let _idle = tokio::net::TcpStream::connect(("127.0.0.1", port))
+                            .await
+                            .unwrap();
+                        let response = reqwest::get(&redirect).await.unwrap();
+                        assert_eq!(response.status(), 200);
+                    });
+                    Ok(())
+                },
+                Duration::from_secs(15),
+            )
+            .await
+            .unwrap();
+            assert_eq!(tokens.access_token, "sso-access");
+        }
+
+        #[tokio::test]
+        async fn test_user_id_from_userinfo_without_id_token() {
+            let api = MockApi::start().await;
+            api.on(
+                "GET",
+                "/oauth/userinfo",
+                MockResponse::json(200, serde_json::json!({"sub": "user-42"})),
+            );
+            let mut settings = settings_for(&api);
+            let mut tokens = OAuthTokens {
+                access_token: "sso-access".to_string(),
+                refresh_token: None,
+                expires_in: None,
+                id_token: None,
+            };
+            let client = reqwest::Client::new();
+            // Нет ни id_token, ни userinfo — в чей профиль сохранять токены, неизвестно
+            assert!(crate::oauth::resolve_user_id(&settings, &client, &tokens)
+                .await
+                .is_err());
+
+            settings.userinfo_endpoint = Some(format!("{}/oauth/userinfo", api.base_url));
+            assert_eq!(
+                crate::oauth::resolve_user_id(&settings, &client, &tokens)
+                    .await
+                    .unwrap(),
+                "user-42"
+            );
+            let requests = api.requests();
+            assert_eq!(
+                requests[0].header("authorization"),
+                Some("Bearer sso-access")
+            );
+
+            // id_token без sub — тоже через userinfo
+            tokens.id_token = Some("not-a-jwt".to_string());
+            assert_eq!(
+                crate::oauth::resolve_user_id(&settings, &client, &tokens)
+                    .await
+                    .unwrap(),
+                "user-42"
+            );
+        }
+
+        #[tokio::test]
+        async fn test_device_flow_polls_until_approved() {
+            let api = MockApi::start().await;
+            api.on(
+                "POST",
+                "/oauth/device",
+                MockResponse::json(
+                    200,
+                    serde_json::json!({
+                        "device_code": "dev-code",
+                        "user_code": "ABCD-EFGH",
+                        "verification_uri": "https://id.example.com/activate",
+                        "expires_in": 60,
+                        "interval": 0,
+                    }),
+                ),
+            );
+            api.on(
+                "POST",
+                "/oauth/token",
+                MockResponse::json(400, serde_json::json!({"error": "authorization_pending"})),
+            );
+            api.on(
+                "POST",
+                "/oauth/token",
+                MockResponse::json(
+                    200,
+                    serde_json::json!({"access_token": "sso-access", "refresh_token": "sso-refresh"}),
+                ),
+            );
+            let settings = settings_for(&api);
+            let client = reqwest::Client::new();
+
+            let device = crate::oauth::start_device_authorization(&settings, &client)
+                .await
+                .unwrap();
+            assert_eq!(device.user_code, "ABCD-EFGH");
+            let tokens = crate::oauth::poll_device_token(&settings, &client, &device)
+                .await
+                .unwrap();
+            assert_eq!(tokens.access_token, "sso-access");
+
+            let polls: Vec<_> = api
+                .requests()
+                .into_iter()
+                .filter(|r| r.path == "/oauth/token")
+                .collect();
+            assert_eq!(polls.len(), 2);
+            assert_eq!(
+                form_field(&polls[0].body, "grant_type").as_deref(),
+                Some("urn:ietf:params:oauth:grant-type:device_code")
+            );
+            assert_eq!(
+                form_field(&polls[0].body, "device_code").as_deref(),
+                Some("dev-code")
+            );
+        }
+
+        #[tokio::test]
+        async fn test_device_flow_denied() {
+            let api = MockApi::start().await;
+            api.on(
+                "POST",
+                "/oauth/token",
+                MockResponse::json(400, serde_json::json!({"error": "access_denied"})),
+            );
+            let device = crate::oauth::DeviceAuthorization {
+                device_code: "dev-code".to_string(),
+                user_code: "ABCD-EFGH".to_string(),
+                verification_uri: "https://id.example.com/activate".to_string(),
+                verification_uri_complete: None,
+                expires_in: 60,
+                interval: 0,
+            };
+            let result =
+                crate::oauth::poll_device_token(&settings_for(&api), &reqwest::Client::new(), &device)
+                    .await;
+            assert_eq!(result.unwrap_err(), "Sign-in was denied");
+        }
+
+        #[tokio::test]
+        async fn test_sso_tokens_refresh_at_provider_after_restart() {
+            let api = MockApi::start().await;
+            let temp_dir = TempDir::new().unwrap();
+            let db_path = temp_dir.path().join("test.db");
+            let config = || crate::sync::SyncConfig {
+                api_base_url: api.base_url.clone(),
+                http_timeout_secs: 1,
+                ..Default::default()
+            };
+            let db = Arc::new(Database::new(db_path.to_str().unwrap()).unwrap());
+            let sync_manager = SyncManager::new_with_config(db, config());
+            let settings = settings_for(&api);
+            sync_manager
+                .auth_manager
+                .sign_in_with_oauth(
+                    OAuthTokens {
+                        access_token: "sso-access".to_string(),
+                        refresh_token: Some("sso-refresh".to_string()),
+                        expires_in: Some(3600),
+                        id_token: None,
+                    },
+                    settings.refresh_client().unwrap(),
+                )
+                .await;
+            drop(sync_manager);
+
+            // Перезапуск: refresh token и token endpoint провайдера восстановлены из app_meta
+            let db = Arc::new(Database::new(db_path.to_str().unwrap()).unwrap());
+            let sync_manager = SyncManager::new_with_config(db, config());
+            api.on(
+                "POST",
+                "/oauth/token",
+                MockResponse::json(
+                    200,
+                    serde_json::json!({"access_token": "sso-access-2", "refresh_token": "sso-refresh-2"}),
+                ),
+            );
+            let auth = &sync_manager.auth_manager;
+            assert_eq!(auth.get_fresh_token().await.unwrap(), "sso-access-2");
+
+            let requests = api.requests();
+            assert!(requests.iter().all(|r| r.path != "/auth/refresh"));
+            let refresh = requests.iter().find(|r| r.path == "/oauth/token").unwrap();
+            assert_eq!(
+                form_field(&refresh.body, "grant_type").as_deref(),
+                Some("refresh_token")
+            );
+            assert_eq!(
+                form_field(&refresh.body, "refresh_token").as_deref(),
+                Some("sso-refresh")
+            );
+            assert_eq!(
+                form_field(&refresh.body, "client_id").as_deref(),
+                Some("desktop")
+            );
+
+            // invalid_grant — refresh token отозван провайдером
+            api.on(
+                "POST",
+                "/oauth/token",
+                MockResponse::json(400, serde_json::json!({"error": "invalid_grant"})),
+            );
+            let err = auth
+                .refresh_access_token(Some("sso-access-2"))
+                .await
+                .unwrap_err();
+            assert!(err.is_refresh_rejected());
+            assert_eq!(auth.get_refresh_token().await.unwrap(), None);
+        }
+    }
+
+    mod profile_tests {
+        use super::mock_api::{MockApi, MockResponse};
+        use super::*;
+        use crate::profiles::{profile_db_path, ProfileManager};
+        use std::sync::Arc;
+        use tempfile::TempDir;
+
+        /// ProfileManager в каталоге (повторный вызов — как перезапуск приложения)
+        fn manager_in(api: &MockApi, dir: &std::path::Path) -> ProfileManager {
+            let app_db = Database::new(dir.join("hubnity.db").to_str().unwrap()).unwrap();
+            // Как при старте: сервер API записан в hubnity.db до профилей
+            app_db.switch_api_origin(&api.base_url).unwrap();
+            let config = crate::sync::SyncConfig {
+                api_base_url: api.base_url.clone(),
+                http_timeout_secs: 1,
+                ..Default::default()
+            };
+            ProfileManager::new(dir.to_path_buf(), Arc::new(app_db), config)
+        }
+
+        fn enqueue_start(profiles: &ProfileManager, project: &str) {
+            profiles
+                .sync_manager()
+                .enqueue_time_entry(
+                    "start",
+                    serde_json::json!({"projectId": project}),
+                    String::new(),
+                    None,
+                )
+                .unwrap();
+        }
+
+        fn profile(profiles: &ProfileManager, user_id: &str) -> crate::profiles::Profile {
+            profiles
+                .profiles()
+                .into_iter()
+                .find(|p| p.user_id == user_id)
+                .unwrap()
+        }
+
+        #[tokio::test]
+        async fn test_switching_user_keeps_previous_profile_data() {
+            let api = MockApi::start().await;
+            let temp_dir = TempDir::new().unwrap();
+            let profiles = manager_in(&api, temp_dir.path());
+            assert_eq!(profiles.active_user_id(), "");
+
+            profiles.activate("alice").await.unwrap();
+            assert!(profile_db_path(temp_dir.path(), "alice").exists());
+            enqueue_start(&profiles, "p1");
+
+            profiles.activate("bob").await.unwrap();
+            assert_eq!(profiles.active_user_id(), "bob");
+            assert!(profile_db_path(temp_dir.path(), "bob").exists());
+            assert_eq!(profiles.sync_manager().db.get_pending_count().unwrap(), 0);
+            assert_eq!(
+                profile(&profiles, "alice").db.get_pending_count().unwrap(),
+                1
+            );
+
+            // Обратно к alice — тот же профиль, очередь на месте
+            profiles.activate("alice").await.unwrap();
+            assert_eq!(profiles.sync_manager().db.get_pending_count().unwrap(), 1);
+            assert_eq!(profiles.profiles()[0].user_id, "alice");
+        }
+
+        #[tokio::test]
+        async fn test_switching_user_pauses_previous_timer() {
+            let api = MockApi::start().await;
+            let temp_dir = TempDir::new().unwrap();
+            let profiles = manager_in(&api, temp_dir.path());
+            profiles.activate("alice").await.unwrap();
+            profiles
+                .sync_manager()
+                .db
+                .set_app_meta("last_active_time_entry_id", "entry-a")
+                .unwrap();
+            profiles.engine().start().unwrap();
+
+            profiles.activate("bob").await.unwrap();
+
+            let alice = profile(&profiles, "alice");
+            assert!(matches!(
+                alice.engine.get_state().unwrap().state,
+                crate::engine::TimerStateForAPI::Paused
+            ));
+            // Запись на сервере тоже ставится на паузу
+            let pending = alice.db.get_retry_tasks(5, 10, false).unwrap();
+            assert_eq!(pending.len(), 1);
+            assert_eq!(pending[0].1, "time_entry_pause");
+            assert!(pending[0].2.contains("entry-a"));
+            assert!(matches!(
+                profiles.engine().get_state().unwrap().state,
+                crate::engine::TimerStateForAPI::Stopped
+            ));
+        }
+
+        #[tokio::test]
+        async fn test_inactive_profile_syncs_with_its_own_token() {
+            let api = MockApi::start().await;
+            api.on(
+                "POST",
+                "/time-entries",
+                MockResponse::json(200, serde_json::json!({"id": "entry-1"})),
+            );
+            let temp_dir = TempDir::new().unwrap();
+            let profiles = manager_in(&api, temp_dir.path());
+
+            profiles.activate("alice").await.unwrap();
+            profiles
+                .sync_manager()
+                .auth_manager
+                .set_tokens(Some("access-a".into()), Some("refresh-a".into()))
+                .await;
+            enqueue_start(&profiles, "p1");
+
+            profiles.activate("bob").await.unwrap();
+            profiles
+                .sync_manager()
+                .auth_manager
+                .set_tokens(Some("access-b".into()), Some("refresh-b".into()))
+                .await;
+
+            let alice = profile(&profiles, "alice");
+            assert_eq!(alice.sync_manager.sync_queue(5).await.unwrap(), 1);
+            let requests = api.requests();
+            assert_eq!(requests.len(), 1);
+            assert_eq!(requests[0].path, "/time-entries");
+            assert_eq!(requests[0].header("authorization"), Some("Bearer access-a"));
+            assert_eq!(alice.db.get_pending_count().unwrap(), 0);
+        }
+
+        #[tokio::test]
+        async fn test_sso_sign_in_goes_to_signed_in_users_profile() {
+            use base64::Engine;
+            let api = MockApi::start().await;
+            let temp_dir = TempDir::new().unwrap();
+            let profiles = manager_in(&api, temp_dir.path());
+            profiles.sign_in("alice").await.unwrap();
+            profiles
+                .sync_manager()
+                .auth_manager
+                .set_tokens(Some("access-a".into()), Some("refresh-a".into()))
+                .await;
+
+            // Через SSO входит bob, пока активен профиль alice
+            let encode = |v: serde_json::Value| {
+                base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(v.to_string())
+            };
+            let id_token = format!(
+                "{}.{}.signature",
+                encode(serde_json::json!({"alg": "RS256"})),
+                encode(serde_json::json!({"sub": "bob"}))
+            );
+            let settings = crate::oauth::OAuthSettings {
+                token_endpoint: Some(format!("{}/oauth/token", api.base_url)),
+                client_id: "desktop".to_string(),
+                ..Default::default()
+            };
+            crate::commands::sign_in_sso_user(
+                &profiles,
+                &settings,
+                &reqwest::Client::new(),
+                crate::oauth::OAuthTokens {
+                    access_token: "access-b".to_string(),
+                    refresh_token: Some("refresh-b".to_string()),
+                    expires_in: None,
+                    id_token: Some(id_token),
+                },
+                settings.refresh_client().unwrap(),
+            )
+            .await
+            .unwrap();
+
+            assert_eq!(profiles.active_user_id(), "bob");
+            assert_eq!(
+                profiles
+                    .app_db()
+                    .get_app_meta("current_user_id")
+                    .unwrap()
+                    .as_deref(),
+                Some("bob")
+            );
+            let bob = profile(&profiles, "bob").sync_manager.auth_manager;
+            assert_eq!(bob.get_access_token().await.unwrap(), "access-b");
+            let alice = profile(&profiles, "alice").sync_manager.auth_manager;
+            assert_eq!(alice.get_access_token().await.unwrap(), "access-a");
+        }
+
+        #[tokio::test]
+        async fn test_legacy_data_moves_into_first_profile() {
+            let api = MockApi::start().await;
+            let temp_dir = TempDir::new().unwrap();
+            let profiles = manager_in(&api, temp_dir.path());
+            // Данные до профилей: всё в hubnity.db, владелец — current_user_id
+            profiles
+                .app_db()
+                .set_app_meta("current_user_id", "alice")
+                .unwrap();
+            enqueue_start(&profiles, "p1");
+            profiles
+                .sync_manager()
+                .auth_manager
+                .set_tokens(Some("access-a".into()), Some("refresh-a".into()))
+                .await;
+
+            profiles.activate("alice").await.unwrap();
+            let active = profiles.sync_manager();
+            assert_eq!(active.db.get_pending_count().unwrap(), 1);
+            assert_eq!(
+                active.auth_manager.get_refresh_token().await.unwrap(),
+                Some("refresh-a".to_string())
+            );
+            assert!(!profiles.app_db().has_user_data().unwrap());
+            assert_eq!(
+                profile(&profiles, "")
+                    .sync_manager
+                    .auth_manager
+                    .get_refresh_token()
+                    .await
+                    .unwrap(),
+                None
+            );
+
+            // Данные чужого пользователя в новый профиль не переносятся
+            profiles
+                .app_db()
+                .set_app_meta("current_user_id", "bob")
+                .unwrap();
+            profiles.activate("").await.unwrap();
+            enqueue_start(&profiles, "p2");
+            profiles.activate("carol").await.unwrap();
+            assert_eq!(profiles.sync_manager().db.get_pending_count().unwrap(), 0);
+            assert!(profiles.app_db().has_user_data().unwrap());
+        }
+
+        #[tokio::test]
+        async fn test_existing_profiles_reload_after_restart() {
+            let api = MockApi::start().await;
+            let temp_dir = TempDir::new().unwrap();
+            {
+                let profiles = manager_in(&api, temp_dir.path());
+                profiles.activate("alice").await.unwrap();
+                enqueue_start(&profiles, "p1");
+                profiles.activate("user@example.com").await.unwrap();
+            }
+
+            let profiles = manager_in(&api, temp_dir.path());
+            assert_eq!(profiles.open_existing(), 2);
+            assert_eq!(profiles.active_user_id(), "");
+            assert_eq!(
+                profile(&profiles, "alice").db.get_pending_count().unwrap(),
+                1
+            );
+            assert!(profiles
+                .profiles()
+                .iter()
+                .any(|p| p.user_id == "user@example.com"));
+        }
+
+        #[tokio::test]
+        async fn test_corrupted_profile_is_recovered_with_its_queue() {
+            let api = MockApi::start().await;
+            let temp_dir = TempDir::new().unwrap();
+            let alice_path = profile_db_path(temp_dir.path(), "alice");
+            {
+                let profiles = manager_in(&api, temp_dir.path());
+                profiles.activate("alice").await.unwrap();
+                enqueue_start(&profiles, "p1");
+                let db = profiles.sync_manager().db.clone();
+                for i in 0..30 {
+                    let payload = format!(r#"{{"i":{},"note":"{}"}}"#, i, "x".repeat(5000));
+                    db.enqueue_sync("time_entry_pause", &payload).unwrap();
+                }
+                db.test_conn()
+                    .query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))
+                    .unwrap();
+            }
+            let mut bytes = std::fs::read(&alice_path).unwrap();
+            let len = bytes.len();
+            for b in &mut bytes[len - 2 * 4096..len - 4096] {
+                *b = 0xFF;
+            }
+            std::fs::write(&alice_path, &bytes).unwrap();
+
+            let profiles = manager_in(&api, temp_dir.path());
+            assert_eq!(profiles.open_existing(), 1);
+            let alice = profile(&profiles, "alice");
+            assert!(alice.db.get_pending_count().unwrap() > 1);
+            let started: i64 = alice
+                .db
+                .test_conn()
+                .query_row(
+                    "SELECT COUNT(*) FROM sync_queue WHERE entity_type = 'time_entry_start'",
+                    [],
+                    |r| r.get(0),
+                )
+                .unwrap();
+            assert_eq!(started, 1);
+
+            // Событие для frontend — когда появится sink
+            let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+            let sink_events = events.clone();
+            profiles.set_event_sink(Arc::new(move |event: &str, payload: serde_json::Value| {
+                sink_events
+                    .lock()
+                    .unwrap()
+                    .push((event.to_string(), payload));
+            }));
+            let events = events.lock().unwrap();
+            let recovered: Vec<_> = events
+                .iter()
+                .filter(|(event, _)| event == crate::ipc::events::DB_RECOVERED)
+                .collect();
+            assert_eq!(recovered.len(), 1);
+            let corrupted_path = recovered[0].1["corrupted_path"].as_str().unwrap();
+            assert!(corrupted_path.contains("hubnity-alice.db.corrupted."));
+            assert!(std::path::Path::new(corrupted_path).exists());
+        }
+
+        #[cfg(not(feature = "sqlcipher"))]
+        #[tokio::test]
+        async fn test_key_purge_refused_while_other_databases_need_the_key() {
+            let api = MockApi::start().await;
+            let temp_dir = TempDir::new().unwrap();
+            let profiles = manager_in(&api, temp_dir.path());
+            profiles.activate("alice").await.unwrap();
+            enqueue_start(&profiles, "p1");
+            profiles.activate("bob").await.unwrap();
+            enqueue_start(&profiles, "p2");
+
+            // Очередь alice зашифрована тем же ключом; своя очередь bob не в счёт (Flush её удалит)
+            assert_eq!(
+                profiles.key_dependents("bob"),
+                vec![profile_db_path(temp_dir.path(), "alice")]
+            );
+            profile(&profiles, "alice").db.clear_user_data().unwrap();
+            assert!(profiles.key_dependents("bob").is_empty());
+
+            // Секреты hubnity.db (refresh token, пароль прокси) тоже зашифрованы общим ключом
+            let app_db = profiles.app_db();
+            let secret = app_db.encryption.encrypt("refresh-token").unwrap();
+            app_db.set_app_meta("auth_refresh_token", &secret).unwrap();
+            assert_eq!(
+                profiles.key_dependents("bob"),
+                vec![temp_dir.path().join("hubnity.db")]
+            );
+            app_db.set_app_meta("auth_refresh_token", "").unwrap();
+            assert!(profiles.key_dependents("bob").is_empty());
+            crate::http_client::HttpClientSettings {
+                proxy_url: Some("http://proxy.corp.local:3128".to_string()),
+                proxy_password: Some("proxy-password".to_string()),
+                ..Default::default()
+            }
+            .save(app_db)
+            .unwrap();
+            assert_eq!(profiles.key_dependents("bob").len(), 1);
+        }
+
+        #[test]
+        fn test_profile_file_name_hashes_unsafe_ids() {
+            let dir = std::path::Path::new("/data");
+            assert_eq!(
+                profile_db_path(dir, "user-42"),
+                dir.join("hubnity-user-42.db")
+            );
+            let hashed = profile_db_path(dir, "../user@example.com");
+            let name = hashed.file_name().unwrap().to_str().unwrap();
+            assert!(name.starts_with("hubnity-") && name.ends_with(".db"));
+            assert_eq!(name.len(), "hubnity-".len() + 32 + ".db".len());
+            assert_ne!(hashed, profile_db_path(dir, "../user@example.org"));
+        }
+    }
+
+    mod backup_tests {
+        use super::*;
+        use crate::backup::{
+            list_backups, restore_newest_good, stage_restore, BackupInfo, BACKUP_KEEP,
+        };
+        use std::path::Path;
+        use tempfile::TempDir;
+
+        fn create_test_db() -> (Database, TempDir) {
+            let temp_dir = TempDir::new().unwrap();
+            let db_path = temp_dir.path().join("hubnity.db");
+            let db = Database::new(db_path.to_str().unwrap()).unwrap();
+            (db, temp_dir)
+        }
+
+        fn key(db: &Database) -> Option<String> {
+            db.encryption.database_key()
+        }
+
+        /// Копия с заданным временем (имя файла) — без ожидания между run_backup
+        fn copy_backup(from: &BackupInfo, db_path: &Path, created_at: i64) -> BackupInfo {
+            let file_name = format!("hubnity.db.{}.bak", created_at);
+            let path = crate::backup::backup_dir(db_path).join(&file_name);
+            std::fs::copy(&from.path, &path).unwrap();
+            BackupInfo {
+                path: path.to_string_lossy().into_owned(),
+                size_bytes: from.size_bytes,
+                file_name,
+                created_at,
+            }
+        }
+
+        #[test]
+        fn test_backup_is_listed_and_rotated() {
+            let (db, _temp_dir) = create_test_db();
+            assert!(db.backup_due());
+            db.save_timer_state("2026-01-05", 3600, "stopped", None)
+                .unwrap();
+
+            let first = db.run_backup().unwrap();
+            assert!(!db.backup_due());
+            crate::backup::verify_backup(Path::new(&first.path), key(&db).as_deref()).unwrap();
+            assert_eq!(list_backups(db.path()), vec![first.clone()]);
+
+            // Старые копии (и копия другой БД в том же каталоге)
+            for age in 1..=BACKUP_KEEP as i64 {
+                copy_backup(&first, db.path(), first.created_at - age * 3600);
+            }
+            let other = crate::backup::backup_dir(db.path()).join("hubnity-user.db.1.bak");
+            std::fs::copy(&first.path, &other).unwrap();
+            assert_eq!(list_backups(db.path()).len(), BACKUP_KEEP + 1);
+
+            let newest = db.run_backup().unwrap();
+            let kept = list_backups(db.path());
+            assert_eq!(kept.len(), BACKUP_KEEP);
+            assert_eq!(kept[0], newest);
+            let oldest = first.created_at - BACKUP_KEEP as i64 * 3600;
+            assert!(kept.iter().all(|b| b.created_at > oldest));
+            assert!(other.exists());
+        }
+
+        #[test]
+        fn test_staged_restore_is_applied_on_next_open() {
+            let (db, temp_dir) = create_test_db();
+            let db_path = temp_dir.path().join("hubnity.db");
+            db.save_timer_state("2026-01-05", 3600, "stopped", None)
+                .unwrap();
+            let backup = db.run_backup().unwrap();
+            let old = copy_backup(&backup, &db_path, backup.created_at - 60);
+            std::fs::remove_file(&backup.path).unwrap();
+            db.save_timer_state("2026-01-05", 7200, "stopped", None)
+                .unwrap();
+
+            stage_restore(&db_path, &old, key(&db).as_deref()).unwrap();
+            // Открытая БД не изменена до перезапуска
+            assert_eq!(db.load_timer_state().unwrap().unwrap().1, 7200);
+            drop(db);
+
+            let db = Database::new(db_path.to_str().unwrap()).unwrap();
+            assert_eq!(db.load_timer_state().unwrap().unwrap().1, 3600);
+            // Заменённая БД сохранена рядом с копиями, но не в их списке (и ротации)
+            assert_eq!(list_backups(&db_path), vec![old.clone()]);
+            let replaced: Vec<_> = std::fs::read_dir(crate::backup::backup_dir(&db_path))
+                .unwrap()
+                .filter_map(|e| e.ok())
+                .filter(|e| e.file_name().to_string_lossy().ends_with(".replaced"))
+                .collect();
+            assert_eq!(replaced.len(), 1);
+            drop(db);
+            // Повторное открытие — без повторной подмены
+            let db = Database::new(db_path.to_str().unwrap()).unwrap();
+            assert_eq!(db.load_timer_state().unwrap().unwrap().1, 3600);
+        }
+
+        #[test]
+        fn test_restore_keeps_unsent_queue_of_replaced_database() {
+            let (db, temp_dir) = create_test_db();
+            let db_path = temp_dir.path().join("hubnity.db");
+            let sent = db
+                .enqueue_sync("time_entry_pause", r#"{"id": "e1"}"#)
+                .unwrap();
+            let backup = db.run_backup().unwrap();
+            // После копии: задача отправлена, поставлены новые
+            db.mark_task_sent(sent).unwrap();
+            let stop = db
+                .enqueue_sync("time_entry_stop", r#"{"id": "e1"}"#)
+                .unwrap();
+            let start = db
+                .enqueue_sync("time_entry_start", r#"{"projectId": "p1"}"#)
+                .unwrap();
+            let key_of = |db: &Database, id: i64| -> String {
+                db.test_conn()
+                    .query_row(
+                        "SELECT idempotency_key FROM sync_queue WHERE id = ?1",
+                        [id],
+                        |r| r.get(0),
+                    )
+                    .unwrap()
+            };
+            let mut unsent_keys = vec![key_of(&db, stop), key_of(&db, start)];
+            let sent_key = key_of(&db, sent);
+
+            stage_restore(&db_path, &backup, key(&db).as_deref()).unwrap();
+            drop(db);
+            let db = Database::new(db_path.to_str().unwrap()).unwrap();
+
+            // Задачи, поставленные после копии, — с прежними ключами; задача из копии не удвоена
+            let tasks = db.get_retry_tasks(5, 10, false).unwrap();
+            let mut keys: Vec<String> = tasks.iter().filter_map(|t| t.4.clone()).collect();
+            keys.sort();
+            unsent_keys.push(sent_key);
+            unsent_keys.sort();
+            assert_eq!(keys, unsent_keys);
+        }
+
+        #[test]
+        fn test_corrupted_backup_is_not_restored() {
+            let (db, temp_dir) = create_test_db();
+            let db_path = temp_dir.path().join("hubnity.db");
+            db.save_timer_state("2026-01-05", 3600, "stopped", None)
+                .unwrap();
+            let good = db.run_backup().unwrap();
+            let mut corrupted = copy_backup(&good, &db_path, good.created_at + 60);
+            let mut bytes = std::fs::read(&corrupted.path).unwrap();
+            for b in bytes.iter_mut().skip(100) {
+                *b = 0xAB;
+            }
+            std::fs::write(&corrupted.path, &bytes).unwrap();
+            corrupted.size_bytes = bytes.len() as u64;
+
+            assert!(stage_restore(&db_path, &corrupted, key(&db).as_deref()).is_err());
+            assert!(!temp_dir.path().join("hubnity.db.restore").exists());
+            let db_key = key(&db);
+            drop(db);
+
+            // Путь повреждения в lib.rs: файл перемещён, берётся самая новая годная копия
+            std::fs::rename(&db_path, temp_dir.path().join("hubnity.db.corrupted")).unwrap();
+            let restored = restore_newest_good(&db_path, db_key.as_deref()).unwrap();
+            assert_eq!(restored.file_name, good.file_name);
+            let db = Database::new(db_path.to_str().unwrap()).unwrap();
+            assert_eq!(db.load_timer_state().unwrap().unwrap().1, 3600);
+        }
+    }
+
+    mod salvage_tests {
+        use super::*;
+        use crate::salvage::salvage_into;
+        use tempfile::TempDir;
+
+        const PAGE_SIZE: usize = 4096;
+
+        fn task_keys(db: &Database) -> Vec<String> {
+            let conn = db.test_conn();
+            let mut stmt = conn
+                .prepare(
+                    "SELECT idempotency_key FROM sync_queue
+                     WHERE status = 'pending' ORDER BY idempotency_key",
+                )
+                .unwrap();
+            let keys = stmt
+                .query_map([], |row| row.get(0))
+                .unwrap()
+                .collect::<rusqlite::Result<Vec<String>>>()
+                .unwrap();
+            keys
+        }
+
+        #[test]
+        fn test_salvage_rescues_readable_unsent_rows() {
+            let temp_dir = TempDir::new().unwrap();
+            let corrupted_path = temp_dir.path().join("hubnity.db.corrupted");
+            let original_keys = {
+                let db = Database::new(corrupted_path.to_str().unwrap()).unwrap();
+                db.save_timer_state("2026-01-05", 3600, "stopped", None)
+                    .unwrap();
+                db.save_timer_state("2026-01-06", 600, "stopped", None)
+                    .unwrap();
+                // Payload больше страницы — строки на отдельных страницах
+                for i in 0..60 {
+                    let payload = format!(r#"{{"i":{},"note":"{}"}}"#, i, "x".repeat(5000));
+                    let id = db.enqueue_sync("time_entry_pause", &payload).unwrap();
+                    if i % 10 == 0 {
+                        db.mark_task_sent(id).unwrap();
+                    }
+                }
+                // Всё из WAL — в основной файл, повреждаем именно его страницы
+                db.test_conn()
+                    .query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))
+                    .unwrap();
+                task_keys(&db)
+            };
+            assert_eq!(original_keys.len(), 54);
+
+            // Повреждаем страницу в середине файла
+            let mut bytes = std::fs::read(&corrupted_path).unwrap();
+            let page = bytes.len() / PAGE_SIZE / 2;
+            for b in &mut bytes[page * PAGE_SIZE..(page + 1) * PAGE_SIZE] {
+                *b = 0xFF;
+            }
+            std::fs::write(&corrupted_path, &bytes).unwrap();
+            assert!(Database::new(corrupted_path.to_str().unwrap()).is_err());
+
+            let db_path = temp_dir.path().join("hubnity.db");
+            let db = Database::new(db_path.to_str().unwrap()).unwrap();
+            db.save_timer_state("2026-01-06", 900, "stopped", None)
+                .unwrap();
+            let report = salvage_into(&corrupted_path, &db).unwrap();
+
+            let rescued = task_keys(&db);
+            assert_eq!(report.sync_tasks_rescued, rescued.len());
+            assert!(report.unreadable_rows > 0);
+            assert!(rescued.len() > original_keys.len() / 2, "{:?}", report);
+            assert!(rescued.len() < original_keys.len());
+            // Те же idempotency keys; отправленные задачи не переносятся
+            assert!(rescued.iter().all(|key| original_keys.contains(key)));
+            // День, записанный в новой БД позже, не перезаписан
+            assert_eq!(report.time_entries_rescued, 1);
+            let conn = db.test_conn();
+            let seconds: i64 = conn
+                .query_row(
+                    "SELECT accumulated_seconds FROM time_entries WHERE day = '2026-01-06'",
+                    [],
+                    |r| r.get(0),
+                )
+                .unwrap();
+            assert_eq!(seconds, 900);
+
+            // Повтор (или задачи уже в восстановленной копии) — без дубликатов
+            let again = salvage_into(&corrupted_path, &db).unwrap();
+            assert_eq!(again.sync_tasks_rescued, 0);
+            assert_eq!(again.sync_tasks_skipped, rescued.len());
+            assert_eq!(task_keys(&db), rescued);
+        }
+
+        #[test]
+        fn test_recovery_keeps_blobs_of_salvaged_screenshots() {
+            let temp_dir = TempDir::new().unwrap();
+            let db_path = temp_dir.path().join("hubnity.db");
+            let blob_ref = {
+                let db = Database::new(db_path.to_str().unwrap()).unwrap();
+                let blob_ref = db.blobs.put(b"screenshot bytes").unwrap();
+                let payload = serde_json::json!({"imageRef": blob_ref, "timeEntryId": "e1"});
+                db.enqueue_sync_with_blob("screenshot", &payload.to_string(), Some(&blob_ref))
+                    .unwrap();
+                for i in 0..30 {
+                    let payload = format!(r#"{{"i":{},"note":"{}"}}"#, i, "x".repeat(5000));
+                    db.enqueue_sync("time_entry_pause", &payload).unwrap();
+                }
+                db.test_conn()
+                    .query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))
+                    .unwrap();
+                blob_ref
+            };
+            // Скриншот снят задолго до сбоя — старше BLOB_SWEEP_MIN_AGE
+            let blob_path = temp_dir
+                .path()
+                .join("blobs")
+                .join(format!("{}.blob", blob_ref));
+            std::fs::File::options()
+                .write(true)
+                .open(&blob_path)
+                .unwrap()
+                .set_modified(std::time::SystemTime::now() - std::time::Duration::from_secs(3600))
+                .unwrap();
+
+            // Повреждаем последние страницы (поздние задачи), строка скриншота остаётся читаемой
+            let mut bytes = std::fs::read(&db_path).unwrap();
+            let len = bytes.len();
+            for b in &mut bytes[len - 2 * PAGE_SIZE..len - PAGE_SIZE] {
+                *b = 0xFF;
+            }
+            std::fs::write(&db_path, &bytes).unwrap();
+            let err = Database::new(db_path.to_str().unwrap()).err().unwrap();
+            assert!(crate::salvage::is_corruption_error(&err.to_string()));
+
+            let (db, event) = crate::salvage::recover_corrupted(&db_path, None).unwrap();
+            assert!(event.salvage.unwrap().sync_tasks_rescued > 0);
+            let rescued: String = db
+                .test_conn()
+                .query_row(
+                    "SELECT blob_ref FROM sync_queue WHERE entity_type = 'screenshot'",
+                    [],
+                    |r| r.get(0),
+                )
+                .unwrap();
+            assert_eq!(rescued, blob_ref);
+            assert_eq!(db.blobs.get(&blob_ref).unwrap(), b"screenshot bytes");
+        }
+    }
+
+    mod key_rotation_tests {
+        use super::*;
+        use crate::key_rotation::rotate_encryption_key;
+        use std::sync::Arc;
+        use tempfile::TempDir;
+
+        fn open(dir: &TempDir, name: &str) -> Arc<Database> {
+            let path = dir.path().join(format!("{}.db", name));
+            let blobs = dir.path().join(format!("blobs-{}", name));
+            Arc::new(Database::with_blob_dir(path.to_str().unwrap(), &blobs).unwrap())
+        }
+
+        fn payloads(db: &Database) -> Vec<String> {
+            let conn = db.test_conn();
+            let mut stmt = conn
+                .prepare("SELECT payload FROM sync_queue ORDER BY id")
+                .unwrap();
+            let rows = stmt
+                .query_map([], |row| row.get(0))
+                .unwrap()
+                .collect::<rusqlite::Result<Vec<String>>>()
+                .unwrap();
+            rows
+        }
+
+        #[test]
+        fn test_rotation_reencrypts_profiles_and_retires_old_key() {
+            let dir = TempDir::new().unwrap();
+            let app_db = open(&dir, "hubnity");
+            let profile_db = open(&dir, "hubnity-user-1");
+            let old_key_id = app_db.encryption.active_key_id();
+            for (i, db) in [&app_db, &profile_db].into_iter().enumerate() {
+                db.enqueue_sync("time_entry_pause", &format!(r#"{{"id":"entry-{}"}}"#, i))
+                    .unwrap();
+                let secret = db.encryption.encrypt("refresh-token").unwrap();
+                db.set_app_meta("auth_refresh_token", &secret).unwrap();
+                db.set_app_meta("sync_retention_sent_days", "7").unwrap();
+            }
+            let blob_ref = profile_db.blobs.put(b"screenshot").unwrap();
+            profile_db.run_backup().unwrap();
+
+            let report = rotate_encryption_key(&[app_db.clone(), profile_db.clone()]).unwrap();
+
+            assert_eq!(report.payloads_reencrypted, 2);
+            assert_eq!(report.secrets_reencrypted, 2);
+            assert_eq!(report.blobs_reencrypted, 1);
+            assert_eq!(report.undecryptable, 0);
+            assert_eq!(report.retired_keys, 1);
+            let new_key_id = app_db.encryption.active_key_id();
+            assert_ne!(new_key_id, old_key_id);
+            assert_eq!(report.key_id, format!("{:08x}", new_key_id));
+            assert_eq!(profile_db.encryption.key_ids(), vec![new_key_id]);
+            // Обычное значение app_meta не тронуто
+            assert_eq!(
+                app_db.get_app_meta("sync_retention_sent_days").unwrap(),
+                Some("7".to_string())
+            );
+            // Копия до ротации заменена копией на новом ключе
+            assert_eq!(crate::backup::list_backups(profile_db.path()).len(), 1);
+            assert!(!dir.path().join(".hubnity_encryption_key.previous").exists());
+
+            // После перезапуска известен только новый ключ — и его достаточно
+            let restarted = TokenEncryption::new(Some(dir.path())).unwrap();
+            assert_eq!(restarted.key_ids(), vec![new_key_id]);
+            for db in [&app_db, &profile_db] {
+                for payload in payloads(db) {
+                    assert!(restarted.is_current(&payload));
+                    assert!(restarted.is_bound(&payload));
+                }
+                // Прежний ключ удалён — payload расшифровывается новым
+                assert_eq!(db.get_retry_tasks(5, 10, false).unwrap().len(), 1);
+                let secret = db.get_app_meta("auth_refresh_token").unwrap().unwrap();
+                assert_eq!(restarted.decrypt(&secret).unwrap(), "refresh-token");
+            }
+            assert_eq!(profile_db.blobs.get(&blob_ref).unwrap(), b"screenshot");
+        }
+
+        #[test]
+        fn test_rotation_covers_unloaded_profiles_and_proxy_password() {
+            let dir = TempDir::new().unwrap();
+            let app_db = open(&dir, "hubnity");
+            // Профиль на диске, не загруженный в ProfileManager (владелец не записан)
+            {
+                let carol = Database::with_blob_dir(
+                    dir.path().join("hubnity-carol.db").to_str().unwrap(),
+                    &dir.path().join("blobs-carol"),
+                )
+                .unwrap();
+                carol
+                    .enqueue_sync("time_entry_pause", r#"{"id":"entry-c"}"#)
+                    .unwrap();
+            }
+            crate::http_client::HttpClientSettings {
+                proxy_url: Some("http://proxy.corp.local:3128".to_string()),
+                proxy_password: Some("proxy-password".to_string()),
+                ..Default::default()
+            }
+            .save(&app_db)
+            .unwrap();
+            let profiles = crate::profiles::ProfileManager::new(
+                dir.path().to_path_buf(),
+                app_db.clone(),
+                crate::sync::SyncConfig::default(),
+            );
+
+            let dbs = profiles.all_databases().unwrap();
+            assert_eq!(dbs.len(), 2);
+            let report = rotate_encryption_key(&dbs).unwrap();
+            drop(dbs);
+            assert_eq!(report.payloads_reencrypted, 1);
+            assert_eq!(report.secrets_reencrypted, 1);
+            assert_eq!(report.retired_keys, 1);
+
+            // После перезапуска известен только новый ключ
+            let carol = Database::with_blob_dir(
+                dir.path().join("hubnity-carol.db").to_str().unwrap(),
+                &dir.path().join("blobs-carol"),
+            )
+            .unwrap();
+            assert_eq!(carol.encryption.key_ids().len(), 1);
+            assert_eq!(carol.get_retry_tasks(5, 10, false).unwrap().len(), 1);
+            let restarted = Database::new(dir.path().join("hubnity.db").to_str().unwrap()).unwrap();
+            assert_eq!(
+                crate::http_client::HttpClientSettings::load(&restarted).proxy_password,
+                Some("proxy-password".to_string())
+            );
+        }
+
+        #[test]
+        fn test_previous_key_decrypts_until_retired() {
+            let dir = TempDir::new().unwrap();
+            let encryption = TokenEncryption::new(Some(dir.path())).unwrap();
+            let old = encryption.encrypt("payload").unwrap();
+            let old_key_id = encryption.active_key_id();
+
+            let new_key_id = encryption.rotate_key().unwrap();
+            assert_eq!(encryption.key_ids(), vec![new_key_id, old_key_id]);
+            assert!(!encryption.is_current(&old));
+            assert_eq!(
+                encryption.decrypt_with_legacy_fallback(&old).unwrap(),
+                ("payload".to_string(), true)
+            );
+            let new = encryption.encrypt("payload").unwrap();
+            assert!(encryption.is_current(&new));
+            assert_eq!(
+                encryption.decrypt_with_legacy_fallback(&new).unwrap(),
+                ("payload".to_string(), false)
+            );
+
+            // Ротация прервана (перезапуск) — прежний ключ читается из файла
+            let restarted = TokenEncryption::new(Some(dir.path())).unwrap();
+            assert_eq!(restarted.key_ids(), vec![new_key_id, old_key_id]);
+            assert_eq!(restarted.decrypt(&old).unwrap(), "payload");
+
+            assert_eq!(encryption.retire_previous_keys().unwrap(), 1);
+            assert!(encryption.decrypt(&old).is_err());
+            assert_eq!(encryption.decrypt(&new).unwrap(), "payload");
+        }
+
+        #[test]
+        fn test_ciphertext_without_key_id_still_decrypts() {
+            use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+            use aes_gcm::Aes256Gcm;
+            use base64::{engine::general_purpose, Engine as _};
+
+            let dir = TempDir::new().unwrap();
+            let encryption = TokenEncryption::new(Some(dir.path())).unwrap();
+            // Формат до key id: nonce + ciphertext тем же ключом
+            let key_hex = std::fs::read_to_string(dir.path().join(".hubnity_encryption_key")).unwrap();
+            let key: [u8; 32] = hex::decode(key_hex.trim()).unwrap().try_into().unwrap();
+            let cipher = Aes256Gcm::new(&key.into());
+            let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+            let mut data = nonce.to_vec();
+            data.extend(cipher.encrypt(&nonce, b"old format".as_ref()).unwrap());
+            let old = general_purpose::STANDARD.encode(&data);
+
+            assert!(!encryption.is_current(&old));
+            assert_eq!(
+                encryption.decrypt_with_legacy_fallback(&old).unwrap(),
+                ("old format".to_string(), true)
+            );
+        }
+
+        #[cfg(feature = "sqlcipher")]
+        #[test]
+        fn test_interrupted_rotation_rekeys_database_on_open() {
+            let dir = TempDir::new().unwrap();
+            let db_path = dir.path().join("hubnity.db");
+            {
+                let db = Database::new(db_path.to_str().unwrap()).unwrap();
+                db.set_app_meta("k", "v").unwrap();
+                // Новый ключ записан, но до rekey файла дело не дошло
+                db.encryption.rotate_key().unwrap();
+            }
+            let db = Database::new(db_path.to_str().unwrap()).unwrap();
+            assert_eq!(db.get_app_meta("k").unwrap(), Some("v".to_string()));
+
+            let report = rotate_encryption_key(&[Arc::new(db)]).unwrap();
+            assert_eq!(report.databases_rekeyed, 1);
+            let db = Database::new(db_path.to_str().unwrap()).unwrap();
+            assert_eq!(db.get_app_meta("k").unwrap(), Some("v".to_string()));
+        }
+    }
 }
@@ -16,10 +16,10 @@ import { Tabs, TabsContent, TabsList, TabsTrigger } from './components/ui/tabs';
 import { Button } from './components/ui/button';
 import { LogOut } from 'lucide-react';
 import { logger } from './lib/logger';
-import { IPC_EVENTS, IPC_COMMANDS } from './lib/ipc';
+import { IPC_EVENTS, IPC_COMMANDS, type DbRecoveredEvent, type TokensRefreshedEvent } from './lib/ipc';
 import { setSentryUser } from './lib/sentry';
 import { setCurrentUser } from './lib/current-user';
-import { api, USER_ROLES } from './lib/api';
+import { api, USER_ROLES, type ApiEndpoint } from './lib/api';
 import './App.css';
 
 const RELEASES_URL = 'https://github.com/balabiturembekov/hubnity-desktop/releases';
@@ -34,7 +34,7 @@ function App() {
   const [appVersion, setAppVersion] = useState<string | null>(null);
   const [isCheckingForUpdate, setIsCheckingForUpdate] = useState(false);
   const [updateCheckResult, setUpdateCheckResult] = useState<'idle' | 'latest' | 'available' | 'error'>('idle');
-  const [dbRecoveredBannerVisible, setDbRecoveredBannerVisible] = useState(false);
+  const [dbRecovered, setDbRecovered] = useState<DbRecoveredEvent | null>(null);
 
   // Восстанавливаем токены в Rust AuthManager — иначе фоновая синхронизация всегда получает "token not set" и Синхронизировано = 0
   const restoreTokens = useCallback(async () => {
@@ -73,6 +73,25 @@ function App() {
     return () => document.removeEventListener('visibilitychange', onVisibilityChange);
   }, []);
 
+  // Rust обновил токены (refresh в фоновой синхронизации) — иначе pushTokensAndSync вернёт в Rust
+  // старый, уже ротированный refresh token
+  useEffect(() => {
+    let unlisten: (() => void) | null = null;
+    let cancelled = false;
+    listen<TokensRefreshedEvent>(IPC_EVENTS.TOKENS_REFRESHED, (event) => {
+      api.setToken(event.payload.access_token);
+      localStorage.setItem('refresh_token', event.payload.refresh_token);
+      logger.info('APP', 'Tokens refreshed by background sync');
+    }).then((fn) => {
+      if (cancelled) fn();
+      else unlisten = fn;
+    });
+    return () => {
+      cancelled = true;
+      unlisten?.();
+    };
+  }, []);
+
   // Восстанавливаем токены при монтировании и при смене isAuthenticated
   useEffect(() => {
     restoreTokens();
@@ -522,6 +541,15 @@ function App() {
     };
   }, [isAuthenticated]);
 
+  // Адрес API (self-hosted / staging) из Rust: policy > HUBNITY_API_URL > настройки > default
+  useEffect(() => {
+    invoke<ApiEndpoint>('get_api_endpoint')
+      .then((endpoint) => {
+        if (endpoint?.url) api.setBaseUrl(endpoint.url);
+      })
+      .catch((error) => logger.debug('APP', 'Failed to get API endpoint, using default', error));
+  }, []);
+
   // Listen for auth logout events (from refresh token failure)
   useEffect(() => {
     const handleLogout = async () => {
@@ -544,11 +572,14 @@ function App() {
   useEffect(() => {
     let unlisten: (() => void) | null = null;
     let cancelled = false;
-    listen(IPC_EVENTS.DB_RECOVERED, () => {
-      setDbRecoveredBannerVisible(true);
+    listen<DbRecoveredEvent>(IPC_EVENTS.DB_RECOVERED, (event) => {
+      setDbRecovered(event.payload);
+      const backup = event.payload.restored_backup;
       invoke('show_notification', {
         title: 'Hubnity — Database Recovered',
-        body: 'Database was corrupted and has been recovered. Pending sync data may have been lost. Please check your time entries.',
+        body: backup
+          ? `Database was corrupted and has been restored from the backup of ${new Date(backup.created_at * 1000).toLocaleString()}. Changes made after it may be missing.`
+          : 'Database was corrupted and has been recovered. Pending sync data may have been lost. Please check your time entries.',
       }).catch(() => {});
     }).then((fn) => {
       if (cancelled) fn();
@@ -1714,16 +1745,21 @@ function App() {
   return (
     <ErrorBoundary>
       <div className="h-screen bg-background overflow-hidden flex flex-col">
-        {dbRecoveredBannerVisible && (
+        {dbRecovered && (
           <div className="px-4 py-3 bg-amber-500/90 text-amber-950 flex items-center justify-between gap-3 shrink-0">
             <span className="text-sm font-medium">
-              Database was recovered from corruption. Pending sync data may have been lost. Please verify your time entries.
+              {dbRecovered.restored_backup
+                ? `Database was restored from the backup of ${new Date(dbRecovered.restored_backup.created_at * 1000).toLocaleString()} after corruption. Please verify your recent time entries.`
+                : 'Database was recovered from corruption. Pending sync data may have been lost. Please verify your time entries.'}
+              {dbRecovered.salvage && dbRecovered.salvage.sync_tasks_rescued > 0
+                ? ` ${dbRecovered.salvage.sync_tasks_rescued} unsent change(s) were rescued from the damaged file.`
+                : ''}
             </span>
             <Button
               variant="ghost"
               size="sm"
               className="shrink-0 text-amber-950 hover:bg-amber-950/20"
-              onClick={() => setDbRecoveredBannerVisible(false)}
+              onClick={() => setDbRecovered(null)}
             >
               Dismiss
             </Button>
@@ -10,6 +10,7 @@ import {
   DialogTitle,
 } from './ui/dialog';
 import { logger } from '../lib/logger';
+import { api } from '../lib/api';
 import { getCurrentUser } from '../lib/current-user';
 
 /**
@@ -192,7 +193,8 @@ export function ScreenshotsView({ timeEntryId }: ScreenshotsViewProps) {
 
   const getFullImageUrl = (imageUrl: string) => {
     if (imageUrl.startsWith('/')) {
-      return `https://app.automatonsoft.de${imageUrl}`;
+      // Origin сервера API (self-hosted / staging), а не захардкоженный production-хост
+      return `${new URL(api.getBaseUrl()).origin}${imageUrl}`;
     }
     return imageUrl;
   };
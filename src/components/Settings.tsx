@@ -14,7 +14,11 @@ interface QueueStats {
   pending_count: number;
   failed_count: number;
   sent_count: number;
+  parked_count?: number;
   pending_by_type: Record<string, number>;
+  upload_bytes_raw?: number;
+  upload_bytes_sent?: number;
+  compression_saved_bytes?: number;
 }
 
 export function Settings() {
@@ -346,6 +350,20 @@ export function Settings() {
                 <span className="text-muted-foreground">Synced:</span>
                 <span className="font-medium text-foreground">{queueStats.sent_count}</span>
               </div>
+              {(queueStats.parked_count ?? 0) > 0 && (
+                <div className="flex items-center justify-between text-sm">
+                  <span className="text-muted-foreground">Other server (not sent):</span>
+                  <span className="font-medium text-foreground">{queueStats.parked_count}</span>
+                </div>
+              )}
+              {(queueStats.compression_saved_bytes ?? 0) > 0 && (
+                <div className="flex items-center justify-between text-sm">
+                  <span className="text-muted-foreground">Saved by compression:</span>
+                  <span className="font-medium text-foreground">
+                    {((queueStats.compression_saved_bytes ?? 0) / 1024).toFixed(1)} KB
+                  </span>
+                </div>
+              )}
               {Object.keys(queueStats.pending_by_type).length > 0 && (
                 <div className="pt-2 border-t border-border">
                   <Label className="text-xs text-muted-foreground mb-1.5 block">By task type:</Label>
@@ -2,8 +2,15 @@ import axios, { AxiosInstance } from 'axios';
 import { invoke } from '@tauri-apps/api/core';
 import { logger } from './logger';
 
+/** Адрес по умолчанию; фактический (policy / env / настройки) приходит из Rust через get_api_endpoint */
 const API_BASE_URL = 'https://app.automatonsoft.de/api';
 
+export interface ApiEndpoint {
+  url: string;
+  source: 'policy' | 'env' | 'settings' | 'default';
+  locked: boolean;
+}
+
 /** Включить логи API в терминал (dev или localStorage DEBUG_API=1) */
 const isDebugApi = () =>
   import.meta.env.DEV || (typeof localStorage !== 'undefined' && localStorage.getItem('DEBUG_API') === '1');
@@ -147,6 +154,7 @@ export interface BatchUrlActivityResponse {
 class ApiClient {
   private client: AxiosInstance;
   private accessToken: string | null = null;
+  private baseUrl = API_BASE_URL;
 
   constructor() {
     this.client = axios.create({
@@ -258,7 +266,7 @@ class ApiClient {
             
             // Try to refresh the token
             const response = await axios.post<LoginResponse>(
-              `${API_BASE_URL}/auth/refresh`,
+              `${this.baseUrl}/auth/refresh`,
               { refresh_token: refreshToken },
               { timeout: 10000 } // 10 second timeout
             );
@@ -325,6 +333,16 @@ class ApiClient {
     }
   }
 
+  /** Адрес API, выбранный в Rust (должен совпадать с адресом очереди синхронизации) */
+  setBaseUrl(url: string) {
+    this.baseUrl = url;
+    this.client.defaults.baseURL = url;
+  }
+
+  getBaseUrl(): string {
+    return this.baseUrl;
+  }
+
   setToken(token: string) {
     this.accessToken = token;
     localStorage.setItem('access_token', token);
@@ -7,14 +7,109 @@ export const IPC_EVENTS = {
   TIMER_STATE_UPDATE: 'timer-state-update',
   ACTIVITY_DETECTED: 'activity-detected',
   IDLE_STATE_UPDATE: 'idle-state-update',
+  /** Corrupted DB moved aside: { corrupted_path, restored_backup } (null — started empty) */
   DB_RECOVERED: 'db-recovered-from-corruption',
   RESUME_TRACKING: 'resume-tracking',
   STOP_TRACKING: 'stop-tracking',
   REQUEST_IDLE_STATE: 'request-idle-state-for-idle-window',
   /** Emitted when sleep detected; frontend can suppress activity for 30s (get_idle_time reset) */
   SYSTEM_SLEEP_DETECTED: 'system-sleep-detected',
+  /** Sync batch started: { total } */
+  SYNC_STARTED: 'sync-started',
+  /** Task synced: { task_id, entity_type, index, total } */
+  SYNC_TASK_DONE: 'sync-task-done',
+  /** Task failed: { task_id, entity_type, index, total, error_class, error, will_retry } */
+  SYNC_TASK_FAILED: 'sync-task-failed',
+  /** Sync batch finished: { total, synced, failed, deferred, synced_by_type, failed_by_type, duration_ms } */
+  SYNC_FINISHED: 'sync-finished',
+  /** API connectivity changed: { state, previous, latency_ms } */
+  CONNECTIVITY_CHANGED: 'connectivity-changed',
+  /** Rust refreshed the access token: { access_token, refresh_token, expires_at } */
+  TOKENS_REFRESHED: 'auth-tokens-refreshed',
+  /** Logout finished: { revoked, queue_policy, flushed_tasks, remaining_tasks, keys_purged } */
+  LOGGED_OUT: 'auth-logged-out',
 } as const;
 
+export interface TokensRefreshedEvent {
+  access_token: string;
+  refresh_token: string;
+  expires_at: number | null;
+}
+
+export type LogoutQueuePolicy = 'flush' | 'keep';
+
+export interface LoggedOutEvent {
+  revoked: boolean;
+  queue_policy: LogoutQueuePolicy;
+  flushed_tasks: number;
+  /** Unsent tasks kept in the queue (flush that could not send everything falls back to keep) */
+  remaining_tasks: number;
+  keys_purged: boolean;
+}
+
+export interface BackupInfo {
+  file_name: string;
+  path: string;
+  created_at: number;
+  size_bytes: number;
+}
+
+export interface KeyRotationReport {
+  key_id: string;
+  payloads_reencrypted: number;
+  secrets_reencrypted: number;
+  blobs_reencrypted: number;
+  undecryptable: number;
+  databases_rekeyed: number;
+  retired_keys: number;
+}
+
+export interface SalvageReport {
+  sync_tasks_rescued: number;
+  sync_tasks_skipped: number;
+  time_entries_rescued: number;
+  unreadable_rows: number;
+}
+
+export interface DbRecoveredEvent {
+  corrupted_path: string;
+  restored_backup: BackupInfo | null;
+  salvage: SalvageReport | null;
+}
+
+export type ConnectivityState = 'online' | 'degraded' | 'offline';
+
+export interface ConnectivityChangedEvent {
+  state: ConnectivityState;
+  previous: ConnectivityState | null;
+  latency_ms: number | null;
+}
+
+export interface SyncStartedEvent {
+  total: number;
+}
+
+export interface SyncTaskEvent {
+  task_id: number;
+  entity_type: string;
+  index: number;
+  total: number;
+  error_class: string | null;
+  error: string | null;
+  will_retry: boolean;
+}
+
+export interface SyncFinishedEvent {
+  total: number;
+  synced: number;
+  failed: number;
+  /** Left pending without an attempt in this run (server asked to wait); synced + failed + deferred = total */
+  deferred: number;
+  synced_by_type: Record<string, number>;
+  failed_by_type: Record<string, number>;
+  duration_ms: number;
+}
+
 export const IPC_COMMANDS = {
   TAKE_SCREENSHOT: 'take_screenshot',
   TAKE_SCREENSHOT_TO_TEMP: 'take_screenshot_to_temp',
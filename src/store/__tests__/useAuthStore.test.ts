@@ -116,7 +116,8 @@ describe('useAuthStore', () => {
 
     await useAuthStore.getState().logout();
 
-    expect(mockApiLogout).toHaveBeenCalledWith('ref');
+    expect(mockInvoke).toHaveBeenCalledWith('logout', { queuePolicy: 'keep' });
+    expect(mockApiLogout).not.toHaveBeenCalled();
     expect(mockSetCurrentUser).toHaveBeenCalledWith(null);
     expect(mockApiClearToken).toHaveBeenCalled();
     expect(localStorage.getItem('refresh_token')).toBeNull();
@@ -125,6 +126,26 @@ describe('useAuthStore', () => {
     expect(useAuthStore.getState().isAuthenticated).toBe(false);
   });
 
+  it('logout falls back to API revocation when Rust logout fails', async () => {
+    localStorage.setItem('refresh_token', 'ref');
+    mockApiGetAccessToken.mockReturnValue('acc');
+    mockApiLogout.mockResolvedValue(undefined);
+    mockInvoke.mockImplementation((cmd: string) =>
+      cmd === 'logout' ? Promise.reject(new Error('not available')) : Promise.resolve(undefined)
+    );
+
+    await useAuthStore.getState().logout();
+
+    expect(mockApiLogout).toHaveBeenCalledWith('ref');
+    expect(mockInvoke).toHaveBeenCalledWith('set_auth_tokens', {
+      accessToken: null,
+      refreshToken: null,
+      userId: null,
+    });
+    expect(localStorage.getItem('refresh_token')).toBeNull();
+    expect(useAuthStore.getState().isAuthenticated).toBe(false);
+  });
+
   it('clearTokens clears state without requiring access token', async () => {
     mockApiGetAccessToken.mockReturnValue(null);
     mockInvoke.mockResolvedValue(undefined);
@@ -74,23 +74,29 @@ export const useAuthStore = create<AuthState>()(
         }
       },
       logout: async () => {
-        const refreshToken = localStorage.getItem('refresh_token');
-        const accessToken = api.getAccessToken();
+        // Rust отзывает refresh token (POST /auth/logout) и стирает токены AuthManager;
+        // очередь синхронизации остаётся до следующего входа
         try {
-          if (accessToken) {
-            await api.logout(refreshToken ?? undefined);
-          }
+          await invoke('logout', { queuePolicy: 'keep' });
         } catch (e) {
-          logger.warn('AUTH', 'Logout API call failed (clearing local state anyway)', e);
+          logger.warn('AUTH', 'Rust logout failed, revoking via API', e);
+          const refreshToken = localStorage.getItem('refresh_token');
+          try {
+            if (api.getAccessToken()) {
+              await api.logout(refreshToken ?? undefined);
+            }
+          } catch (err) {
+            logger.warn('AUTH', 'Logout API call failed (clearing local state anyway)', err);
+          }
+          await invoke('set_auth_tokens', {
+            accessToken: null,
+            refreshToken: null,
+            userId: null,
+          }).catch((err) => {
+            logger.error('AUTH', 'Failed to clear tokens in Rust AuthManager', err);
+          });
         }
         setCurrentUser(null);
-        await invoke('set_auth_tokens', {
-          accessToken: null,
-          refreshToken: null,
-          userId: null,
-        }).catch((e) => {
-          logger.error('AUTH', 'Failed to clear tokens in Rust AuthManager', e);
-        });
         api.clearToken();
         localStorage.removeItem('refresh_token');
         clearSentryUser();
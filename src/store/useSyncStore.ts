@@ -1,11 +1,14 @@
 import { create } from 'zustand';
 import { invoke } from '@tauri-apps/api/core';
 import { logger } from '../lib/logger';
+import type { ConnectivityState } from '../lib/ipc';
 
 export interface SyncStatus {
   pending_count: number;
   failed_count: number;
   is_online: boolean;
+  /** online / degraded (API медленный или 5xx) / offline */
+  connectivity?: ConnectivityState;
   last_sync_at?: number | null;
 }
 
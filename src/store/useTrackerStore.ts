@@ -33,6 +33,32 @@ function handleEnqueueError(
   return null;
 }
 
+/** Проект из Rust projects_cache (list_cached_projects) */
+interface CachedProject {
+  id: string;
+  name: string;
+  description: string | null;
+  color: string | null;
+  client_name: string | null;
+  status: string | null;
+  updated_at: string | null;
+}
+
+function cachedProjectToProject(p: CachedProject): Project {
+  return {
+    id: p.id,
+    name: p.name,
+    description: p.description ?? '',
+    color: p.color ?? '',
+    clientName: p.client_name ?? '',
+    budget: 0,
+    status: p.status ?? '',
+    companyId: '',
+    createdAt: '',
+    updatedAt: p.updated_at ?? '',
+  };
+}
+
 export interface TrackerState {
   projects: Project[];
   selectedProject: Project | null;
@@ -119,6 +145,12 @@ export const useTrackerStore = create<TrackerState>((set, get) => ({
       const projects = await api.getProjects();
       set({ projects, isLoading: false });
     } catch (error: any) {
+      // Offline: проекты из локального кэша Rust (projects_cache), чтобы можно было начать трекинг
+      const cached = await invoke<CachedProject[]>('list_cached_projects').catch(() => null);
+      if (Array.isArray(cached) && cached.length > 0) {
+        set({ projects: cached.map(cachedProjectToProject), isLoading: false });
+        return;
+      }
       set({ error: error.message || 'Failed to load projects', isLoading: false });
     }
   },